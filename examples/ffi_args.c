@@ -0,0 +1,35 @@
+/***************************************************************************
+ * Shared library used by `ffi_args.sq`, build it using:
+ * cc -shared -fPIC -o libffi_args.so examples/ffi_args.c
+ ***************************************************************************/
+
+#include <stdbool.h>
+#include <stdint.h>
+
+/**
+ * Returns true if every argument has the value passed by `ffi_args.sq`,
+ * the last four arguments are passed on the stack.
+ */
+bool check_args(int32_t a, int64_t b, bool c, uint64_t d, int32_t e,
+                bool f, int64_t g, uint64_t h, int32_t i, bool j) {
+    return a == -1 && b == 5000000000 && c && d == UINT64_MAX && e == 7 &&
+           !f && g == -9000000000 && h == 42 && i == -100 && j;
+}
+
+/**
+ * Weighted sum of the arguments, the result changes if any two arguments are swapped.
+ */
+int64_t sum_args(int32_t a, int64_t b, bool c, uint64_t d, int32_t e,
+                 bool f, int64_t g, uint64_t h, int32_t i, bool j) {
+    uint64_t sum = (uint64_t) a;
+    sum += 2 * (uint64_t) b;
+    sum += 3 * (uint64_t) c;
+    sum += 5 * d;
+    sum += 7 * (uint64_t) e;
+    sum += 11 * (uint64_t) f;
+    sum += 13 * (uint64_t) g;
+    sum += 17 * h;
+    sum += 19 * (uint64_t) i;
+    sum += 23 * (uint64_t) j;
+    return (int64_t) sum;
+}
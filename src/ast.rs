@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 use std::{fmt, cmp};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cell::RefCell;
 use string_interner::{StringInterner, DefaultSymbol};
 use crate::parser::ParseSpan;
@@ -20,6 +20,7 @@ pub struct File {
     pub span: Span,
     pub lines: Vec<u32>, // bytepos for each line in the file
     pub imported_files: HashMap<String, Box<File>>,
+    pub ctx: u16, // span context of this file
     pub error_count: u32
 }
 
@@ -45,12 +46,210 @@ pub enum Item {
  */
 #[derive(Debug, Clone, PartialEq)]
 pub struct FnItem {
+    pub attrs: Vec<Attribute>,
     pub ident: ExprIdent,
     pub decl: FnDecl,
     pub block: Block,
     pub span: Span,
 }
 
+/**
+ * Attribute attached to an item e.g. `#[inline]` or `#[inline(never)]`,
+ * the arguments are plain identifiers.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub ident: ExprIdent,
+    pub args: Vec<ExprIdent>,
+    pub span: Span,
+}
+
+/**
+ * Finds the first attribute with the given name.
+ */
+pub fn find_attribute<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attrs.iter().find(|attr| resolve_symbol(attr.ident.sym) == name)
+}
+
+/**
+ * Marks the calls to the function itself whose value is directly returned,
+ * either by a return expression or by the tail expression of the body.
+ * These calls reuse the frame of the caller, so calls that may pass references
+ * into that frame are not marked, see `find_frame_reference_argument`.
+ */
+pub fn mark_tail_calls(func: &mut FnItem) {
+    let mut locals = HashSet::new();
+    collect_locals_in_block(&func.block, &mut locals);
+
+    let target = TailCallTarget {
+        sym: func.ident.sym,
+        is_void: func.decl.output.is_none(),
+        inputs: &func.decl.inputs,
+        locals: &locals,
+    };
+    mark_tail_calls_in_block(&mut func.block, &target, true);
+}
+
+struct TailCallTarget<'a> {
+    sym: Symbol,
+    is_void: bool,
+    inputs: &'a [Argument],
+    locals: &'a HashSet<Symbol>, // every local declared in the function
+}
+
+fn mark_tail_calls_in_block(block: &mut Block, target: &TailCallTarget, is_tail: bool) {
+    let len = block.stmts.len();
+    for (i, stmt) in block.stmts.iter_mut().enumerate() {
+        let is_last = is_tail && i + 1 == len;
+        match stmt {
+            Stmt::Local(local) => {
+                if let Some(init) = &mut *local.init {
+                    mark_tail_calls_in_expr(init, target, false);
+                }
+            }
+            Stmt::Item(_) => {}
+            // NOTE(alexander): the value of a void function is discarded anyways.
+            Stmt::Semi(expr) => mark_tail_calls_in_expr(expr, target, is_last && target.is_void),
+            Stmt::Expr(expr) => mark_tail_calls_in_expr(expr, target, is_last),
+        }
+    }
+}
+
+fn mark_tail_calls_in_expr(expr: &mut Expr, target: &TailCallTarget, is_tail: bool) {
+    match expr {
+        Expr::Call(call) => {
+            for arg in &mut call.args {
+                mark_tail_calls_in_expr(arg, target, false);
+            }
+            call.is_tail = is_tail &&
+                call.ident.sym == target.sym &&
+                find_frame_reference_argument(call, target.inputs, target.locals).is_none();
+        }
+        Expr::Return(ret) => {
+            if let Some(expr) = &mut *ret.expr {
+                mark_tail_calls_in_expr(expr, target, true);
+            }
+        }
+        Expr::If(expr) => {
+            mark_tail_calls_in_expr(&mut expr.cond, target, false);
+            mark_tail_calls_in_block(&mut expr.then_block, target, is_tail);
+            if let Some(block) = &mut expr.else_block {
+                mark_tail_calls_in_block(block, target, is_tail);
+            }
+        }
+        Expr::Block(expr) => mark_tail_calls_in_block(&mut expr.block, target, is_tail),
+        Expr::Paren(expr) => mark_tail_calls_in_expr(&mut expr.expr, target, is_tail),
+        Expr::While(expr) => {
+            mark_tail_calls_in_expr(&mut expr.cond, target, false);
+            mark_tail_calls_in_block(&mut expr.block, target, false);
+        }
+        Expr::Assign(expr) => {
+            mark_tail_calls_in_expr(&mut expr.left, target, false);
+            mark_tail_calls_in_expr(&mut expr.right, target, false);
+        }
+        Expr::Binary(expr) => {
+            mark_tail_calls_in_expr(&mut expr.left, target, false);
+            mark_tail_calls_in_expr(&mut expr.right, target, false);
+        }
+        Expr::Asm(expr) => {
+            for operand in &mut expr.operands {
+                mark_tail_calls_in_expr(&mut operand.expr, target, false);
+            }
+        }
+        Expr::Reference(expr) => mark_tail_calls_in_expr(&mut expr.expr, target, false),
+        Expr::Unary(expr) => mark_tail_calls_in_expr(&mut expr.expr, target, false),
+        Expr::Break(_) | Expr::Continue(_) | Expr::Ident(_) | Expr::Lit(_) => {}
+    }
+}
+
+/**
+ * Finds the first argument of a self call that may reference the frame of the caller.
+ * Reference parameters can only receive the incoming reference parameters passed
+ * straight through, any other reference may point to a local in the reused frame.
+ */
+pub fn find_frame_reference_argument(call: &ExprCall, inputs: &[Argument], locals: &HashSet<Symbol>) -> Option<usize> {
+    let is_incoming_reference = |arg: &Expr| {
+        let mut arg = arg;
+        while let Expr::Paren(paren) = arg {
+            arg = &paren.expr;
+        }
+
+        match arg {
+            // NOTE(alexander): mutable or shadowed parameters may have been assigned a local reference
+            Expr::Ident(ident) => !locals.contains(&ident.sym) && inputs.iter().any(|input| {
+                input.ident.sym == ident.sym && !input.mutable && input.ty.get_ref().is_some()
+            }),
+            _ => false,
+        }
+    };
+
+    inputs.iter().zip(&call.args).position(|(input, arg)| {
+        input.ty.get_ref().is_some() && !is_incoming_reference(arg)
+    })
+}
+
+/**
+ * Collects the symbols of every local declared in the block, including nested blocks.
+ */
+pub fn collect_locals_in_block(block: &Block, locals: &mut HashSet<Symbol>) {
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Local(local) => {
+                locals.insert(local.ident.sym);
+                if let Some(init) = &*local.init {
+                    collect_locals_in_expr(init, locals);
+                }
+            }
+            Stmt::Item(_) => {}
+            Stmt::Semi(expr) | Stmt::Expr(expr) => collect_locals_in_expr(expr, locals),
+        }
+    }
+}
+
+fn collect_locals_in_expr(expr: &Expr, locals: &mut HashSet<Symbol>) {
+    match expr {
+        Expr::If(expr) => {
+            collect_locals_in_expr(&expr.cond, locals);
+            collect_locals_in_block(&expr.then_block, locals);
+            if let Some(block) = &expr.else_block {
+                collect_locals_in_block(block, locals);
+            }
+        }
+        Expr::Block(expr) => collect_locals_in_block(&expr.block, locals),
+        Expr::While(expr) => {
+            collect_locals_in_expr(&expr.cond, locals);
+            collect_locals_in_block(&expr.block, locals);
+        }
+        Expr::Paren(expr) => collect_locals_in_expr(&expr.expr, locals),
+        Expr::Return(ret) => {
+            if let Some(expr) = &*ret.expr {
+                collect_locals_in_expr(expr, locals);
+            }
+        }
+        Expr::Assign(expr) => {
+            collect_locals_in_expr(&expr.left, locals);
+            collect_locals_in_expr(&expr.right, locals);
+        }
+        Expr::Binary(expr) => {
+            collect_locals_in_expr(&expr.left, locals);
+            collect_locals_in_expr(&expr.right, locals);
+        }
+        Expr::Call(call) => {
+            for arg in &call.args {
+                collect_locals_in_expr(arg, locals);
+            }
+        }
+        Expr::Asm(expr) => {
+            for operand in &expr.operands {
+                collect_locals_in_expr(&operand.expr, locals);
+            }
+        }
+        Expr::Reference(expr) => collect_locals_in_expr(&expr.expr, locals),
+        Expr::Unary(expr) => collect_locals_in_expr(&expr.expr, locals),
+        Expr::Break(_) | Expr::Continue(_) | Expr::Ident(_) | Expr::Lit(_) => {}
+    }
+}
+
 /**
  * Foreign item function struct defines the properties of a
  * foreign function, the identifier and its declaration.
@@ -157,6 +356,8 @@ pub struct Ty {
 #[derive(Debug, Clone, PartialEq)]
 pub enum TyKind {
     Int,
+    Int64,
+    UInt64,
     Bool,
     Ref(TypeRef),
     Error, // used by type checker
@@ -193,17 +394,17 @@ impl Ty {
     }
 
     /**
-     * Returns true if type is i32.
+     * Returns true if type is any of the integer types.
      */
     pub fn is_int(&self) -> bool {
         match &self.kind {
-            TyKind::Int => true,
+            TyKind::Int | TyKind::Int64 | TyKind::UInt64 => true,
             _ => false,
         }
     }
 
     /**
-     * Returns true if type is i32.
+     * Returns true if type is bool.
      */
     pub fn is_bool(&self) -> bool {
         match self.kind {
@@ -273,6 +474,8 @@ impl fmt::Display for TyKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             TyKind::Int => write!(f, "i32"),
+            TyKind::Int64 => write!(f, "i64"),
+            TyKind::UInt64 => write!(f, "u64"),
             TyKind::Bool => write!(f, "bool"),
             TyKind::Ref(r) => write!(f, "{}", r),
             TyKind::Error => write!(f, "()"),
@@ -485,6 +688,9 @@ pub enum Assoc {
  */
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
+    /// Inline assembly e.g. `asm!("rdtsc", out("eax") lo, out("edx") hi)`.
+    Asm(ExprAsm),
+
     /// Expression for mutation for variable e.g. `a = calc()`.
     Assign(ExprAssign),
 
@@ -518,7 +724,7 @@ pub enum Expr {
     /// Reference expression e.g. &342, &mut false.
     Reference(ExprReference),
 
-    /// Expression for return statements e.g. `return true;`, `return;`.
+    /// Expression for return statements e.g. `return true;`, `return;`, `become f(x);`.
     Return(ExprReturn),
 
     /// Expression for unary operations e.g. `-a`, `!is_err()`.
@@ -534,6 +740,7 @@ impl Expr {
      */
     pub fn get_span(&self) -> Span {
         match self {
+            Expr::Asm(expr)       => expr.span,
             Expr::Assign(expr)    => expr.span,
             Expr::Binary(expr)    => expr.span,
             Expr::Block(expr)     => expr.span,
@@ -552,6 +759,50 @@ impl Expr {
     }
 }
 
+/**
+ * Inline assembly block, the template is written in intel syntax and refers to
+ * registers directly. Values are passed in and out through register operands,
+ * other registers written by the template has to be listed as clobbers.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprAsm {
+    pub template: String,
+    pub operands: Vec<AsmOperand>,
+    pub clobbers: Vec<AsmRegister>,
+    pub span: Span,
+}
+
+/**
+ * Inline assembly operand binds an expression to a register e.g. `in("ecx") x`,
+ * outputs and inouts have to be local variables.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmOperand {
+    pub dir: AsmDir,
+    pub reg: AsmRegister,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+/**
+ * Direction of inline assembly operand.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AsmDir {
+    In,
+    Out,
+    InOut,
+}
+
+/**
+ * Register named by an inline assembly operand or clobber e.g. `"eax"`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmRegister {
+    pub name: String,
+    pub span: Span,
+}
+
 /**
  * Assignment of mutable variable, e.g. x = 5;
  */
@@ -593,11 +844,14 @@ pub struct ExprBreak {
 
 /**
  * Function call contains the identifier and arguments.
+ * Tail calls are self calls whose value is directly returned,
+ * these are marked by `mark_tail_calls` after parsing.
  */
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExprCall {
     pub ident: ExprIdent,
     pub args: Vec<Expr>,
+    pub is_tail: bool,
     pub span: Span,
 }
 
@@ -660,11 +914,13 @@ pub struct ExprReference {
 }
 
 /**
- * Return statement can optionally return an expression
+ * Return statement can optionally return an expression,
+ * `become` returns a call that is guaranteed to be a tail call.
  */
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExprReturn {
     pub expr: Box<Option<Expr>>,
+    pub is_become: bool,
     pub span: Span,
 }
 
@@ -698,6 +954,10 @@ pub struct ExprWhile {
 pub enum Lit {
     /// Literal for integers e.g. 5
     Int(i32),
+    /// Literal for 64-bit integers e.g. 5i64
+    Int64(i64),
+    /// Literal for unsigned 64-bit integers e.g. 5u64
+    UInt64(u64),
     /// Literal for booleans e.g. false
     Bool(bool),
 }
@@ -773,9 +1033,24 @@ impl Span {
     }
 }
 
+/**
+ * Finds the file that the span points into, this is either the file
+ * itself or one of its imported files e.g. the prelude.
+ */
+pub fn get_span_file(file: &File, span: Span) -> &File {
+    if span.ctx != file.ctx {
+        for imported_file in file.imported_files.values() {
+            if imported_file.ctx == span.ctx {
+                return imported_file;
+            }
+        }
+    }
+    file
+}
+
 pub fn get_span_location_in_file(lines: &Vec<u32>, span: Span) -> (usize, usize, usize, usize) {
     let line_number = match lines.binary_search(&span.base) {
-        Ok(line) => line.saturating_sub(1),
+        Ok(line) => line,
         Err(line) => line.saturating_sub(1),
     };
     let end_byte_pos = span.base + span.len as u32;
@@ -224,6 +224,29 @@ fn borrow_check_stmt<'a>(bc: &mut BorrowContext<'a>, stmt: &'a Stmt) -> Option<B
 
 fn borrow_check_expr<'a>(bc: &mut BorrowContext<'a>, expr: &'a Expr) -> Option<BorrowInfo> {
     match expr {
+        Expr::Asm(asm_expr) => {
+            for operand in &asm_expr.operands {
+                match (&operand.dir, &operand.expr) {
+                    // NOTE(alexander): outputs may be the first assignment of the local
+                    (AsmDir::Out, Expr::Ident(ident_expr)) => {
+                        let len = bc.scopes.len();
+                        let ident = Ident {
+                            symbol: ident_expr.sym,
+                            index: 0,
+                        };
+                        if !bc.scopes[len - 1].locals.contains_key(&ident) {
+                            insert_borrow_info(bc, ident_expr.sym, None, ident_expr.span);
+                        }
+                    }
+
+                    _ => {
+                        borrow_check_expr(bc, &operand.expr);
+                    }
+                }
+            }
+            None
+        }
+
         Expr::Assign(assign) => {
             let lhs_borrow_info = borrow_check_expr(bc, &*assign.left);
             let rhs_borrow_info = borrow_check_expr(bc, &*assign.right);
@@ -385,7 +408,8 @@ fn create_error_msg<'a>(
     message: &str,
     label: &str
 ) -> ErrorMsg {
-    create_error_msg_from_span(level, &bc.file.lines, span, &bc.file.filename, &bc.file.source, message, label)
+    let file = get_span_file(bc.file, span);
+    create_error_msg_from_span(level, &file.lines, span, &file.filename, &file.source, message, label)
 }
 
 impl fmt::Display for Ident {
@@ -0,0 +1,864 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::ast::*;
+use crate::ffi::is_c_abi;
+
+/***************************************************************************
+ * C Backend:
+ * - Translates the type checked AST into portable C99, the output only
+ *   depends on the C standard library and can be built using `cc out.c`.
+ * - Functions written in sqrrl (incl. native functions) are prefixed
+ *   with `sqrrl_` to avoid clashing with the C library, foreign functions
+ *   from `extern "C"` blocks keep their names.
+ * - References become pointers, if- and block-expressions that produce
+ *   values are assigned to temporaries declared at the top of the function.
+ * - Function calls are also stored in temporaries, this keeps the
+ *   evaluation order the same as the interpreter.
+ * - Integer arithmetic wraps on overflow, same as the interpreter.
+ ***************************************************************************/
+
+pub struct CContext<'a> {
+    pub file: Option<&'a File>,
+    declarations: String,
+    definitions: String,
+    temps: String, // temporaries of the current function, declared at the top
+    body: String,  // statements of the current function
+    indent: usize,
+    signatures: HashMap<Symbol, CSignature<'a>>,
+    natives: Vec<&'static str>,
+    scopes: Vec<HashMap<Symbol, CLocal>>,
+    local_names: HashSet<String>,
+    foreign_names: HashSet<String>,
+    params: Vec<String>, // names of the parameters of the current function, assigned by tail calls
+    is_void: bool,
+    is_void_main: bool,
+    has_tail_call: bool,
+    uses_div: bool,
+    uses_pow: bool,
+    next_temp: u32,
+    trace_symbol: Symbol,
+    debug_break_symbol: Symbol,
+}
+
+struct CSignature<'a> {
+    decl: &'a FnDecl,
+    name: String,
+}
+
+#[derive(Clone)]
+struct CLocal {
+    name: String,
+    ty: TyKind,
+}
+
+pub fn create_c_context<'a>() -> CContext<'a> {
+    CContext {
+        file: None,
+        declarations: String::new(),
+        definitions: String::new(),
+        temps: String::new(),
+        body: String::new(),
+        indent: 1,
+        signatures: HashMap::new(),
+        natives: Vec::new(),
+        scopes: Vec::new(),
+        local_names: HashSet::new(),
+        foreign_names: HashSet::new(),
+        params: Vec::new(),
+        is_void: false,
+        is_void_main: false,
+        has_tail_call: false,
+        uses_div: false,
+        uses_pow: false,
+        next_temp: 0,
+        trace_symbol: intern_string("trace"),
+        debug_break_symbol: intern_string("debug_break"),
+    }
+}
+
+/**
+ * Generates a C99 translation unit containing every function in the file.
+ */
+pub fn build_c_source_from_ast<'a>(cc: &mut CContext<'a>, file: &'a File) -> String {
+    cc.file = Some(file);
+
+    fn register_items<'a>(cc: &mut CContext<'a>, items: &'a Vec<Item>, abi: &Option<String>) {
+        for item in items {
+            match item {
+                Item::Fn(func) => {
+                    let name = format!("sqrrl_{}", resolve_symbol(func.ident.sym));
+                    let _ = writeln!(cc.declarations, "static {};", to_c_prototype(&name, &func.decl));
+                    cc.signatures.insert(func.ident.sym, CSignature { decl: &func.decl, name });
+                }
+
+                Item::ForeignFn(func) => {
+                    // NOTE(alexander): backend and interpreter level intrinsics are never declared
+                    if func.ident.sym == cc.trace_symbol || func.ident.sym == cc.debug_break_symbol {
+                        continue;
+                    }
+
+                    let ident = resolve_symbol(func.ident.sym);
+                    let name = if is_c_abi(abi) {
+                        let _ = writeln!(cc.declarations, "{};", to_c_prototype(ident, &func.decl));
+                        cc.foreign_names.insert(ident.to_string());
+                        ident.to_string()
+                    } else {
+                        let name = format!("sqrrl_{}", ident);
+                        match get_c_native_function(ident) {
+                            Some(source) => cc.natives.push(source),
+                            None => {
+                                // NOTE(alexander): host functions without C implementation are linked in
+                                let _ = writeln!(cc.declarations, "extern {};", to_c_prototype(&name, &func.decl));
+                            }
+                        }
+                        name
+                    };
+                    cc.signatures.insert(func.ident.sym, CSignature { decl: &func.decl, name });
+                }
+
+                Item::ForeignMod(module) => register_items(cc, &module.items, &module.abi),
+            }
+        }
+    }
+
+    register_items(cc, &file.items, &None);
+
+    let mut main_decl = None;
+    for item in &file.items {
+        if let Item::Fn(func) = item {
+            if resolve_symbol(func.ident.sym) == "main" {
+                main_decl = Some(&func.decl);
+            }
+            build_c_source_from_function(cc, func);
+        }
+    }
+
+    let mut source = String::new();
+    let _ = writeln!(source, "/* Generated from {} */", file.filename);
+    source.push_str(C_HEADER);
+    source.push_str(&cc.declarations);
+    source.push('\n');
+
+    source.push_str(C_RUNTIME);
+    for native in &cc.natives {
+        source.push_str(native);
+    }
+    if cc.uses_div {
+        source.push_str(C_DIV_FUNCTIONS);
+    }
+    if cc.uses_pow {
+        source.push_str(C_POW_FUNCTION);
+    }
+
+    source.push_str(&cc.definitions);
+
+    // The C entry point stores the program arguments and calls the sqrrl main function
+    source.push_str("int main(int argc, char **argv) {\n");
+    source.push_str("    sqrrl_argc = argc;\n");
+    source.push_str("    sqrrl_argv = argv;\n");
+    match main_decl {
+        Some(decl) if !decl.output.is_none() => source.push_str("    return sqrrl_main();\n"),
+        _ => {
+            source.push_str("    sqrrl_main();\n");
+            source.push_str("    return 0;\n");
+        }
+    }
+    source.push_str("}\n");
+    source
+}
+
+const C_HEADER: &str = "
+#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+";
+
+/**
+ * Program arguments, standard input and runtime errors shared by the native functions.
+ */
+const C_RUNTIME: &str = "static int sqrrl_argc = 0;
+static char **sqrrl_argv = NULL;
+static char sqrrl_input_token[256];
+static bool sqrrl_has_input_token = false;
+
+static void sqrrl_error(const char *msg) {
+    fflush(stdout);
+    fprintf(stderr, \"error: %s\\n\", msg);
+    exit(1);
+}
+
+static bool sqrrl_peek_token(void) {
+    if (!sqrrl_has_input_token) {
+        sqrrl_has_input_token = scanf(\"%255s\", sqrrl_input_token) == 1;
+    }
+    return sqrrl_has_input_token;
+}
+
+";
+
+/**
+ * Division and remainder operators, dividing by zero or overflowing is an error in the interpreter.
+ */
+const C_DIV_FUNCTIONS: &str = "static int32_t sqrrl_div(int32_t a, int32_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    if (b == -1 && a == INT32_MIN) sqrrl_error(\"attempt to divide with overflow\");
+    return a / b;
+}
+
+static int32_t sqrrl_mod(int32_t a, int32_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    if (b == -1 && a == INT32_MIN) sqrrl_error(\"attempt to calculate the remainder with overflow\");
+    return a % b;
+}
+
+static int64_t sqrrl_div_i64(int64_t a, int64_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    if (b == -1 && a == INT64_MIN) sqrrl_error(\"attempt to divide with overflow\");
+    return a / b;
+}
+
+static int64_t sqrrl_mod_i64(int64_t a, int64_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    if (b == -1 && a == INT64_MIN) sqrrl_error(\"attempt to calculate the remainder with overflow\");
+    return a % b;
+}
+
+static uint64_t sqrrl_div_u64(uint64_t a, uint64_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    return a / b;
+}
+
+static uint64_t sqrrl_mod_u64(uint64_t a, uint64_t b) {
+    if (b == 0) sqrrl_error(\"attempt to divide by zero\");
+    return a % b;
+}
+
+";
+
+/**
+ * Integer power function used by the `**` operator.
+ */
+const C_POW_FUNCTION: &str = "static uint64_t sqrrl_pow_u64(uint64_t base, uint64_t exp) {
+    uint64_t result = 1;
+    uint64_t b = base;
+    uint32_t e = (uint32_t) exp;
+    while (e > 0) {
+        if (e & 1) result *= b;
+        b *= b;
+        e >>= 1;
+    }
+    return result;
+}
+
+static int32_t sqrrl_pow(int32_t base, int32_t exp) {
+    uint32_t result = 1;
+    uint32_t b = (uint32_t) base;
+    uint32_t e = (uint32_t) exp;
+    while (e > 0) {
+        if (e & 1) result *= b;
+        b *= b;
+        e >>= 1;
+    }
+    return (int32_t) result;
+}
+
+static int64_t sqrrl_pow_i64(int64_t base, int64_t exp) {
+    return (int64_t) sqrrl_pow_u64((uint64_t) base, (uint64_t) exp);
+}
+
+";
+
+/**
+ * C implementations of the native functions declared in `basic.sq`.
+ */
+fn get_c_native_function(name: &str) -> Option<&'static str> {
+    let source = match name {
+        "print_int" => "static void sqrrl_print_int(int32_t val) {
+    printf(\"%d\\n\", val);
+}
+
+",
+        "print_i64" => "static void sqrrl_print_i64(int64_t val) {
+    printf(\"%\" PRId64 \"\\n\", val);
+}
+
+",
+        "print_u64" => "static void sqrrl_print_u64(uint64_t val) {
+    printf(\"%\" PRIu64 \"\\n\", val);
+}
+
+",
+        "print_bool" => "static void sqrrl_print_bool(bool val) {
+    printf(\"%s\\n\", val ? \"true\" : \"false\");
+}
+
+",
+        "assert" => "static void sqrrl_assert(bool val) {
+    if (!val) sqrrl_error(\"assertion failed\");
+}
+
+",
+        "assert_eq_int" => "static void sqrrl_assert_eq_int(int32_t left, int32_t right) {
+    if (left != right) {
+        fflush(stdout);
+        fprintf(stderr, \"error: assertion failed: `(left == right)`\\n  left: `%d`,\\n right: `%d`\\n\", left, right);
+        exit(1);
+    }
+}
+
+",
+        "assert_eq_bool" => "static void sqrrl_assert_eq_bool(bool left, bool right) {
+    if (left != right) {
+        fflush(stdout);
+        fprintf(stderr, \"error: assertion failed: `(left == right)`\\n  left: `%s`,\\n right: `%s`\\n\",
+                left ? \"true\" : \"false\", right ? \"true\" : \"false\");
+        exit(1);
+    }
+}
+
+",
+        "arg_count" => "static int32_t sqrrl_arg_count(void) {
+    return sqrrl_argc > 0 ? sqrrl_argc - 1 : 0;
+}
+
+",
+        "arg_int" => "static int32_t sqrrl_arg_int(int32_t index) {
+    char *end;
+    long val;
+    if (index < 0 || index + 1 >= sqrrl_argc) sqrrl_error(\"program argument is missing\");
+    val = strtol(sqrrl_argv[index + 1], &end, 10);
+    if (*end != '\\0' || end == sqrrl_argv[index + 1]) sqrrl_error(\"program argument is not an integer\");
+    return (int32_t) val;
+}
+
+",
+        "is_eof" => "static bool sqrrl_is_eof(void) {
+    return !sqrrl_peek_token();
+}
+
+",
+        "read_int" => "static int32_t sqrrl_read_int(void) {
+    char *end;
+    long val;
+    if (!sqrrl_peek_token()) return 0;
+    sqrrl_has_input_token = false;
+    val = strtol(sqrrl_input_token, &end, 10);
+    if (*end != '\\0') sqrrl_error(\"expected integer on standard input\");
+    return (int32_t) val;
+}
+
+",
+        "read_bool" => "static bool sqrrl_read_bool(void) {
+    if (!sqrrl_peek_token()) return false;
+    sqrrl_has_input_token = false;
+    if (strcmp(sqrrl_input_token, \"true\") == 0 || strcmp(sqrrl_input_token, \"1\") == 0) return true;
+    if (strcmp(sqrrl_input_token, \"false\") == 0 || strcmp(sqrrl_input_token, \"0\") == 0) return false;
+    sqrrl_error(\"expected boolean on standard input\");
+    return false;
+}
+
+",
+        _ => return None,
+    };
+    Some(source)
+}
+
+fn build_c_source_from_function<'a>(cc: &mut CContext<'a>, func: &'a FnItem) {
+    cc.temps.clear();
+    cc.body.clear();
+    cc.scopes.clear();
+    cc.local_names.clear();
+    cc.indent = 1;
+    cc.next_temp = 0;
+    cc.params.clear();
+    cc.is_void = func.decl.output.is_none();
+    cc.is_void_main = resolve_symbol(func.ident.sym) == "main" && func.decl.output.is_none();
+    cc.has_tail_call = false;
+
+    // Parameters are declared in the outermost scope
+    let mut params = Vec::new();
+    cc.scopes.push(HashMap::new());
+    for arg in &func.decl.inputs {
+        let name = create_c_local(cc, arg.ident.sym, &arg.ty.kind);
+        params.push(to_c_declaration(&arg.ty.kind, &name));
+        cc.params.push(name);
+    }
+
+    let (value, ty) = build_c_source_from_block(cc, &func.block);
+    cc.scopes.pop();
+
+    if !func.decl.output.is_none() && ty != TyKind::None {
+        push_c_stmt(cc, format!("return {};", value));
+    }
+
+    let name = &cc.signatures[&func.ident.sym].name;
+    let params = if params.is_empty() { String::from("void") } else { params.join(", ") };
+    let _ = writeln!(cc.definitions, "static {}({}) {{", to_c_declaration(&func.decl.output.kind, name), params);
+    cc.definitions.push_str(&cc.temps);
+    if cc.has_tail_call {
+        cc.definitions.push_str("_tail_call:\n");
+    }
+    cc.definitions.push_str(&cc.body);
+    cc.definitions.push_str("}\n\n");
+}
+
+fn build_c_source_from_block<'a>(cc: &mut CContext<'a>, block: &'a Block) -> (String, TyKind) {
+    cc.scopes.push(HashMap::new());
+    let mut result = (String::new(), TyKind::None);
+    for (i, stmt) in block.stmts.iter().enumerate() {
+        let is_last = i == block.stmts.len() - 1;
+        match stmt {
+            Stmt::Local(local) => {
+                // NOTE(alexander): the initializer is evaluated before the new local shadows any previous one
+                let init = local.init.as_ref().as_ref().map(|init| build_c_source_from_expr(cc, init));
+
+                let ty = match &init {
+                    Some((_, init_ty)) if local.ty.is_none() => init_ty.clone(),
+                    _ => local.ty.kind.clone(),
+                };
+
+                let name = create_c_local(cc, local.ident.sym, &ty);
+                let decl = to_c_declaration(&ty, &name);
+                match init {
+                    Some((value, _)) => push_c_stmt(cc, format!("{} = {};", decl, value)),
+                    None => push_c_stmt(cc, format!("{};", decl)),
+                }
+            }
+
+            // NOTE(alexander): nested items are not supported, same as the x86 backend
+            Stmt::Item(_) => {}
+
+            Stmt::Semi(expr) => {
+                build_c_source_from_stmt(cc, expr);
+            }
+
+            Stmt::Expr(expr) => {
+                if is_last {
+                    result = build_c_source_from_expr(cc, expr);
+                } else {
+                    build_c_source_from_stmt(cc, expr);
+                }
+            }
+        }
+    }
+    cc.scopes.pop();
+    result
+}
+
+/**
+ * Builds an expression whose value is not used, values
+ * that don't have side effects are discarded.
+ */
+fn build_c_source_from_stmt<'a>(cc: &mut CContext<'a>, expr: &'a Expr) {
+    let (value, ty) = build_c_source_from_expr(cc, expr);
+    if let Expr::Call(_) = expr {
+        if ty != TyKind::None {
+            push_c_stmt(cc, format!("(void) {};", value));
+        }
+    }
+}
+
+fn build_c_source_from_expr<'a>(cc: &mut CContext<'a>, expr: &'a Expr) -> (String, TyKind) {
+    match expr {
+        Expr::Asm(_) => panic!("c: inline assembly is only supported by the x86 backend"),
+
+        Expr::Assign(assign) => {
+            let (value, _) = build_c_source_from_expr(cc, &assign.right);
+            let place = build_c_source_from_place(cc, &assign.left);
+            push_c_stmt(cc, format!("{} = {};", place, value));
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Binary(binary) => {
+            let (lhs, ty) = build_c_source_from_expr(cc, &binary.left);
+            let (rhs, _) = build_c_source_from_expr(cc, &binary.right);
+            let c_ty = to_c_type(&ty);
+            let (unsigned_ty, suffix) = match ty {
+                TyKind::Int64  => ("uint64_t", "_i64"),
+                TyKind::UInt64 => ("uint64_t", "_u64"),
+                _              => ("uint32_t", ""),
+            };
+            match binary.op {
+                // NOTE(alexander): unsigned arithmetic wraps on overflow instead of being undefined
+                BinOp::Add => (format!("({}) (({}) {} + ({}) {})", c_ty, unsigned_ty, lhs, unsigned_ty, rhs), ty),
+                BinOp::Sub => (format!("({}) (({}) {} - ({}) {})", c_ty, unsigned_ty, lhs, unsigned_ty, rhs), ty),
+                BinOp::Mul => (format!("({}) (({}) {} * ({}) {})", c_ty, unsigned_ty, lhs, unsigned_ty, rhs), ty),
+                BinOp::Div => {
+                    cc.uses_div = true;
+                    (format!("sqrrl_div{}({}, {})", suffix, lhs, rhs), ty)
+                }
+                BinOp::Mod => {
+                    cc.uses_div = true;
+                    (format!("sqrrl_mod{}({}, {})", suffix, lhs, rhs), ty)
+                }
+                BinOp::Pow => {
+                    cc.uses_pow = true;
+                    (format!("sqrrl_pow{}({}, {})", suffix, lhs, rhs), ty)
+                }
+                // NOTE(alexander): both operands are always evaluated, same as the interpreter
+                BinOp::And => (format!("({} & {})", lhs, rhs), TyKind::Bool),
+                BinOp::Or  => (format!("({} | {})", lhs, rhs), TyKind::Bool),
+                BinOp::Eq  => (format!("({} == {})", lhs, rhs), TyKind::Bool),
+                BinOp::Ne  => (format!("({} != {})", lhs, rhs), TyKind::Bool),
+                BinOp::Lt  => (format!("({} < {})", lhs, rhs), TyKind::Bool),
+                BinOp::Le  => (format!("({} <= {})", lhs, rhs), TyKind::Bool),
+                BinOp::Gt  => (format!("({} > {})", lhs, rhs), TyKind::Bool),
+                BinOp::Ge  => (format!("({} >= {})", lhs, rhs), TyKind::Bool),
+            }
+        }
+
+        Expr::Block(block) => {
+            push_c_stmt(cc, String::from("{"));
+            cc.indent += 1;
+            let (value, ty) = build_c_source_from_block(cc, &block.block);
+            let result = store_c_temp(cc, value, ty);
+            cc.indent -= 1;
+            push_c_stmt(cc, String::from("}"));
+            result
+        }
+
+        Expr::Break(_) => {
+            push_c_stmt(cc, String::from("break;"));
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Continue(_) => {
+            push_c_stmt(cc, String::from("continue;"));
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Call(call) => build_c_source_from_call(cc, call),
+
+        Expr::Ident(ident) => {
+            let local = find_c_local(cc, ident.sym);
+            (local.name, local.ty)
+        }
+
+        Expr::If(if_expr) => build_c_source_from_if(cc, if_expr),
+
+        Expr::Lit(literal) => match literal.lit {
+            Lit::Int(i32::MIN) => (String::from("(-2147483647 - 1)"), TyKind::Int),
+            Lit::Int(val) => (val.to_string(), TyKind::Int),
+            Lit::Int64(i64::MIN) => (String::from("(INT64_C(-9223372036854775807) - 1)"), TyKind::Int64),
+            Lit::Int64(val) => (format!("INT64_C({})", val), TyKind::Int64),
+            Lit::UInt64(val) => (format!("UINT64_C({})", val), TyKind::UInt64),
+            Lit::Bool(val) => (val.to_string(), TyKind::Bool),
+        },
+
+        Expr::Paren(paren) => build_c_source_from_expr(cc, &paren.expr),
+
+        Expr::Reference(reference) => {
+            let (name, ty) = match &*reference.expr {
+                Expr::Ident(ident) => {
+                    let local = find_c_local(cc, ident.sym);
+                    (local.name, local.ty)
+                }
+
+                expr => {
+                    // Temporary values are stored in a temporary to be able to reference them
+                    let (value, ty) = build_c_source_from_expr(cc, expr);
+                    let temp = create_c_temp(cc, &ty);
+                    push_c_stmt(cc, format!("{} = {};", temp, value));
+                    (temp, ty)
+                }
+            };
+
+            let ty = TyKind::Ref(TypeRef {
+                mutable: reference.mutable,
+                elem: Box::new(Ty::new(ty, reference.span)),
+            });
+            (format!("&{}", name), ty)
+        }
+
+        Expr::Return(return_expr) => {
+            let stmt = match &*return_expr.expr {
+                Some(expr) => {
+                    let (value, ty) = build_c_source_from_expr(cc, expr);
+                    if ty != TyKind::None {
+                        format!("return {};", value)
+                    } else if cc.is_void {
+                        String::from("return;")
+                    } else {
+                        // NOTE(alexander): the value is a tail call which has already jumped to the start
+                        return (String::new(), TyKind::None);
+                    }
+                }
+                None => String::from("return;"),
+            };
+            push_c_stmt(cc, stmt);
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Unary(unary) => {
+            let (value, ty) = build_c_source_from_expr(cc, &unary.expr);
+            match unary.op {
+                UnOp::Neg => match ty {
+                    TyKind::Int64 => (format!("(int64_t) (UINT64_C(0) - (uint64_t) {})", value), ty),
+                    _ => (format!("(int32_t) (0u - (uint32_t) {})", value), TyKind::Int),
+                },
+                UnOp::Not => (format!("(!{})", value), TyKind::Bool),
+                UnOp::Deref => {
+                    let elem_ty = match ty {
+                        TyKind::Ref(r) => r.elem.kind,
+                        _ => panic!("c: cannot dereference non-reference type"),
+                    };
+                    (format!("(*{})", value), elem_ty)
+                }
+            }
+        }
+
+        Expr::While(while_expr) => {
+            // NOTE(alexander): conditions that need statements are evaluated at the start of every iteration
+            let start = cc.body.len();
+            cc.indent += 1;
+            let (cond, _) = build_c_source_from_expr(cc, &while_expr.cond);
+            cc.indent -= 1;
+            let cond_stmts = cc.body.split_off(start);
+
+            if cond_stmts.is_empty() {
+                push_c_stmt(cc, format!("while ({}) {{", strip_c_parens(&cond)));
+                cc.indent += 1;
+            } else {
+                push_c_stmt(cc, String::from("while (1) {"));
+                cc.indent += 1;
+                cc.body.push_str(&cond_stmts);
+                push_c_stmt(cc, format!("if (!{}) break;", cond));
+            }
+
+            build_c_source_from_block(cc, &while_expr.block);
+            cc.indent -= 1;
+            push_c_stmt(cc, String::from("}"));
+            (String::new(), TyKind::None)
+        }
+    }
+}
+
+/**
+ * Returns the lvalue that an assignment writes to.
+ */
+fn build_c_source_from_place<'a>(cc: &mut CContext<'a>, expr: &'a Expr) -> String {
+    match expr {
+        Expr::Ident(ident) => find_c_local(cc, ident.sym).name,
+        Expr::Paren(paren) => build_c_source_from_place(cc, &paren.expr),
+        Expr::Unary(unary) if unary.op == UnOp::Deref => {
+            format!("(*{})", build_c_source_from_expr(cc, &unary.expr).0)
+        }
+        _ => panic!("c: invalid left-hand side expression"),
+    }
+}
+
+fn build_c_source_from_call<'a>(cc: &mut CContext<'a>, call: &'a ExprCall) -> (String, TyKind) {
+    if call.ident.sym == cc.trace_symbol || call.ident.sym == cc.debug_break_symbol {
+        return (String::new(), TyKind::None); // NOTE(alexander): interpreter and debugger level intrinsics
+    }
+
+    let (decl, name) = match cc.signatures.get(&call.ident.sym) {
+        Some(sig) => (sig.decl, sig.name.clone()),
+        None => panic!("c: cannot find function `{}`", resolve_symbol(call.ident.sym)),
+    };
+
+    let args: Vec<String> = call.args.iter().map(|arg| build_c_source_from_expr(cc, arg).0).collect();
+    if call.is_tail {
+        // NOTE(alexander): all the arguments are evaluated before any of the parameters are overwritten
+        let mut temps = Vec::new();
+        for (arg, input) in args.into_iter().zip(&decl.inputs) {
+            temps.push(store_c_temp(cc, arg, input.ty.kind.clone()).0);
+        }
+        for (param, temp) in cc.params.clone().into_iter().zip(temps) {
+            push_c_stmt(cc, format!("{} = {};", param, temp));
+        }
+        push_c_stmt(cc, String::from("goto _tail_call;"));
+        cc.has_tail_call = true;
+        return (String::new(), TyKind::None);
+    }
+
+    let call = format!("{}({})", name, args.join(", "));
+    if decl.output.is_none() {
+        push_c_stmt(cc, format!("{};", call));
+        (String::new(), TyKind::None)
+    } else {
+        let ty = decl.output.kind.clone();
+        let temp = create_c_temp(cc, &ty);
+        push_c_stmt(cc, format!("{} = {};", temp, call));
+        (temp, ty)
+    }
+}
+
+fn build_c_source_from_if<'a>(cc: &mut CContext<'a>, if_expr: &'a ExprIf) -> (String, TyKind) {
+    let (cond, _) = build_c_source_from_expr(cc, &if_expr.cond);
+
+    // NOTE(alexander): the result temporary is declared once the type of the branches is known
+    let result_index = cc.next_temp;
+    cc.next_temp += 1;
+    let result = format!("_t{}", result_index);
+
+    push_c_stmt(cc, format!("if ({}) {{", strip_c_parens(&cond)));
+    cc.indent += 1;
+    let (then_value, then_ty) = build_c_source_from_block(cc, &if_expr.then_block);
+    if then_ty != TyKind::None && if_expr.else_block.is_some() {
+        push_c_stmt(cc, format!("{} = {};", result, then_value));
+    }
+    cc.indent -= 1;
+
+    let mut else_ty = TyKind::None;
+    if let Some(else_block) = &if_expr.else_block {
+        push_c_stmt(cc, String::from("} else {"));
+        cc.indent += 1;
+        let (else_value, ty) = build_c_source_from_block(cc, else_block);
+        if ty != TyKind::None {
+            push_c_stmt(cc, format!("{} = {};", result, else_value));
+        }
+        else_ty = ty;
+        cc.indent -= 1;
+    }
+    push_c_stmt(cc, String::from("}"));
+
+    // If-expressions without else or with diverging branches don't produce values
+    let ty = if then_ty != TyKind::None { then_ty } else { else_ty };
+    if ty == TyKind::None || if_expr.else_block.is_none() {
+        return (String::new(), TyKind::None);
+    }
+
+    let _ = writeln!(cc.temps, "    {};", to_c_declaration(&ty, &result));
+    (result, ty)
+}
+
+/***************************************************************************
+ * C source builder helpers
+ ***************************************************************************/
+
+fn push_c_stmt(cc: &mut CContext, stmt: String) {
+    for _ in 0..cc.indent {
+        cc.body.push_str("    ");
+    }
+    cc.body.push_str(&stmt);
+    cc.body.push('\n');
+}
+
+/**
+ * Stores the value in a temporary so it can be used outside of the current C block.
+ */
+fn store_c_temp(cc: &mut CContext, value: String, ty: TyKind) -> (String, TyKind) {
+    if ty == TyKind::None {
+        return (value, ty);
+    }
+    let temp = create_c_temp(cc, &ty);
+    push_c_stmt(cc, format!("{} = {};", temp, value));
+    (temp, ty)
+}
+
+/**
+ * Removes redundant outer parentheses, e.g. from conditions in `if ((a < b))`.
+ */
+fn strip_c_parens(expr: &str) -> &str {
+    if !expr.starts_with('(') || !expr.ends_with(')') {
+        return expr;
+    }
+
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 && i != expr.len() - 1 {
+                    return expr; // the first parenthesis closes before the end, e.g. `(a) + (b)`
+                }
+            }
+            _ => {}
+        }
+    }
+    &expr[1..expr.len() - 1]
+}
+
+fn create_c_temp(cc: &mut CContext, ty: &TyKind) -> String {
+    let temp = format!("_t{}", cc.next_temp);
+    cc.next_temp += 1;
+    let _ = writeln!(cc.temps, "    {};", to_c_declaration(ty, &temp));
+    temp
+}
+
+/**
+ * Creates a uniquely named C variable for the local, since sqrrl allows
+ * shadowing locals in the same scope which C does not. Names that clash
+ * with C keywords, temporaries or foreign functions get a trailing underscore.
+ */
+fn create_c_local(cc: &mut CContext, symbol: Symbol, ty: &TyKind) -> String {
+    let ident = resolve_symbol(symbol);
+    let is_reserved = ident.starts_with('_') ||
+        ident.starts_with("sqrrl_") ||
+        C_KEYWORDS.contains(&ident) ||
+        cc.foreign_names.contains(ident);
+    let mut name = if is_reserved {
+        format!("{}_", ident)
+    } else {
+        ident.to_string()
+    };
+
+    let mut counter = 1;
+    while cc.local_names.contains(&name) {
+        name = format!("{}_{}", ident, counter);
+        counter += 1;
+    }
+    cc.local_names.insert(name.clone());
+
+    if let Some(scope) = cc.scopes.last_mut() {
+        scope.insert(symbol, CLocal { name: name.clone(), ty: ty.clone() });
+    }
+    name
+}
+
+fn find_c_local(cc: &CContext, symbol: Symbol) -> CLocal {
+    for scope in cc.scopes.iter().rev() {
+        if let Some(local) = scope.get(&symbol) {
+            return local.clone();
+        }
+    }
+    panic!("c: cannot find value `{}` in this scope", resolve_symbol(symbol));
+}
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "bool", "case", "char", "const", "default", "do", "double", "enum", "extern",
+    "float", "for", "goto", "inline", "int", "long", "register", "restrict", "short",
+    "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "int32_t", "uint32_t", "int64_t", "uint64_t", "NULL",
+];
+
+fn to_c_type(ty: &TyKind) -> String {
+    match ty {
+        TyKind::Int => String::from("int32_t"),
+        TyKind::Int64 => String::from("int64_t"),
+        TyKind::UInt64 => String::from("uint64_t"),
+        TyKind::Bool => String::from("bool"),
+        TyKind::Ref(r) => format!("{} *", to_c_type(&r.elem.kind)),
+        TyKind::Error |
+        TyKind::None => String::from("void"),
+    }
+}
+
+fn to_c_declaration(ty: &TyKind, name: &str) -> String {
+    let ty = to_c_type(ty);
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+fn to_c_prototype(name: &str, decl: &FnDecl) -> String {
+    let params = if decl.inputs.is_empty() {
+        String::from("void")
+    } else {
+        decl.inputs.iter()
+            .map(|arg| to_c_type(&arg.ty.kind))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    format!("{}({})", to_c_declaration(&decl.output.kind, name), params)
+}
@@ -0,0 +1,498 @@
+use crate::x86::X86Object;
+
+/***************************************************************************
+ * ELF64 writer, produces either a relocatable object containing the `.text`
+ * section together with its symbol table and relocations, linked using the
+ * system C compiler e.g. `cc prog.o src/libstd/runtime.c -o prog`,
+ * or a statically linked executable that runs without libc.
+ ***************************************************************************/
+
+const ELF_HEADER_SIZE: usize = 64;
+const ELF_PROGRAM_HEADER_SIZE: usize = 56;
+const ELF_SECTION_HEADER_SIZE: usize = 64;
+const ELF_SYMBOL_SIZE: usize = 24;
+const ELF_RELA_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_ABS: u16 = 0xfff1;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0x1;
+const PF_R: u32 = 0x4;
+
+const R_X86_64_PLT32: u32 = 4;
+
+// NOTE(alexander): executables are loaded at the traditional non-PIE base address
+const ELF_BASE_ADDRESS: u64 = 0x400000;
+const ELF_PAGE_SIZE: u64 = 0x1000;
+
+// NOTE(alexander): section indices, in the order the section headers are written
+const TEXT_SECTION: u16 = 1;
+const SYMTAB_SECTION: u32 = 2;
+const STRTAB_SECTION: u32 = 3;
+
+struct ElfSectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+}
+
+struct ElfSymbol {
+    name: u32,
+    info: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/**
+ * String table where each string is null terminated,
+ * the first byte is always the empty string.
+ */
+struct ElfStringTable {
+    bytes: Vec<u8>,
+}
+
+fn create_elf_string_table() -> ElfStringTable {
+    ElfStringTable { bytes: vec![0] }
+}
+
+fn push_elf_string(table: &mut ElfStringTable, s: &str) -> u32 {
+    let index = table.bytes.len() as u32;
+    table.bytes.extend_from_slice(s.as_bytes());
+    table.bytes.push(0);
+    index
+}
+
+/**
+ * Builds an ELF64 relocatable object from the compiled x86 object,
+ * the filename is recorded as the source file symbol.
+ */
+pub fn build_elf_object(filename: &str, object: &X86Object) -> Vec<u8> {
+    let mut strtab = create_elf_string_table();
+    let (mut symbols, first_global_symbol) = build_elf_symbols(&mut strtab, filename, object, 0);
+
+    // Foreign functions are undefined symbols, each gets one symbol shared by all its relocations
+    let first_foreign_symbol = symbols.len() as u32;
+    let mut foreign_symbols: Vec<&str> = Vec::new();
+    let mut relocations: Vec<(u64, u32)> = Vec::new();
+    for reloc in &object.relocations {
+        let index = match foreign_symbols.iter().position(|name| *name == reloc.symbol) {
+            Some(index) => index,
+            None => {
+                foreign_symbols.push(&reloc.symbol);
+                symbols.push(ElfSymbol {
+                    name: push_elf_string(&mut strtab, &reloc.symbol),
+                    info: elf_symbol_info(STB_GLOBAL, STT_NOTYPE),
+                    shndx: 0,
+                    value: 0,
+                    size: 0,
+                });
+                foreign_symbols.len() - 1
+            }
+        };
+        relocations.push((reloc.offset as u64, first_foreign_symbol + index as u32));
+    }
+
+    let mut shstrtab = create_elf_string_table();
+    let text_name = push_elf_string(&mut shstrtab, ".text");
+    let symtab_name = push_elf_string(&mut shstrtab, ".symtab");
+    let strtab_name = push_elf_string(&mut shstrtab, ".strtab");
+    let rela_name = push_elf_string(&mut shstrtab, ".rela.text");
+    let note_name = push_elf_string(&mut shstrtab, ".note.GNU-stack");
+    let shstrtab_name = push_elf_string(&mut shstrtab, ".shstrtab");
+
+    // Section contents are placed directly after the ELF header
+    let mut buf: Vec<u8> = vec![0; ELF_HEADER_SIZE];
+
+    align_elf_buffer(&mut buf, 16);
+    let text_offset = buf.len();
+    buf.extend_from_slice(&object.machine_code);
+
+    align_elf_buffer(&mut buf, 8);
+    let symtab_offset = buf.len();
+    push_elf_symbols(&mut buf, &symbols);
+
+    let strtab_offset = buf.len();
+    buf.extend_from_slice(&strtab.bytes);
+
+    align_elf_buffer(&mut buf, 8);
+    let rela_offset = buf.len();
+    for (offset, symbol_index) in &relocations {
+        // NOTE(alexander): the rel32 is relative to the end of the call instruction
+        push_u64(&mut buf, *offset);
+        push_u64(&mut buf, ((*symbol_index as u64) << 32) | R_X86_64_PLT32 as u64);
+        push_u64(&mut buf, -4i64 as u64);
+    }
+
+    let shstrtab_offset = buf.len();
+    buf.extend_from_slice(&shstrtab.bytes);
+
+    let sections = [
+        ElfSectionHeader {
+            name: 0, kind: 0, flags: 0, addr: 0, offset: 0, size: 0,
+            link: 0, info: 0, addralign: 0, entsize: 0,
+        },
+        ElfSectionHeader {
+            name: text_name,
+            kind: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            addr: 0,
+            offset: text_offset,
+            size: object.machine_code.len(),
+            link: 0,
+            info: 0,
+            addralign: 16,
+            entsize: 0,
+        },
+        ElfSectionHeader {
+            name: symtab_name,
+            kind: SHT_SYMTAB,
+            flags: 0,
+            addr: 0,
+            offset: symtab_offset,
+            size: symbols.len() * ELF_SYMBOL_SIZE,
+            link: STRTAB_SECTION,
+            info: first_global_symbol,
+            addralign: 8,
+            entsize: ELF_SYMBOL_SIZE as u64,
+        },
+        ElfSectionHeader {
+            name: strtab_name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: strtab_offset,
+            size: strtab.bytes.len(),
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        },
+        ElfSectionHeader {
+            name: rela_name,
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            addr: 0,
+            offset: rela_offset,
+            size: relocations.len() * ELF_RELA_SIZE,
+            link: SYMTAB_SECTION,
+            info: TEXT_SECTION as u32,
+            addralign: 8,
+            entsize: ELF_RELA_SIZE as u64,
+        },
+        // NOTE(alexander): marks the stack as non-executable
+        ElfSectionHeader {
+            name: note_name,
+            kind: SHT_PROGBITS,
+            flags: 0,
+            addr: 0,
+            offset: shstrtab_offset,
+            size: 0,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        },
+        ElfSectionHeader {
+            name: shstrtab_name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: shstrtab_offset,
+            size: shstrtab.bytes.len(),
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        },
+    ];
+
+    align_elf_buffer(&mut buf, 8);
+    let section_headers_offset = buf.len();
+    push_elf_section_headers(&mut buf, &sections);
+    write_elf_header(&mut buf, ET_REL, 0, 0, section_headers_offset, sections.len() as u16);
+
+    buf
+}
+
+/**
+ * Builds a statically linked ELF64 executable from the compiled x86 executable,
+ * the whole file is mapped as a single read and execute segment.
+ */
+pub fn build_elf_executable(filename: &str, object: &X86Object) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![0; ELF_HEADER_SIZE + ELF_PROGRAM_HEADER_SIZE];
+
+    align_elf_buffer(&mut buf, 16);
+    let text_offset = buf.len();
+    let text_addr = ELF_BASE_ADDRESS + text_offset as u64;
+    buf.extend_from_slice(&object.machine_code);
+    let segment_size = buf.len() as u64;
+
+    let entry = match object.symbols.iter().find(|sym| sym.is_global) {
+        Some(sym) => text_addr + sym.offset as u64,
+        None => panic!("elf: executable is missing an entry point"),
+    };
+
+    // Program header, the segment starts at offset 0 to keep file offsets and addresses page aligned
+    let mut program_header: Vec<u8> = Vec::with_capacity(ELF_PROGRAM_HEADER_SIZE);
+    push_u32(&mut program_header, PT_LOAD);
+    push_u32(&mut program_header, PF_R | PF_X);
+    push_u64(&mut program_header, 0); // p_offset
+    push_u64(&mut program_header, ELF_BASE_ADDRESS); // p_vaddr
+    push_u64(&mut program_header, ELF_BASE_ADDRESS); // p_paddr
+    push_u64(&mut program_header, segment_size); // p_filesz
+    push_u64(&mut program_header, segment_size); // p_memsz
+    push_u64(&mut program_header, ELF_PAGE_SIZE);
+    buf[ELF_HEADER_SIZE..ELF_HEADER_SIZE + ELF_PROGRAM_HEADER_SIZE].copy_from_slice(&program_header);
+
+    // Symbols are not loaded, but makes the executable easier to debug
+    let mut strtab = create_elf_string_table();
+    let (symbols, first_global_symbol) = build_elf_symbols(&mut strtab, filename, object, text_addr);
+
+    let mut shstrtab = create_elf_string_table();
+    let text_name = push_elf_string(&mut shstrtab, ".text");
+    let symtab_name = push_elf_string(&mut shstrtab, ".symtab");
+    let strtab_name = push_elf_string(&mut shstrtab, ".strtab");
+    let shstrtab_name = push_elf_string(&mut shstrtab, ".shstrtab");
+
+    align_elf_buffer(&mut buf, 8);
+    let symtab_offset = buf.len();
+    push_elf_symbols(&mut buf, &symbols);
+
+    let strtab_offset = buf.len();
+    buf.extend_from_slice(&strtab.bytes);
+
+    let shstrtab_offset = buf.len();
+    buf.extend_from_slice(&shstrtab.bytes);
+
+    let sections = [
+        ElfSectionHeader {
+            name: 0, kind: 0, flags: 0, addr: 0, offset: 0, size: 0,
+            link: 0, info: 0, addralign: 0, entsize: 0,
+        },
+        ElfSectionHeader {
+            name: text_name,
+            kind: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            addr: text_addr,
+            offset: text_offset,
+            size: object.machine_code.len(),
+            link: 0,
+            info: 0,
+            addralign: 16,
+            entsize: 0,
+        },
+        ElfSectionHeader {
+            name: symtab_name,
+            kind: SHT_SYMTAB,
+            flags: 0,
+            addr: 0,
+            offset: symtab_offset,
+            size: symbols.len() * ELF_SYMBOL_SIZE,
+            link: STRTAB_SECTION,
+            info: first_global_symbol,
+            addralign: 8,
+            entsize: ELF_SYMBOL_SIZE as u64,
+        },
+        ElfSectionHeader {
+            name: strtab_name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: strtab_offset,
+            size: strtab.bytes.len(),
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        },
+        ElfSectionHeader {
+            name: shstrtab_name,
+            kind: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: shstrtab_offset,
+            size: shstrtab.bytes.len(),
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        },
+    ];
+
+    align_elf_buffer(&mut buf, 8);
+    let section_headers_offset = buf.len();
+    push_elf_section_headers(&mut buf, &sections);
+    write_elf_header(&mut buf, ET_EXEC, entry, 1, section_headers_offset, sections.len() as u16);
+
+    buf
+}
+
+/**
+ * Creates the symbols for the source file and each function in `.text`,
+ * local symbols have to come before any global symbols.
+ */
+fn build_elf_symbols(
+    strtab: &mut ElfStringTable,
+    filename: &str,
+    object: &X86Object,
+    text_addr: u64
+) -> (Vec<ElfSymbol>, u32) {
+    let mut symbols: Vec<ElfSymbol> = Vec::new();
+    symbols.push(ElfSymbol { name: 0, info: 0, shndx: 0, value: 0, size: 0 });
+    symbols.push(ElfSymbol {
+        name: push_elf_string(strtab, filename),
+        info: elf_symbol_info(STB_LOCAL, STT_FILE),
+        shndx: SHN_ABS,
+        value: 0,
+        size: 0,
+    });
+    symbols.push(ElfSymbol {
+        name: 0,
+        info: elf_symbol_info(STB_LOCAL, STT_SECTION),
+        shndx: TEXT_SECTION,
+        value: text_addr,
+        size: 0,
+    });
+
+    for sym in object.symbols.iter().filter(|sym| !sym.is_global) {
+        symbols.push(ElfSymbol {
+            name: push_elf_string(strtab, &sym.name),
+            info: elf_symbol_info(STB_LOCAL, STT_FUNC),
+            shndx: TEXT_SECTION,
+            value: text_addr + sym.offset as u64,
+            size: sym.size as u64,
+        });
+    }
+
+    let first_global_symbol = symbols.len() as u32;
+    for sym in object.symbols.iter().filter(|sym| sym.is_global) {
+        symbols.push(ElfSymbol {
+            name: push_elf_string(strtab, &sym.name),
+            info: elf_symbol_info(STB_GLOBAL, STT_FUNC),
+            shndx: TEXT_SECTION,
+            value: text_addr + sym.offset as u64,
+            size: sym.size as u64,
+        });
+    }
+
+    (symbols, first_global_symbol)
+}
+
+fn push_elf_symbols(buf: &mut Vec<u8>, symbols: &[ElfSymbol]) {
+    for sym in symbols {
+        push_u32(buf, sym.name);
+        buf.push(sym.info);
+        buf.push(0); // st_other, default visibility
+        push_u16(buf, sym.shndx);
+        push_u64(buf, sym.value);
+        push_u64(buf, sym.size);
+    }
+}
+
+fn push_elf_section_headers(buf: &mut Vec<u8>, sections: &[ElfSectionHeader]) {
+    for section in sections {
+        push_u32(buf, section.name);
+        push_u32(buf, section.kind);
+        push_u64(buf, section.flags);
+        push_u64(buf, section.addr);
+        push_u64(buf, section.offset as u64);
+        push_u64(buf, section.size as u64);
+        push_u32(buf, section.link);
+        push_u32(buf, section.info);
+        push_u64(buf, section.addralign);
+        push_u64(buf, section.entsize);
+    }
+}
+
+/**
+ * Writes the ELF header at the start of the buffer, the program headers directly
+ * follows it and the section header string table is always the last section.
+ */
+fn write_elf_header(
+    buf: &mut [u8],
+    kind: u16,
+    entry: u64,
+    num_program_headers: u16,
+    section_headers_offset: usize,
+    num_sections: u16
+) {
+    let mut header: Vec<u8> = Vec::with_capacity(ELF_HEADER_SIZE);
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F']);
+    header.push(2); // 64-bit
+    header.push(1); // little endian
+    header.push(1); // ELF version
+    header.push(0); // System V ABI
+    header.extend_from_slice(&[0; 8]);
+    push_u16(&mut header, kind);
+    push_u16(&mut header, EM_X86_64);
+    push_u32(&mut header, 1);
+    push_u64(&mut header, entry);
+    if num_program_headers > 0 {
+        push_u64(&mut header, ELF_HEADER_SIZE as u64);
+    } else {
+        push_u64(&mut header, 0);
+    }
+    push_u64(&mut header, section_headers_offset as u64);
+    push_u32(&mut header, 0); // e_flags
+    push_u16(&mut header, ELF_HEADER_SIZE as u16);
+    if num_program_headers > 0 {
+        push_u16(&mut header, ELF_PROGRAM_HEADER_SIZE as u16);
+    } else {
+        push_u16(&mut header, 0);
+    }
+    push_u16(&mut header, num_program_headers);
+    push_u16(&mut header, ELF_SECTION_HEADER_SIZE as u16);
+    push_u16(&mut header, num_sections);
+    push_u16(&mut header, num_sections - 1);
+    buf[..ELF_HEADER_SIZE].copy_from_slice(&header);
+}
+
+#[inline]
+fn elf_symbol_info(bind: u8, kind: u8) -> u8 {
+    (bind << 4) | kind
+}
+
+fn align_elf_buffer(buf: &mut Vec<u8>, align: usize) {
+    while !buf.len().is_multiple_of(align) {
+        buf.push(0);
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn push_u64(buf: &mut Vec<u8>, val: u64) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
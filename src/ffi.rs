@@ -0,0 +1,199 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::ffi::CString;
+
+/**
+ * Maximum number of arguments that can be passed to foreign functions,
+ * this is limited by the number of trampolines defined in `call_foreign_function`.
+ */
+pub const MAX_FOREIGN_ARGUMENTS: usize = 12;
+
+/**
+ * Handle to a dynamically loaded shared library.
+ */
+struct Library {
+    path: String,
+    handle: usize,
+}
+
+thread_local!(static LIBRARIES: RefCell<Vec<Library>> = const { RefCell::new(Vec::new()) });
+
+/**
+ * Returns true if the abi string refers to the C calling convention,
+ * these functions are resolved from the loaded shared libraries.
+ */
+pub fn is_c_abi(abi: &Option<String>) -> bool {
+    match abi {
+        Some(abi) => abi == "C",
+        None => false,
+    }
+}
+
+/**
+ * Loads a shared library so its symbols can be used by `extern "C"` blocks.
+ */
+#[cfg(any(target_os="linux", target_os="macos"))]
+pub fn load_library(path: &str) -> Result<(), String> {
+    use libc;
+
+    let c_path = CString::new(path).map_err(|_| format!("invalid library path `{}`", path))?;
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+    if handle.is_null() {
+        let reason = unsafe {
+            let err = libc::dlerror();
+            if err.is_null() {
+                String::from("unknown error")
+            } else {
+                std::ffi::CStr::from_ptr(err).to_string_lossy().into_owned()
+            }
+        };
+        return Err(format!("could not load library `{}`: {}", path, reason));
+    }
+
+    LIBRARIES.with(|libs| libs.borrow_mut().push(Library {
+        path: path.to_string(),
+        handle: handle as usize,
+    }));
+    Ok(())
+}
+
+#[cfg(target_os="windows")]
+pub fn load_library(path: &str) -> Result<(), String> {
+    let c_path = CString::new(path).map_err(|_| format!("invalid library path `{}`", path))?;
+    let handle = unsafe { kernel32::LoadLibraryA(c_path.as_ptr()) };
+    if handle.is_null() {
+        return Err(format!("could not load library `{}`", path));
+    }
+
+    LIBRARIES.with(|libs| libs.borrow_mut().push(Library {
+        path: path.to_string(),
+        handle: handle as usize,
+    }));
+    Ok(())
+}
+
+/**
+ * Finds the address of a C symbol, first searching the libraries loaded
+ * using `--link-lib` (in the given order) and then the running process (incl. libc).
+ */
+#[cfg(any(target_os="linux", target_os="macos"))]
+pub fn lookup_symbol(name: &str) -> Option<usize> {
+    use libc;
+
+    let c_name = CString::new(name).ok()?;
+    let mut addr = LIBRARIES.with(|libs| {
+        for lib in libs.borrow().iter() {
+            let sym = unsafe { libc::dlsym(lib.handle as *mut libc::c_void, c_name.as_ptr()) };
+            if !sym.is_null() {
+                return sym as usize;
+            }
+        }
+        0
+    });
+
+    if addr == 0 {
+        addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, c_name.as_ptr()) as usize };
+    }
+
+    if addr == 0 {
+        None
+    } else {
+        Some(addr)
+    }
+}
+
+#[cfg(target_os="windows")]
+pub fn lookup_symbol(name: &str) -> Option<usize> {
+    let c_name = CString::new(name).ok()?;
+    let mut addr = LIBRARIES.with(|libs| {
+        for lib in libs.borrow().iter() {
+            let sym = unsafe { kernel32::GetProcAddress(lib.handle as _, c_name.as_ptr()) };
+            if !sym.is_null() {
+                return sym as usize;
+            }
+        }
+        0
+    });
+
+    if addr == 0 {
+        // NOTE(alexander): the C runtime is not part of the process symbols on windows
+        let crt = CString::new("msvcrt.dll").unwrap();
+        unsafe {
+            let handle = kernel32::LoadLibraryA(crt.as_ptr());
+            if !handle.is_null() {
+                addr = kernel32::GetProcAddress(handle, c_name.as_ptr()) as usize;
+            }
+        }
+    }
+
+    if addr == 0 {
+        None
+    } else {
+        Some(addr)
+    }
+}
+
+/**
+ * Calls a C function at the given address, used by the interpreter.
+ * Every argument is passed as a 64-bit integer register, which is compatible
+ * with 32-bit integer and boolean parameters in the C calling convention.
+ * The returned value is the full return register, callers should
+ * truncate it to the declared return type.
+ */
+pub unsafe fn call_foreign_function(addr: usize, args: &[i64]) -> Result<i64, String> {
+    type F0 = extern "C" fn() -> i64;
+    type F1 = extern "C" fn(i64) -> i64;
+    type F2 = extern "C" fn(i64, i64) -> i64;
+    type F3 = extern "C" fn(i64, i64, i64) -> i64;
+    type F4 = extern "C" fn(i64, i64, i64, i64) -> i64;
+    type F5 = extern "C" fn(i64, i64, i64, i64, i64) -> i64;
+    type F6 = extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64;
+    type F7 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64) -> i64;
+    type F8 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64) -> i64;
+    type F9 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64;
+    type F10 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64;
+    type F11 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64;
+    type F12 = extern "C" fn(i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64;
+
+    let result = match args.len() {
+        0 => std::mem::transmute::<usize, F0>(addr)(),
+        1 => std::mem::transmute::<usize, F1>(addr)(args[0]),
+        2 => std::mem::transmute::<usize, F2>(addr)(args[0], args[1]),
+        3 => std::mem::transmute::<usize, F3>(addr)(args[0], args[1], args[2]),
+        4 => std::mem::transmute::<usize, F4>(addr)(args[0], args[1], args[2], args[3]),
+        5 => std::mem::transmute::<usize, F5>(addr)(args[0], args[1], args[2], args[3], args[4]),
+        6 => std::mem::transmute::<usize, F6>(addr)(args[0], args[1], args[2], args[3], args[4], args[5]),
+        7 => std::mem::transmute::<usize, F7>(addr)(args[0], args[1], args[2], args[3], args[4], args[5], args[6]),
+        8 => std::mem::transmute::<usize, F8>(addr)(args[0], args[1], args[2], args[3], args[4], args[5],
+                                                    args[6], args[7]),
+        9 => std::mem::transmute::<usize, F9>(addr)(args[0], args[1], args[2], args[3], args[4], args[5],
+                                                    args[6], args[7], args[8]),
+        10 => std::mem::transmute::<usize, F10>(addr)(args[0], args[1], args[2], args[3], args[4], args[5],
+                                                      args[6], args[7], args[8], args[9]),
+        11 => std::mem::transmute::<usize, F11>(addr)(args[0], args[1], args[2], args[3], args[4], args[5],
+                                                      args[6], args[7], args[8], args[9], args[10]),
+        12 => std::mem::transmute::<usize, F12>(addr)(args[0], args[1], args[2], args[3], args[4], args[5],
+                                                      args[6], args[7], args[8], args[9], args[10], args[11]),
+        n => return Err(format!("foreign functions can take at most {} arguments, found {}",
+                                MAX_FOREIGN_ARGUMENTS, n)),
+    };
+    Ok(result)
+}
+
+/**
+ * Flushes output buffered by the C standard library, e.g. from `putchar`,
+ * so it is not printed after the output of the compiler itself.
+ */
+#[cfg(any(target_os="linux", target_os="macos"))]
+pub fn flush_foreign_output() {
+    use libc;
+
+    unsafe {
+        libc::fflush(std::ptr::null_mut());
+    }
+}
+
+#[cfg(target_os="windows")]
+pub fn flush_foreign_output() {
+}
@@ -3,18 +3,21 @@
 use std::fmt;
 use std::collections::HashMap;
 use crate::ast::*;
-use crate::intrinsics::*;
+use crate::native::lookup_native_function;
 use crate::error::*;
+use crate::ffi::{is_c_abi, lookup_symbol, call_foreign_function};
 
 pub type IResult<T> = Result<T, ErrorMsg>;
 
 pub struct InterpContext<'a> {
     pub file:          Option<&'a File>,
     pub signatures:    HashMap<Symbol, &'a Item>,
+    pub foreign_fns:   HashMap<Symbol, Option<usize>>, // address of `extern "C"` functions
     pub call_stack:    Vec<InterpScope>,
     pub stack:         Vec<InterpValue>,
     pub stack_pointer: usize,
     pub base_pointer:  usize,
+    pub tail_call_args: Option<Vec<InterpValue>>, // arguments of a pending tail call
 }
 
 #[derive(Clone)]
@@ -37,6 +40,8 @@ pub struct InterpValue {
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(i32),
+    Int64(i64),
+    UInt64(u64),
     Bool(bool),
     Ref(Reference),
     Void, // signal that there is no value
@@ -54,10 +59,12 @@ pub fn create_interp_context<'a>() -> InterpContext<'a> {
     InterpContext {
         file: None,
         signatures: HashMap::new(),
+        foreign_fns: HashMap::new(),
         call_stack: Vec::new(),
         stack: Vec::with_capacity(1000),
         stack_pointer: 0,
         base_pointer: 0,
+        tail_call_args: None,
     }
 }
 
@@ -95,6 +102,8 @@ pub fn is_value_empty(value: &Value) -> bool {
 pub fn to_type_kind(value: &Value) -> TyKind {
     match value {
         Value::Int(_) => TyKind::Int,
+        Value::Int64(_) => TyKind::Int64,
+        Value::UInt64(_) => TyKind::UInt64,
         Value::Bool(_) => TyKind::Bool,
         Value::Ref(r) => TyKind::Ref(
             TypeRef {
@@ -169,6 +178,14 @@ pub fn interp_item<'a>(ic: &mut InterpContext<'a>, item: &'a Item) {
         Item::ForeignMod(module) => {
             for foreign_item in &module.items {
                 interp_item(ic, foreign_item);
+
+                // Resolve the address of C functions, these are called through trampolines
+                if let Item::ForeignFn(func) = foreign_item {
+                    if is_c_abi(&module.abi) {
+                        let addr = lookup_symbol(resolve_symbol(func.ident.sym));
+                        ic.foreign_fns.insert(func.ident.sym, addr);
+                    }
+                }
             }
         }
     };
@@ -193,9 +210,17 @@ pub fn interp_entry_point<'a>(ic: &mut InterpContext<'a>) -> i32 {
 
     let new_scope = create_interp_scope(main_function.decl.span, false);
     ic.call_stack.push(new_scope);
-    let result = match interp_block(ic, &main_function.block) {
+    let mut result = interp_block(ic, &main_function.block);
+    while result.is_ok() && ic.tail_call_args.take().is_some() {
+        let len = ic.call_stack.len();
+        ic.call_stack[len - 1].entities.clear();
+        result = interp_block(ic, &main_function.block);
+    }
+    let result = match result {
         Ok(val) => match val.data {
             Value::Int(out) => out,
+            Value::Int64(out) => out as i32,
+            Value::UInt64(out) => out as i32,
             _ => 0, // TODO(alexander): should this be a type error maybe?
         }
         Err(err) => {
@@ -207,71 +232,97 @@ pub fn interp_entry_point<'a>(ic: &mut InterpContext<'a>) -> i32 {
     return result;
 }
 
-fn interp_intrinsics<'a>(
+/**
+ * Calls a native function from the registry, using its interpreter implementation
+ * if there is one, otherwise it is called through the foreign function trampolines.
+ */
+fn interp_native_function<'a>(
     ic: &mut InterpContext<'a>,
     item: &ForeignFnItem,
-    values: Vec<InterpValue>
+    values: Vec<InterpValue>,
+    span: Span
 ) -> IResult<InterpValue> {
-    match resolve_symbol(item.ident.sym) {
-        "trace" => {
-            trace(ic);
-        },
-
-        "print_int" => {
-            match values[0].data {
-                Value::Int(arg) => print_int(arg),
-                _ => return Err(mismatched_types_fatal_error(
-                    ic, values[0].span, &TyKind::Int, &to_type(&values[0])))
-            };
-        },
+    let func = match lookup_native_function(resolve_symbol(item.ident.sym)) {
+        Some(func) => func,
+        None => return Err(interp_error(
+            ic,
+            span,
+            &format!("`{}` is not a valid intrinsic function", resolve_symbol(item.ident.sym)),
+            "")),
+    };
 
-        "print_bool" => {
-            match values[0].data {
-                Value::Bool(arg) => print_bool(arg),
-                _ => return Err(mismatched_types_fatal_error(
-                    ic, values[0].span, &TyKind::Bool, &to_type(&values[0]))),
-            };
-        },
+    for (value, (_, ty)) in values.iter().zip(&func.inputs) {
+        if to_type_kind(&value.data) != *ty {
+            return Err(mismatched_types_fatal_error(ic, value.span, ty, &to_type(value)));
+        }
+    }
 
-        "assert" => {
-            match values[0].data {
-                Value::Bool(arg) => assert(arg),
-                _ => return Err(mismatched_types_fatal_error(
-                    ic, values[0].span, &TyKind::Bool, &to_type(&values[0]))),
-            };
-        },
+    if let Some(interp) = func.interp {
+        let args: Vec<Value> = values.iter().map(|value| value.data.clone()).collect();
+        return match interp(ic, &args) {
+            Ok(data) => Ok(create_interp_value(data, span, false)),
+            Err(msg) => Err(interp_error(ic, span, &msg, "")),
+        };
+    }
 
-        "assert_eq_int" => {
-            match values[0].data {
-                Value::Int(arg0) => match values[1].data {
-                    Value::Int(arg1) => assert_eq_int(arg0, arg1),
-                    _ => return Err(mismatched_types_fatal_error(
-                        ic, values[1].span, &TyKind::Int, &to_type(&values[1])))
-                }
-                _ => return Err(mismatched_types_fatal_error(ic,
-                    values[0].span, &TyKind::Int, &to_type(&values[0])))
-            };
-        },
+    if func.address.is_none() {
+        return Err(interp_error(
+            ic,
+            span,
+            &format!("`{}` cannot be called by the interpreter", func.name),
+            ""));
+    }
 
-        "assert_eq_bool" => {
-            match values[0].data {
-                Value::Bool(arg0) => match values[1].data {
-                    Value::Bool(arg1) => assert_eq_bool(arg0, arg1),
-                    _ => return Err(mismatched_types_fatal_error(
-                        ic, values[1].span, &TyKind::Bool, &to_type(&values[1])))
-                }
-                _ => return Err(mismatched_types_fatal_error(
-                    ic, values[0].span, &TyKind::Bool, &to_type(&values[0]))),
-            };
-        },
+    interp_foreign_function(ic, item, func.address, values)
+}
 
-        _ => return Err(interp_error(
+/**
+ * Calls a C function through the foreign function trampolines,
+ * integer and boolean values are passed and returned as registers.
+ */
+fn interp_foreign_function<'a>(
+    ic: &mut InterpContext<'a>,
+    item: &ForeignFnItem,
+    addr: Option<usize>,
+    values: Vec<InterpValue>
+) -> IResult<InterpValue> {
+    let addr = match addr {
+        Some(addr) => addr,
+        None => return Err(interp_error(
             ic,
-            Span::new(),
-            &format!("`{}` is not a valid intrinsic function", resolve_symbol(item.ident.sym)),
+            item.ident.span,
+            &format!("cannot find foreign function `{}` in any linked library", resolve_symbol(item.ident.sym)),
             "")),
     };
-    Ok(create_interp_value(Value::None, Span::new(), false))
+
+    let mut args = Vec::with_capacity(values.len());
+    for value in &values {
+        match value.data {
+            Value::Int(arg) => args.push(arg as i64),
+            Value::Int64(arg) => args.push(arg),
+            Value::UInt64(arg) => args.push(arg as i64),
+            Value::Bool(arg) => args.push(arg as i64),
+            _ => return Err(interp_error(
+                ic,
+                value.span,
+                &format!("cannot pass value of type `{}` to foreign function", to_type(value)),
+                "")),
+        };
+    }
+
+    let result = match unsafe { call_foreign_function(addr, &args) } {
+        Ok(result) => result,
+        Err(msg) => return Err(interp_error(ic, item.span, &msg, "")),
+    };
+
+    let data = match item.decl.output.kind {
+        TyKind::Int => Value::Int(result as i32),
+        TyKind::Int64 => Value::Int64(result),
+        TyKind::UInt64 => Value::UInt64(result as u64),
+        TyKind::Bool => Value::Bool(result as u8 != 0),
+        _ => Value::None,
+    };
+    Ok(create_interp_value(data, Span::new(), false))
 }
 
 pub fn interp_block<'a>(ic: &mut InterpContext<'a>, block: &Block) -> IResult<InterpValue> {
@@ -340,6 +391,9 @@ pub fn interp_stmt<'a>(ic: &mut InterpContext<'a>, stmt: &Stmt) -> IResult<Inter
 
 pub fn interp_expr(ic: &mut InterpContext, expr: &Expr) -> IResult<InterpValue> {
     match expr {
+        Expr::Asm       (e) => Err(interp_error(ic, e.span,
+                                                "inline assembly is not supported by the interpreter",
+                                                "help: compile with `--backend x86`")),
         Expr::Assign    (e) => interp_assign_expr(ic, e),
         Expr::Binary    (e) => interp_binary_expr(ic, e),
         Expr::Block     (e) => interp_block_expr(ic, e),
@@ -443,18 +497,117 @@ pub fn interp_binary_expr<'a>(ic: &mut InterpContext<'a>, expr: &ExprBinary) ->
     let left_type = to_type(&left_val);
     let right_val = interp_expr(ic, &expr.right)?;
     let right_type = to_type(&right_val);
-    let combined_span = Span::combine(left_val.span, right_val.span);
+    // NOTE(alexander): values passed as arguments may come from another file e.g. the prelude
+    let combined_span = if left_val.span.ctx == right_val.span.ctx {
+        Span::combine(left_val.span, right_val.span)
+    } else {
+        expr.span
+    };
 
     let result = match left_val.data {
         Value::Int(lhs) => {
             match right_val.data {
+                Value::Int(0) if expr.op == BinOp::Div || expr.op == BinOp::Mod => {
+                    return Err(interp_error(
+                        ic,
+                        expr.span,
+                        "attempt to divide by zero",
+                        "division by zero"));
+                },
+
                 Value::Int(rhs) => match expr.op {
-                    BinOp::Add => Value::Int(lhs + rhs),
-                    BinOp::Sub => Value::Int(lhs - rhs),
-                    BinOp::Div => Value::Int(lhs / rhs),
-                    BinOp::Mul => Value::Int(lhs * rhs),
-                    BinOp::Pow => Value::Int(lhs.pow(rhs as u32)),
-                    BinOp::Mod => Value::Int(lhs % rhs),
+                    BinOp::Add => Value::Int(lhs.wrapping_add(rhs)),
+                    BinOp::Sub => Value::Int(lhs.wrapping_sub(rhs)),
+                    BinOp::Div => match lhs.checked_div(rhs) {
+                        Some(val) => Value::Int(val),
+                        None => return Err(interp_error(
+                            ic,
+                            expr.span,
+                            "attempt to divide with overflow",
+                            "division overflow")),
+                    },
+                    BinOp::Mul => Value::Int(lhs.wrapping_mul(rhs)),
+                    BinOp::Pow => Value::Int(lhs.wrapping_pow(rhs as u32)),
+                    BinOp::Mod => match lhs.checked_rem(rhs) {
+                        Some(val) => Value::Int(val),
+                        None => return Err(interp_error(
+                            ic,
+                            expr.span,
+                            "attempt to calculate the remainder with overflow",
+                            "remainder overflow")),
+                    },
+                    BinOp::Eq  => Value::Bool(lhs == rhs),
+                    BinOp::Ne  => Value::Bool(lhs != rhs),
+                    BinOp::Lt  => Value::Bool(lhs <  rhs),
+                    BinOp::Le  => Value::Bool(lhs <= rhs),
+                    BinOp::Gt  => Value::Bool(lhs >  rhs),
+                    BinOp::Ge  => Value::Bool(lhs >= rhs),
+                    _ => Value::None,
+                },
+                _ => Value::None,
+            }
+        },
+
+        Value::Int64(lhs) => {
+            match right_val.data {
+                Value::Int64(0) if expr.op == BinOp::Div || expr.op == BinOp::Mod => {
+                    return Err(interp_error(
+                        ic,
+                        expr.span,
+                        "attempt to divide by zero",
+                        "division by zero"));
+                },
+
+                Value::Int64(rhs) => match expr.op {
+                    BinOp::Add => Value::Int64(lhs.wrapping_add(rhs)),
+                    BinOp::Sub => Value::Int64(lhs.wrapping_sub(rhs)),
+                    BinOp::Div => match lhs.checked_div(rhs) {
+                        Some(val) => Value::Int64(val),
+                        None => return Err(interp_error(
+                            ic,
+                            expr.span,
+                            "attempt to divide with overflow",
+                            "division overflow")),
+                    },
+                    BinOp::Mul => Value::Int64(lhs.wrapping_mul(rhs)),
+                    BinOp::Pow => Value::Int64(lhs.wrapping_pow(rhs as u32)),
+                    BinOp::Mod => match lhs.checked_rem(rhs) {
+                        Some(val) => Value::Int64(val),
+                        None => return Err(interp_error(
+                            ic,
+                            expr.span,
+                            "attempt to calculate the remainder with overflow",
+                            "remainder overflow")),
+                    },
+                    BinOp::Eq  => Value::Bool(lhs == rhs),
+                    BinOp::Ne  => Value::Bool(lhs != rhs),
+                    BinOp::Lt  => Value::Bool(lhs <  rhs),
+                    BinOp::Le  => Value::Bool(lhs <= rhs),
+                    BinOp::Gt  => Value::Bool(lhs >  rhs),
+                    BinOp::Ge  => Value::Bool(lhs >= rhs),
+                    _ => Value::None,
+                },
+                _ => Value::None,
+            }
+        },
+
+        Value::UInt64(lhs) => {
+            match right_val.data {
+                Value::UInt64(0) if expr.op == BinOp::Div || expr.op == BinOp::Mod => {
+                    return Err(interp_error(
+                        ic,
+                        expr.span,
+                        "attempt to divide by zero",
+                        "division by zero"));
+                },
+
+                Value::UInt64(rhs) => match expr.op {
+                    BinOp::Add => Value::UInt64(lhs.wrapping_add(rhs)),
+                    BinOp::Sub => Value::UInt64(lhs.wrapping_sub(rhs)),
+                    BinOp::Div => Value::UInt64(lhs / rhs),
+                    BinOp::Mul => Value::UInt64(lhs.wrapping_mul(rhs)),
+                    BinOp::Pow => Value::UInt64(lhs.wrapping_pow(rhs as u32)),
+                    BinOp::Mod => Value::UInt64(lhs % rhs),
                     BinOp::Eq  => Value::Bool(lhs == rhs),
                     BinOp::Ne  => Value::Bool(lhs != rhs),
                     BinOp::Lt  => Value::Bool(lhs <  rhs),
@@ -512,6 +665,12 @@ pub fn interp_call_expr(ic: &mut InterpContext, call: &ExprCall) -> IResult<Inte
         values.push(val);
     }
 
+    // NOTE(alexander): tail calls are performed by the caller after returning from this frame.
+    if call.is_tail {
+        ic.tail_call_args = Some(values);
+        return Ok(create_interp_value(Value::Void, call.span, false));
+    }
+
     let item = match ic.signatures.get(&call.ident.sym) {
         Some(item) => item,
         None => return Err(interp_error(
@@ -529,33 +688,47 @@ pub fn interp_call_expr(ic: &mut InterpContext, call: &ExprCall) -> IResult<Inte
             let new_scope = create_interp_scope(func.decl.span, false);
             ic.call_stack.push(new_scope);
 
-            let inputs = &func.decl.inputs;
-            if inputs.len() == values.len() {
-                for i in 0..inputs.len() {
-                    let arg_ty = &inputs[i].ty;
-                    let val_ty = &to_type(&values[i]);
-                    if arg_ty != val_ty {
-                        let span = values[i].span;
-                        return Err(mismatched_types_fatal_error(ic, span, &arg_ty.kind, val_ty));
+            let mut values = values;
+            let result = loop {
+                let inputs = &func.decl.inputs;
+                if inputs.len() == values.len() {
+                    for i in 0..inputs.len() {
+                        let arg_ty = &inputs[i].ty;
+                        let val_ty = &to_type(&values[i]);
+                        if arg_ty != val_ty {
+                            let span = values[i].span;
+                            return Err(mismatched_types_fatal_error(ic, span, &arg_ty.kind, val_ty));
+                        }
+                        let id = &inputs[i].ident;
+                        let addr = store_local_variable(ic, values[i].clone(), Some(id.sym));
+                        let len = ic.call_stack.len();
+                        ic.call_stack[len - 1].entities.insert(id.sym, addr);
                     }
-                    let id = &inputs[i].ident;
-                    let addr = store_local_variable(ic, values[i].clone(), Some(id.sym));
-                    let len = ic.call_stack.len();
-                    ic.call_stack[len - 1].entities.insert(id.sym, addr);
+                } else {
+                    let err = interp_error(
+                        ic,
+                        func.span,
+                        &format!("this function takes {} parameters but {} parameters were supplied",
+                                 inputs.len(),
+                                 values.len()),
+                        ""
+                    );
+                    return Err(err);
                 }
-            } else {
-                let err = interp_error(
-                    ic,
-                    func.span,
-                    &format!("this function takes {} parameters but {} parameters were supplied",
-                             inputs.len(),
-                             values.len()),
-                    ""
-                );
-                return Err(err);
-            }
 
-            let result = interp_block(ic, &func.block);
+                let result = interp_block(ic, &func.block);
+
+                // NOTE(alexander): self tail calls reuse this frame instead of recursing.
+                match ic.tail_call_args.take() {
+                    Some(args) if result.is_ok() => {
+                        values = args;
+                        ic.stack_pointer = ic.base_pointer;
+                        let len = ic.call_stack.len();
+                        ic.call_stack[len - 1].entities.clear();
+                    }
+                    _ => break result,
+                }
+            };
             ic.stack_pointer = stack_pointer;
             ic.base_pointer = base_pointer;
             ic.call_stack.pop();
@@ -572,7 +745,13 @@ pub fn interp_call_expr(ic: &mut InterpContext, call: &ExprCall) -> IResult<Inte
                              values.len()),
                     ""))
             }
-            interp_intrinsics(ic, &func, values)
+            match ic.foreign_fns.get(&func.ident.sym) {
+                Some(addr) => {
+                    let addr = *addr;
+                    interp_foreign_function(ic, func, addr, values)
+                }
+                None => interp_native_function(ic, func, values, call.span),
+            }
         }
 
         _ => {
@@ -614,6 +793,8 @@ pub fn interp_if_expr(ic: &mut InterpContext, if_expr: &ExprIf) -> IResult<Inter
 pub fn interp_lit_expr(literal: &ExprLit) -> InterpValue {
     match literal.lit {
         Lit::Int(val)  => create_interp_value(Value::Int(val), literal.span, false),
+        Lit::Int64(val) => create_interp_value(Value::Int64(val), literal.span, false),
+        Lit::UInt64(val) => create_interp_value(Value::UInt64(val), literal.span, false),
         Lit::Bool(val) => create_interp_value(Value::Bool(val), literal.span, false),
     }
 }
@@ -667,7 +848,8 @@ pub fn interp_unary_expr<'a>(ic: &mut InterpContext, unary: &ExprUnary) -> IResu
 
     let result = match unary.op {
         UnOp::Neg => match value.data {
-            Value::Int(val) => Value::Int(-val),
+            Value::Int(val) => Value::Int(val.wrapping_neg()),
+            Value::Int64(val) => Value::Int64(val.wrapping_neg()),
             _ => Value::None,
         },
 
@@ -741,13 +923,16 @@ fn create_error_msg<'a>(
     label: &str
 ) -> ErrorMsg {
     match ic.file {
-        Some(file) => create_error_msg_from_span(level,
-                                                 &file.lines,
-                                                 span,
-                                                 &file.filename,
-                                                 &file.source,
-                                                 message,
-                                                 label),
+        Some(file) => {
+            let file = get_span_file(file, span);
+            create_error_msg_from_span(level,
+                                       &file.lines,
+                                       span,
+                                       &file.filename,
+                                       &file.source,
+                                       message,
+                                       label)
+        }
 
         None => ErrorMsg {
             level: level,
@@ -837,6 +1022,8 @@ impl fmt::Debug for InterpValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.data {
             Value::Int(val) => write!(f, "{:<6} (i32)", val),
+            Value::Int64(val) => write!(f, "{:<6} (i64)", val),
+            Value::UInt64(val) => write!(f, "{:<6} (u64)", val),
             Value::Bool(val) => write!(f, "{:<6} (bool)", val),
             Value::Ref(r) => if r.mutable {
                 write!(f, "{:<6} (&mut {})", r.addr, r.ref_ty)
@@ -1,188 +1,204 @@
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::io::BufRead;
 use crate::ast::*;
-use crate::interp::InterpContext;
-
-// FIXME(alexander): everything here is temporary!!1
-pub fn get_intrinsic_ast_items() -> Item {
-    Item::ForeignMod(
-        ForeignModItem {
-            abi: Some("intrinsic".to_string()),
-            items: vec![
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("trace"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("debug_break"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("print_int"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("val"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Int, Span::new()),
-                                    span: Span::new(),
-                                },
-                            ],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-                
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("print_bool"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("val"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Bool, Span::new()),
-                                    span: Span::new(),
-                                },
-                            ],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-                
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("assert"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("val"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Bool, Span::new()),
-                                    span: Span::new(),
-                                },
-                            ],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-                
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("assert_eq_int"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("left"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Int, Span::new()),
-                                    span: Span::new(),
-                                },
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("right"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Int, Span::new()),
-                                    span: Span::new(),
-                                },
-                            ],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-                
-                Item::ForeignFn(
-                    ForeignFnItem {
-                        ident: ExprIdent {
-                            sym: intern_string("assert_eq_bool"),
-                            span: Span::new(),
-                        },
-                        decl: FnDecl {
-                            inputs: vec![
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("left"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Bool, Span::new()),
-                                    span: Span::new(),
-                                },
-                                Argument {
-                                    mutable: false,
-                                    ident: ExprIdent {
-                                        sym: intern_string("right"),
-                                        span: Span::new(),
-                                    },
-                                    ty: Ty::new(TyKind::Bool, Span::new()),
-                                    span: Span::new(),
-                                },
-                            ],
-                            output: Ty::default(),
-                            span: Span::new(),
-                        },
-                        span: Span::new(),
-                    }
-                ),
-            ],
-            span: Span::new(),
+use crate::parser::parse_file_with_ctx;
+use crate::interp::{InterpContext, Value};
+use crate::ffi::flush_foreign_output;
+use crate::native::{NativeFunction, create_native_function, get_native_ast_items};
+
+/**
+ * The prelude is included in every program, it declares the intrinsics
+ * and defines the standard library functions written in sqrrl.
+ */
+const PRELUDE_SOURCE: &str = include_str!("libstd/basic.sq");
+const PRELUDE_FILENAME: &str = "src/libstd/basic.sq";
+const PRELUDE_CTX: u16 = 1;
+
+thread_local!(static PROGRAM_ARGUMENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) });
+
+thread_local!(static INPUT_TOKENS: RefCell<VecDeque<String>> = const { RefCell::new(VecDeque::new()) });
+
+/**
+ * Sets the command-line arguments passed to the compiled program,
+ * these are accessed through the `arg_count` and `arg_int` intrinsics.
+ */
+pub fn set_program_arguments(args: Vec<String>) {
+    PROGRAM_ARGUMENTS.with(|arguments| {
+        *arguments.borrow_mut() = args;
+    });
+}
+
+/**
+ * Returns the program argument at the given index parsed as an integer,
+ * or None if the index is out of bounds or the argument is not an integer.
+ */
+pub fn get_program_argument(index: i32) -> Option<i32> {
+    PROGRAM_ARGUMENTS.with(|arguments| {
+        let arguments = arguments.borrow();
+        if index < 0 {
+            return None;
+        }
+        arguments.get(index as usize).and_then(|arg| arg.parse::<i32>().ok())
+    })
+}
+
+/**
+ * Reads the next whitespace separated token from standard input,
+ * returns None if the end of the input has been reached.
+ */
+pub fn read_input_token() -> Option<String> {
+    if is_eof() {
+        return None;
+    }
+    INPUT_TOKENS.with(|tokens| tokens.borrow_mut().pop_front())
+}
+
+/**
+ * Parses an integer token from the standard input.
+ */
+pub fn parse_int_token(token: &str) -> Result<i32, String> {
+    token.parse::<i32>().map_err(|_| format!("expected integer on standard input, found `{}`", token))
+}
+
+/**
+ * Parses a boolean token from the standard input, accepts `true`, `false`, `1` and `0`.
+ */
+pub fn parse_bool_token(token: &str) -> Result<bool, String> {
+    match token {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(format!("expected boolean on standard input, found `{}`", token)),
+    }
+}
+
+/**
+ * Parses the prelude and includes its items in the given file, functions
+ * defined by the file itself shadow the ones defined in the prelude.
+ */
+pub fn include_prelude(file: &mut File) {
+    let prelude = parse_file_with_ctx(PRELUDE_SOURCE.to_string(), PRELUDE_FILENAME.to_string(), PRELUDE_CTX);
+    file.error_count += prelude.error_count;
+
+    fn collect_item_symbols(items: &Vec<Item>, symbols: &mut HashSet<Symbol>) {
+        for item in items {
+            match item {
+                Item::Fn(func) => { symbols.insert(func.ident.sym); },
+                Item::ForeignFn(func) => { symbols.insert(func.ident.sym); },
+                Item::ForeignMod(module) => collect_item_symbols(&module.items, symbols),
+            }
+        }
+    }
+
+    fn filter_item(item: &Item, defined: &HashSet<Symbol>) -> Option<Item> {
+        match item {
+            Item::Fn(func) if defined.contains(&func.ident.sym) => None,
+            Item::ForeignFn(func) if defined.contains(&func.ident.sym) => None,
+            Item::ForeignMod(module) => Some(Item::ForeignMod(ForeignModItem {
+                abi: module.abi.clone(),
+                items: module.items.iter().filter_map(|item| filter_item(item, defined)).collect(),
+                span: module.span,
+            })),
+            _ => Some(item.clone()),
+        }
+    }
+
+    let mut defined = HashSet::new();
+    collect_item_symbols(&file.items, &mut defined);
+    for item in &prelude.items {
+        if let Some(item) = filter_item(item, &defined) {
+            file.items.push(item);
+        }
+    }
+
+    // Declare the native functions missing from the prelude, e.g. registered host functions
+    collect_item_symbols(&prelude.items, &mut defined);
+    file.items.push(get_native_ast_items(&defined));
+
+    file.imported_files.insert(prelude.filename.clone(), Box::new(prelude));
+}
+
+/**
+ * Creates the native functions built into the compiler, every intrinsic
+ * is declared here once with its signature and implementations.
+ */
+pub fn create_intrinsic_functions() -> Vec<NativeFunction> {
+    vec![
+        create_native_function("trace", &[], TyKind::None, Some(interp_trace), None),
+        create_native_function("debug_break", &[], TyKind::None, None, None),
+        create_native_function("print_int", &[("val", TyKind::Int)], TyKind::None,
+                               None, Some(print_int as *const () as usize)),
+        create_native_function("print_i64", &[("val", TyKind::Int64)], TyKind::None,
+                               None, Some(print_i64 as *const () as usize)),
+        create_native_function("print_u64", &[("val", TyKind::UInt64)], TyKind::None,
+                               None, Some(print_u64 as *const () as usize)),
+        create_native_function("print_bool", &[("val", TyKind::Bool)], TyKind::None,
+                               None, Some(print_bool as *const () as usize)),
+        create_native_function("assert", &[("val", TyKind::Bool)], TyKind::None,
+                               None, Some(assert as *const () as usize)),
+        create_native_function("assert_eq_int", &[("left", TyKind::Int), ("right", TyKind::Int)], TyKind::None,
+                               None, Some(assert_eq_int as *const () as usize)),
+        create_native_function("assert_eq_bool", &[("left", TyKind::Bool), ("right", TyKind::Bool)], TyKind::None,
+                               None, Some(assert_eq_bool as *const () as usize)),
+        create_native_function("arg_count", &[], TyKind::Int,
+                               None, Some(arg_count as *const () as usize)),
+        create_native_function("arg_int", &[("index", TyKind::Int)], TyKind::Int,
+                               Some(interp_arg_int), Some(arg_int as *const () as usize)),
+        create_native_function("read_int", &[], TyKind::Int,
+                               Some(interp_read_int), Some(read_int as *const () as usize)),
+        create_native_function("read_bool", &[], TyKind::Bool,
+                               Some(interp_read_bool), Some(read_bool as *const () as usize)),
+        create_native_function("is_eof", &[], TyKind::Bool,
+                               None, Some(is_eof as *const () as usize)),
+    ]
+}
+
+/***************************************************************************
+ * Interpreter implementations, used when errors should be reported
+ * by the interpreter instead of panicking.
+ ***************************************************************************/
+
+fn interp_trace(ic: &mut InterpContext, _args: &[Value]) -> Result<Value, String> {
+    trace(ic);
+    Ok(Value::None)
+}
+
+fn interp_arg_int(_ic: &mut InterpContext, args: &[Value]) -> Result<Value, String> {
+    match args[0] {
+        Value::Int(index) => match get_program_argument(index) {
+            Some(arg) => Ok(Value::Int(arg)),
+            None => Err(format!("program argument {} is missing or not an integer", index)),
         },
-    )
+        _ => Err(String::from("expected integer index")),
+    }
+}
+
+fn interp_read_int(_ic: &mut InterpContext, _args: &[Value]) -> Result<Value, String> {
+    match read_input_token() {
+        Some(token) => parse_int_token(&token).map(Value::Int),
+        None => Ok(Value::Int(0)),
+    }
+}
+
+fn interp_read_bool(_ic: &mut InterpContext, _args: &[Value]) -> Result<Value, String> {
+    match read_input_token() {
+        Some(token) => parse_bool_token(&token).map(Value::Bool),
+        None => Ok(Value::Bool(false)),
+    }
+}
+
+/***************************************************************************
+ * Native implementations, called by jitted code using the C calling convention
+ ***************************************************************************/
+
+/**
+ * Reports an error from an intrinsic called by jitted code and exits the process,
+ * panicking is not an option since it cannot unwind through the C calling convention.
+ */
+pub fn exit_with_runtime_error(msg: &str) -> ! {
+    flush_foreign_output();
+    eprintln!("\nerror: {}", msg);
+    std::process::exit(1);
 }
 
 /**
@@ -200,6 +216,22 @@ pub extern "C" fn print_int(val: i32) {
     println!("{}", val);
 }
 
+/**
+ * Prints the given 64-bit integer.
+ */
+#[no_mangle]
+pub extern "C" fn print_i64(val: i64) {
+    println!("{}", val);
+}
+
+/**
+ * Prints the given unsigned 64-bit integer.
+ */
+#[no_mangle]
+pub extern "C" fn print_u64(val: u64) {
+    println!("{}", val);
+}
+
 /**
  * Prints the given boolean.
  */
@@ -231,3 +263,70 @@ pub extern "C" fn assert_eq_int(left: i32, right: i32) {
 pub extern "C" fn assert_eq_bool(left: bool, right: bool) {
     assert_eq!(left, right);
 }
+
+/**
+ * Returns the number of arguments passed to the program.
+ */
+#[no_mangle]
+pub extern "C" fn arg_count() -> i32 {
+    PROGRAM_ARGUMENTS.with(|arguments| arguments.borrow().len() as i32)
+}
+
+/**
+ * Returns the program argument at the given index as an integer.
+ */
+#[no_mangle]
+pub extern "C" fn arg_int(index: i32) -> i32 {
+    match get_program_argument(index) {
+        Some(val) => val,
+        None => exit_with_runtime_error(&format!("program argument {} is missing or not an integer", index)),
+    }
+}
+
+/**
+ * Reads the next integer from standard input, returns 0 at end of input.
+ */
+#[no_mangle]
+pub extern "C" fn read_int() -> i32 {
+    match read_input_token() {
+        Some(token) => match parse_int_token(&token) {
+            Ok(val) => val,
+            Err(msg) => exit_with_runtime_error(&msg),
+        },
+        None => 0,
+    }
+}
+
+/**
+ * Reads the next boolean from standard input, returns false at end of input.
+ */
+#[no_mangle]
+pub extern "C" fn read_bool() -> bool {
+    match read_input_token() {
+        Some(token) => match parse_bool_token(&token) {
+            Ok(val) => val,
+            Err(msg) => exit_with_runtime_error(&msg),
+        },
+        None => false,
+    }
+}
+
+/**
+ * Returns true if there are no more tokens left to read from standard input.
+ */
+#[no_mangle]
+pub extern "C" fn is_eof() -> bool {
+    INPUT_TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        while tokens.is_empty() {
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return true,
+                Ok(_) => tokens.extend(line.split_whitespace().map(|s| s.to_string())),
+            }
+        }
+        false
+    })
+}
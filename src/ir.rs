@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 use std::fmt;
 use crate::ast::*;
-use crate::intrinsics;
+use crate::native::lookup_native_function;
+use crate::ffi::{is_c_abi, lookup_symbol};
 
 /**
  * Used for building low-level intermediate representation.
@@ -14,6 +15,8 @@ pub struct IrBuilder<'a> {
 
     scopes: Vec<IrScope>,
     live_intervals: HashMap<IrIdent, IrLiveInterval>, // used per function moves to its basic block
+    tail_call_params: Vec<(IrIdent, IrType)>, // parameters of the current function
+    tail_call_label: Option<IrIdent>, // beginning of the current function body, if it has tail calls
 
     // Unique identifier generators
     register_symbol: Symbol,
@@ -53,9 +56,22 @@ pub struct IrBasicBlock {
     pub return_type: IrType,
     pub func_address: Option<usize>, // used by jitter to call foreign functions
     pub is_foreign: bool,
+    pub inline_hint: IrInlineHint,
     pub live_intervals: HashMap<IrIdent, IrLiveInterval>
 }
 
+/**
+ * Inline hint given by the `#[inline]` attribute on a function,
+ * used by the inliner to override its size heuristic.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrInlineHint {
+    None,   // no attribute, decided by the size heuristic
+    Hint,   // #[inline]
+    Always, // #[inline(always)]
+    Never,  // #[inline(never)]
+}
+
 /**
  * Denotes an interval in the IR where a particular variable is alive.
  */
@@ -66,7 +82,8 @@ pub struct IrLiveInterval {
 }
 
 /**
- * Three address code instruction, is defined an op code and up to three operands.
+ * Three address code instruction, is defined an op code and up to three operands
+ * (four for select).
  * Span is also used for debugging to retrieve the source location of a given instruction.
  */
 #[derive(Debug, Clone, PartialEq)]
@@ -75,8 +92,28 @@ pub struct IrInstruction {
     pub op1: IrOperand,
     pub op2: IrOperand,
     pub op3: IrOperand,
+    pub op4: IrOperand, // only used by select
     pub ty: IrType,
     pub span: Span,
+    pub phi_args: Vec<(IrIdent, IrOperand)>, // incoming values of phi, stored with the label of the predecessor
+    pub asm: Option<Box<IrInlineAsm>>, // only used by inline assembly
+}
+
+/**
+ * Inline assembly is kept as text until it reaches the backend, the registers
+ * are named by the operands and the backend moves the values in and out of them.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrInlineAsm {
+    pub template: String,
+    pub operands: Vec<IrAsmOperand>,
+    pub clobbers: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrAsmOperand {
+    pub reg: String,
+    pub is_output: bool, // the register is written by the template
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -104,6 +141,7 @@ pub enum IrOpcode {
     Ge,
     Eq,
     Ne,
+    Select, // op1 = op2 ? op3 : op4 (op2 always boolean)
     IfLt, // jump op3 (if op1 binop op2 equals true)
     IfGt,
     IfLe,
@@ -117,6 +155,10 @@ pub enum IrOpcode {
     Return,   // return op1 (where op1 is optional)
     Prologue, // marks beginning of function
     Epilogue, // marks end of function
+    Phi,      // op1 = phi [value, label]... (only in SSA form)
+    AsmIn,    // asm_in op1 (op2 is the operand index, ordered before asm)
+    Asm,      // asm (template and registers stored in asm)
+    AsmOut,   // op1 = asm_out (op2 is the operand index, ordered after asm)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -135,6 +177,7 @@ pub struct IrIdent {
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IrValue {
     I32(i32),
+    I64(i64),
     U32(u32),
     U64(u64),
     Bool(bool),
@@ -149,6 +192,8 @@ pub enum IrType {
     U64,
     PtrI8(usize), // NOTE(alexander): argument defines the numbers of indirections
     PtrI32(usize),
+    PtrI64(usize),
+    PtrU64(usize),
     None,
 }
 
@@ -161,6 +206,8 @@ pub fn create_ir_builder<'a>() -> IrBuilder<'a> {
 
         scopes: Vec::new(),
         live_intervals: HashMap::new(),
+        tail_call_params: Vec::new(),
+        tail_call_label: None,
 
         register_symbol: intern_string(""),
         register_index: 0,
@@ -181,34 +228,48 @@ pub fn create_ir_ident(symbol: Symbol, index: u32) -> IrIdent {
     IrIdent { symbol, index }
 }
 
+/**
+ * Creates a new label that is unique in the entire program.
+ */
+pub fn create_unique_ir_label<'a>(ib: &mut IrBuilder<'a>) -> IrIdent {
+    let label = IrIdent {
+        symbol: ib.basic_block_symbol,
+        index: ib.basic_block_index,
+    };
+    ib.basic_block_index += 1;
+    return label;
+}
+
 fn create_ir_basic_block<'a>(
     ib: &mut IrBuilder<'a>,
     enter: Option<IrIdent>,
     exit: Option<IrIdent>,
     is_foreign: bool
 ) -> IrBasicBlock {
-
-    fn unique_bb_label<'a>(ib: &mut IrBuilder<'a>) -> IrIdent {
-        let label = IrIdent {
-            symbol: ib.basic_block_symbol,
-            index: ib.basic_block_index,
-        };
-        ib.basic_block_index += 1;
-        return label;
-    }
-
     IrBasicBlock {
         prologue_index: 0,
         epilogue_index: 0,
-        enter_label: enter.unwrap_or(unique_bb_label(ib)),
-        exit_label: exit.unwrap_or(unique_bb_label(ib)),
+        enter_label: enter.unwrap_or(create_unique_ir_label(ib)),
+        exit_label: exit.unwrap_or(create_unique_ir_label(ib)),
         return_type: IrType::None,
         func_address: None,
         is_foreign,
+        inline_hint: IrInlineHint::None,
         live_intervals: HashMap::new(),
     }
 }
 
+fn to_ir_inline_hint(attrs: &[Attribute]) -> IrInlineHint {
+    match find_attribute(attrs, "inline") {
+        Some(attr) => match attr.args.first().map(|arg| resolve_symbol(arg.sym)) {
+            Some("always") => IrInlineHint::Always,
+            Some("never") => IrInlineHint::Never,
+            _ => IrInlineHint::Hint,
+        },
+        None => IrInlineHint::None,
+    }
+}
+
 fn create_ir_live_interval(begin: usize) -> IrLiveInterval {
     IrLiveInterval {
         begin,
@@ -226,6 +287,18 @@ fn update_ir_live_interval<'a>(ib: &mut IrBuilder<'a>, op: IrOperand) {
     }
 }
 
+/**
+ * Variables that are alive when entering the loop and used inside of it has to be kept
+ * alive until the jump back to the beginning, since the next iteration reads them again.
+ */
+fn extend_ir_live_intervals_over_loop<'a>(ib: &mut IrBuilder<'a>, enter_index: usize, jump_index: usize) {
+    for live_interval in ib.live_intervals.values_mut() {
+        if live_interval.begin < enter_index && live_interval.end >= enter_index && live_interval.end < jump_index {
+            live_interval.end = jump_index;
+        }
+    }
+}
+
 #[inline]
 pub fn is_alive(insn: usize, interval: IrLiveInterval) -> bool {
     return insn >= interval.begin && insn < interval.end;
@@ -244,6 +317,8 @@ fn allocate_register<'a>(ib: &mut IrBuilder<'a>) -> IrOperand {
 fn to_ir_ptr_type(ty: &Ty, indirections: &mut usize) -> IrType {
     match &ty.kind {
         TyKind::Int => IrType::PtrI32(*indirections),
+        TyKind::Int64 => IrType::PtrI64(*indirections),
+        TyKind::UInt64 => IrType::PtrU64(*indirections),
         TyKind::Bool => IrType::PtrI8(*indirections),
         TyKind::Ref(type_ref) => {
             *indirections += 1;
@@ -257,6 +332,8 @@ fn to_ir_ptr_type(ty: &Ty, indirections: &mut usize) -> IrType {
 fn to_ir_type(ty: &Ty) -> IrType {
     match &ty.kind {
         TyKind::Int => IrType::I32,
+        TyKind::Int64 => IrType::I64,
+        TyKind::UInt64 => IrType::U64,
         TyKind::Bool => IrType::I8,
         TyKind::Ref(type_ref) => to_ir_ptr_type(&type_ref.elem, &mut 1),
         TyKind::Error => IrType::None,
@@ -272,7 +349,9 @@ pub fn size_of_ir_type(ty: IrType, addr_size: isize) -> isize {
         IrType::U32 => 4,
         IrType::U64 => 8,
         IrType::PtrI8(_) |
-        IrType::PtrI32(_) => addr_size,
+        IrType::PtrI32(_) |
+        IrType::PtrI64(_) |
+        IrType::PtrU64(_) => addr_size,
         IrType::None => 0,
     }
 }
@@ -280,7 +359,7 @@ pub fn size_of_ir_type(ty: IrType, addr_size: isize) -> isize {
 pub fn build_ir_from_ast<'a>(ib: &mut IrBuilder<'a>, file: &'a File) {
     ib.file = Some(file);
 
-    fn register_ast_items<'a>(ib: &mut IrBuilder<'a>, items: &Vec<Item>) {
+    fn register_ast_items<'a>(ib: &mut IrBuilder<'a>, items: &Vec<Item>, abi: &Option<String>) {
         for item in items {
             match item {
                 Item::Fn(func) => {
@@ -289,43 +368,29 @@ pub fn build_ir_from_ast<'a>(ib: &mut IrBuilder<'a>, file: &'a File) {
                     let ident = create_ir_ident(func.ident.sym, 0);
                     let mut block = create_ir_basic_block(ib, Some(enter_label), Some(exit_label), false);
                     block.return_type = to_ir_type(&func.decl.output);
+                    block.inline_hint = to_ir_inline_hint(&func.attrs);
                     ib.functions.insert(ident, block);
                 }
 
                 Item::ForeignFn(func) => {
                     let func_ident = resolve_symbol(func.ident.sym);
-                    let func_address = match func_ident {
-                        "print_int" => {
-                            intrinsics::print_int as *const () as usize
-                        }
-
-                        "print_bool" => {
-                            intrinsics::print_bool as *const () as usize
-                        }
-
-                        "assert" => {
-                            intrinsics::assert as *const () as usize
-                        }
-
-                        "assert_eq_int" => {
-                            intrinsics::assert_eq_int as *const () as usize
-                        }
-
-                        "assert_eq_bool" => {
-                            intrinsics::assert_eq_bool as *const () as usize
+                    let func_address = if is_c_abi(abi) {
+                        match lookup_symbol(func_ident) {
+                            Some(addr) => addr,
+                            None => panic!("unresolved foreign function {}", func_ident),
                         }
+                    } else {
+                        match lookup_native_function(func_ident) {
+                            // NOTE: debug_break - backend level intrinsic
+                            Some(_) if func_ident == "debug_break" => {
+                                continue; // dont't generate NULL function address, should be callable
+                            }
 
-                        // NOTE: debug_break - backend level intrinsic
-                        "debug_break" => {
-                            continue; // dont't generate NULL function address, should be callable
-                        }
+                            // NOTE: functions without address e.g. trace are interpreter level intrinsics
+                            Some(native) => native.address.unwrap_or(0usize), // NULL function address
 
-                        // NOTE: trace - interpreter level intrinsic
-                        "trace" => {
-                            0usize // should never be called, NULL function address
+                            None => panic!("unknown foreign function {}", func_ident),
                         }
-
-                        _ => panic!("unknown foreign function {}", func_ident),
                     };
 
                     let ident = create_ir_ident(func.ident.sym, 0);
@@ -335,13 +400,13 @@ pub fn build_ir_from_ast<'a>(ib: &mut IrBuilder<'a>, file: &'a File) {
                     ib.functions.insert(ident, block);
                 }
 
-                Item::ForeignMod(module) => register_ast_items(ib, &module.items),
+                Item::ForeignMod(module) => register_ast_items(ib, &module.items, &module.abi),
             }
         }
     }
 
     // First register all items made available from the AST
-    register_ast_items(ib, &file.items);
+    register_ast_items(ib, &file.items, &None);
 
     for item in &file.items {
         build_ir_from_item(ib, &item);
@@ -377,10 +442,13 @@ pub fn build_ir_from_item<'a>(ib: &mut IrBuilder<'a>, item: &Item) {
                 locals: HashMap::new(),
             };
 
+            ib.tail_call_params.clear();
+            ib.tail_call_label = None;
             for arg in &func.decl.inputs {
                 let ty = to_ir_type(&arg.ty);
                 let ident = create_ir_ident(arg.ident.sym, 0);
                 scope.locals.insert(ident, ty);
+                ib.tail_call_params.push((ident, ty));
                 ib.instructions.push(IrInstruction {
                     opcode: IrOpcode::Param,
                     op1: IrOperand::Ident(ident),
@@ -389,6 +457,11 @@ pub fn build_ir_from_item<'a>(ib: &mut IrBuilder<'a>, item: &Item) {
                 });
             }
 
+            // NOTE(alexander): parameters are defined by alloc_params, so they are all alive at the same time
+            for ident in scope.locals.keys() {
+                ib.live_intervals.insert(*ident, create_ir_live_interval(ib.instructions.len()));
+            }
+
             ib.instructions.push(IrInstruction {
                 opcode: IrOpcode::AllocParams,
                 ..Default::default()
@@ -396,8 +469,32 @@ pub fn build_ir_from_item<'a>(ib: &mut IrBuilder<'a>, item: &Item) {
 
             ib.scopes.push(scope);
 
+            let body_index = ib.instructions.len();
             build_ir_from_block(ib, &func.block, Some(enter_label), Some(exit_label), None);
 
+            // NOTE(alexander): tail calls jump to the beginning of the body, the label is
+            // only inserted if there are any tail calls so shift the live intervals after it.
+            if let Some(body_label) = ib.tail_call_label {
+                for live_interval in ib.live_intervals.values_mut() {
+                    if live_interval.begin >= body_index {
+                        live_interval.begin += 1;
+                    }
+                    if live_interval.end >= body_index {
+                        live_interval.end += 1;
+                    }
+                }
+
+                ib.instructions.insert(body_index, IrInstruction {
+                    opcode: IrOpcode::Label,
+                    op1: IrOperand::Ident(body_label),
+                    span: func.block.span,
+                    ..Default::default()
+                });
+
+                let jump_index = ib.instructions.len();
+                extend_ir_live_intervals_over_loop(ib, body_index, jump_index);
+            }
+
             ib.instructions.push(IrInstruction {
                 opcode: IrOpcode::Label,
                 op1: IrOperand::Ident(exit_label),
@@ -458,6 +555,7 @@ pub fn build_ir_from_block<'a>(
             (IrOperand::None, IrType::None)
         } else {
             if ib.scopes.len() <= 2 { // Outermost scope, safe to return
+                update_ir_live_interval(ib, last_op);
                 ib.instructions.push(IrInstruction {
                     opcode: IrOpcode::Return,
                     op1: last_op,
@@ -467,6 +565,8 @@ pub fn build_ir_from_block<'a>(
                 });
                 (last_op, last_ty)
             } else if let Some(op1) = assign_op { // Not outermost scope, store to register instead
+                update_ir_live_interval(ib, op1);
+                update_ir_live_interval(ib, last_op);
                 ib.instructions.push(IrInstruction {
                     opcode: IrOpcode::Copy,
                     op1,
@@ -495,6 +595,16 @@ pub fn build_ir_from_stmt<'a>(ib: &mut IrBuilder<'a>, stmt: &Stmt) -> (IrOperand
             let ident = create_ir_ident(local.ident.sym, 0);
             let op1 = IrOperand::Ident(ident);
 
+            // NOTE(alexander): the interval starts at the allocation since if expressions assign to
+            // the local from inside their blocks, shadowed locals share the same interval.
+            let alloca_index = ib.instructions.len();
+            match ib.live_intervals.get_mut(&ident) {
+                Some(live_interval) => live_interval.end = alloca_index,
+                None => {
+                    ib.live_intervals.insert(ident, create_ir_live_interval(alloca_index));
+                }
+            }
+
             ib.instructions.push(IrInstruction {
                 opcode: IrOpcode::Alloca,
                 op1,
@@ -503,28 +613,25 @@ pub fn build_ir_from_stmt<'a>(ib: &mut IrBuilder<'a>, stmt: &Stmt) -> (IrOperand
                 ..Default::default()
             });
 
-            match &*local.init {
-                Some(expr) => {
-                    if let Expr::If(if_expr) = expr {
-                        build_ir_if_expr(ib, if_expr, Some(op1));
-                    } else {
-                        let op2 = build_ir_from_expr(ib, expr).0;
-                        update_ir_live_interval(ib, op2);
-                        ib.instructions.push(IrInstruction {
-                            opcode: IrOpcode::Copy,
-                            op1,
-                            op2,
-                            ty: init_type,
-                            span: local.span,
-                            ..Default::default()
-                        });
-                    }
+            if let Some(expr) = &*local.init {
+                if let Expr::If(if_expr) = expr {
+                    build_ir_if_expr(ib, if_expr, Some(op1));
+                } else {
+                    let op2 = build_ir_from_expr(ib, expr).0;
+                    update_ir_live_interval(ib, op1);
+                    update_ir_live_interval(ib, op2);
+                    ib.instructions.push(IrInstruction {
+                        opcode: IrOpcode::Copy,
+                        op1,
+                        op2,
+                        ty: init_type,
+                        span: local.span,
+                        ..Default::default()
+                    });
                 }
-                None => return (IrOperand::None, IrType::None)
-            };
+            }
 
             ib.scopes[0].locals.insert(ident, init_type);
-            ib.live_intervals.insert(ident, create_ir_live_interval(ib.instructions.len()));
 
             (IrOperand::None, IrType::None)
         }
@@ -574,6 +681,8 @@ fn build_ir_conditional_if<'a>(ib: &mut IrBuilder<'a>, cond: &Expr, span: Span,
         ty = IrType::I8;
     }
 
+    update_ir_live_interval(ib, op1);
+    update_ir_live_interval(ib, op2);
     ib.instructions.push(IrInstruction {
         opcode,
         op1,
@@ -585,7 +694,83 @@ fn build_ir_conditional_if<'a>(ib: &mut IrBuilder<'a>, cond: &Expr, span: Span,
     });
 }
 
-fn build_ir_if_expr<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, assign_op: Option<IrOperand>) {
+/**
+ * Maximum cost of both arms of an if expression built as a select,
+ * both arms are always evaluated so they have to be cheap.
+ */
+const IR_SELECT_MAX_COST: usize = 2;
+
+/**
+ * Estimated number of instructions needed to evaluate the expression, `None` if the
+ * expression has side effects, may trap or reads memory since these cannot be
+ * evaluated unless the arm is taken.
+ */
+fn ir_select_cost(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Ident(_) | Expr::Lit(_) => Some(0),
+        Expr::Paren(paren) => ir_select_cost(&paren.expr),
+        Expr::Unary(unary) => match unary.op {
+            UnOp::Neg | UnOp::Not => ir_select_cost(&unary.expr).map(|cost| cost + 1),
+            UnOp::Deref => None,
+        },
+        Expr::Binary(binary) => match binary.op {
+            BinOp::Div | BinOp::Mod | BinOp::Pow => None,
+            _ => Some(ir_select_cost(&binary.left)? + ir_select_cost(&binary.right)? + 1),
+        },
+        _ => None,
+    }
+}
+
+/**
+ * The expression of an if expression arm that only consists of a single expression.
+ */
+fn ir_select_arm(block: &Block) -> Option<&Expr> {
+    match block.stmts.as_slice() {
+        [Stmt::Expr(expr)] => Some(expr),
+        _ => None,
+    }
+}
+
+/**
+ * Builds `op1 = if cond { a } else { b }` as `op1 = select cond, a, b`, both arms are evaluated.
+ */
+fn build_ir_select<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, then_expr: &Expr, else_expr: &Expr, op1: IrOperand) -> IrType {
+    let op2 = build_ir_from_expr(ib, &if_expr.cond).0;
+    let (op3, ty) = build_ir_from_expr(ib, then_expr);
+    let op4 = build_ir_from_expr(ib, else_expr).0;
+
+    update_ir_live_interval(ib, op1);
+    update_ir_live_interval(ib, op2);
+    update_ir_live_interval(ib, op3);
+    update_ir_live_interval(ib, op4);
+    ib.instructions.push(IrInstruction {
+        opcode: IrOpcode::Select,
+        op1,
+        op2,
+        op3,
+        op4,
+        ty,
+        span: if_expr.span,
+        ..Default::default()
+    });
+    ty
+}
+
+/**
+ * Builds the if expression and stores the value of the taken arm in `assign_op`,
+ * returns the type of the value or `IrType::None` if the arms have no value.
+ */
+fn build_ir_if_expr<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, assign_op: Option<IrOperand>) -> IrType {
+    // Cheap if expressions without side effects are selects, avoiding the branches
+    if let (Some(op1), Some(else_block)) = (assign_op, &if_expr.else_block) {
+        if let (Some(then_expr), Some(else_expr)) = (ir_select_arm(&if_expr.then_block), ir_select_arm(else_block)) {
+            let cost = ir_select_cost(then_expr).zip(ir_select_cost(else_expr)).map(|(a, b)| a + b);
+            if matches!(cost, Some(cost) if cost <= IR_SELECT_MAX_COST) {
+                return build_ir_select(ib, if_expr, then_expr, else_expr, op1);
+            }
+        }
+    }
+
     let exit_label = create_ir_ident(ib.if_exit_symbol, ib.if_exit_index);
     ib.if_exit_index += 1;
     let else_label = create_ir_ident(ib.if_else_symbol, ib.if_else_index);
@@ -598,7 +783,7 @@ fn build_ir_if_expr<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, assign_op: Opt
 
     build_ir_conditional_if(ib, &*if_expr.cond, if_expr.span, false_label);
 
-    build_ir_from_block(ib, &if_expr.then_block, None, Some(false_label), assign_op);
+    let mut ty = build_ir_from_block(ib, &if_expr.then_block, None, Some(false_label), assign_op).1;
 
     if false_label == else_label {
         ib.instructions.push(IrInstruction {
@@ -615,7 +800,10 @@ fn build_ir_if_expr<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, assign_op: Opt
     }
 
     if let Some(block) = &if_expr.else_block {
-        build_ir_from_block(ib, &block, Some(false_label), Some(exit_label), assign_op);
+        let else_ty = build_ir_from_block(ib, &block, Some(false_label), Some(exit_label), assign_op).1;
+        if ty == IrType::None {
+            ty = else_ty;
+        }
     }
 
     ib.instructions.push(IrInstruction {
@@ -623,6 +811,7 @@ fn build_ir_if_expr<'a>(ib: &mut IrBuilder<'a>, if_expr: &ExprIf, assign_op: Opt
         op1: IrOperand::Ident(exit_label),
         ..Default::default()
     });
+    ty
 }
 
 pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand, IrType) {
@@ -647,8 +836,12 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                     let ty = match ty {
                         IrType::PtrI8(1) => IrType::I8,
                         IrType::PtrI32(1) => IrType::I32,
+                        IrType::PtrI64(1) => IrType::I64,
+                        IrType::PtrU64(1) => IrType::U64,
                         IrType::PtrI8(x) => IrType::PtrI8(x - 1),
                         IrType::PtrI32(x) => IrType::PtrI32(x - 1),
+                        IrType::PtrI64(x) => IrType::PtrI64(x - 1),
+                        IrType::PtrU64(x) => IrType::PtrU64(x - 1),
                         _ => panic!("cannot dereference non-pointer type"),
                     };
                     (op, ty)
@@ -676,17 +869,80 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
             (op1, ty)
         }
 
+        Expr::Asm(asm_expr) => {
+            // NOTE(alexander): every input is evaluated before they are moved into their registers
+            let mut inputs = Vec::new();
+            for (i, operand) in asm_expr.operands.iter().enumerate() {
+                if operand.dir != AsmDir::Out {
+                    let (op1, ty) = build_ir_from_expr(ib, &operand.expr);
+                    inputs.push((op1, ty, i, operand.span));
+                }
+            }
+
+            let mut input_ops = Vec::new();
+            for (op1, ty, i, span) in inputs {
+                input_ops.push(op1);
+                ib.instructions.push(IrInstruction {
+                    opcode: IrOpcode::AsmIn,
+                    op1,
+                    op2: IrOperand::Value(IrValue::I32(i as i32)),
+                    ty,
+                    span,
+                    ..Default::default()
+                });
+            }
+
+            for op in input_ops {
+                update_ir_live_interval(ib, op);
+            }
+
+            let operands = asm_expr.operands.iter().map(|operand| IrAsmOperand {
+                reg: operand.reg.name.clone(),
+                is_output: operand.dir != AsmDir::In,
+            }).collect();
+
+            ib.instructions.push(IrInstruction {
+                opcode: IrOpcode::Asm,
+                span: asm_expr.span,
+                asm: Some(Box::new(IrInlineAsm {
+                    template: asm_expr.template.clone(),
+                    operands,
+                    clobbers: asm_expr.clobbers.iter().map(|reg| reg.name.clone()).collect(),
+                })),
+                ..Default::default()
+            });
+
+            for (i, operand) in asm_expr.operands.iter().enumerate() {
+                if let (AsmDir::Out | AsmDir::InOut, Expr::Ident(ident)) = (&operand.dir, &operand.expr) {
+                    let ident = create_ir_ident(ident.sym, 0);
+                    let op1 = IrOperand::Ident(ident);
+                    let ty = *ib.scopes[0].locals.get(&ident).unwrap();
+                    update_ir_live_interval(ib, op1);
+                    ib.instructions.push(IrInstruction {
+                        opcode: IrOpcode::AsmOut,
+                        op1,
+                        op2: IrOperand::Value(IrValue::I32(i as i32)),
+                        ty,
+                        span: operand.span,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            (IrOperand::None, IrType::None)
+        }
+
         Expr::Binary(binary) => {
             let op1 = allocate_register(ib);
             let (op2, lhs_ty) = build_ir_from_expr(ib, &binary.left);
             let op3 = build_ir_from_expr(ib, &binary.right).0;
             let (opcode, ty) = match binary.op {
-                BinOp::Add => (IrOpcode::Add, IrType::I32),
-                BinOp::Sub => (IrOpcode::Sub, IrType::I32),
-                BinOp::Mul => (IrOpcode::Mul, IrType::I32),
-                BinOp::Div => (IrOpcode::Div, IrType::I32),
-                BinOp::Pow => (IrOpcode::Pow, IrType::I32),
-                BinOp::Mod => (IrOpcode::Mod, IrType::I32),
+                BinOp::Add => (IrOpcode::Add, lhs_ty),
+                BinOp::Sub => (IrOpcode::Sub, lhs_ty),
+                BinOp::Mul => (IrOpcode::Mul, lhs_ty),
+                BinOp::Div => (IrOpcode::Div, lhs_ty),
+                BinOp::Pow => (IrOpcode::Pow, lhs_ty),
+                BinOp::Mod => (IrOpcode::Mod, lhs_ty),
                 BinOp::And => (IrOpcode::And, IrType::I8),
                 BinOp::Or  => (IrOpcode::Or,  IrType::I8),
                 BinOp::Eq  => (IrOpcode::Eq,  lhs_ty),
@@ -697,6 +953,8 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                 BinOp::Ge  => (IrOpcode::Ge,  lhs_ty),
             };
 
+            // NOTE(alexander): the result is allocated before the operands, but written here
+            update_ir_live_interval(ib, op1);
             update_ir_live_interval(ib, op2);
             update_ir_live_interval(ib, op3);
 
@@ -707,9 +965,15 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                 op3,
                 ty,
                 span: binary.span,
+                ..Default::default()
             });
 
-            (op1, ty)
+            // NOTE(alexander): the type of comparisons is the type of the operands, but the result is a boolean
+            match binary.op {
+                BinOp::Eq | BinOp::Ne | BinOp::Lt |
+                BinOp::Le | BinOp::Gt | BinOp::Ge => (op1, IrType::I8),
+                _ => (op1, ty),
+            }
         }
 
         Expr::Block(block) => build_ir_from_block(ib, &block.block, None, None, None),
@@ -742,30 +1006,93 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
             (IrOperand::None, IrType::None)
         }
 
+        Expr::Call(call) if call.is_tail => {
+            // Evaluate all the arguments before any of the parameters are overwritten
+            let mut arg_ops = Vec::new();
+            for arg in &call.args {
+                let (op2, ty) = build_ir_from_expr(ib, arg);
+                let op1 = allocate_register(ib);
+                update_ir_live_interval(ib, op2);
+                ib.instructions.push(IrInstruction {
+                    opcode: IrOpcode::Copy,
+                    op1,
+                    op2,
+                    ty,
+                    span: arg.get_span(),
+                    ..Default::default()
+                });
+                arg_ops.push(op1);
+            }
+
+            let params = ib.tail_call_params.clone();
+            for ((param, ty), op2) in params.into_iter().zip(arg_ops) {
+                let op1 = IrOperand::Ident(param);
+                update_ir_live_interval(ib, op1);
+                update_ir_live_interval(ib, op2);
+                ib.instructions.push(IrInstruction {
+                    opcode: IrOpcode::Copy,
+                    op1,
+                    op2,
+                    ty,
+                    span: call.span,
+                    ..Default::default()
+                });
+            }
+
+            if ib.tail_call_label.is_none() {
+                ib.tail_call_label = Some(create_unique_ir_label(ib));
+            }
+            ib.instructions.push(IrInstruction {
+                opcode: IrOpcode::Jump,
+                op1: IrOperand::Ident(ib.tail_call_label.unwrap()),
+                span: call.span,
+                ..Default::default()
+            });
+
+            // NOTE(alexander): the value is never used since the jump never returns here,
+            // but it has to be typed like the call it replaces.
+            let function_label = create_ir_ident(call.ident.sym, 0);
+            let return_type = match ib.functions.get(&function_label) {
+                Some(bb) => bb.return_type,
+                None => IrType::None,
+            };
+            let op = match return_type {
+                IrType::I32 => IrOperand::Value(IrValue::I32(0)),
+                IrType::I64 => IrOperand::Value(IrValue::I64(0)),
+                IrType::U64 => IrOperand::Value(IrValue::U64(0)),
+                IrType::I8 => IrOperand::Value(IrValue::Bool(false)),
+                _ => IrOperand::None,
+            };
+            (op, return_type)
+        }
+
         Expr::Call(call) => {
-            // Setup parameters
+            // Setup parameters, NOTE(alexander): every argument is evaluated before the first param
+            // since arguments containing calls would otherwise take the params of this call.
             let mut param_size = 0;
-            let call_insn_pos = ib.instructions.len() + call.args.len();
+            let mut param_ops = Vec::new();
+            let mut params = Vec::new();
             for arg in &call.args {
                 let (op1, ty) = build_ir_from_expr(ib, &arg);
+                param_ops.push(op1);
+                params.push((op1, ty, arg.get_span()));
+                param_size += 1;
+            }
 
-                // NOTE(alexander): update lifetime to include the call instruction also
-                if let IrOperand::Ident(ident) = op1 {
-                    match ib.live_intervals.get_mut(&ident) {
-                        Some(live_interval) => live_interval.end = call_insn_pos,
-                        None => {},
-                    }
-                }
-                
+            for (op1, ty, span) in params {
                 ib.instructions.push(IrInstruction {
                     opcode: IrOpcode::Param,
                     op1,
                     ty,
-                    span: arg.get_span(),
+                    span,
                     ..Default::default()
                 });
+            }
 
-                param_size += 1;
+            // NOTE(alexander): update lifetime to include the call instruction also,
+            // arguments may emit instructions of their own so this is done after all params.
+            for op in param_ops {
+                update_ir_live_interval(ib, op);
             }
 
             // Make the function call
@@ -811,12 +1138,24 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
         }
 
         Expr::If(if_expr) => {
-            build_ir_if_expr(ib, if_expr, None);
-            (IrOperand::None, IrType::None)
+            // NOTE(alexander): the value of if expressions e.g. the tail of a function is stored
+            // in a new register assigned by both arms, the arms of ifs without else have no value.
+            if if_expr.else_block.is_none() {
+                build_ir_if_expr(ib, if_expr, None);
+                return (IrOperand::None, IrType::None);
+            }
+
+            let op1 = allocate_register(ib);
+            match build_ir_if_expr(ib, if_expr, Some(op1)) {
+                IrType::None => (IrOperand::None, IrType::None),
+                ty => (op1, ty),
+            }
         }
 
         Expr::Lit(literal) => match literal.lit {
             Lit::Int(val)  => (IrOperand::Value(IrValue::I32(val)), IrType::I32),
+            Lit::Int64(val) => (IrOperand::Value(IrValue::I64(val)), IrType::I64),
+            Lit::UInt64(val) => (IrOperand::Value(IrValue::U64(val)), IrType::U64),
             Lit::Bool(val) => (IrOperand::Value(IrValue::Bool(val)), IrType::I8),
         }
 
@@ -829,8 +1168,12 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
             let ref_ty = match ty {
                 IrType::I8        => IrType::PtrI8(1),
                 IrType::I32       => IrType::PtrI32(1),
+                IrType::I64       => IrType::PtrI64(1),
+                IrType::U64       => IrType::PtrU64(1),
                 IrType::PtrI8(i)  => IrType::PtrI8(i + 1),
                 IrType::PtrI32(i) => IrType::PtrI32(i + 1),
+                IrType::PtrI64(i) => IrType::PtrI64(i + 1),
+                IrType::PtrU64(i) => IrType::PtrU64(i + 1),
                 IrType::None      => panic!("missing type info"),
                 _                 => panic!("unsupported type"),
             };
@@ -887,7 +1230,8 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                     ib.instructions.push(IrInstruction {
                         opcode: IrOpcode::Sub,
                         op1,
-                        op2,
+                        op2: op1,
+                        op3: op2,
                         ty,
                         span: unary.span,
                         ..Default::default()
@@ -897,13 +1241,15 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                 },
 
                 UnOp::Not => {
-                    let (op1, ty) = build_ir_from_expr(ib, &unary.expr);
+                    let (op2, ty) = build_ir_from_expr(ib, &unary.expr);
+                    let op1 = allocate_register(ib);
 
-                    update_ir_live_interval(ib, op1);
+                    update_ir_live_interval(ib, op2);
                     ib.instructions.push(IrInstruction {
                         opcode: IrOpcode::Xor,
                         op1,
-                        op2: IrOperand::Value(IrValue::Bool(true)),
+                        op2,
+                        op3: IrOperand::Value(IrValue::Bool(true)),
                         ty,
                         span: unary.span,
                         ..Default::default()
@@ -935,6 +1281,18 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
                             IrType::I32
                         }
 
+                        IrType::PtrI64(i) => if i > 1 {
+                            IrType::PtrI64(i - 1)
+                        } else {
+                            IrType::I64
+                        }
+
+                        IrType::PtrU64(i) => if i > 1 {
+                            IrType::PtrU64(i - 1)
+                        } else {
+                            IrType::U64
+                        }
+
                         IrType::None => panic!("missing type info"),
                     };
 
@@ -960,6 +1318,7 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
             ib.while_enter_index += 1;
             ib.while_exit_index += 1;
 
+            let enter_index = ib.instructions.len();
             ib.instructions.push(IrInstruction {
                 opcode: IrOpcode::Label,
                 op1: IrOperand::Ident(enter_label),
@@ -970,6 +1329,7 @@ pub fn build_ir_from_expr<'a>(ib: &mut IrBuilder<'a>, expr: &Expr) -> (IrOperand
 
             build_ir_from_block(ib, &while_expr.block, Some(enter_label), Some(exit_label), None);
 
+            extend_ir_live_intervals_over_loop(ib, enter_index, ib.instructions.len());
             ib.instructions.push(IrInstruction {
                 opcode: IrOpcode::Jump,
                 op1: IrOperand::Ident(enter_label),
@@ -994,8 +1354,11 @@ impl Default for IrInstruction {
             op1: IrOperand::None,
             op2: IrOperand::None,
             op3: IrOperand::None,
+            op4: IrOperand::None,
             ty: IrType::None,
             span: Span::new(),
+            phi_args: Vec::new(),
+            asm: None,
         }
     }
 }
@@ -1029,6 +1392,7 @@ impl fmt::Display for IrInstruction {
             IrOpcode::IfEq        |
             IrOpcode::IfNe        |
             IrOpcode::Param       |
+            IrOpcode::AsmIn       |
             IrOpcode::Return      |
             IrOpcode::Label       |
             IrOpcode::Jump        |
@@ -1050,6 +1414,29 @@ impl fmt::Display for IrInstruction {
                 }
             }
 
+            IrOpcode::Asm => {
+                write!(f, "{}", self.opcode)?;
+                if let Some(asm) = &self.asm {
+                    write!(f, " {:?}", asm.template)?;
+                    for operand in &asm.operands {
+                        write!(f, ", {}(\"{}\")", if operand.is_output { "out" } else { "in" }, operand.reg)?;
+                    }
+                    for reg in &asm.clobbers {
+                        write!(f, ", clobber(\"{}\")", reg)?;
+                    }
+                }
+            }
+
+            IrOpcode::Phi => {
+                write!(f, "{} = {} {}", self.op1, self.opcode, self.ty)?;
+                for (i, (label, value)) in self.phi_args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " [{}, {}]", value, label)?;
+                }
+            }
+
             _ => {
                 write!(f, "{} = {}", self.op1, self.opcode)?;
                 if let IrType::None = self.ty {
@@ -1062,6 +1449,10 @@ impl fmt::Display for IrInstruction {
                     if let IrOperand::None = self.op3 {
                     } else {
                         write!(f, ", {}", self.op3)?;
+                        if let IrOperand::None = self.op4 {
+                        } else {
+                            write!(f, ", {}", self.op4)?;
+                        }
                     }
                 }
             }
@@ -1081,6 +1472,8 @@ impl fmt::Display for IrType {
             IrType::U64       => write!(f, "u64"),
             IrType::PtrI8(i)  => write!(f, "i8{}", "*".repeat(*i as usize)),
             IrType::PtrI32(i) => write!(f, "i32{}", "*".repeat(*i as usize)),
+            IrType::PtrI64(i) => write!(f, "i64{}", "*".repeat(*i)),
+            IrType::PtrU64(i) => write!(f, "u64{}", "*".repeat(*i)),
             IrType::None      => write!(f, ""),
         }
     }
@@ -1103,6 +1496,7 @@ impl fmt::Display for IrOperand {
             IrOperand::Ident(label) => write!(f, "%{}", label),
             IrOperand::Value(val) => match val {
                 IrValue::I32(v) => write!(f, "{}", v),
+                IrValue::I64(v) => write!(f, "{}", v),
                 IrValue::U32(v) => write!(f, "{}", v),
                 IrValue::U64(v) => write!(f, "{}", v),
                 IrValue::Bool(v) => write!(f, "{}", v),
@@ -1138,6 +1532,7 @@ impl fmt::Display for IrOpcode {
             IrOpcode::Le            => write!(f, "le"),
             IrOpcode::Gt            => write!(f, "gt"),
             IrOpcode::Ge            => write!(f, "ge"),
+            IrOpcode::Select        => write!(f, "select"),
             IrOpcode::IfLt          => write!(f, "iflt"),
             IrOpcode::IfGt          => write!(f, "ifgt"),
             IrOpcode::IfLe          => write!(f, "ifle"),
@@ -1151,6 +1546,10 @@ impl fmt::Display for IrOpcode {
             IrOpcode::Jump          => write!(f, "jump"),
             IrOpcode::Prologue      => write!(f, "prologue"),
             IrOpcode::Epilogue      => write!(f, "epilogue\n"),
+            IrOpcode::Phi           => write!(f, "phi"),
+            IrOpcode::AsmIn         => write!(f, "asm_in"),
+            IrOpcode::Asm           => write!(f, "asm"),
+            IrOpcode::AsmOut        => write!(f, "asm_out"),
         }
     }
 }
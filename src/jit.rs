@@ -1,3 +1,5 @@
+use crate::intrinsics::exit_with_runtime_error;
+
 const PAGE_SIZE: usize = 4096;
 
 pub struct JitCode {
@@ -55,6 +57,9 @@ pub fn allocate_jit_code(size: usize) -> JitCode {
 
     let addr: *mut u8;
 
+    // NOTE(alexander): protection is set per page, make sure no other heap data shares our pages
+    let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
     unsafe {
         let mut raw_addr: *mut libc::c_void = std::mem::uninitialized();
 
@@ -80,10 +85,37 @@ pub fn finalize_jit_code(jit: &JitCode) {
     }
 }
 
+/**
+ * Division by zero and division overflow trap on x86, report these
+ * as runtime errors instead of silently killing the process.
+ */
+#[cfg(any(target_os="linux", target_os="macos"))]
+extern "C" fn handle_arithmetic_trap(_signal: libc::c_int) {
+    exit_with_runtime_error("attempt to divide by zero or with overflow");
+}
+
+#[cfg(any(target_os="linux", target_os="macos"))]
+pub fn execute_jit_code(jit: &JitCode) -> i32 {
+    use libc;
+
+    unsafe {
+        let handler = handle_arithmetic_trap as extern "C" fn(libc::c_int);
+        let prev_handler = libc::signal(libc::SIGFPE, handler as libc::sighandler_t);
+
+        let main_function_ptr: extern "C" fn () -> i32;
+        main_function_ptr = std::mem::transmute(jit.addr);
+        let ret = main_function_ptr(); // NOTE(alexander): program arguments are accessed through intrinsics
+
+        libc::signal(libc::SIGFPE, prev_handler);
+        ret
+    }
+}
+
+#[cfg(target_os="windows")]
 pub fn execute_jit_code(jit: &JitCode) -> i32 {
     unsafe {
         let main_function_ptr: extern "C" fn () -> i32;
         main_function_ptr = std::mem::transmute(jit.addr);
-        main_function_ptr() // TODO(alexander): maybe run with arguments in the future!
+        main_function_ptr() // NOTE(alexander): program arguments are accessed through intrinsics
     }
 }
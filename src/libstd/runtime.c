@@ -0,0 +1,189 @@
+/***************************************************************************
+ * Runtime for programs compiled ahead of time, e.g. using the LLVM backend
+ * or object files emitted by the x86 backend. Provides the native functions
+ * declared in `basic.sq` using the C calling convention, mirroring the
+ * implementations in `intrinsics.rs`.
+ *
+ * Usage: clang out.ll src/libstd/runtime.c -o out
+ *        cc prog.o src/libstd/runtime.c -o prog
+ *
+ * Or build it once as an archive and link against that instead:
+ *        cc -c src/libstd/runtime.c -o runtime.o && ar rcs libsqrrl.a runtime.o
+ *        cc prog.o libsqrrl.a -o prog
+ ***************************************************************************/
+
+#include <inttypes.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static int program_argc = 0;
+static char **program_argv = NULL;
+
+/**
+ * Captures the program arguments before main is called (glibc passes these to constructors).
+ */
+__attribute__((constructor))
+static void init_program_arguments(int argc, char **argv) {
+    program_argc = argc;
+    program_argv = argv;
+}
+
+/**
+ * Flushes the program output before aborting, so output printed
+ * before the error is not lost.
+ */
+static void runtime_abort(void) {
+    fflush(stdout);
+    abort();
+}
+
+/**
+ * Reports an error detected by the compiled code, e.g. dividing by zero.
+ */
+void sqrrl_runtime_error(const char *msg) {
+    fprintf(stderr, "error: %s\n", msg);
+    runtime_abort();
+}
+
+/**
+ * Prints the given integer.
+ */
+void print_int(int val) {
+    printf("%d\n", val);
+}
+
+/**
+ * Prints the given 64-bit integer.
+ */
+void print_i64(int64_t val) {
+    printf("%" PRId64 "\n", val);
+}
+
+/**
+ * Prints the given unsigned 64-bit integer.
+ */
+void print_u64(uint64_t val) {
+    printf("%" PRIu64 "\n", val);
+}
+
+/**
+ * Prints the given boolean.
+ */
+void print_bool(bool val) {
+    printf("%s\n", val ? "true" : "false");
+}
+
+/**
+ * Simple assertion.
+ */
+void assert(bool val) {
+    if (!val) {
+        fprintf(stderr, "assertion failed\n");
+        runtime_abort();
+    }
+}
+
+/**
+ * Simple equals assertion.
+ */
+void assert_eq_int(int left, int right) {
+    if (left != right) {
+        fprintf(stderr, "assertion failed: `(left == right)`\n  left: `%d`,\n right: `%d`\n", left, right);
+        runtime_abort();
+    }
+}
+
+/**
+ * Simple equals assertion.
+ */
+void assert_eq_bool(bool left, bool right) {
+    if (left != right) {
+        fprintf(stderr, "assertion failed: `(left == right)`\n  left: `%s`,\n right: `%s`\n",
+                left ? "true" : "false", right ? "true" : "false");
+        runtime_abort();
+    }
+}
+
+/**
+ * Returns the number of arguments passed to the program.
+ */
+int arg_count(void) {
+    return program_argc > 0 ? program_argc - 1 : 0;
+}
+
+/**
+ * Returns the program argument at the given index as an integer.
+ */
+int arg_int(int index) {
+    char *end;
+    long val;
+    if (index < 0 || index >= arg_count()) {
+        fprintf(stderr, "program argument %d is missing or not an integer\n", index);
+        runtime_abort();
+    }
+    val = strtol(program_argv[index + 1], &end, 10);
+    if (*end != '\0' || end == program_argv[index + 1]) {
+        fprintf(stderr, "program argument %d is missing or not an integer\n", index);
+        runtime_abort();
+    }
+    return (int) val;
+}
+
+static char input_token[256];
+static bool has_input_token = false;
+
+/**
+ * Returns true if there are no more tokens left to read from standard input.
+ */
+bool is_eof(void) {
+    if (!has_input_token) {
+        has_input_token = scanf("%255s", input_token) == 1;
+    }
+    return !has_input_token;
+}
+
+static const char *read_input_token(void) {
+    if (is_eof()) {
+        return NULL;
+    }
+    has_input_token = false;
+    return input_token;
+}
+
+/**
+ * Reads the next integer from standard input, returns 0 at end of input.
+ */
+int read_int(void) {
+    const char *token = read_input_token();
+    char *end;
+    long val;
+    if (!token) {
+        return 0;
+    }
+    val = strtol(token, &end, 10);
+    if (*end != '\0') {
+        fprintf(stderr, "expected integer on standard input, found `%s`\n", token);
+        runtime_abort();
+    }
+    return (int) val;
+}
+
+/**
+ * Reads the next boolean from standard input, returns false at end of input.
+ */
+bool read_bool(void) {
+    const char *token = read_input_token();
+    if (!token) {
+        return false;
+    }
+    if (strcmp(token, "true") == 0 || strcmp(token, "1") == 0) {
+        return true;
+    }
+    if (strcmp(token, "false") == 0 || strcmp(token, "0") == 0) {
+        return false;
+    }
+    fprintf(stderr, "expected boolean on standard input, found `%s`\n", token);
+    runtime_abort();
+}
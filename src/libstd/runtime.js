@@ -0,0 +1,145 @@
+/***************************************************************************
+ * Runtime for programs compiled using the WebAssembly backend.
+ * Provides the native functions declared in `basic.sq` as imports
+ * from the `env` module, mirroring the implementations in `intrinsics.rs`.
+ *
+ * Usage: node src/libstd/runtime.js out.wasm [ARGS]...
+ ***************************************************************************/
+
+"use strict";
+
+const fs = require("fs");
+
+const [wasmPath, ...programArgs] = process.argv.slice(2);
+if (!wasmPath) {
+    console.error("usage: node runtime.js <file.wasm> [ARGS]...");
+    process.exit(1);
+}
+
+let output = "";
+let inputTokens = null;
+
+/**
+ * Writes the program output before exiting, so output printed before the error is not lost.
+ */
+function runtimeError(msg) {
+    process.stdout.write(output);
+    process.stderr.write(`error: ${msg}\n`);
+    process.exit(1);
+}
+
+/**
+ * Standard input is split into whitespace separated tokens the first time it is read.
+ */
+function peekToken() {
+    if (inputTokens === null) {
+        let input = "";
+        try {
+            input = fs.readFileSync(0, "utf8");
+        } catch (err) {
+            input = "";
+        }
+        inputTokens = input.split(/\s+/).filter((token) => token.length > 0);
+    }
+    return inputTokens.length > 0 ? inputTokens[0] : null;
+}
+
+const env = {
+    print_int(val) {
+        output += `${val}\n`;
+    },
+
+    // NOTE(alexander): 64-bit integers are passed as BigInt, which is always signed
+    print_i64(val) {
+        output += `${val}\n`;
+    },
+
+    print_u64(val) {
+        output += `${BigInt.asUintN(64, val)}\n`;
+    },
+
+    print_bool(val) {
+        output += `${val !== 0}\n`;
+    },
+
+    assert(val) {
+        if (val === 0) {
+            runtimeError("assertion failed");
+        }
+    },
+
+    assert_eq_int(left, right) {
+        if (left !== right) {
+            runtimeError(`assertion failed: \`(left == right)\`\n  left: \`${left}\`,\n right: \`${right}\``);
+        }
+    },
+
+    assert_eq_bool(left, right) {
+        if ((left !== 0) !== (right !== 0)) {
+            runtimeError(`assertion failed: \`(left == right)\`\n  left: \`${left !== 0}\`,\n right: \`${right !== 0}\``);
+        }
+    },
+
+    arg_count() {
+        return programArgs.length;
+    },
+
+    arg_int(index) {
+        const arg = programArgs[index];
+        if (arg === undefined || !/^[+-]?\d+$/.test(arg)) {
+            runtimeError(`program argument ${index} is missing or not an integer`);
+        }
+        return parseInt(arg, 10) | 0;
+    },
+
+    is_eof() {
+        return peekToken() === null ? 1 : 0;
+    },
+
+    read_int() {
+        const token = peekToken();
+        if (token === null) {
+            return 0;
+        }
+        inputTokens.shift();
+        if (!/^[+-]?\d+$/.test(token)) {
+            runtimeError(`expected integer on standard input, found \`${token}\``);
+        }
+        return parseInt(token, 10) | 0;
+    },
+
+    read_bool() {
+        const token = peekToken();
+        if (token === null) {
+            return 0;
+        }
+        inputTokens.shift();
+        if (token === "true" || token === "1") {
+            return 1;
+        }
+        if (token === "false" || token === "0") {
+            return 0;
+        }
+        runtimeError(`expected boolean on standard input, found \`${token}\``);
+    },
+};
+
+const wasmModule = new WebAssembly.Module(fs.readFileSync(wasmPath));
+for (const imported of WebAssembly.Module.imports(wasmModule)) {
+    if (imported.module === "env" && !(imported.name in env)) {
+        console.error(`error: function \`${imported.name}\` is not provided by the runtime`);
+        process.exit(1);
+    }
+}
+
+const instance = new WebAssembly.Instance(wasmModule, { env });
+let code = 0;
+try {
+    const result = instance.exports.main();
+    code = result === undefined ? 0 : result;
+} catch (err) {
+    runtimeError(err.message);
+}
+
+process.stdout.write(output);
+process.exitCode = code & 0xff;
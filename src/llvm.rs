@@ -0,0 +1,797 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::ast::*;
+use crate::ffi::is_c_abi;
+
+/***************************************************************************
+ * LLVM Backend:
+ * - Generates textual LLVM IR (.ll) directly from the AST, there is
+ *   no need to link against libLLVM, the output can be compiled using
+ *   `clang out.ll src/libstd/runtime.c` (or `llc` followed by any C linker).
+ * - Every local variable is stored in an alloca placed in the entry block,
+ *   LLVM promotes these to SSA registers using the mem2reg pass.
+ * - If-expressions that produce values use phi nodes.
+ * - Mutable reference parameters are marked `noalias`, this is
+ *   guaranteed by the borrow checker.
+ * - References use opaque pointers (`ptr`), loads and stores carry the
+ *   element type, this requires LLVM 15 (or `-opaque-pointers` on LLVM 14).
+ ***************************************************************************/
+
+pub struct LlvmContext<'a> {
+    pub file: Option<&'a File>,
+    declarations: String,
+    definitions: String,
+    allocas: String, // allocas of the current function, placed in entry block
+    body: String,    // instructions of the current function
+    signatures: HashMap<Symbol, LlvmSignature<'a>>,
+    scopes: Vec<HashMap<Symbol, LlvmLocal>>,
+    loops: Vec<(String, String)>, // (continue label, break label)
+    curr_label: String,
+    is_terminated: bool,
+    is_void_main: bool,
+    uses_pow: bool,
+    uses_div: bool,
+    uses_debugtrap: bool,
+    next_temp: u32,
+    next_label: u32,
+    next_local: u32,
+    trace_symbol: Symbol,
+    debug_break_symbol: Symbol,
+}
+
+struct LlvmSignature<'a> {
+    decl: &'a FnDecl,
+    is_foreign: bool,
+}
+
+#[derive(Clone)]
+struct LlvmLocal {
+    ptr: String,
+    ty: TyKind,
+}
+
+pub fn create_llvm_context<'a>() -> LlvmContext<'a> {
+    LlvmContext {
+        file: None,
+        declarations: String::new(),
+        definitions: String::new(),
+        allocas: String::new(),
+        body: String::new(),
+        signatures: HashMap::new(),
+        scopes: Vec::new(),
+        loops: Vec::new(),
+        curr_label: String::new(),
+        is_terminated: false,
+        is_void_main: false,
+        uses_pow: false,
+        uses_div: false,
+        uses_debugtrap: false,
+        next_temp: 0,
+        next_label: 0,
+        next_local: 0,
+        trace_symbol: intern_string("trace"),
+        debug_break_symbol: intern_string("debug_break"),
+    }
+}
+
+/**
+ * Generates an LLVM IR module containing every function in the file.
+ */
+pub fn build_llvm_ir_from_ast<'a>(lc: &mut LlvmContext<'a>, file: &'a File) -> String {
+    lc.file = Some(file);
+
+    fn register_items<'a>(lc: &mut LlvmContext<'a>, items: &'a Vec<Item>, abi: &Option<String>) {
+        for item in items {
+            match item {
+                Item::Fn(func) => {
+                    lc.signatures.insert(func.ident.sym, LlvmSignature { decl: &func.decl, is_foreign: false });
+                }
+
+                Item::ForeignFn(func) => {
+                    lc.signatures.insert(func.ident.sym, LlvmSignature { decl: &func.decl, is_foreign: true });
+
+                    // NOTE(alexander): backend and interpreter level intrinsics are never declared
+                    if func.ident.sym == lc.trace_symbol || func.ident.sym == lc.debug_break_symbol {
+                        continue;
+                    }
+
+                    // NOTE(alexander): natives are provided by the runtime using the C calling convention
+                    let comment = if is_c_abi(abi) { "" } else { " ; provided by runtime" };
+                    let _ = writeln!(lc.declarations, "declare {} @{}({}){}",
+                                     to_llvm_foreign_return_type(&func.decl.output.kind),
+                                     resolve_symbol(func.ident.sym),
+                                     func.decl.inputs.iter()
+                                         .map(|arg| to_llvm_foreign_type(&arg.ty.kind))
+                                         .collect::<Vec<String>>()
+                                         .join(", "),
+                                     comment);
+                }
+
+                Item::ForeignMod(module) => register_items(lc, &module.items, &module.abi),
+            }
+        }
+    }
+
+    register_items(lc, &file.items, &None);
+
+    for item in &file.items {
+        if let Item::Fn(func) = item {
+            build_llvm_ir_from_function(lc, func);
+        }
+    }
+
+    let mut module = String::new();
+    let _ = writeln!(module, "; ModuleID = '{}'", file.filename);
+    let _ = writeln!(module, "source_filename = \"{}\"\n", file.filename);
+    module.push_str(&lc.definitions);
+
+    if lc.uses_pow {
+        module.push_str(LLVM_POW_FUNCTION);
+    }
+
+    if lc.uses_div {
+        module.push_str(LLVM_DIV_FUNCTIONS);
+    }
+
+    module.push_str(&lc.declarations);
+    if lc.uses_debugtrap {
+        module.push_str("declare void @llvm.debugtrap()\n");
+    }
+    module
+}
+
+/**
+ * Integer power function used by the `**` operator, computed by squaring. The exponent
+ * is treated as a 32-bit unsigned integer, the same as the interpreter.
+ */
+const LLVM_POW_FUNCTION: &str = "define internal i32 @sqrrl.pow(i32 %base, i32 %exp) {
+entry:
+    br label %loop
+loop:
+    %result = phi i32 [ 1, %entry ], [ %result.next, %body ]
+    %b = phi i32 [ %base, %entry ], [ %b.next, %body ]
+    %e = phi i32 [ %exp, %entry ], [ %e.next, %body ]
+    %done = icmp eq i32 %e, 0
+    br i1 %done, label %exit, label %body
+body:
+    %bit = and i32 %e, 1
+    %odd = icmp ne i32 %bit, 0
+    %mul = mul i32 %result, %b
+    %result.next = select i1 %odd, i32 %mul, i32 %result
+    %b.next = mul i32 %b, %b
+    %e.next = lshr i32 %e, 1
+    br label %loop
+exit:
+    ret i32 %result
+}
+
+define internal i64 @sqrrl.pow.i64(i64 %base, i64 %exp) {
+entry:
+    %exp.u32 = trunc i64 %exp to i32
+    br label %loop
+loop:
+    %result = phi i64 [ 1, %entry ], [ %result.next, %body ]
+    %b = phi i64 [ %base, %entry ], [ %b.next, %body ]
+    %e = phi i32 [ %exp.u32, %entry ], [ %e.next, %body ]
+    %done = icmp eq i32 %e, 0
+    br i1 %done, label %exit, label %body
+body:
+    %bit = and i32 %e, 1
+    %odd = icmp ne i32 %bit, 0
+    %mul = mul i64 %result, %b
+    %result.next = select i1 %odd, i64 %mul, i64 %result
+    %b.next = mul i64 %b, %b
+    %e.next = lshr i32 %e, 1
+    br label %loop
+exit:
+    ret i64 %result
+}
+
+";
+
+/**
+ * Division and remainder operators, dividing by zero or overflowing is reported
+ * by the runtime (the same errors as the interpreter) instead of being undefined.
+ */
+const LLVM_DIV_FUNCTIONS: &str = "@sqrrl.str.div.zero = private unnamed_addr constant [26 x i8] c\"attempt to divide by zero\\00\"
+@sqrrl.str.div.overflow = private unnamed_addr constant [32 x i8] c\"attempt to divide with overflow\\00\"
+@sqrrl.str.rem.overflow = private unnamed_addr constant [49 x i8] c\"attempt to calculate the remainder with overflow\\00\"
+
+define internal i32 @sqrrl.div(i32 %a, i32 %b) {
+entry:
+    %zero = icmp eq i32 %b, 0
+    %neg = icmp eq i32 %b, -1
+    %min = icmp eq i32 %a, -2147483648
+    %overflow = and i1 %neg, %min
+    %error = or i1 %zero, %overflow
+    br i1 %error, label %fail, label %exit
+fail:
+    %msg = select i1 %zero, ptr @sqrrl.str.div.zero, ptr @sqrrl.str.div.overflow
+    call void @sqrrl_runtime_error(ptr %msg)
+    unreachable
+exit:
+    %result = sdiv i32 %a, %b
+    ret i32 %result
+}
+
+define internal i32 @sqrrl.rem(i32 %a, i32 %b) {
+entry:
+    %zero = icmp eq i32 %b, 0
+    %neg = icmp eq i32 %b, -1
+    %min = icmp eq i32 %a, -2147483648
+    %overflow = and i1 %neg, %min
+    %error = or i1 %zero, %overflow
+    br i1 %error, label %fail, label %exit
+fail:
+    %msg = select i1 %zero, ptr @sqrrl.str.div.zero, ptr @sqrrl.str.rem.overflow
+    call void @sqrrl_runtime_error(ptr %msg)
+    unreachable
+exit:
+    %result = srem i32 %a, %b
+    ret i32 %result
+}
+
+define internal i64 @sqrrl.div.i64(i64 %a, i64 %b) {
+entry:
+    %zero = icmp eq i64 %b, 0
+    %neg = icmp eq i64 %b, -1
+    %min = icmp eq i64 %a, -9223372036854775808
+    %overflow = and i1 %neg, %min
+    %error = or i1 %zero, %overflow
+    br i1 %error, label %fail, label %exit
+fail:
+    %msg = select i1 %zero, ptr @sqrrl.str.div.zero, ptr @sqrrl.str.div.overflow
+    call void @sqrrl_runtime_error(ptr %msg)
+    unreachable
+exit:
+    %result = sdiv i64 %a, %b
+    ret i64 %result
+}
+
+define internal i64 @sqrrl.rem.i64(i64 %a, i64 %b) {
+entry:
+    %zero = icmp eq i64 %b, 0
+    %neg = icmp eq i64 %b, -1
+    %min = icmp eq i64 %a, -9223372036854775808
+    %overflow = and i1 %neg, %min
+    %error = or i1 %zero, %overflow
+    br i1 %error, label %fail, label %exit
+fail:
+    %msg = select i1 %zero, ptr @sqrrl.str.div.zero, ptr @sqrrl.str.rem.overflow
+    call void @sqrrl_runtime_error(ptr %msg)
+    unreachable
+exit:
+    %result = srem i64 %a, %b
+    ret i64 %result
+}
+
+define internal i64 @sqrrl.div.u64(i64 %a, i64 %b) {
+entry:
+    %zero = icmp eq i64 %b, 0
+    br i1 %zero, label %fail, label %exit
+fail:
+    call void @sqrrl_runtime_error(ptr @sqrrl.str.div.zero)
+    unreachable
+exit:
+    %result = udiv i64 %a, %b
+    ret i64 %result
+}
+
+define internal i64 @sqrrl.rem.u64(i64 %a, i64 %b) {
+entry:
+    %zero = icmp eq i64 %b, 0
+    br i1 %zero, label %fail, label %exit
+fail:
+    call void @sqrrl_runtime_error(ptr @sqrrl.str.div.zero)
+    unreachable
+exit:
+    %result = urem i64 %a, %b
+    ret i64 %result
+}
+
+declare void @sqrrl_runtime_error(ptr) cold noreturn
+";
+
+fn build_llvm_ir_from_function<'a>(lc: &mut LlvmContext<'a>, func: &'a FnItem) {
+    lc.allocas.clear();
+    lc.body.clear();
+    lc.scopes.clear();
+    lc.loops.clear();
+    lc.next_temp = 0;
+    lc.next_label = 0;
+    lc.next_local = 0;
+    lc.curr_label = String::from("entry");
+    lc.is_terminated = false;
+
+    let name = resolve_symbol(func.ident.sym);
+    lc.is_void_main = name == "main" && func.decl.output.is_none();
+
+    // NOTE(alexander): only main is visible outside the module, also avoids clashing with libc e.g. `abs`
+    let (linkage, return_type) = if name == "main" {
+        ("", String::from("i32"))
+    } else {
+        ("internal ", to_llvm_type(&func.decl.output.kind))
+    };
+
+    // Store parameters in allocas so they can be mutated
+    let mut params = Vec::new();
+    lc.scopes.push(HashMap::new());
+    for arg in &func.decl.inputs {
+        let arg_name = resolve_symbol(arg.ident.sym);
+        let ty = to_llvm_type(&arg.ty.kind);
+        let noalias = match &arg.ty.kind {
+            TyKind::Ref(r) if r.mutable => " noalias",
+            _ => "",
+        };
+        params.push(format!("{}{} %{}", ty, noalias, arg_name));
+
+        let ptr = create_llvm_local(lc, arg.ident.sym, &arg.ty.kind);
+        push_llvm_insn(lc, format!("store {} %{}, ptr {}", ty, arg_name, ptr));
+    }
+
+    let (value, ty) = build_llvm_ir_from_block(lc, &func.block);
+    lc.scopes.pop();
+
+    if !lc.is_terminated {
+        if lc.is_void_main {
+            push_llvm_terminator(lc, String::from("ret i32 0"));
+        } else if func.decl.output.is_none() {
+            push_llvm_terminator(lc, String::from("ret void"));
+        } else if ty != TyKind::None {
+            push_llvm_terminator(lc, format!("ret {} {}", to_llvm_type(&ty), value));
+        } else {
+            push_llvm_terminator(lc, String::from("unreachable"));
+        }
+    }
+
+    let _ = writeln!(lc.definitions, "define {}{} @{}({}) {{", linkage, return_type, name, params.join(", "));
+    lc.definitions.push_str("entry:\n");
+    lc.definitions.push_str(&lc.allocas);
+    lc.definitions.push_str(&lc.body);
+    lc.definitions.push_str("}\n\n");
+}
+
+fn build_llvm_ir_from_block<'a>(lc: &mut LlvmContext<'a>, block: &'a Block) -> (String, TyKind) {
+    lc.scopes.push(HashMap::new());
+    let mut result = (String::new(), TyKind::None);
+    for (i, stmt) in block.stmts.iter().enumerate() {
+        let is_last = i == block.stmts.len() - 1;
+        match stmt {
+            Stmt::Local(local) => {
+                // NOTE(alexander): the initializer is evaluated before the new local shadows any previous one
+                let init = local.init.as_ref().as_ref().map(|init| build_llvm_ir_from_expr(lc, init));
+
+                let ty = match &init {
+                    Some((_, init_ty)) if local.ty.is_none() => init_ty.clone(),
+                    _ => local.ty.kind.clone(),
+                };
+
+                let ptr = create_llvm_local(lc, local.ident.sym, &ty);
+                if let Some((value, _)) = init {
+                    let llvm_ty = to_llvm_type(&ty);
+                    push_llvm_insn(lc, format!("store {} {}, ptr {}", llvm_ty, value, ptr));
+                }
+            }
+
+            // NOTE(alexander): nested items are not supported, same as the x86 backend
+            Stmt::Item(_) => {}
+
+            Stmt::Semi(expr) => {
+                build_llvm_ir_from_expr(lc, expr);
+            }
+
+            Stmt::Expr(expr) => {
+                let value = build_llvm_ir_from_expr(lc, expr);
+                if is_last {
+                    result = value;
+                }
+            }
+        }
+    }
+    lc.scopes.pop();
+    result
+}
+
+fn build_llvm_ir_from_expr<'a>(lc: &mut LlvmContext<'a>, expr: &'a Expr) -> (String, TyKind) {
+    match expr {
+        Expr::Asm(_) => panic!("llvm: inline assembly is only supported by the x86 backend"),
+
+        Expr::Assign(assign) => {
+            let (value, ty) = build_llvm_ir_from_expr(lc, &assign.right);
+            let ptr = build_llvm_ir_from_place(lc, &assign.left);
+            let ty = to_llvm_type(&ty);
+            push_llvm_insn(lc, format!("store {} {}, ptr {}", ty, value, ptr));
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Binary(binary) => {
+            let (lhs, lhs_ty) = build_llvm_ir_from_expr(lc, &binary.left);
+            let (rhs, _) = build_llvm_ir_from_expr(lc, &binary.right);
+            let ty = to_llvm_type(&lhs_ty);
+            let unsigned = lhs_ty == TyKind::UInt64;
+            let (insn, result_ty) = match binary.op {
+                BinOp::Add => ("add", lhs_ty),
+                BinOp::Sub => ("sub", lhs_ty),
+                BinOp::Mul => ("mul", lhs_ty),
+                BinOp::And => ("and", TyKind::Bool),
+                BinOp::Or  => ("or", TyKind::Bool),
+                BinOp::Eq  => ("icmp eq", TyKind::Bool),
+                BinOp::Ne  => ("icmp ne", TyKind::Bool),
+                BinOp::Lt  => (if unsigned { "icmp ult" } else { "icmp slt" }, TyKind::Bool),
+                BinOp::Le  => (if unsigned { "icmp ule" } else { "icmp sle" }, TyKind::Bool),
+                BinOp::Gt  => (if unsigned { "icmp ugt" } else { "icmp sgt" }, TyKind::Bool),
+                BinOp::Ge  => (if unsigned { "icmp uge" } else { "icmp sge" }, TyKind::Bool),
+                BinOp::Div |
+                BinOp::Mod => {
+                    lc.uses_div = true;
+                    let func = match (binary.op, &lhs_ty) {
+                        (BinOp::Div, TyKind::Int)   => "sqrrl.div",
+                        (BinOp::Div, TyKind::Int64) => "sqrrl.div.i64",
+                        (BinOp::Div, _)             => "sqrrl.div.u64",
+                        (_, TyKind::Int)            => "sqrrl.rem",
+                        (_, TyKind::Int64)          => "sqrrl.rem.i64",
+                        _                           => "sqrrl.rem.u64",
+                    };
+                    let temp = create_llvm_temp(lc);
+                    push_llvm_insn(lc, format!("{} = call {} @{}({} {}, {} {})", temp, ty, func, ty, lhs, ty, rhs));
+                    return (temp, lhs_ty);
+                }
+                BinOp::Pow => {
+                    lc.uses_pow = true;
+                    let temp = create_llvm_temp(lc);
+                    let func = if ty == "i64" { "sqrrl.pow.i64" } else { "sqrrl.pow" };
+                    push_llvm_insn(lc, format!("{} = call {} @{}({} {}, {} {})", temp, ty, func, ty, lhs, ty, rhs));
+                    return (temp, lhs_ty);
+                }
+            };
+
+            let temp = create_llvm_temp(lc);
+            push_llvm_insn(lc, format!("{} = {} {} {}, {}", temp, insn, ty, lhs, rhs));
+            (temp, result_ty)
+        }
+
+        Expr::Block(block) => build_llvm_ir_from_block(lc, &block.block),
+
+        Expr::Break(_) => {
+            if let Some((_, break_label)) = lc.loops.last() {
+                let insn = format!("br label %{}", break_label);
+                push_llvm_terminator(lc, insn);
+            }
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Continue(_) => {
+            if let Some((continue_label, _)) = lc.loops.last() {
+                let insn = format!("br label %{}", continue_label);
+                push_llvm_terminator(lc, insn);
+            }
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Call(call) => build_llvm_ir_from_call(lc, call),
+
+        Expr::Ident(ident) => {
+            let local = find_llvm_local(lc, ident.sym);
+            let ty = to_llvm_type(&local.ty);
+            let temp = create_llvm_temp(lc);
+            push_llvm_insn(lc, format!("{} = load {}, ptr {}", temp, ty, local.ptr));
+            (temp, local.ty)
+        }
+
+        Expr::If(if_expr) => build_llvm_ir_from_if(lc, if_expr),
+
+        Expr::Lit(literal) => match literal.lit {
+            Lit::Int(val) => (val.to_string(), TyKind::Int),
+            Lit::Int64(val) => (val.to_string(), TyKind::Int64),
+            // NOTE(alexander): llvm integers have no sign, large unsigned values are written as negative
+            Lit::UInt64(val) => ((val as i64).to_string(), TyKind::UInt64),
+            Lit::Bool(val) => (val.to_string(), TyKind::Bool),
+        },
+
+        Expr::Paren(paren) => build_llvm_ir_from_expr(lc, &paren.expr),
+
+        Expr::Reference(reference) => {
+            let ptr = match &*reference.expr {
+                Expr::Ident(ident) => {
+                    let local = find_llvm_local(lc, ident.sym);
+                    (local.ptr, local.ty)
+                }
+
+                expr => {
+                    // Temporary values are stored in an alloca to be able to reference them
+                    let (value, ty) = build_llvm_ir_from_expr(lc, expr);
+                    let ptr = create_llvm_local(lc, intern_string("ref"), &ty);
+                    let llvm_ty = to_llvm_type(&ty);
+                    push_llvm_insn(lc, format!("store {} {}, ptr {}", llvm_ty, value, ptr));
+                    (ptr, ty)
+                }
+            };
+
+            let ty = TyKind::Ref(TypeRef {
+                mutable: reference.mutable,
+                elem: Box::new(Ty::new(ptr.1, reference.span)),
+            });
+            (ptr.0, ty)
+        }
+
+        Expr::Return(return_expr) => {
+            let insn = match &*return_expr.expr {
+                Some(expr) => {
+                    let (value, ty) = build_llvm_ir_from_expr(lc, expr);
+                    if lc.is_terminated {
+                        // NOTE(alexander): the value is a tail call which has already returned
+                        return (String::new(), TyKind::None);
+                    }
+                    format!("ret {} {}", to_llvm_type(&ty), value)
+                }
+                None if lc.is_void_main => String::from("ret i32 0"),
+                None => String::from("ret void"),
+            };
+            push_llvm_terminator(lc, insn);
+            (String::new(), TyKind::None)
+        }
+
+        Expr::Unary(unary) => {
+            let (value, ty) = build_llvm_ir_from_expr(lc, &unary.expr);
+            let temp = create_llvm_temp(lc);
+            match unary.op {
+                UnOp::Neg => {
+                    push_llvm_insn(lc, format!("{} = sub {} 0, {}", temp, to_llvm_type(&ty), value));
+                    (temp, ty)
+                }
+
+                UnOp::Not => {
+                    push_llvm_insn(lc, format!("{} = xor i1 {}, true", temp, value));
+                    (temp, TyKind::Bool)
+                }
+
+                UnOp::Deref => {
+                    let elem_ty = match ty {
+                        TyKind::Ref(r) => r.elem.kind,
+                        _ => panic!("llvm: cannot dereference non-reference type"),
+                    };
+                    let llvm_ty = to_llvm_type(&elem_ty);
+                    push_llvm_insn(lc, format!("{} = load {}, ptr {}", temp, llvm_ty, value));
+                    (temp, elem_ty)
+                }
+            }
+        }
+
+        Expr::While(while_expr) => {
+            let cond_label = create_llvm_label(lc, "while.cond");
+            let body_label = create_llvm_label(lc, "while.body");
+            let exit_label = create_llvm_label(lc, "while.exit");
+
+            push_llvm_label(lc, &cond_label);
+            let (cond, _) = build_llvm_ir_from_expr(lc, &while_expr.cond);
+            push_llvm_terminator(lc, format!("br i1 {}, label %{}, label %{}", cond, body_label, exit_label));
+
+            push_llvm_label(lc, &body_label);
+            lc.loops.push((cond_label.clone(), exit_label.clone()));
+            build_llvm_ir_from_block(lc, &while_expr.block);
+            lc.loops.pop();
+            push_llvm_terminator(lc, format!("br label %{}", cond_label));
+
+            push_llvm_label(lc, &exit_label);
+            (String::new(), TyKind::None)
+        }
+    }
+}
+
+/**
+ * Returns a pointer to the memory location that an assignment writes to.
+ */
+fn build_llvm_ir_from_place<'a>(lc: &mut LlvmContext<'a>, expr: &'a Expr) -> String {
+    match expr {
+        Expr::Ident(ident) => find_llvm_local(lc, ident.sym).ptr,
+        Expr::Paren(paren) => build_llvm_ir_from_place(lc, &paren.expr),
+        Expr::Unary(unary) if unary.op == UnOp::Deref => build_llvm_ir_from_expr(lc, &unary.expr).0,
+        _ => panic!("llvm: invalid left-hand side expression"),
+    }
+}
+
+fn build_llvm_ir_from_call<'a>(lc: &mut LlvmContext<'a>, call: &'a ExprCall) -> (String, TyKind) {
+    if call.ident.sym == lc.trace_symbol {
+        return (String::new(), TyKind::None); // NOTE(alexander): interpreter level intrinsic
+    }
+
+    if call.ident.sym == lc.debug_break_symbol {
+        lc.uses_debugtrap = true;
+        push_llvm_insn(lc, String::from("call void @llvm.debugtrap()"));
+        return (String::new(), TyKind::None);
+    }
+
+    let (decl, is_foreign) = match lc.signatures.get(&call.ident.sym) {
+        Some(sig) => (sig.decl, sig.is_foreign),
+        None => panic!("llvm: cannot find function `{}`", resolve_symbol(call.ident.sym)),
+    };
+
+    let mut args = Vec::new();
+    for (arg, input) in call.args.iter().zip(&decl.inputs) {
+        let (value, _) = build_llvm_ir_from_expr(lc, arg);
+        let ty = if is_foreign {
+            to_llvm_foreign_type(&input.ty.kind)
+        } else {
+            to_llvm_type(&input.ty.kind)
+        };
+        args.push(format!("{} {}", ty, value));
+    }
+
+    let return_type = if is_foreign {
+        to_llvm_foreign_return_type(&decl.output.kind)
+    } else {
+        to_llvm_type(&decl.output.kind)
+    };
+
+    let name = resolve_symbol(call.ident.sym);
+    if call.is_tail {
+        // NOTE(alexander): musttail guarantees that the frame is reused, the call has to be followed by ret
+        if decl.output.is_none() {
+            push_llvm_insn(lc, format!("musttail call void @{}({})", name, args.join(", ")));
+            push_llvm_terminator(lc, String::from("ret void"));
+        } else {
+            let temp = create_llvm_temp(lc);
+            push_llvm_insn(lc, format!("{} = musttail call {} @{}({})", temp, return_type, name, args.join(", ")));
+            push_llvm_terminator(lc, format!("ret {} {}", return_type, temp));
+        }
+        return (String::new(), TyKind::None);
+    }
+
+    if decl.output.is_none() {
+        push_llvm_insn(lc, format!("call void @{}({})", name, args.join(", ")));
+        (String::new(), TyKind::None)
+    } else {
+        let temp = create_llvm_temp(lc);
+        push_llvm_insn(lc, format!("{} = call {} @{}({})", temp, return_type, name, args.join(", ")));
+        (temp, decl.output.kind.clone())
+    }
+}
+
+fn build_llvm_ir_from_if<'a>(lc: &mut LlvmContext<'a>, if_expr: &'a ExprIf) -> (String, TyKind) {
+    let then_label = create_llvm_label(lc, "if.then");
+    let else_label = create_llvm_label(lc, "if.else");
+    let exit_label = create_llvm_label(lc, "if.exit");
+
+    let (cond, _) = build_llvm_ir_from_expr(lc, &if_expr.cond);
+    let false_label = if if_expr.else_block.is_some() { &else_label } else { &exit_label };
+    push_llvm_terminator(lc, format!("br i1 {}, label %{}, label %{}", cond, then_label, false_label));
+
+    // Values flowing out of each branch, together with the block they came from
+    let mut incoming = Vec::new();
+
+    push_llvm_label(lc, &then_label);
+    let (then_value, then_ty) = build_llvm_ir_from_block(lc, &if_expr.then_block);
+    if !lc.is_terminated {
+        incoming.push((then_value, lc.curr_label.clone()));
+        push_llvm_terminator(lc, format!("br label %{}", exit_label));
+    }
+
+    let mut else_ty = TyKind::None;
+    if let Some(else_block) = &if_expr.else_block {
+        push_llvm_label(lc, &else_label);
+        let (else_value, ty) = build_llvm_ir_from_block(lc, else_block);
+        else_ty = ty;
+        if !lc.is_terminated {
+            incoming.push((else_value, lc.curr_label.clone()));
+            push_llvm_terminator(lc, format!("br label %{}", exit_label));
+        }
+    }
+
+    push_llvm_label(lc, &exit_label);
+
+    // If-expressions without else or with diverging branches don't produce values
+    let ty = if then_ty != TyKind::None { then_ty } else { else_ty };
+    if ty == TyKind::None || if_expr.else_block.is_none() {
+        return (String::new(), TyKind::None);
+    }
+
+    match incoming.len() {
+        0 => {
+            push_llvm_terminator(lc, String::from("unreachable"));
+            (String::from("undef"), ty)
+        }
+
+        1 => (incoming.pop().unwrap().0, ty),
+
+        _ => {
+            let temp = create_llvm_temp(lc);
+            let values: Vec<String> = incoming.iter()
+                .map(|(value, label)| format!("[ {}, %{} ]", value, label))
+                .collect();
+            push_llvm_insn(lc, format!("{} = phi {} {}", temp, to_llvm_type(&ty), values.join(", ")));
+            (temp, ty)
+        }
+    }
+}
+
+/***************************************************************************
+ * LLVM IR builder helpers
+ ***************************************************************************/
+
+fn push_llvm_insn(lc: &mut LlvmContext, insn: String) {
+    if lc.is_terminated {
+        // NOTE(alexander): code after return, break etc. is placed in an unreachable block
+        let label = create_llvm_label(lc, "dead");
+        push_llvm_label(lc, &label);
+    }
+    lc.body.push_str("    ");
+    lc.body.push_str(&insn);
+    lc.body.push('\n');
+}
+
+fn push_llvm_terminator(lc: &mut LlvmContext, insn: String) {
+    push_llvm_insn(lc, insn);
+    lc.is_terminated = true;
+}
+
+fn push_llvm_label(lc: &mut LlvmContext, label: &str) {
+    if !lc.is_terminated {
+        lc.body.push_str(&format!("    br label %{}\n", label));
+    }
+    lc.body.push_str(label);
+    lc.body.push_str(":\n");
+    lc.curr_label = label.to_string();
+    lc.is_terminated = false;
+}
+
+fn create_llvm_temp(lc: &mut LlvmContext) -> String {
+    let temp = format!("%t{}", lc.next_temp);
+    lc.next_temp += 1;
+    temp
+}
+
+fn create_llvm_label(lc: &mut LlvmContext, name: &str) -> String {
+    let label = format!("{}{}", name, lc.next_label);
+    lc.next_label += 1;
+    label
+}
+
+fn create_llvm_local(lc: &mut LlvmContext, symbol: Symbol, ty: &TyKind) -> String {
+    let ptr = format!("%{}.{}", resolve_symbol(symbol), lc.next_local);
+    lc.next_local += 1;
+    let _ = writeln!(lc.allocas, "    {} = alloca {}", ptr, to_llvm_type(ty));
+    if let Some(scope) = lc.scopes.last_mut() {
+        scope.insert(symbol, LlvmLocal { ptr: ptr.clone(), ty: ty.clone() });
+    }
+    ptr
+}
+
+fn find_llvm_local(lc: &LlvmContext, symbol: Symbol) -> LlvmLocal {
+    for scope in lc.scopes.iter().rev() {
+        if let Some(local) = scope.get(&symbol) {
+            return local.clone();
+        }
+    }
+    panic!("llvm: cannot find value `{}` in this scope", resolve_symbol(symbol));
+}
+
+fn to_llvm_type(ty: &TyKind) -> String {
+    match ty {
+        TyKind::Int => String::from("i32"),
+        TyKind::Int64 |
+        TyKind::UInt64 => String::from("i64"),
+        TyKind::Bool => String::from("i1"),
+        TyKind::Ref(_) => String::from("ptr"),
+        TyKind::Error |
+        TyKind::None => String::from("void"),
+    }
+}
+
+/**
+ * Types used when calling foreign functions, C booleans are zero extended.
+ */
+fn to_llvm_foreign_type(ty: &TyKind) -> String {
+    match ty {
+        TyKind::Bool => String::from("i1 zeroext"),
+        _ => to_llvm_type(ty),
+    }
+}
+
+fn to_llvm_foreign_return_type(ty: &TyKind) -> String {
+    match ty {
+        TyKind::Bool => String::from("zeroext i1"),
+        _ => to_llvm_type(ty),
+    }
+}
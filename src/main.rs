@@ -12,33 +12,56 @@ mod interp;
 mod typeck;
 mod borrowck;
 mod ir;
+mod ssa;
+mod opt;
 mod jit;
 mod x86;
 mod intrinsics;
-// mod llvm;
+mod native;
+mod ffi;
+mod llvm;
+mod c;
+mod wasm;
+mod elf;
+mod riscv;
+mod rvsim;
 
 use atty;
 use log::{info, error};
 use std::path::{Path, PathBuf};
 use std::{env, fs, time};
-use clap::{App, Arg, AppSettings};
+use clap::{App, Arg, AppSettings, SubCommand};
 use termcolor::ColorChoice;
 use crate::ast::{File, Item, intern_string};
 use crate::parser::{parse_file, parse_run_code};
-use crate::intrinsics::get_intrinsic_ast_items;
+use crate::intrinsics::{include_prelude, set_program_arguments};
 use crate::interp::{create_interp_context, interp_file, interp_entry_point};
 use crate::typeck::{create_type_context, type_check_file};
 use crate::borrowck::borrow_check_file;
 use crate::ir::{create_ir_builder, build_ir_from_ast};
-use crate::x86::{compile_ir_to_x86_machine_code};
+use crate::ssa::{build_ssa_from_ir, build_ir_from_ssa};
+use crate::opt::optimize_ir;
+use crate::x86::{compile_ir_to_x86_machine_code, compile_ir_to_x86_object, compile_ir_to_x86_executable, build_x86_assembly_file};
+use crate::x86::{check_x86_inline_asm, format_x86_register_allocation, X86_EXECUTABLE_FOREIGN_FUNCTIONS};
 use crate::jit::{allocate_jit_code, finalize_jit_code, execute_jit_code};
-// use crate::llvm::codegen_test;
+use crate::ffi::{load_library, flush_foreign_output};
+use crate::llvm::{create_llvm_context, build_llvm_ir_from_ast};
+use crate::c::{create_c_context, build_c_source_from_ast};
+use crate::wasm::{compile_ir_to_wasm_module, encode_wasm_module};
+use crate::elf::{build_elf_object, build_elf_executable};
+use crate::riscv::{compile_ir_to_riscv_program, RISCV_ADDR_SIZE};
+use crate::rvsim::{create_riscv_simulator, execute_riscv_simulator};
 
 struct Config {
     input: Option<String>,
     run: Option<String>,
+    args: Vec<String>,
+    link_libs: Vec<String>,
     backend: Backend,
+    emit: Emit,
+    output: Option<String>,
     print: Print,
+    opt_level: u32,
     color_choice: ColorChoice,
     profile: bool,
     type_checking: bool,
@@ -46,15 +69,32 @@ struct Config {
     compiletest: bool,
 }
 
+#[derive(PartialEq)]
 enum Backend {
     Interpreter,
     X86,
     LLVM,
+    C,
+    Wasm,
+    Riscv,
+}
+
+enum Emit {
+    LlvmIr,
+    C,
+    Wasm,
+    Wat,
+    Obj,
+    Asm,
+    Executable,
+    None,
 }
 
 enum Print {
     Ast,
     Ir,
+    Ssa,
+    RegAlloc,
     Assembly,
     MachineCode,
     None,
@@ -68,9 +108,14 @@ pub fn main() {
             // input: None,
             // run: Some(String::from("let x: i32 = if true { false } else { 20 };")),
             run: None,
+            args: Vec::new(),
+            link_libs: Vec::new(),
             backend: Backend::X86,
+            emit: Emit::None,
+            output: None,
             print: Print::Assembly,
             // print: Print::None,
+            opt_level: 0,
             color_choice: ColorChoice::Auto,
             profile: false,
             type_checking: true,
@@ -91,18 +136,47 @@ pub fn main() {
              .short("r")
              .value_name("CODE")
              .help("Runs the code immediately before executing main"))
+        .arg(Arg::with_name("ARGS")
+             .help("Arguments passed to the program, accessed using `arg_count()` and `arg_int(i)`")
+             .value_name("ARGS")
+             .multiple(true)
+             .last(true))
+        .arg(Arg::with_name("link-lib")
+             .long("link-lib")
+             .help("Shared library to resolve `extern \"C\"` functions from (libc is always searched)")
+             .value_name("PATH")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1))
         .arg(Arg::with_name("backend")
              .long("backend")
-             .help(r#"Compiler backend "interp", "x86", "llvm" (default is "interpreter")"#)
+             .help(r#"Compiler backend "interp", "x86", "llvm", "c", "wasm", "riscv" (default is "interpreter")"#)
              .value_name("BACKEND")
              .takes_value(true)
              .default_value("interp"))
+        .arg(Arg::with_name("emit")
+             .long("emit")
+             .help(r#"Write compiler output to file "llvm-ir", "c", "wasm", "wat", "obj", "asm", "none" (default is "none")"#)
+             .value_name("KIND")
+             .takes_value(true)
+             .default_value("none"))
+        .arg(Arg::with_name("output")
+             .short("o")
+             .value_name("FILE")
+             .help("Output file used by --emit (default is derived from the input file)")
+             .takes_value(true))
         .arg(Arg::with_name("print")
              .long("print")
-             .help(r#"Print info "ast", "ir", "asm", "machinecode", "none" (default is "none")"#)
+             .help(r#"Print info "ast", "ir", "ssa", "regalloc", "asm", "machinecode", "none" (default is "none")"#)
              .value_name("BACKEND")
              .takes_value(true)
              .default_value("none"))
+        .arg(Arg::with_name("opt-level")
+             .short("O")
+             .help(r#"Optimization level of the IR "0", "1", "2" (default is "0")"#)
+             .value_name("LEVEL")
+             .takes_value(true)
+             .default_value("0"))
         .arg(Arg::with_name("profile")
              .long("profile")
              .help("Timer for the entire execution of the program"))
@@ -128,6 +202,24 @@ pub fn main() {
              .long("Zcompiletest")
              .help("Runs the compiler in testing mode")
              .hidden(true))
+        .subcommand(SubCommand::with_name("build")
+                    .about("Compiles the input file to a statically linked executable that runs without libc")
+                    .arg(Arg::with_name("INPUT")
+                         .help("The input source file to compile")
+                         .value_name("FILE")
+                         .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .value_name("FILE")
+                         .help("Output executable (default is the input filename without extension)")
+                         .takes_value(true))
+                    .arg(Arg::with_name("opt-level")
+                         .short("O")
+                         .help(r#"Optimization level of the IR "0", "1", "2" (default is "0")"#)
+                         .value_name("LEVEL")
+                         .takes_value(true)
+                         .default_value("0")))
         .get_matches();
 
     let mut skip_compilation = false;
@@ -159,36 +251,119 @@ pub fn main() {
         "interp" => Backend::Interpreter,
         "x86" => Backend::X86,
         "llvm" => Backend::LLVM,
+        "c" => Backend::C,
+        "wasm" => Backend::Wasm,
+        "riscv" => Backend::Riscv,
         _ => {
-            println!("\n--backend expectes one of these values \"interp\", \"x86\", \"llvm\"\n");
+            println!("\n--backend expectes one of these values \"interp\", \"x86\", \"llvm\", \"c\", \"wasm\", \"riscv\"\n");
             skip_compilation = true;
             Backend::Interpreter
         }
     };
 
+    let (emit, emit_backend) = match matches.value_of("emit").unwrap().to_lowercase().as_str() {
+        "llvm-ir" => (Emit::LlvmIr, Some(("llvm", Backend::LLVM))),
+        "c" => (Emit::C, Some(("c", Backend::C))),
+        "wasm" => (Emit::Wasm, Some(("wasm", Backend::Wasm))),
+        "wat" => (Emit::Wat, Some(("wasm", Backend::Wasm))),
+        "obj" => (Emit::Obj, Some(("x86", Backend::X86))),
+        "asm" => (Emit::Asm, Some(("x86", Backend::X86))),
+        "none" => (Emit::None, None),
+        _ => {
+            println!("\n--emit expectes one of these values \"llvm-ir\", \"c\", \"wasm\", \"wat\", \"obj\", \"asm\", \"none\"\n");
+            skip_compilation = true;
+            (Emit::None, None)
+        }
+    };
+
+    // NOTE(alexander): --emit selects the backend producing the output, unless another backend was given explicitly
+    let mut backend = backend;
+    if let Some((name, emit_backend)) = emit_backend {
+        if matches.occurrences_of("backend") == 0 {
+            backend = emit_backend;
+        } else if backend != emit_backend {
+            println!("\n--emit {} is only supported by --backend {}\n", matches.value_of("emit").unwrap(), name);
+            skip_compilation = true;
+        }
+    }
+
     let print = match matches.value_of("print").unwrap().to_lowercase().as_str() {
         "ast" => Print::Ast,
         "ir" => Print::Ir,
+        "ssa" => Print::Ssa,
+        "regalloc" => Print::RegAlloc,
         "asm" => Print::Assembly,
         "machinecode" => Print::MachineCode,
         "none" => Print::None,
         _ => {
-            println!("\n--print expectes one of these values \"ast\", \"ir\", \"asm\", \"machinecode\", \"none\"\n");
+            println!("\n--print expectes one of these values \"ast\", \"ir\", \"ssa\", \"regalloc\", \"asm\", \"machinecode\", \"none\"\n");
             skip_compilation = true;
             Print::None
         }
     };
     
+    let opt_level = match matches.value_of("opt-level").unwrap() {
+        "0" => 0,
+        "1" => 1,
+        "2" => 2,
+        _ => {
+            println!("\n-O expectes one of these values \"0\", \"1\", \"2\"\n");
+            skip_compilation = true;
+            0
+        }
+    };
+
+    // NOTE(alexander): build always compiles using the x86 backend, only the input, output and -O is used
+    if let Some(build) = matches.subcommand_matches("build") {
+        let opt_level = match build.value_of("opt-level").unwrap() {
+            "0" => 0,
+            "1" => 1,
+            "2" => 2,
+            _ => {
+                println!("\n-O expectes one of these values \"0\", \"1\", \"2\"\n");
+                std::process::exit(1);
+            }
+        };
+
+        let config = Config {
+            input: build.value_of("INPUT").map(|s| s.to_string()),
+            run: None,
+            args: Vec::new(),
+            link_libs: Vec::new(),
+            backend: Backend::X86,
+            emit: Emit::Executable,
+            output: build.value_of("output").map(|s| s.to_string()),
+            print: Print::None,
+            opt_level,
+            color_choice,
+            profile: false,
+            type_checking: true,
+            borrow_checking: true,
+            compiletest: false,
+        };
+
+        // NOTE(alexander): build scripts check the exit code to know if the executable was built
+        if !run_compiler(&config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if !skip_compilation {
         let config = Config {
             input: matches.value_of("INPUT").map(|s| s.to_string()),
             run: matches.value_of("run").map(|s| s.to_string()),
+            args: matches.values_of("ARGS").map(|v| v.map(|s| s.to_string()).collect()).unwrap_or_default(),
+            link_libs: matches.values_of("link-lib").map(|v| v.map(|s| s.to_string()).collect()).unwrap_or_default(),
             profile: matches.is_present("profile"),
             type_checking: !matches.is_present("Znotypecheck"),
             borrow_checking: !matches.is_present("Znoborrowcheck"),
             compiletest: matches.is_present("Zcompiletest"),
+            output: matches.value_of("output").map(|s| s.to_string()),
             backend,
+            emit,
             print,
+            opt_level,
             color_choice,
         };
 
@@ -196,12 +371,26 @@ pub fn main() {
     }
 }
 
-fn run_compiler(config: &Config) {
+/**
+ * Compiles and runs the input given by the config, returns false if any errors were reported.
+ */
+fn run_compiler(config: &Config) -> bool {
     info!("setting up the compiler");
 
     error::COLOR_CHOICE.with(|color_choice| {
         *color_choice.borrow_mut() = config.color_choice;
     });
+
+    // Make the program arguments available to both the interpreter and jitted code
+    set_program_arguments(config.args.clone());
+
+    // Load shared libraries used to resolve foreign functions
+    for path in &config.link_libs {
+        if let Err(err) = load_library(path) {
+            eprintln!("error: {}", err);
+            return false;
+        }
+    }
     
     let mut _working_dir = env::current_dir().unwrap_or(PathBuf::new());
 
@@ -210,11 +399,12 @@ fn run_compiler(config: &Config) {
         let filename = "<run>";
         let mut ast = parse_run_code(source.to_string(), String::from(filename));
 
-        // Include compiler intrinsics in the parsed ast file    
-        let intrinsic_mod = get_intrinsic_ast_items();
-        ast.items.push(intrinsic_mod);
+        // Include the prelude in the parsed ast file
+        include_prelude(&mut ast);
 
-        run_parsed_code(ast, &config);
+        if !run_parsed_code(ast, config) {
+            return false;
+        }
         true
     } else {
         false
@@ -224,15 +414,15 @@ fn run_compiler(config: &Config) {
     let mut ast = if let Some(input) = &config.input {
         let path = Path::new(input);
         let source;
-        match fs::read_to_string(&input) {
+        match fs::read_to_string(input) {
             Ok(string) => source = string,
             Err(err) => {
                 eprintln!("error: {}", err);
-                return;
+                return false;
             }
         };
         
-        let mut filename = String::clone(&input);
+        let mut filename = String::clone(input);
         if path.is_absolute() {
             _working_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or(_working_dir);
             filename = String::from(path.file_name().map(|s| s.to_str().unwrap()).unwrap());
@@ -244,37 +434,45 @@ fn run_compiler(config: &Config) {
         if !has_run_code {
             eprintln!("\nerror: no input file or code");
         }
-        return;
+        return has_run_code;
     };
 
-    // Include compiler intrinsics in the parsed ast file    
-    let intrinsic_mod = get_intrinsic_ast_items();
-    ast.items.push(intrinsic_mod);
+    // Include the prelude in the parsed ast file
+    include_prelude(&mut ast);
 
     if ast.error_count > 0 {
         error!("parse errors reported {} errors, stopping compilation", ast.error_count);
         eprintln!("\nerror: aborting due to previous error");
-        return;
+        return false;
     }
 
     if let Print::Ast = config.print {
         print!("\n\n{:#?}", ast.items);
     }
 
-    run_parsed_code(ast, config);
+    run_parsed_code(ast, config)
 }
 
 
-fn run_parsed_code(ast: File, config: &Config) {
+fn run_parsed_code(ast: File, config: &Config) -> bool {
 
     // Type check the current file
     if config.type_checking {
         let mut tc = create_type_context();
+        if let Emit::Executable = config.emit {
+            tc.target.foreign_functions = Some(X86_EXECUTABLE_FOREIGN_FUNCTIONS);
+        }
+        if let Backend::X86 = config.backend {
+            tc.target.inline_asm = Some(check_x86_inline_asm);
+        }
+        if let Backend::Riscv = config.backend {
+            tc.target.int64_unsupported = Some("RV32IM");
+        }
         type_check_file(&mut tc, &ast);
         if tc.error_count > 0 {
             error!("type checker reported {} errors, stopping compilation", tc.error_count);
             eprintln!("\nerror: aborting due to previous error");
-            return;
+            return false;
         }
     }
 
@@ -284,7 +482,7 @@ fn run_parsed_code(ast: File, config: &Config) {
         if borrow_error_count > 0 {
             error!("borrow checker reported {} errors, stopping compilation", borrow_error_count);
             eprintln!("\nerror: aborting due to previous error");
-            return;
+            return false;
         }
     }
 
@@ -302,7 +500,7 @@ fn run_parsed_code(ast: File, config: &Config) {
 
     if !has_main {
         eprintln!("\nerror: no main function was found");
-        return;
+        return false;
     }
 
     match config.backend {
@@ -313,11 +511,12 @@ fn run_parsed_code(ast: File, config: &Config) {
             interp_file(&mut ic, &ast);
             let code = interp_entry_point(&mut ic);
             let execution_time = now.elapsed().as_secs_f32();
+            flush_foreign_output();
             println!("\nInterpreter exited with code {}", code);
             if config.profile {
                 println!("Interpreter execution time: {} seconds", execution_time)
             }
-            return;
+            return true;
         }
 
         Backend::X86 => {
@@ -330,12 +529,57 @@ fn run_parsed_code(ast: File, config: &Config) {
                 print!("\n\n{}", ir_builder);
             }
 
+            // Convert to SSA form and optimize it, then convert back before lowering to x86
+            let mut ssa_functions = build_ssa_from_ir(&mut ir_builder);
+            optimize_ir(&mut ir_builder, &mut ssa_functions, config.opt_level, matches!(config.print, Print::Ir));
+            if let Print::Ssa = config.print {
+                println!("\n");
+                for func in &ssa_functions {
+                    println!("{}", func);
+                }
+            }
+            build_ir_from_ssa(&mut ir_builder, ssa_functions);
+            if let (Print::Ir, 1..=2) = (&config.print, config.opt_level) {
+                print!("\n; after out-of-ssa\n{}", ir_builder);
+            }
+
             // The resulting intermediate representation
             let ir_instructions = ir_builder.instructions;
             let ir_functions = ir_builder.functions;
 
+            if let Print::RegAlloc = config.print {
+                print!("\n\n{}", format_x86_register_allocation(&ir_instructions, &ir_functions));
+            }
+
+            // NOTE(alexander): the peephole optimizer runs on the x86 instructions when optimizing
+            let peephole = config.opt_level > 0;
+
+            // NOTE(alexander): object files are linked ahead of time instead of being jitted
+            if let Emit::Obj = config.emit {
+                let object = compile_ir_to_x86_object(&ir_instructions, &ir_functions, peephole);
+                if let Print::Assembly = config.print {
+                    println!("\n\n{}", object.assembly);
+                }
+
+                let filename = config.input.as_deref().unwrap_or("main.sq");
+                return write_output_file(config, "o", build_elf_object(filename, &object)).is_some();
+            }
+
+            if let Emit::Executable = config.emit {
+                let object = compile_ir_to_x86_executable(&ir_instructions, &ir_functions, peephole);
+                if let Print::Assembly = config.print {
+                    println!("\n\n{}", object.assembly);
+                }
+
+                let filename = config.input.as_deref().unwrap_or("main.sq");
+                return match write_output_file(config, "", build_elf_executable(filename, &object)) {
+                    Some(path) => set_executable_permissions(&path),
+                    None => false,
+                };
+            }
+
             // Generate code to jit
-            let (machine_code, assembly) = compile_ir_to_x86_machine_code(ir_instructions, ir_functions);
+            let (machine_code, assembly) = compile_ir_to_x86_machine_code(&ir_instructions, &ir_functions, peephole);
 
             if let Print::Assembly = config.print {
                 println!("\n\n{}", assembly);
@@ -346,10 +590,22 @@ fn run_parsed_code(ast: File, config: &Config) {
                 for (i, byte) in machine_code.iter().enumerate() {
                     print!("{:02x} ", byte);
                     if i % 16 == 15 {
-                        println!("");
+                        println!();
                     }
                 }
                 println!("\n\nSize of code is {} bytes", machine_code.len());
+                if peephole {
+                    let (unoptimized_code, _) = compile_ir_to_x86_machine_code(&ir_instructions, &ir_functions, false);
+                    let saved = unoptimized_code.len() as f64 - machine_code.len() as f64;
+                    println!("Peephole optimizer reduced the size from {} to {} bytes ({:.1}% smaller)",
+                             unoptimized_code.len(), machine_code.len(), 100.0 * saved / unoptimized_code.len() as f64);
+                }
+            }
+
+            // NOTE(alexander): the assembly is the code that would be jitted, foreign functions are called by address
+            if let Emit::Asm = config.emit {
+                let filename = config.input.as_deref().unwrap_or("main.sq");
+                return write_output_file(config, "s", build_x86_assembly_file(filename, &assembly)).is_some();
             }
 
             let jit_code = allocate_jit_code(machine_code.len());
@@ -365,6 +621,7 @@ fn run_parsed_code(ast: File, config: &Config) {
             let now = time::Instant::now();
             let ret = execute_jit_code(&jit_code);
             let execution_time = now.elapsed().as_secs_f32();
+            flush_foreign_output();
             println!("\nProgram exited with code {}", ret);
             if config.profile {
                 println!("Program execution time: {} seconds", execution_time)
@@ -372,7 +629,327 @@ fn run_parsed_code(ast: File, config: &Config) {
         }
 
         Backend::LLVM => {
-            unimplemented!()
+            let mut llvm_context = create_llvm_context();
+            let module = build_llvm_ir_from_ast(&mut llvm_context, &ast);
+            if let Emit::LlvmIr = config.emit {
+                write_output_file(config, "ll", module);
+            } else {
+                print!("{}", module);
+            }
+        }
+
+        Backend::Wasm => {
+            let mut ir_builder = create_ir_builder();
+            build_ir_from_ast(&mut ir_builder, &ast);
+            if let Print::Ir = config.print {
+                print!("\n\n{}", ir_builder);
+            }
+
+            // NOTE(alexander): wasm can use the intermediate representation directly, only convert it when optimizing
+            // or when the SSA form is printed
+            if config.opt_level > 0 || matches!(config.print, Print::Ssa) {
+                let mut ssa_functions = build_ssa_from_ir(&mut ir_builder);
+                optimize_ir(&mut ir_builder, &mut ssa_functions, config.opt_level, matches!(config.print, Print::Ir));
+                if let Print::Ssa = config.print {
+                    println!("\n");
+                    for func in &ssa_functions {
+                        println!("{}", func);
+                    }
+                }
+                build_ir_from_ssa(&mut ir_builder, ssa_functions);
+                if let Print::Ir = config.print {
+                    print!("\n; after out-of-ssa\n{}", ir_builder);
+                }
+            }
+
+            let module = compile_ir_to_wasm_module(ir_builder.instructions, ir_builder.functions);
+            if let Print::Assembly = config.print {
+                println!("\n\n{}", module);
+            }
+
+            // NOTE(alexander): the binary module is written unless the text format is requested
+            if let Emit::Wat = config.emit {
+                write_output_file(config, "wat", module.to_string());
+            } else {
+                write_output_file(config, "wasm", encode_wasm_module(&module));
+            }
+        }
+
+        Backend::Riscv => {
+            // NOTE(alexander): the address size is the only target parameter of the intermediate representation
+            let mut ir_builder = create_ir_builder();
+            ir_builder.addr_size = RISCV_ADDR_SIZE;
+            build_ir_from_ast(&mut ir_builder, &ast);
+            if let Print::Ir = config.print {
+                print!("\n\n{}", ir_builder);
+            }
+
+            let mut ssa_functions = build_ssa_from_ir(&mut ir_builder);
+            optimize_ir(&mut ir_builder, &mut ssa_functions, config.opt_level, matches!(config.print, Print::Ir));
+            if let Print::Ssa = config.print {
+                println!("\n");
+                for func in &ssa_functions {
+                    println!("{}", func);
+                }
+            }
+            build_ir_from_ssa(&mut ir_builder, ssa_functions);
+            if let (Print::Ir, 1..=2) = (&config.print, config.opt_level) {
+                print!("\n; after out-of-ssa\n{}", ir_builder);
+            }
+
+            let program = compile_ir_to_riscv_program(&ir_builder.instructions, &ir_builder.functions);
+            if let Print::Assembly = config.print {
+                println!("\n\n{}", program.assembly);
+            }
+
+            if let Print::MachineCode = config.print {
+                println!("\n");
+                for (i, byte) in program.machine_code.iter().enumerate() {
+                    print!("{:02x} ", byte);
+                    if i % 16 == 15 {
+                        println!();
+                    }
+                }
+                println!("\n\nSize of code is {} bytes", program.machine_code.len());
+            }
+
+            let mut sim = create_riscv_simulator(&program);
+            let now = time::Instant::now();
+            let result = execute_riscv_simulator(&mut sim);
+            let execution_time = now.elapsed().as_secs_f32();
+            flush_foreign_output();
+            match result {
+                Ok(code) => println!("\nProgram exited with code {}", code),
+                Err(msg) => eprintln!("\nerror: rvsim: {}", msg),
+            }
+            if config.profile {
+                println!("Program execution time: {} seconds ({} instructions)", execution_time, sim.instructions_executed)
+            }
+        }
+
+        Backend::C => {
+            let mut c_context = create_c_context();
+            let source = build_c_source_from_ast(&mut c_context, &ast);
+            if let Emit::C = config.emit {
+                write_output_file(config, "c", source);
+            } else {
+                print!("{}", source);
+            }
+        }
+    }
+    true
+}
+
+/**
+ * Writes the emitted output to the file given by `-o`,
+ * otherwise the input filename with the given extension is used.
+ */
+fn write_output_file(config: &Config, extension: &str, contents: impl AsRef<[u8]>) -> Option<PathBuf> {
+    let path = match &config.output {
+        Some(output) => PathBuf::from(output),
+        None => match &config.input {
+            Some(input) => Path::new(input).with_extension(extension),
+            None => PathBuf::from(format!("out.{}", extension)),
+        }
+    };
+
+    match fs::write(&path, contents) {
+        Ok(_) => {
+            println!("wrote {}", path.display());
+            Some(path)
+        }
+
+        Err(err) => {
+            eprintln!("error: could not write `{}`: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_executable_permissions(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    if let Err(err) = fs::set_permissions(path, fs::Permissions::from_mode(0o755)) {
+        eprintln!("error: could not make `{}` executable: {}", path.display(), err);
+        return false;
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn set_executable_permissions(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Programs whose `main` returns the result, so that the backends that run in process
+     * can be compared without capturing the output.
+     */
+    const PROGRAMS: [(&str, &str, i32); 4] = [
+        ("factorial", "
+            fn main() -> i32 {
+                factorial(12)
+            }
+
+            fn factorial(n: i32) -> i32 {
+                if n <= 1 {
+                    1
+                } else {
+                    n * factorial(n - 1)
+                }
+            }", 479001600),
+
+        ("implicit tail if", "
+            fn main() -> i32 {
+                pick(5) * 1000 + pick(-5) * 100 + sign(-7) * 10 + clamp(80)
+            }
+
+            fn pick(n: i32) -> i32 { if n > 0 { 1 } else { 2 } }
+
+            fn sign(n: i32) -> i32 {
+                if n > 0 {
+                    1
+                } else {
+                    if n < 0 {
+                        -1
+                    } else {
+                        0
+                    }
+                }
+            }
+
+            fn clamp(n: i32) -> i32 {
+                let m: i32 = n * 2;
+                if m > 100 {
+                    100
+                } else {
+                    m
+                }
+            }", 1290),
+
+        ("implicit tail if in main", "
+            fn main() -> i32 {
+                let n: i32 = 3;
+                if n * n > 8 {
+                    n + 40
+                } else {
+                    n
+                }
+            }", 43),
+
+        ("self tail call in implicit tail if", "
+            fn main() -> i32 {
+                sum_to(1000000, 0) % 1000
+            }
+
+            fn sum_to(n: i32, acc: i32) -> i32 {
+                if n == 0 {
+                    acc
+                } else {
+                    sum_to(n - 1, acc + n)
+                }
+            }", 664),
+    ];
+
+    fn parse_program(name: &str, source: &str) -> File {
+        let mut ast = parse_file(source.to_string(), format!("{}.sq", name));
+        include_prelude(&mut ast);
+        assert_eq!(ast.error_count, 0, "{}", name);
+        ast
+    }
+
+    fn build_optimized_ir<'a>(ast: &'a File, addr_size: isize, opt_level: u32) -> ir::IrBuilder<'a> {
+        let mut ir_builder = create_ir_builder();
+        ir_builder.addr_size = addr_size;
+        build_ir_from_ast(&mut ir_builder, ast);
+        let mut ssa_functions = build_ssa_from_ir(&mut ir_builder);
+        optimize_ir(&mut ir_builder, &mut ssa_functions, opt_level, false);
+        build_ir_from_ssa(&mut ir_builder, ssa_functions);
+        ir_builder
+    }
+
+    #[test]
+    fn interpret_programs() {
+        for (name, source, expected) in &PROGRAMS {
+            let ast = parse_program(name, source);
+            let mut ic = create_interp_context();
+            interp_file(&mut ic, &ast);
+            assert_eq!(interp_entry_point(&mut ic), *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn simulate_programs_on_riscv() {
+        for (name, source, expected) in &PROGRAMS {
+            let ast = parse_program(name, source);
+            for opt_level in 0..=2 {
+                let ir_builder = build_optimized_ir(&ast, RISCV_ADDR_SIZE, opt_level);
+                let program = compile_ir_to_riscv_program(&ir_builder.instructions, &ir_builder.functions);
+                let mut sim = create_riscv_simulator(&program);
+                assert_eq!(execute_riscv_simulator(&mut sim), Ok(*expected), "{} -O{}", name, opt_level);
+            }
+        }
+    }
+
+    #[test]
+    fn report_division_errors_on_riscv() {
+        let errors = [
+            ("divide by zero", "fn main() -> i32 { div(7, 0) }", "attempt to divide by zero"),
+            ("divide with overflow", "fn main() -> i32 { div(-2147483647 - 1, -1) }", "attempt to divide with overflow"),
+            ("remainder with overflow", "fn main() -> i32 { rem(-2147483647 - 1, -1) }",
+             "attempt to calculate the remainder with overflow"),
+        ];
+
+        for (name, main, expected) in &errors {
+            let source = format!("{}
+                fn div(a: i32, b: i32) -> i32 {{ a / b }}
+                fn rem(a: i32, b: i32) -> i32 {{ a % b }}", main);
+            let ast = parse_program(name, &source);
+            for opt_level in 0..=2 {
+                let ir_builder = build_optimized_ir(&ast, RISCV_ADDR_SIZE, opt_level);
+                let program = compile_ir_to_riscv_program(&ir_builder.instructions, &ir_builder.functions);
+                let mut sim = create_riscv_simulator(&program);
+                assert_eq!(execute_riscv_simulator(&mut sim), Err(expected.to_string()), "{} -O{}", name, opt_level);
+            }
+        }
+    }
+
+    #[test]
+    fn lower_programs_to_structured_wasm() {
+        for (name, source, _) in &PROGRAMS {
+            let ast = parse_program(name, source);
+            for opt_level in 0..=2 {
+                let ir_builder = build_optimized_ir(&ast, std::mem::size_of::<usize>() as isize, opt_level);
+                let module = compile_ir_to_wasm_module(ir_builder.instructions, ir_builder.functions);
+                for func in &module.functions {
+                    let uses_dispatcher = func.body.iter().any(|insn| matches!(insn, wasm::WasmInstruction::BrTable(..)));
+                    assert!(!uses_dispatcher, "{} -O{}: `{}` uses the dispatcher", name, opt_level, func.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn execute_programs_on_x86() {
+        for (name, source, expected) in &PROGRAMS {
+            let ast = parse_program(name, source);
+            for opt_level in 0..=2 {
+                let ir_builder = build_optimized_ir(&ast, std::mem::size_of::<usize>() as isize, opt_level);
+                let (machine_code, _) = compile_ir_to_x86_machine_code(&ir_builder.instructions,
+                                                                       &ir_builder.functions,
+                                                                       opt_level > 0);
+                let jit_code = allocate_jit_code(machine_code.len());
+                unsafe {
+                    std::ptr::copy_nonoverlapping(machine_code.as_ptr(), jit_code.addr, machine_code.len());
+                }
+                finalize_jit_code(&jit_code);
+                assert_eq!(execute_jit_code(&jit_code), *expected, "{} -O{}", name, opt_level);
+            }
         }
     }
 }
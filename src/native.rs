@@ -0,0 +1,142 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use crate::ast::*;
+use crate::interp::{InterpContext, Value};
+use crate::intrinsics::create_intrinsic_functions;
+
+/**
+ * Interpreter implementation of a native function, receives the
+ * already type checked argument values and returns the resulting value.
+ */
+pub type NativeInterpFn = fn(ic: &mut InterpContext, args: &[Value]) -> Result<Value, String>;
+
+/**
+ * Native function is a function implemented by the host, it is declared once
+ * in the registry and used by the type checker, interpreter and the x86 backend.
+ */
+#[derive(Debug, Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub inputs: Vec<(String, TyKind)>,
+    pub output: TyKind,
+    /// Interpreter implementation, if none is given the interpreter
+    /// calls the function through `address` using the C calling convention.
+    pub interp: Option<NativeInterpFn>,
+    /// Address of the function using the C calling convention, used by jitted code.
+    pub address: Option<usize>,
+}
+
+/**
+ * Registry of all the native functions available to programs.
+ */
+struct NativeRegistry {
+    functions: Vec<NativeFunction>,
+    indices: HashMap<String, usize>,
+}
+
+thread_local!(static NATIVE_REGISTRY: RefCell<NativeRegistry> = RefCell::new(create_native_registry()));
+
+fn create_native_registry() -> NativeRegistry {
+    let mut registry = NativeRegistry {
+        functions: Vec::new(),
+        indices: HashMap::new(),
+    };
+
+    for func in create_intrinsic_functions() {
+        insert_native_function(&mut registry, func);
+    }
+
+    registry
+}
+
+fn insert_native_function(registry: &mut NativeRegistry, func: NativeFunction) {
+    match registry.indices.get(&func.name) {
+        Some(&index) => registry.functions[index] = func,
+        None => {
+            registry.indices.insert(func.name.clone(), registry.functions.len());
+            registry.functions.push(func);
+        }
+    }
+}
+
+pub fn create_native_function(
+    name: &str,
+    inputs: &[(&str, TyKind)],
+    output: TyKind,
+    interp: Option<NativeInterpFn>,
+    address: Option<usize>,
+) -> NativeFunction {
+    NativeFunction {
+        name: name.to_string(),
+        inputs: inputs.iter().map(|(arg, ty)| (arg.to_string(), ty.clone())).collect(),
+        output,
+        interp,
+        address,
+    }
+}
+
+/**
+ * Registers a host function that programs can call, this has to be done
+ * before the program is parsed. Registering a function with the same name
+ * as an existing native function replaces it.
+ */
+pub fn register_native_function(func: NativeFunction) {
+    NATIVE_REGISTRY.with(|registry| insert_native_function(&mut registry.borrow_mut(), func));
+}
+
+/**
+ * Finds the native function with the given name.
+ */
+pub fn lookup_native_function(name: &str) -> Option<NativeFunction> {
+    NATIVE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        registry.indices.get(name).map(|&index| registry.functions[index].clone())
+    })
+}
+
+/**
+ * Creates the foreign module declaring the registered native functions that
+ * are not already declared, e.g. host functions not included in the prelude.
+ */
+pub fn get_native_ast_items(declared: &HashSet<Symbol>) -> Item {
+    NATIVE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        let undeclared = registry.functions.iter().filter(|func| !declared.contains(&intern_string(&func.name)));
+        let items = undeclared.map(|func| {
+            Item::ForeignFn(
+                ForeignFnItem {
+                    ident: ExprIdent {
+                        sym: intern_string(&func.name),
+                        span: Span::new(),
+                    },
+                    decl: FnDecl {
+                        inputs: func.inputs.iter().map(|(arg, ty)| {
+                            Argument {
+                                mutable: false,
+                                ident: ExprIdent {
+                                    sym: intern_string(arg),
+                                    span: Span::new(),
+                                },
+                                ty: Ty::new(ty.clone(), Span::new()),
+                                span: Span::new(),
+                            }
+                        }).collect(),
+                        output: Ty::new(func.output.clone(), Span::new()),
+                        span: Span::new(),
+                    },
+                    span: Span::new(),
+                }
+            )
+        }).collect();
+
+        Item::ForeignMod(
+            ForeignModItem {
+                abi: Some("intrinsic".to_string()),
+                items,
+                span: Span::new(),
+            }
+        )
+    })
+}
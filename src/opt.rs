@@ -0,0 +1,1359 @@
+use std::collections::{HashMap, HashSet};
+use crate::ast::{Symbol, intern_string};
+use crate::ir::*;
+use crate::ssa::*;
+
+/***************************************************************************
+ * IR Optimizations:
+ * - Passes are run on functions in SSA form, every variable has exactly one
+ *   definition so its value can be substituted directly into its uses,
+ *   except for referenced variables that are stored in memory.
+ * - -O1 runs every pass once and -O2 repeats the passes until no pass
+ *   makes any further changes.
+ * - Inlining is done first, -O1 only inlines functions marked `#[inline]`
+ *   while -O2 also inlines small functions.
+ ***************************************************************************/
+
+/**
+ * Optimization pass over a single function, returns true if the function was changed.
+ */
+pub struct IrPass {
+    pub name: &'static str,
+    pub run: fn(&mut IrBuilder, &mut IrSsaFunction) -> bool,
+}
+
+pub const IR_PASSES: [IrPass; 7] = [
+    IrPass { name: "constant-propagation",       run: propagate_ir_constants },
+    IrPass { name: "copy-propagation",           run: propagate_ir_copies },
+    IrPass { name: "branch-simplification",      run: simplify_ir_branches },
+    IrPass { name: "unreachable-block-removal",  run: remove_ir_unreachable_blocks },
+    IrPass { name: "loop-invariant-code-motion", run: hoist_ir_loop_invariants },
+    IrPass { name: "strength-reduction",         run: reduce_ir_loop_strength },
+    IrPass { name: "dead-code-elimination",      run: eliminate_ir_dead_code },
+];
+
+/**
+ * Upper limit of the number of times the passes are repeated in -O2.
+ */
+const IR_MAX_ITERATIONS: usize = 16;
+
+/**
+ * Runs the optimization passes for the given optimization level,
+ * if print is set then the functions are printed after each pass that changed them.
+ */
+pub fn optimize_ir<'a>(ib: &mut IrBuilder<'a>, functions: &mut [IrSsaFunction], opt_level: u32, print: bool) {
+    if opt_level == 0 {
+        return;
+    }
+
+    let inlined = inline_ir_functions(ib, functions, opt_level);
+    if print {
+        print!("\n; after inlining\n");
+        for index in &inlined {
+            print!("\n{}", functions[*index]);
+        }
+        if inlined.is_empty() {
+            println!("; no changes");
+        }
+    }
+
+    let iterations = if opt_level >= 2 { IR_MAX_ITERATIONS } else { 1 };
+    for iteration in 0..iterations {
+        let mut changed = false;
+        for pass in &IR_PASSES {
+            if print {
+                print!("\n; after {} (iteration {})\n", pass.name, iteration + 1);
+            }
+
+            let mut pass_changed = false;
+            for func in functions.iter_mut() {
+                if (pass.run)(ib, func) {
+                    pass_changed = true;
+                    if print {
+                        print!("\n{}", func);
+                    }
+                }
+            }
+
+            if print && !pass_changed {
+                println!("; no changes");
+            }
+            changed |= pass_changed;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+/***************************************************************************
+ * Passes
+ ***************************************************************************/
+
+/**
+ * Evaluates instructions with constant operands and replaces uses of the result by the constant.
+ * Phi instructions where every incoming value is the same constant are also replaced, and
+ * selects with a constant condition are replaced by a copy of the selected value.
+ */
+pub fn propagate_ir_constants<'a>(_ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut values: HashMap<IrIdent, IrOperand> = HashMap::new();
+    let mut changed = false;
+    loop {
+        let mut found = false;
+        for block in &mut func.cfg.blocks {
+            for insn in &mut block.instructions {
+                changed |= substitute_ir_operands(insn, &values);
+
+                if let (IrOpcode::Select, Some(src)) = (&insn.opcode, simplified_ir_select(insn)) {
+                    *insn = IrInstruction {
+                        opcode: IrOpcode::Copy,
+                        op1: insn.op1,
+                        op2: src,
+                        ty: insn.ty,
+                        span: insn.span,
+                        ..Default::default()
+                    };
+                    changed = true;
+                }
+
+                let dst = match ir_defined_variable(insn) {
+                    Some(dst) if !func.referenced.contains(&dst) && !values.contains_key(&dst) => dst,
+                    _ => continue,
+                };
+
+                let value = match insn.opcode {
+                    IrOpcode::Copy => match insn.op2 {
+                        IrOperand::Value(val) => Some(val),
+                        _ => None,
+                    },
+
+                    IrOpcode::Clear => match insn.ty {
+                        IrType::I8 => Some(IrValue::Bool(false)),
+                        IrType::I32 => Some(IrValue::I32(0)),
+                        IrType::I64 => Some(IrValue::I64(0)),
+                        IrType::U64 => Some(IrValue::U64(0)),
+                        _ => None,
+                    },
+
+                    IrOpcode::Phi => match unique_ir_phi_value(insn, dst) {
+                        Some(IrOperand::Value(val)) => Some(val),
+                        _ => None,
+                    },
+
+                    _ => match (insn.op2, insn.op3) {
+                        (IrOperand::Value(lhs), IrOperand::Value(rhs)) => fold_ir_binary(&insn.opcode, lhs, rhs),
+                        _ => None,
+                    },
+                };
+
+                if let Some(val) = value {
+                    values.insert(dst, IrOperand::Value(val));
+                    if insn.opcode != IrOpcode::Copy && insn.opcode != IrOpcode::Phi {
+                        *insn = IrInstruction {
+                            opcode: IrOpcode::Copy,
+                            op1: IrOperand::Ident(dst),
+                            op2: IrOperand::Value(val),
+                            ty: ir_defined_type(insn),
+                            span: insn.span,
+                            ..Default::default()
+                        };
+                    }
+                    found = true;
+                }
+            }
+        }
+
+        if !found {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+/**
+ * Replaces uses of variables that are copies of other variables by the original variable.
+ */
+pub fn propagate_ir_copies<'a>(_ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut values: HashMap<IrIdent, IrOperand> = HashMap::new();
+    let mut changed = false;
+    loop {
+        let mut found = false;
+        for block in &mut func.cfg.blocks {
+            for insn in &mut block.instructions {
+                changed |= substitute_ir_operands(insn, &values);
+
+                let dst = match ir_defined_variable(insn) {
+                    Some(dst) if !func.referenced.contains(&dst) && !values.contains_key(&dst) => dst,
+                    _ => continue,
+                };
+
+                let value = match insn.opcode {
+                    IrOpcode::Copy => Some(insn.op2),
+                    IrOpcode::Phi => unique_ir_phi_value(insn, dst),
+                    _ => None,
+                };
+
+                match value {
+                    Some(IrOperand::Ident(src)) if !func.referenced.contains(&src) && src != dst => {
+                        values.insert(dst, IrOperand::Ident(src));
+                        found = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if !found {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+/**
+ * Conditional jumps with constant operands are replaced by a jump or removed,
+ * jumps to the block that directly follows are also removed.
+ */
+pub fn simplify_ir_branches<'a>(_ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut changed = false;
+    let num_blocks = func.cfg.blocks.len();
+    for b in 0..num_blocks - 1 {
+        let next_label = func.cfg.blocks[b + 1].label.map(IrOperand::Ident);
+        let block = &mut func.cfg.blocks[b];
+        let insn = match block.instructions.last_mut() {
+            Some(insn) => insn,
+            None => continue,
+        };
+
+        if insn.opcode == IrOpcode::Jump {
+            if Some(insn.op1) == next_label {
+                block.instructions.pop();
+                changed = true;
+            }
+            continue;
+        }
+
+        if !is_ir_conditional_jump(&insn.opcode) {
+            continue;
+        }
+
+        let condition = match (insn.op1, insn.op2) {
+            (IrOperand::Value(lhs), IrOperand::Value(rhs)) => evaluate_ir_condition(&insn.opcode, lhs, rhs),
+            _ => None,
+        };
+
+        if Some(insn.op3) == next_label || condition == Some(false) {
+            block.instructions.pop();
+            changed = true;
+        } else if condition == Some(true) {
+            *insn = IrInstruction {
+                opcode: IrOpcode::Jump,
+                op1: insn.op3,
+                span: insn.span,
+                ..Default::default()
+            };
+            changed = true;
+        }
+    }
+
+    if changed {
+        connect_ir_blocks(&mut func.cfg);
+        remove_stale_ir_phi_args(&mut func.cfg);
+    }
+    changed
+}
+
+/**
+ * Removes blocks that can no longer be reached, e.g. after simplifying branches.
+ */
+pub fn remove_ir_unreachable_blocks<'a>(_ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    if remove_unreachable_ir_blocks(&mut func.cfg) {
+        compute_ir_dominators(&mut func.cfg);
+        true
+    } else {
+        false
+    }
+}
+
+/**
+ * Moves computations whose operands doesn't change inside a loop to the preheader of the loop,
+ * i.e. the block that is always executed right before entering the loop. Only instructions
+ * that can't trap are moved, since they may not have been executed in the original loop.
+ */
+pub fn hoist_ir_loop_invariants<'a>(ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut changed = false;
+    'restart: loop {
+        compute_ir_dominators(&mut func.cfg);
+        for lp in find_ir_loops(&func.cfg) {
+            let defined = ir_loop_defined_variables(&func.cfg, &lp);
+            let mut blocks: Vec<usize> = lp.blocks.iter().copied().collect();
+            blocks.sort_unstable();
+
+            // Find the invariant instructions, the order they are found in respects their dependencies
+            let mut invariants: HashSet<IrIdent> = HashSet::new();
+            let mut hoisted: Vec<(usize, usize)> = Vec::new();
+            let mut found = true;
+            while found {
+                found = false;
+                for &b in &blocks {
+                    for (i, insn) in func.cfg.blocks[b].instructions.iter().enumerate() {
+                        let dst = match ir_defined_variable(insn) {
+                            Some(dst) if !func.referenced.contains(&dst) && !invariants.contains(&dst) => dst,
+                            _ => continue,
+                        };
+
+                        let is_invariant = is_ir_speculatable(insn) && ir_used_variables(insn).iter().all(|ident| {
+                            !func.referenced.contains(ident) && (!defined.contains(ident) || invariants.contains(ident))
+                        });
+
+                        if is_invariant {
+                            invariants.insert(dst);
+                            hoisted.push((b, i));
+                            found = true;
+                        }
+                    }
+                }
+            }
+
+            if hoisted.is_empty() {
+                continue;
+            }
+
+            let preheader = match ir_loop_preheader(&func.cfg, &lp) {
+                Some(preheader) => preheader,
+                None => {
+                    if insert_ir_loop_preheader(ib, &mut func.cfg, &lp) {
+                        changed = true;
+                        continue 'restart;
+                    }
+                    continue;
+                }
+            };
+
+            let insns: Vec<IrInstruction> = hoisted.iter().map(|(b, i)| func.cfg.blocks[*b].instructions[*i].clone()).collect();
+            for &b in &blocks {
+                let mut i = 0;
+                func.cfg.blocks[b].instructions.retain(|_| {
+                    i += 1;
+                    !hoisted.contains(&(b, i - 1))
+                });
+            }
+            push_ir_preheader_instructions(&mut func.cfg.blocks[preheader], insns);
+            changed = true;
+            continue 'restart;
+        }
+        break;
+    }
+    changed
+}
+
+/**
+ * Replaces multiplications of an induction variable by a constant with a new induction variable
+ * that is incremented by the step times the constant, e.g. `i * 4` becomes `j` where `j = j + 4`
+ * whenever `i = i + 1`. Induction variables are phi instructions in the loop header where every
+ * value from inside the loop is the same constant increment of the phi.
+ */
+pub fn reduce_ir_loop_strength<'a>(ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut changed = false;
+    'restart: loop {
+        compute_ir_dominators(&mut func.cfg);
+        for lp in find_ir_loops(&func.cfg) {
+            let header = lp.header;
+            let mut inductions: HashMap<IrIdent, IrInductionVariable> = HashMap::new();
+            for insn in func.cfg.blocks[header].instructions.iter().take_while(|insn| insn.opcode == IrOpcode::Phi) {
+                if let Some(induction) = find_ir_induction_variable(&func.cfg, &lp, &func.referenced, insn) {
+                    inductions.insert(induction.ident, induction);
+                }
+            }
+
+            // Multiplications of induction variables by constants inside the loop
+            let mut reductions: Vec<(usize, usize, IrIdent, i32)> = Vec::new();
+            for &b in &lp.blocks {
+                for (i, insn) in func.cfg.blocks[b].instructions.iter().enumerate() {
+                    if insn.opcode != IrOpcode::Mul || insn.ty != IrType::I32 {
+                        continue;
+                    }
+
+                    match (insn.op2, insn.op3) {
+                        (IrOperand::Ident(ident), IrOperand::Value(IrValue::I32(k))) |
+                        (IrOperand::Value(IrValue::I32(k)), IrOperand::Ident(ident)) if inductions.contains_key(&ident) => {
+                            reductions.push((b, i, ident, k));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if reductions.is_empty() {
+                continue;
+            }
+
+            let preheader = match ir_loop_preheader(&func.cfg, &lp) {
+                Some(preheader) => preheader,
+                None => {
+                    if insert_ir_loop_preheader(ib, &mut func.cfg, &lp) {
+                        changed = true;
+                        continue 'restart;
+                    }
+                    continue;
+                }
+            };
+
+            let mut next_index = next_ir_ident_indices(&func.cfg);
+            let temp_symbol = intern_string("");
+            let mut create_temp = || -> IrIdent {
+                let index = next_index.entry(temp_symbol).or_insert(0);
+                *index += 1;
+                create_ir_ident(temp_symbol, *index - 1)
+            };
+
+            // NOTE(alexander): every multiplication of the same variable and constant shares the new variable
+            let mut reduced: HashMap<(IrIdent, i32), IrIdent> = HashMap::new();
+            let mut preheader_insns: Vec<IrInstruction> = Vec::new();
+            let mut header_phis: Vec<IrInstruction> = Vec::new();
+            let mut increments: Vec<(IrIdent, IrInstruction)> = Vec::new();
+            for (b, i, ident, k) in reductions {
+                let induction = &inductions[&ident];
+                let span = func.cfg.blocks[b].instructions[i].span;
+                let reduced_ident = *reduced.entry((ident, k)).or_insert_with(|| {
+                    let init = create_temp();
+                    let phi = create_temp();
+                    let next = create_temp();
+                    preheader_insns.push(IrInstruction {
+                        opcode: IrOpcode::Mul,
+                        op1: IrOperand::Ident(init),
+                        op2: induction.init,
+                        op3: IrOperand::Value(IrValue::I32(k)),
+                        ty: IrType::I32,
+                        span,
+                        ..Default::default()
+                    });
+
+                    let mut phi_args = vec![(func.cfg.blocks[preheader].label.unwrap(), IrOperand::Ident(init))];
+                    for label in &induction.latch_labels {
+                        phi_args.push((*label, IrOperand::Ident(next)));
+                    }
+                    header_phis.push(IrInstruction {
+                        opcode: IrOpcode::Phi,
+                        op1: IrOperand::Ident(phi),
+                        ty: IrType::I32,
+                        span,
+                        phi_args,
+                        ..Default::default()
+                    });
+
+                    increments.push((induction.next, IrInstruction {
+                        opcode: IrOpcode::Add,
+                        op1: IrOperand::Ident(next),
+                        op2: IrOperand::Ident(phi),
+                        op3: IrOperand::Value(IrValue::I32(induction.step.wrapping_mul(k))),
+                        ty: IrType::I32,
+                        span,
+                        ..Default::default()
+                    }));
+                    phi
+                });
+
+                let insn = &mut func.cfg.blocks[b].instructions[i];
+                *insn = create_ir_copy(insn.op1, IrOperand::Ident(reduced_ident), IrType::I32);
+                insn.span = span;
+            }
+
+            // The new variables are incremented right after the induction variable they are based on
+            for (ident, increment) in increments {
+                for &b in &lp.blocks {
+                    let position = func.cfg.blocks[b].instructions.iter().position(|insn| ir_defined_variable(insn) == Some(ident));
+                    if let Some(i) = position {
+                        func.cfg.blocks[b].instructions.insert(i + 1, increment);
+                        break;
+                    }
+                }
+            }
+
+            for (i, phi) in header_phis.into_iter().enumerate() {
+                func.cfg.blocks[header].instructions.insert(i, phi);
+            }
+            push_ir_preheader_instructions(&mut func.cfg.blocks[preheader], preheader_insns);
+            changed = true;
+            continue 'restart;
+        }
+        break;
+    }
+    changed
+}
+
+/**
+ * Removes instructions without side effects whose result is never used.
+ */
+pub fn eliminate_ir_dead_code<'a>(_ib: &mut IrBuilder<'a>, func: &mut IrSsaFunction) -> bool {
+    let mut changed = false;
+    loop {
+        let mut num_uses: HashMap<IrIdent, usize> = HashMap::new();
+        for block in &func.cfg.blocks {
+            for insn in &block.instructions {
+                for ident in ir_used_variables(insn) {
+                    *num_uses.entry(ident).or_insert(0) += 1;
+                }
+                for (_, value) in &insn.phi_args {
+                    if let IrOperand::Ident(ident) = value {
+                        *num_uses.entry(*ident).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let referenced = &func.referenced;
+        let mut removed = false;
+        for block in &mut func.cfg.blocks {
+            block.instructions.retain(|insn| {
+                let is_dead = match ir_defined_variable(insn) {
+                    Some(dst) => !referenced.contains(&dst) && !num_uses.contains_key(&dst) && is_ir_pure(insn),
+                    None => false,
+                };
+                removed |= is_dead;
+                !is_dead
+            });
+        }
+
+        if !removed {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+/***************************************************************************
+ * Inlining
+ ***************************************************************************/
+
+/**
+ * Functions with at most this many instructions are inlined by the size heuristic in -O2,
+ * functions marked with `#[inline]` are inlined in -O1 also and are allowed to be larger.
+ */
+const IR_INLINE_THRESHOLD: usize = 16;
+const IR_INLINE_HINT_THRESHOLD: usize = 64;
+
+/**
+ * Callers are not grown beyond this number of instructions, except by `#[inline(always)]`.
+ */
+const IR_INLINE_MAX_CALLER_SIZE: usize = 2000;
+
+/**
+ * Replaces calls to small functions by a copy of the function body, returns the indices
+ * of the functions that were changed. Functions are visited bottom-up in the call graph
+ * so callees have their own calls inlined first. Calls between functions in the same
+ * strongly connected component (i.e. recursive calls) are never inlined, and the code
+ * that was inlined into a caller is not visited again, so inlining always terminates.
+ */
+pub fn inline_ir_functions<'a>(ib: &mut IrBuilder<'a>, functions: &mut [IrSsaFunction], opt_level: u32) -> Vec<usize> {
+    let indices: HashMap<IrIdent, usize> = functions.iter().enumerate().map(|(i, func)| (func.ident, i)).collect();
+    let call_graph: Vec<Vec<usize>> = functions.iter().map(|func| {
+        let mut callees = Vec::new();
+        for block in &func.cfg.blocks {
+            for insn in &block.instructions {
+                if let (IrOpcode::Call, IrOperand::Ident(ident)) = (&insn.opcode, insn.op2) {
+                    if let Some(callee) = indices.get(&ident) {
+                        callees.push(*callee);
+                    }
+                }
+            }
+        }
+        callees
+    }).collect();
+
+    let components = strongly_connected_components(&call_graph);
+    let mut component_of = vec![0; functions.len()];
+    for (c, component) in components.iter().enumerate() {
+        for f in component {
+            component_of[*f] = c;
+        }
+    }
+
+    let mut changed = Vec::new();
+    for component in &components {
+        for caller in component {
+            if inline_ir_calls(ib, functions, *caller, &indices, &component_of, opt_level) {
+                changed.push(*caller);
+            }
+        }
+    }
+    changed.sort_unstable();
+    changed
+}
+
+fn inline_ir_calls<'a>(
+    ib: &mut IrBuilder<'a>,
+    functions: &mut [IrSsaFunction],
+    caller: usize,
+    indices: &HashMap<IrIdent, usize>,
+    component_of: &[usize],
+    opt_level: u32,
+) -> bool {
+    let mut next_index = next_ir_ident_indices(&functions[caller].cfg);
+    let mut changed = false;
+    let mut b = 0;
+    let mut k = 0;
+    while b < functions[caller].cfg.blocks.len() {
+        let instructions = &functions[caller].cfg.blocks[b].instructions;
+        if k >= instructions.len() {
+            b += 1;
+            k = 0;
+            continue;
+        }
+
+        let callee = match (&instructions[k].opcode, instructions[k].op2) {
+            (IrOpcode::Call, IrOperand::Ident(ident)) => indices.get(&ident).copied(),
+            _ => None,
+        };
+
+        let callee = match callee {
+            Some(callee) if component_of[callee] != component_of[caller] => callee,
+            _ => {
+                k += 1;
+                continue;
+            }
+        };
+
+        let hint = match ib.functions.get(&functions[callee].ident) {
+            Some(bb) => bb.inline_hint,
+            None => IrInlineHint::Never,
+        };
+        let callee_size = ir_function_size(&functions[callee]);
+        let caller_size = ir_function_size(&functions[caller]);
+        let inline = match hint {
+            IrInlineHint::Never  => false,
+            IrInlineHint::Always => true,
+            IrInlineHint::Hint   => callee_size <= IR_INLINE_HINT_THRESHOLD,
+            IrInlineHint::None   => opt_level >= 2 && callee_size <= IR_INLINE_THRESHOLD,
+        };
+
+        if !inline || (hint != IrInlineHint::Always && caller_size + callee_size > IR_INLINE_MAX_CALLER_SIZE) {
+            k += 1;
+            continue;
+        }
+
+        match inline_ir_call(ib, functions, caller, callee, b, k, &mut next_index) {
+            Some(continuation) => {
+                // NOTE(alexander): skip the inlined code, calls in the callee are already inlined
+                b = continuation;
+                k = 0;
+                changed = true;
+            }
+            None => k += 1,
+        }
+    }
+
+    if changed {
+        let func = &mut functions[caller];
+        connect_ir_blocks(&mut func.cfg);
+        compute_ir_dominators(&mut func.cfg);
+    }
+    changed
+}
+
+/**
+ * Inlines the call at instruction k in block b of the caller. The block is split at the call,
+ * the callee blocks are placed in between, with the parameters copied from the arguments.
+ * Every variable and label of the callee is renamed so they can't collide with the caller,
+ * and returns are replaced by jumps to the continuation block, where a phi instruction
+ * selects the return value. Returns the index of the continuation block, if inlined.
+ */
+fn inline_ir_call<'a>(
+    ib: &mut IrBuilder<'a>,
+    functions: &mut [IrSsaFunction],
+    caller: usize,
+    callee: usize,
+    b: usize,
+    k: usize,
+    next_index: &mut HashMap<Symbol, u32>,
+) -> Option<usize> {
+    let call = functions[caller].cfg.blocks[b].instructions[k].clone();
+    let num_args = match call.op3 {
+        IrOperand::Value(IrValue::I32(num_args)) => num_args as usize,
+        _ => return None,
+    };
+
+    // NOTE(alexander): arguments are all the params since the previous call (in reverse order),
+    // the params before alloc_params are the parameters of the caller itself.
+    let arg_indices: Vec<usize> = {
+        let instructions = &functions[caller].cfg.blocks[b].instructions;
+        (0..k).rev()
+            .take_while(|i| !matches!(instructions[*i].opcode, IrOpcode::Call | IrOpcode::AllocParams))
+            .filter(|i| instructions[*i].opcode == IrOpcode::Param)
+            .collect()
+    };
+    if arg_indices.len() != num_args {
+        return None;
+    }
+
+    let callee_func = &functions[callee];
+    let exit = callee_func.cfg.blocks.len() - 1;
+    let mut labels: HashMap<IrIdent, IrIdent> = HashMap::new();
+    for block in &callee_func.cfg.blocks {
+        labels.insert(block.label?, create_unique_ir_label(ib));
+    }
+    let continuation_label = labels[&callee_func.cfg.blocks[exit].label?];
+
+    let mut variables: HashMap<IrIdent, IrIdent> = HashMap::new();
+    let mut params: Vec<(IrOperand, IrType)> = Vec::new();
+    let mut returns: Vec<(IrIdent, IrOperand)> = Vec::new();
+    let mut inlined_blocks: Vec<IrBlock> = Vec::new();
+    for (i, block) in callee_func.cfg.blocks[..exit].iter().enumerate() {
+        let label = labels[&block.label?];
+        let mut inlined_block = create_ir_block(Some(label), 0);
+        let mut is_param = i == 0;
+        for insn in &block.instructions {
+            let mut insn = insn.clone();
+            rename_inlined_ir_instruction(&mut insn, &labels, &mut variables, next_index);
+
+            if is_param {
+                match insn.opcode {
+                    IrOpcode::Param => {
+                        params.push((insn.op1, insn.ty));
+                        continue;
+                    }
+                    IrOpcode::AllocParams => {
+                        is_param = false;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            if insn.opcode == IrOpcode::Return {
+                returns.push((label, insn.op1));
+                insn = IrInstruction {
+                    opcode: IrOpcode::Jump,
+                    op1: IrOperand::Ident(continuation_label),
+                    span: insn.span,
+                    ..Default::default()
+                };
+            }
+            inlined_block.instructions.push(insn);
+        }
+
+        // NOTE(alexander): the last block falls through to the continuation
+        let is_last = i + 1 == exit;
+        if is_last && inlined_block.instructions.last().map(|insn| &insn.opcode) != Some(&IrOpcode::Jump) {
+            returns.push((label, IrOperand::None));
+        }
+        inlined_blocks.push(inlined_block);
+    }
+
+    if params.len() != num_args {
+        return None;
+    }
+
+    let phi = match call.op1 {
+        IrOperand::Ident(dst) if call.ty != IrType::None && !returns.is_empty() => {
+            if returns.iter().any(|(_, value)| *value == IrOperand::None) {
+                // NOTE(alexander): a path through the callee that doesn't return a value
+                return None;
+            }
+
+            Some(IrInstruction {
+                opcode: IrOpcode::Phi,
+                op1: IrOperand::Ident(dst),
+                ty: call.ty,
+                span: call.span,
+                phi_args: returns.iter().map(|(label, value)| (*label, *value)).collect(),
+                ..Default::default()
+            })
+        }
+        _ => None,
+    };
+
+    let referenced: Vec<IrIdent> = callee_func.referenced.iter()
+        .map(|ident| renamed_ir_variable(*ident, &mut variables, next_index))
+        .collect();
+
+    // Split the caller block at the call, the parameters are copied from the arguments
+    let func = &mut functions[caller];
+    func.referenced.extend(referenced);
+    let block = &mut func.cfg.blocks[b];
+    let rest = block.instructions.split_off(k + 1);
+    block.instructions.pop();
+
+    let mut args: Vec<IrOperand> = arg_indices.iter().map(|i| block.instructions.remove(*i).op1).collect();
+    args.reverse();
+    for ((param, ty), arg) in params.into_iter().zip(args) {
+        let mut copy = create_ir_copy(param, arg, ty);
+        copy.span = call.span;
+        block.instructions.push(copy);
+    }
+
+    // NOTE(alexander): the successors of the call block are now the successors of the continuation
+    let split_label = block.label?;
+    for block in &mut func.cfg.blocks {
+        for insn in block.instructions.iter_mut().take_while(|insn| insn.opcode == IrOpcode::Phi) {
+            for (label, _) in &mut insn.phi_args {
+                if *label == split_label {
+                    *label = continuation_label;
+                }
+            }
+        }
+    }
+
+    let mut continuation = create_ir_block(Some(continuation_label), 0);
+    continuation.instructions.extend(phi);
+    continuation.instructions.extend(rest);
+
+    let continuation_index = b + 1 + inlined_blocks.len();
+    inlined_blocks.push(continuation);
+    func.cfg.blocks.splice(b + 1..b + 1, inlined_blocks);
+    Some(continuation_index)
+}
+
+fn rename_inlined_ir_instruction(
+    insn: &mut IrInstruction,
+    labels: &HashMap<IrIdent, IrIdent>,
+    variables: &mut HashMap<IrIdent, IrIdent>,
+    next_index: &mut HashMap<Symbol, u32>,
+) {
+    let rename_label = |op: &mut IrOperand| {
+        if let IrOperand::Ident(ident) = op {
+            if let Some(label) = labels.get(ident) {
+                *ident = *label;
+            }
+        }
+    };
+
+    match insn.opcode {
+        IrOpcode::Jump => rename_label(&mut insn.op1),
+
+        IrOpcode::IfLt |
+        IrOpcode::IfGt |
+        IrOpcode::IfLe |
+        IrOpcode::IfGe |
+        IrOpcode::IfEq |
+        IrOpcode::IfNe => {
+            rename_ir_variable(&mut insn.op1, variables, next_index);
+            rename_ir_variable(&mut insn.op2, variables, next_index);
+            rename_label(&mut insn.op3);
+        }
+
+        // NOTE(alexander): op2 is the function and op3 is the number of parameters
+        IrOpcode::Call => rename_ir_variable(&mut insn.op1, variables, next_index),
+
+        IrOpcode::Phi => {
+            rename_ir_variable(&mut insn.op1, variables, next_index);
+            for (label, value) in &mut insn.phi_args {
+                *label = labels[label];
+                rename_ir_variable(value, variables, next_index);
+            }
+        }
+
+        _ => {
+            rename_ir_variable(&mut insn.op1, variables, next_index);
+            rename_ir_variable(&mut insn.op2, variables, next_index);
+            rename_ir_variable(&mut insn.op3, variables, next_index);
+            rename_ir_variable(&mut insn.op4, variables, next_index);
+        }
+    }
+}
+
+/**
+ * Renames a variable of the callee to a new variable in the caller, with the same symbol.
+ */
+fn rename_ir_variable(
+    op: &mut IrOperand,
+    variables: &mut HashMap<IrIdent, IrIdent>,
+    next_index: &mut HashMap<Symbol, u32>,
+) {
+    if let IrOperand::Ident(ident) = op {
+        *ident = renamed_ir_variable(*ident, variables, next_index);
+    }
+}
+
+fn renamed_ir_variable(
+    ident: IrIdent,
+    variables: &mut HashMap<IrIdent, IrIdent>,
+    next_index: &mut HashMap<Symbol, u32>,
+) -> IrIdent {
+    *variables.entry(ident).or_insert_with(|| {
+        let index = next_index.entry(ident.symbol).or_insert(0);
+        *index += 1;
+        create_ir_ident(ident.symbol, *index - 1)
+    })
+}
+
+/**
+ * Number of instructions in the function, excluding those that don't generate any code.
+ */
+fn ir_function_size(func: &IrSsaFunction) -> usize {
+    func.cfg.blocks.iter()
+        .flat_map(|block| block.instructions.iter())
+        .filter(|insn| !matches!(insn.opcode, IrOpcode::Phi | IrOpcode::Alloca | IrOpcode::AllocParams))
+        .count()
+}
+
+/**
+ * Strongly connected components of the graph using Tarjan's algorithm, the components
+ * are returned in reverse topological order i.e. callees before callers.
+ */
+fn strongly_connected_components(graph: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        graph: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        lowlink: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next_index: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    fn visit(t: &mut Tarjan, v: usize) {
+        t.index[v] = Some(t.next_index);
+        t.lowlink[v] = t.next_index;
+        t.next_index += 1;
+        t.stack.push(v);
+        t.on_stack[v] = true;
+
+        for &w in t.graph[v].iter() {
+            match t.index[w] {
+                None => {
+                    visit(t, w);
+                    t.lowlink[v] = t.lowlink[v].min(t.lowlink[w]);
+                }
+                Some(index) if t.on_stack[w] => t.lowlink[v] = t.lowlink[v].min(index),
+                _ => {}
+            }
+        }
+
+        if Some(t.lowlink[v]) == t.index[v] {
+            let mut component = Vec::new();
+            while let Some(w) = t.stack.pop() {
+                t.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            t.components.push(component);
+        }
+    }
+
+    let n = graph.len();
+    let mut t = Tarjan {
+        graph,
+        index: vec![None; n],
+        lowlink: vec![0; n],
+        on_stack: vec![false; n],
+        stack: Vec::new(),
+        next_index: 0,
+        components: Vec::new(),
+    };
+
+    for v in 0..n {
+        if t.index[v].is_none() {
+            visit(&mut t, v);
+        }
+    }
+    t.components
+}
+
+/***************************************************************************
+ * Loop helpers
+ ***************************************************************************/
+
+/**
+ * Induction variable defined by a phi in the loop header, the value from outside the loop
+ * is the initial value and the value from every latch is `next = ident + step`.
+ */
+struct IrInductionVariable {
+    ident: IrIdent,
+    init: IrOperand,
+    next: IrIdent,
+    step: i32,
+    latch_labels: Vec<IrIdent>,
+}
+
+fn find_ir_induction_variable(
+    cfg: &IrControlFlowGraph,
+    lp: &IrLoop,
+    referenced: &HashSet<IrIdent>,
+    phi: &IrInstruction
+) -> Option<IrInductionVariable> {
+    let ident = match phi.op1 {
+        IrOperand::Ident(ident) if phi.ty == IrType::I32 && !referenced.contains(&ident) => ident,
+        _ => return None,
+    };
+
+    let mut init: Option<IrOperand> = None;
+    let mut next: Option<IrIdent> = None;
+    let mut latch_labels = Vec::new();
+    for (label, value) in &phi.phi_args {
+        let is_inside = lp.blocks.iter().any(|b| cfg.blocks[*b].label == Some(*label));
+        match (is_inside, value) {
+            (false, _) if init.is_none() => init = Some(*value),
+            (true, IrOperand::Ident(value)) if next.is_none() || next == Some(*value) => {
+                next = Some(*value);
+                latch_labels.push(*label);
+            }
+            _ => return None,
+        }
+    }
+
+    let (init, next) = (init?, next?);
+    for b in &lp.blocks {
+        for insn in &cfg.blocks[*b].instructions {
+            if ir_defined_variable(insn) != Some(next) {
+                continue;
+            }
+
+            let step = match (&insn.opcode, insn.op2, insn.op3) {
+                (IrOpcode::Add, IrOperand::Ident(lhs), IrOperand::Value(IrValue::I32(rhs))) if lhs == ident => rhs,
+                (IrOpcode::Add, IrOperand::Value(IrValue::I32(lhs)), IrOperand::Ident(rhs)) if rhs == ident => lhs,
+                (IrOpcode::Sub, IrOperand::Ident(lhs), IrOperand::Value(IrValue::I32(rhs))) if lhs == ident => rhs.wrapping_neg(),
+                _ => return None,
+            };
+            return Some(IrInductionVariable { ident, init, next, step, latch_labels });
+        }
+    }
+    None
+}
+
+/**
+ * Variables that are assigned by any instruction inside the loop.
+ */
+fn ir_loop_defined_variables(cfg: &IrControlFlowGraph, lp: &IrLoop) -> HashSet<IrIdent> {
+    lp.blocks.iter()
+        .flat_map(|b| cfg.blocks[*b].instructions.iter())
+        .filter_map(ir_defined_variable)
+        .collect()
+}
+
+/**
+ * The only predecessor of the loop header outside the loop, if it has no other successors.
+ */
+fn ir_loop_preheader(cfg: &IrControlFlowGraph, lp: &IrLoop) -> Option<usize> {
+    let outside: Vec<usize> = cfg.blocks[lp.header].predecessors.iter()
+        .filter(|p| !lp.blocks.contains(p))
+        .copied()
+        .collect();
+
+    match outside[..] {
+        [p] if cfg.blocks[p].successors == [lp.header] => Some(p),
+        _ => None,
+    }
+}
+
+/**
+ * Inserts a new block before the loop header, that every edge entering the loop goes through.
+ * Phi instructions in the header with several values from outside the loop are split in two.
+ * Returns false if the loop cannot be entered.
+ */
+fn insert_ir_loop_preheader<'a>(ib: &mut IrBuilder<'a>, cfg: &mut IrControlFlowGraph, lp: &IrLoop) -> bool {
+    let header = lp.header;
+    let header_label = cfg.blocks[header].label.unwrap();
+    let outside: Vec<usize> = cfg.blocks[header].predecessors.iter()
+        .filter(|p| !lp.blocks.contains(p))
+        .copied()
+        .collect();
+    if outside.is_empty() {
+        return false;
+    }
+
+    let label = create_unique_ir_label(ib);
+    let outside_labels: Vec<IrIdent> = outside.iter().map(|p| cfg.blocks[*p].label.unwrap()).collect();
+    for p in &outside {
+        if let Some(insn) = cfg.blocks[*p].instructions.last_mut() {
+            if insn.opcode == IrOpcode::Jump && insn.op1 == IrOperand::Ident(header_label) {
+                insn.op1 = IrOperand::Ident(label);
+            } else if is_ir_conditional_jump(&insn.opcode) && insn.op3 == IrOperand::Ident(header_label) {
+                insn.op3 = IrOperand::Ident(label);
+            }
+        }
+    }
+
+    // NOTE(alexander): blocks inside the loop that falls through to the header has to jump there instead
+    if header > 0 && lp.blocks.contains(&(header - 1)) && ir_block_falls_through(&cfg.blocks[header - 1]) {
+        cfg.blocks[header - 1].instructions.push(IrInstruction {
+            opcode: IrOpcode::Jump,
+            op1: IrOperand::Ident(header_label),
+            ..Default::default()
+        });
+    }
+
+    let mut next_index = next_ir_ident_indices(cfg);
+    let mut preheader = create_ir_block(Some(label), 0);
+    for insn in cfg.blocks[header].instructions.iter_mut().take_while(|insn| insn.opcode == IrOpcode::Phi) {
+        let entering: Vec<(IrIdent, IrOperand)> = insn.phi_args.iter()
+            .filter(|(label, _)| outside_labels.contains(label))
+            .copied()
+            .collect();
+
+        let value = if entering.len() == 1 {
+            entering[0].1
+        } else {
+            let symbol = get_ir_ident(insn.op1).symbol;
+            let index = next_index.entry(symbol).or_insert(0);
+            *index += 1;
+            let ident = create_ir_ident(symbol, *index - 1);
+            preheader.instructions.push(IrInstruction {
+                opcode: IrOpcode::Phi,
+                op1: IrOperand::Ident(ident),
+                ty: insn.ty,
+                span: insn.span,
+                phi_args: entering,
+                ..Default::default()
+            });
+            IrOperand::Ident(ident)
+        };
+
+        insn.phi_args.retain(|(label, _)| !outside_labels.contains(label));
+        insn.phi_args.push((label, value));
+    }
+
+    cfg.blocks.insert(header, preheader);
+    connect_ir_blocks(cfg);
+    true
+}
+
+/**
+ * Appends instructions to the end of the preheader, but before any jump to the loop header.
+ */
+fn push_ir_preheader_instructions(preheader: &mut IrBlock, insns: Vec<IrInstruction>) {
+    let position = match preheader.instructions.last() {
+        Some(insn) if is_ir_terminator(&insn.opcode) => preheader.instructions.len() - 1,
+        _ => preheader.instructions.len(),
+    };
+    preheader.instructions.splice(position..position, insns);
+}
+
+fn ir_block_falls_through(block: &IrBlock) -> bool {
+    match block.instructions.last() {
+        Some(insn) => insn.opcode != IrOpcode::Jump && insn.opcode != IrOpcode::Return,
+        None => true,
+    }
+}
+
+/**
+ * Instructions that can be executed even if they were not executed originally,
+ * they have no side effects and cannot cause an exception.
+ */
+fn is_ir_speculatable(insn: &IrInstruction) -> bool {
+    match insn.opcode {
+        IrOpcode::Copy |
+        IrOpcode::Clear |
+        IrOpcode::Add |
+        IrOpcode::Sub |
+        IrOpcode::Mul |
+        IrOpcode::Pow |
+        IrOpcode::And |
+        IrOpcode::Or  |
+        IrOpcode::Xor |
+        IrOpcode::Lt  |
+        IrOpcode::Le  |
+        IrOpcode::Gt  |
+        IrOpcode::Ge  |
+        IrOpcode::Eq  |
+        IrOpcode::Ne  |
+        IrOpcode::Select => true,
+        IrOpcode::Div |
+        IrOpcode::Mod => is_ir_pure(insn),
+        _ => false,
+    }
+}
+
+fn get_ir_ident(op: IrOperand) -> IrIdent {
+    match op {
+        IrOperand::Ident(ident) => ident,
+        _ => panic!("opt: expected identifier, found `{}`", op),
+    }
+}
+
+/***************************************************************************
+ * Helpers
+ ***************************************************************************/
+
+/**
+ * Replaces the operands that are read by the instruction with their known values.
+ */
+fn substitute_ir_operands(insn: &mut IrInstruction, values: &HashMap<IrIdent, IrOperand>) -> bool {
+    if values.is_empty() {
+        return false;
+    }
+
+    let mut changed = false;
+    let mut substitute = |op: &mut IrOperand| {
+        let value = resolve_ir_value(values, *op);
+        if value != *op {
+            *op = value;
+            changed = true;
+        }
+    };
+
+    if insn.opcode == IrOpcode::Phi {
+        for (_, value) in &mut insn.phi_args {
+            substitute(value);
+        }
+    } else {
+        for op in ir_used_operands(insn) {
+            substitute(op);
+        }
+    }
+    changed
+}
+
+fn resolve_ir_value(values: &HashMap<IrIdent, IrOperand>, mut op: IrOperand) -> IrOperand {
+    // NOTE(alexander): the number of steps is limited, the values should never form a cycle
+    for _ in 0..values.len() {
+        match op {
+            IrOperand::Ident(ident) => match values.get(&ident) {
+                Some(value) => op = *value,
+                None => break,
+            },
+            _ => break,
+        }
+    }
+    op
+}
+
+/**
+ * The incoming value of a phi instruction if every incoming value is the same, ignoring itself.
+ */
+fn unique_ir_phi_value(insn: &IrInstruction, dst: IrIdent) -> Option<IrOperand> {
+    let mut unique: Option<IrOperand> = None;
+    for (_, value) in &insn.phi_args {
+        if *value == IrOperand::Ident(dst) {
+            continue;
+        }
+
+        match unique {
+            Some(other) if other != *value => return None,
+            _ => unique = Some(*value),
+        }
+    }
+    unique
+}
+
+/**
+ * The value chosen by a select if it is known, i.e. the condition is constant or both values are the same.
+ */
+fn simplified_ir_select(insn: &IrInstruction) -> Option<IrOperand> {
+    match insn.op2 {
+        _ if insn.op3 == insn.op4 => Some(insn.op3),
+        IrOperand::Value(IrValue::Bool(cond)) => Some(if cond { insn.op3 } else { insn.op4 }),
+        _ => None,
+    }
+}
+
+/**
+ * Instructions that can be removed if their result is not used. Division is only
+ * removed if the right-hand side is a constant that cannot cause an exception.
+ */
+fn is_ir_pure(insn: &IrInstruction) -> bool {
+    match insn.opcode {
+        IrOpcode::Call => false,
+        IrOpcode::Div |
+        IrOpcode::Mod => match insn.op3 {
+            IrOperand::Value(IrValue::I32(rhs)) => rhs != 0 && rhs != -1,
+            IrOperand::Value(IrValue::I64(rhs)) => rhs != 0 && rhs != -1,
+            IrOperand::Value(IrValue::U64(rhs)) => rhs != 0,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/**
+ * Computes the result of a binary operation, the arithmetic wraps around on overflow
+ * the same way as the generated code does. Division by zero is never evaluated.
+ */
+pub fn fold_ir_binary(opcode: &IrOpcode, lhs: IrValue, rhs: IrValue) -> Option<IrValue> {
+    match (lhs, rhs) {
+        (IrValue::I32(lhs), IrValue::I32(rhs)) => match opcode {
+            IrOpcode::Add => Some(IrValue::I32(lhs.wrapping_add(rhs))),
+            IrOpcode::Sub => Some(IrValue::I32(lhs.wrapping_sub(rhs))),
+            IrOpcode::Mul => Some(IrValue::I32(lhs.wrapping_mul(rhs))),
+            IrOpcode::Div => lhs.checked_div(rhs).map(IrValue::I32),
+            IrOpcode::Mod => lhs.checked_rem(rhs).map(IrValue::I32),
+            IrOpcode::Pow => if rhs >= 0 { Some(IrValue::I32(lhs.wrapping_pow(rhs as u32))) } else { None },
+            IrOpcode::And => Some(IrValue::I32(lhs & rhs)),
+            IrOpcode::Or  => Some(IrValue::I32(lhs | rhs)),
+            IrOpcode::Xor => Some(IrValue::I32(lhs ^ rhs)),
+            IrOpcode::Lt  => Some(IrValue::Bool(lhs < rhs)),
+            IrOpcode::Le  => Some(IrValue::Bool(lhs <= rhs)),
+            IrOpcode::Gt  => Some(IrValue::Bool(lhs > rhs)),
+            IrOpcode::Ge  => Some(IrValue::Bool(lhs >= rhs)),
+            IrOpcode::Eq  => Some(IrValue::Bool(lhs == rhs)),
+            IrOpcode::Ne  => Some(IrValue::Bool(lhs != rhs)),
+            _ => None,
+        },
+
+        (IrValue::I64(lhs), IrValue::I64(rhs)) => match opcode {
+            IrOpcode::Add => Some(IrValue::I64(lhs.wrapping_add(rhs))),
+            IrOpcode::Sub => Some(IrValue::I64(lhs.wrapping_sub(rhs))),
+            IrOpcode::Mul => Some(IrValue::I64(lhs.wrapping_mul(rhs))),
+            IrOpcode::Div => lhs.checked_div(rhs).map(IrValue::I64),
+            IrOpcode::Mod => lhs.checked_rem(rhs).map(IrValue::I64),
+            IrOpcode::Pow => if rhs >= 0 { Some(IrValue::I64(lhs.wrapping_pow(rhs as u32))) } else { None },
+            IrOpcode::And => Some(IrValue::I64(lhs & rhs)),
+            IrOpcode::Or  => Some(IrValue::I64(lhs | rhs)),
+            IrOpcode::Xor => Some(IrValue::I64(lhs ^ rhs)),
+            IrOpcode::Lt  => Some(IrValue::Bool(lhs < rhs)),
+            IrOpcode::Le  => Some(IrValue::Bool(lhs <= rhs)),
+            IrOpcode::Gt  => Some(IrValue::Bool(lhs > rhs)),
+            IrOpcode::Ge  => Some(IrValue::Bool(lhs >= rhs)),
+            IrOpcode::Eq  => Some(IrValue::Bool(lhs == rhs)),
+            IrOpcode::Ne  => Some(IrValue::Bool(lhs != rhs)),
+            _ => None,
+        },
+
+        // NOTE(alexander): the exponent is truncated to 32-bit, same as the interpreter
+        (IrValue::U64(lhs), IrValue::U64(rhs)) => match opcode {
+            IrOpcode::Add => Some(IrValue::U64(lhs.wrapping_add(rhs))),
+            IrOpcode::Sub => Some(IrValue::U64(lhs.wrapping_sub(rhs))),
+            IrOpcode::Mul => Some(IrValue::U64(lhs.wrapping_mul(rhs))),
+            IrOpcode::Div => lhs.checked_div(rhs).map(IrValue::U64),
+            IrOpcode::Mod => lhs.checked_rem(rhs).map(IrValue::U64),
+            IrOpcode::Pow => Some(IrValue::U64(lhs.wrapping_pow(rhs as u32))),
+            IrOpcode::And => Some(IrValue::U64(lhs & rhs)),
+            IrOpcode::Or  => Some(IrValue::U64(lhs | rhs)),
+            IrOpcode::Xor => Some(IrValue::U64(lhs ^ rhs)),
+            IrOpcode::Lt  => Some(IrValue::Bool(lhs < rhs)),
+            IrOpcode::Le  => Some(IrValue::Bool(lhs <= rhs)),
+            IrOpcode::Gt  => Some(IrValue::Bool(lhs > rhs)),
+            IrOpcode::Ge  => Some(IrValue::Bool(lhs >= rhs)),
+            IrOpcode::Eq  => Some(IrValue::Bool(lhs == rhs)),
+            IrOpcode::Ne  => Some(IrValue::Bool(lhs != rhs)),
+            _ => None,
+        },
+
+        (IrValue::Bool(lhs), IrValue::Bool(rhs)) => match opcode {
+            IrOpcode::And => Some(IrValue::Bool(lhs & rhs)),
+            IrOpcode::Or  => Some(IrValue::Bool(lhs | rhs)),
+            IrOpcode::Xor => Some(IrValue::Bool(lhs ^ rhs)),
+            IrOpcode::Eq  => Some(IrValue::Bool(lhs == rhs)),
+            IrOpcode::Ne  => Some(IrValue::Bool(lhs != rhs)),
+            _ => None,
+        },
+
+        _ => None,
+    }
+}
+
+/**
+ * Evaluates the condition of a conditional jump, true if the jump is taken.
+ */
+fn evaluate_ir_condition(opcode: &IrOpcode, lhs: IrValue, rhs: IrValue) -> Option<bool> {
+    let compare = match opcode {
+        IrOpcode::IfLt => IrOpcode::Lt,
+        IrOpcode::IfLe => IrOpcode::Le,
+        IrOpcode::IfGt => IrOpcode::Gt,
+        IrOpcode::IfGe => IrOpcode::Ge,
+        IrOpcode::IfEq => IrOpcode::Eq,
+        IrOpcode::IfNe => IrOpcode::Ne,
+        _ => return None,
+    };
+
+    match fold_ir_binary(&compare, lhs, rhs) {
+        Some(IrValue::Bool(taken)) => Some(taken),
+        _ => None,
+    }
+}
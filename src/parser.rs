@@ -7,7 +7,7 @@ use nom::{
     combinator::{peek, map, opt},
     sequence::{preceded, terminated, pair, tuple},
     branch::alt,
-    multi::{many0, separated_list0},
+    multi::{many0, separated_list0, separated_list1},
     error::context,
     Err::Error,
 };
@@ -32,6 +32,14 @@ pub type IResult<I, O> = nom::IResult<I, O, ParseError>;
  * Parse a source file containing items such as functions.
  */
 pub fn parse_file(source: String, filename: String) -> File {
+    parse_file_with_ctx(source, filename, 0)
+}
+
+/**
+ * Parse a source file where every span is tagged with the given context,
+ * used to tell imported files (e.g. the prelude) apart from the main file.
+ */
+pub fn parse_file_with_ctx(source: String, filename: String, ctx: u16) -> File {
     // Calculate the byte position of each line in the source.
     let mut curr_pos = 0;
     let mut lines = vec![0];
@@ -53,7 +61,7 @@ pub fn parse_file(source: String, filename: String) -> File {
     }
 
     // Parse the source file
-    let input = ParseSpan::new_extra(&source, 0); // TODO(alexander): temporary 0 should be some file id maybe.
+    let input = ParseSpan::new_extra(&source, ctx);
     let mut output = match multispace_comment0(input) {
         Ok((out, _)) => out,
         _ => input,
@@ -80,7 +88,7 @@ pub fn parse_file(source: String, filename: String) -> File {
     let span = Span::from_parse_span(input);
     let imported_files = HashMap::new();
 
-    File { source, filename, items, span, lines, imported_files, error_count }
+    File { source, filename, items, span, lines, imported_files, ctx, error_count }
 }
 
 
@@ -151,6 +159,7 @@ pub fn parse_run_code(source: String, filename: String) -> File {
         };
 
         items.push(Item::Fn(FnItem {
+            attrs: vec![],
             ident: ExprIdent {
                 sym: intern_string("main"),
                 span: Span::new(),
@@ -168,7 +177,7 @@ pub fn parse_run_code(source: String, filename: String) -> File {
         }));
     }
 
-    File { source, filename, items, span, lines, imported_files, error_count }
+    File { source, filename, items, span, lines, imported_files, ctx: 0, error_count }
 }
 
 pub fn parse_item(input: ParseSpan) -> IResult<ParseSpan, Item> {
@@ -193,20 +202,55 @@ pub fn parse_fn_item(input: ParseSpan) -> IResult<ParseSpan, FnItem> {
     context(
         "function",
         map(tuple((
-            preceded(multispace0, tag("fn")),
+            many0(preceded(multispace_comment0, parse_attribute)),
+            preceded(multispace_comment0, tag("fn")),
             preceded(multispace1, parse_ident_expr),
             parse_fn_decl,
             parse_block,
         )),
-            |(start, id, decl, block)| {
+            |(attrs, start, id, decl, block)| {
                 let block_span = block.span;
-                FnItem {
+                let start_span = match attrs.first() {
+                    Some(attr) => attr.span,
+                    None => Span::from_parse_span(start),
+                };
+                let mut func = FnItem {
+                    attrs,
                     ident: id,
                     decl: decl,
                     block: block,
+                    span: Span::combine(start_span, block_span),
+                };
+                mark_tail_calls(&mut func);
+                func
+            }
+        )
+    )(input)
+}
+
+pub fn parse_attribute(input: ParseSpan) -> IResult<ParseSpan, Attribute> {
+    context(
+        "attribute",
+        map(tuple((
+            tag("#["),
+            parse_ident_expr,
+            opt(tuple((
+                preceded(multispace0, tag("(")),
+                separated_list0(
+                    preceded(multispace0, tag(",")),
+                    parse_ident_expr
+                ),
+                preceded(multispace0, tag(")")),
+            ))),
+            preceded(multispace0, tag("]")),
+        )),
+            |(start, ident, args, end)| {
+                Attribute {
+                    ident,
+                    args: args.map(|(_, args, _)| args).unwrap_or_default(),
                     span: Span::combine(
                         Span::from_parse_span(start),
-                        block_span,
+                        Span::from_parse_span(end),
                     ),
                 }
             }
@@ -405,6 +449,8 @@ pub fn parse_ty(input: ParseSpan) -> IResult<ParseSpan, Ty> {
 fn parse_ty_kind(input: ParseSpan) -> IResult<ParseSpan, (TyKind, Span)> {
     alt((
         map(preceded(multispace0, tag("i32")), |s| (TyKind::Int, Span::from_parse_span(s))),
+        map(preceded(multispace0, tag("i64")), |s| (TyKind::Int64, Span::from_parse_span(s))),
+        map(preceded(multispace0, tag("u64")), |s| (TyKind::UInt64, Span::from_parse_span(s))),
         map(preceded(multispace0, tag("bool")), |s| (TyKind::Bool, Span::from_parse_span(s))),
         map(preceded(multispace0, parse_ty_ref), |r| (TyKind::Ref(r.0), r.1)),
     ))(input)
@@ -480,6 +526,7 @@ pub fn parse_expr_atom(input: ParseSpan) -> IResult<ParseSpan, Expr> {
     context(
         "expression",
         alt((
+            map(parse_asm_expr,       Expr::Asm),
             map(parse_lit_expr,       |literal|  Expr::Lit(literal)),
             map(parse_paren_expr,     |expr|     Expr::Paren(expr)),
             map(parse_call_expr,      |call|     Expr::Call(call)),
@@ -490,6 +537,89 @@ pub fn parse_expr_atom(input: ParseSpan) -> IResult<ParseSpan, Expr> {
     )(input)
 }
 
+/**
+ * Arguments following the template strings of inline assembly.
+ */
+enum AsmArgument {
+    Operand(AsmOperand),
+    Clobbers(Vec<AsmRegister>),
+}
+
+pub fn parse_asm_expr(input: ParseSpan) -> IResult<ParseSpan, ExprAsm> {
+    context(
+        "inline assembly",
+        map(tuple((
+            preceded(multispace0, tag("asm!")),
+            preceded(multispace0, tag("(")),
+            separated_list1(preceded(multispace0, tag(",")), parse_string),
+            many0(preceded(preceded(multispace0, tag(",")), parse_asm_argument)),
+            opt(preceded(multispace0, tag(","))),
+            preceded(multispace0, tag(")")),
+        )),
+            |(start, _, templates, args, _, end)| {
+                let mut operands = Vec::new();
+                let mut clobbers = Vec::new();
+                for arg in args {
+                    match arg {
+                        AsmArgument::Operand(operand) => operands.push(operand),
+                        AsmArgument::Clobbers(regs) => clobbers.extend(regs),
+                    }
+                }
+
+                let template: Vec<String> = templates.into_iter().map(|(s, _)| s).collect();
+                ExprAsm {
+                    template: template.join("\n"),
+                    operands,
+                    clobbers,
+                    span: Span::combine(
+                        Span::from_parse_span(start),
+                        Span::from_parse_span(end),
+                    ),
+                }
+            }
+        )
+    )(input)
+}
+
+fn parse_asm_argument(input: ParseSpan) -> IResult<ParseSpan, AsmArgument> {
+    alt((
+        map(tuple((
+            preceded(multispace0, tag("clobber")),
+            preceded(multispace0, tag("(")),
+            separated_list1(preceded(multispace0, tag(",")), parse_asm_register),
+            preceded(multispace0, tag(")")),
+        )),
+            |(_, _, regs, _)| AsmArgument::Clobbers(regs)
+        ),
+        map(tuple((
+            preceded(multispace0, alt((tag("inout"), tag("in"), tag("out")))),
+            preceded(multispace0, tag("(")),
+            parse_asm_register,
+            preceded(multispace0, tag(")")),
+            parse_expr,
+        )),
+            |(start, _, reg, _, expr)| {
+                let dir = match *start.fragment() {
+                    "in" => AsmDir::In,
+                    "out" => AsmDir::Out,
+                    _ => AsmDir::InOut,
+                };
+                let expr_span = expr.get_span();
+                AsmArgument::Operand(AsmOperand {
+                    dir,
+                    reg,
+                    expr,
+                    span: Span::combine(Span::from_parse_span(start), expr_span),
+                })
+            }
+        ),
+    ))(input)
+}
+
+fn parse_asm_register(input: ParseSpan) -> IResult<ParseSpan, AsmRegister> {
+    map(parse_string, |(name, span)| AsmRegister { name, span })(input)
+}
+
 pub fn parse_assign_expr(input: ParseSpan) -> IResult<ParseSpan, ExprAssign> {
     context(
         "assignment",
@@ -589,6 +719,7 @@ fn parse_call_expr(input: ParseSpan) -> IResult<ParseSpan, ExprCall> {
                 ExprCall {
                     ident: id,
                     args: args,
+                    is_tail: false,
                     span: Span::combine(
                         rid.span,
                         Span::from_parse_span(end),
@@ -666,7 +797,7 @@ pub fn parse_lit_expr(input: ParseSpan) -> IResult<ParseSpan, ExprLit> {
     context(
         "literal",
         alt((
-            map(parse_int,  |(val, span)| ExprLit { lit: Lit::Int (val), span: span }),
+            map(parse_int,  |(lit, span)| ExprLit { lit, span }),
             map(parse_bool, |(val, span)| ExprLit { lit: Lit::Bool(val), span: span }),
         )),
     )(input)
@@ -710,12 +841,13 @@ pub fn parse_return_expr(input: ParseSpan) -> IResult<ParseSpan, ExprReturn> {
     context(
         "return",
         map(tuple((
-            preceded(multispace0, tag("return")),
+            preceded(multispace0, alt((tag("return"), tag("become")))),
             opt(preceded(multispace1, parse_expr )),
             preceded(multispace0, peek(tag(";"))),
         )),
             |(start, expr, end)| ExprReturn {
                 expr: Box::new(expr),
+                is_become: *start.fragment() == "become",
                 span: Span::combine(
                     Span::from_parse_span(start),
                     Span::from_parse_span(end)
@@ -756,10 +888,26 @@ pub fn parse_while_expr(input: ParseSpan) -> IResult<ParseSpan, ExprWhile> {
     )(input)
 }
 
-pub fn parse_int(input: ParseSpan) -> IResult<ParseSpan, (i32, Span)> {
+/**
+ * Parses an integer literal with an optional type suffix e.g. `5`, `5i64` or `5u64`,
+ * integers without a suffix are always i32.
+ */
+pub fn parse_int(input: ParseSpan) -> IResult<ParseSpan, (Lit, Span)> {
     let (input, digits) = preceded(multispace0, digit1)(input)?;
-    match digits.fragment().parse::<i32>() {
-        Ok(n) => Ok((input, (n, Span::from_parse_span(digits)))),
+    let (input, suffix) = opt(alt((tag("i32"), tag("i64"), tag("u64"))))(input)?;
+    let span = match suffix {
+        Some(s) => Span::combine(Span::from_parse_span(digits), Span::from_parse_span(s)),
+        None => Span::from_parse_span(digits),
+    };
+
+    let lit = match suffix.map(|s| *s.fragment()) {
+        Some("i64") => digits.fragment().parse::<i64>().map(Lit::Int64),
+        Some("u64") => digits.fragment().parse::<u64>().map(Lit::UInt64),
+        _           => digits.fragment().parse::<i32>().map(Lit::Int),
+    };
+
+    match lit {
+        Ok(lit) => Ok((input, (lit, span))),
         Err(e) => Err(Error(ParseError::new(digits, ParseErrorKind::ParseIntError(e)))),
     }
 }
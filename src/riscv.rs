@@ -0,0 +1,1460 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::ast::{Symbol, intern_string, resolve_symbol};
+use crate::ir::*;
+
+/***************************************************************************
+ * RISC-V Backend:
+ * - Compiles the IR into RV32IM machine code, the program is executed by the
+ *   simulator in `src/rvsim.rs` so it can be run on any host.
+ * - The IR is built using 32-bit addresses, 64-bit integers are not supported
+ *   since there are only 32-bit registers.
+ * - Variables are assigned the callee-saved registers s1-s11 using linear scan
+ *   over their live intervals, these are preserved across calls so no moves
+ *   are needed around them. The rest of the variables are stored in the stack
+ *   frame and t0-t3 are used as scratch registers within an instruction.
+ * - Functions use the ILP32 calling convention, the first eight arguments are
+ *   passed in a0-a7 and the rest on the stack, the result is returned in a0.
+ * - Foreign functions are called using `ecall` with the hook number in a7,
+ *   the simulator passes the arguments on to the host function.
+ * - Division by zero and overflow do not trap in RV32IM, these are checked
+ *   before dividing and reported using `ecall` the same as the interpreter.
+ ***************************************************************************/
+
+pub const RISCV_ADDR_SIZE: isize = 4;
+pub const RISCV_SYS_EXIT: i32 = 93; // same number as the exit syscall on linux
+pub const RISCV_ECALL_RUNTIME_ERROR: i32 = 1023; // reports one of the runtime errors given by a0
+pub const RISCV_RUNTIME_ERRORS: [&str; 3] = [
+    "attempt to divide by zero",
+    "attempt to divide with overflow",
+    "attempt to calculate the remainder with overflow",
+];
+pub const RISCV_ECALL_HOOK_BASE: i32 = 1024; // hook numbers start after the syscall numbers
+const RISCV_MAX_HOOK_ARGUMENTS: usize = 7; // a7 is used by the hook number
+const RISCV_ARGUMENT_REGISTERS: [RiscvReg; 8] = [
+    RiscvReg::A0, RiscvReg::A1, RiscvReg::A2, RiscvReg::A3,
+    RiscvReg::A4, RiscvReg::A5, RiscvReg::A6, RiscvReg::A7,
+];
+const RISCV_ALLOCATABLE_REGISTERS: [RiscvReg; 11] = [
+    RiscvReg::S1, RiscvReg::S2, RiscvReg::S3, RiscvReg::S4,  RiscvReg::S5, RiscvReg::S6,
+    RiscvReg::S7, RiscvReg::S8, RiscvReg::S9, RiscvReg::S10, RiscvReg::S11,
+];
+
+/**
+ * Machine code loaded at address zero, the entry point comes first and exits
+ * with the return value of main.
+ */
+pub struct RiscvProgram {
+    pub machine_code: Vec<u8>,
+    pub assembly: String,
+    pub hooks: Vec<RiscvEcallHook>,
+}
+
+/**
+ * Foreign function called by `ecall`, the hook number is its index plus `RISCV_ECALL_HOOK_BASE`.
+ */
+pub struct RiscvEcallHook {
+    pub name: String,
+    pub address: usize, // host function using the C calling convention
+    pub num_args: usize,
+    pub return_type: IrType,
+}
+
+struct RiscvAssembler {
+    instructions: Vec<RiscvInstruction>, // the entire program, encoded after the jumps are resolved
+    locations: HashMap<IrIdent, RiscvLocation>, // decided by the register allocator
+    variable_types: HashMap<IrIdent, IrType>, // declared type of locals and parameters
+    arguments: Vec<(IrOperand, IrType)>, // ordered left-to-right
+    temp_slots: HashMap<usize, i32>, // stack slots for references to values, e.g. `&mut 5`
+    saved_registers: Vec<RiscvReg>, // callee-saved registers used by the current function
+    hooks: Vec<RiscvEcallHook>,
+    hook_numbers: HashMap<IrIdent, i32>,
+    foreign_functions: HashMap<u32, (IrIdent, usize)>, // truncated function address to foreign function and its address
+    function_labels: HashSet<IrIdent>, // labels that keep their name in the assembly
+    debug_break_symbol: Symbol,
+    local_label_symbol: Symbol,
+    local_label_index: u32, // labels created by the backend e.g. loops in the pow instruction
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RiscvLocation {
+    Register(RiscvReg),
+    Stack(i32), // offset from the frame pointer
+}
+
+/**
+ * RV32IM instruction, every field that is not used by the opcode is zero.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiscvInstruction {
+    pub opcode: RiscvOpcode,
+    pub rd: RiscvReg,
+    pub rs1: RiscvReg,
+    pub rs2: RiscvReg,
+    pub imm: i32,
+    label: Option<IrIdent>, // target of branches and jumps, or the label placed by Label
+    is_long_branch: bool, // inverted branch over a jump, used when the target is out of range
+}
+
+impl Default for RiscvInstruction {
+    fn default() -> Self {
+        RiscvInstruction {
+            opcode: RiscvOpcode::Addi,
+            rd: RiscvReg::Zero,
+            rs1: RiscvReg::Zero,
+            rs2: RiscvReg::Zero,
+            imm: 0,
+            label: None,
+            is_long_branch: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiscvOpcode {
+    Label, // pseudo instruction marking the position of a label
+    Lui,
+    Auipc,
+    Jal,
+    Jalr,
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+    Sb,
+    Sh,
+    Sw,
+    Addi,
+    Slti,
+    Sltiu,
+    Xori,
+    Ori,
+    Andi,
+    Slli,
+    Srli,
+    Srai,
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Fence,
+    Ecall,
+    Ebreak,
+}
+
+/**
+ * Integer registers in the order of their register number.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RiscvReg {
+    Zero,
+    Ra,
+    Sp,
+    Gp,
+    Tp,
+    T0,
+    T1,
+    T2,
+    S0, // frame pointer
+    S1,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    S7,
+    S8,
+    S9,
+    S10,
+    S11,
+    T3,
+    T4,
+    T5,
+    T6,
+}
+
+pub const RISCV_REGISTERS: [RiscvReg; 32] = [
+    RiscvReg::Zero, RiscvReg::Ra, RiscvReg::Sp,  RiscvReg::Gp,  RiscvReg::Tp, RiscvReg::T0, RiscvReg::T1, RiscvReg::T2,
+    RiscvReg::S0,   RiscvReg::S1, RiscvReg::A0,  RiscvReg::A1,  RiscvReg::A2, RiscvReg::A3, RiscvReg::A4, RiscvReg::A5,
+    RiscvReg::A6,   RiscvReg::A7, RiscvReg::S2,  RiscvReg::S3,  RiscvReg::S4, RiscvReg::S5, RiscvReg::S6, RiscvReg::S7,
+    RiscvReg::S8,   RiscvReg::S9, RiscvReg::S10, RiscvReg::S11, RiscvReg::T3, RiscvReg::T4, RiscvReg::T5, RiscvReg::T6,
+];
+
+/**
+ * Compiles the IR into a program for the simulator, the IR has to be built with `RISCV_ADDR_SIZE`.
+ */
+pub fn compile_ir_to_riscv_program(
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>
+) -> RiscvProgram {
+    let mut rv = RiscvAssembler {
+        instructions: Vec::new(),
+        locations: HashMap::new(),
+        variable_types: HashMap::new(),
+        arguments: Vec::new(),
+        temp_slots: HashMap::new(),
+        saved_registers: Vec::new(),
+        hooks: Vec::new(),
+        hook_numbers: HashMap::new(),
+        foreign_functions: HashMap::new(),
+        function_labels: HashSet::new(),
+        debug_break_symbol: intern_string("debug_break"),
+        local_label_symbol: intern_string(".rv"),
+        local_label_index: 0,
+    };
+
+    // NOTE(alexander): the IR calls foreign functions by their (truncated) address, map them back to their names
+    for (ident, bb) in functions {
+        if let Some(addr) = bb.func_address {
+            if addr != 0 {
+                rv.foreign_functions.insert(addr as u32, (*ident, addr));
+            }
+        } else {
+            rv.function_labels.insert(*ident);
+        }
+    }
+
+    let main_ident = create_ir_ident(intern_string("main"), 0);
+    match functions.get(&main_ident) {
+        Some(bb) => push_riscv_entry_point(&mut rv, main_ident, bb.return_type),
+        None => panic!("riscv: failed to locate `main` function"),
+    }
+
+    // Compile the functions in the order they are defined in
+    let mut defined: Vec<(&IrIdent, &IrBasicBlock)> = functions.iter().filter(|(_, bb)| !bb.is_foreign).collect();
+    defined.sort_by_key(|(_, bb)| bb.prologue_index);
+    for (ident, bb) in defined {
+        let insns = &instructions[bb.prologue_index + 1..bb.epilogue_index];
+        push_riscv_function(&mut rv, *ident, insns, bb);
+    }
+
+    let positions = resolve_riscv_branches(&mut rv.instructions);
+    let machine_code = encode_riscv_instructions(&rv, &positions);
+    let assembly = format_riscv_assembly(&rv, &positions);
+
+    RiscvProgram {
+        machine_code,
+        assembly,
+        hooks: rv.hooks,
+    }
+}
+
+/**
+ * Entry point placed at address zero, the simulator starts with the stack pointer
+ * at the end of memory. The result of main is passed to the exit syscall.
+ */
+fn push_riscv_entry_point(rv: &mut RiscvAssembler, main_label: IrIdent, return_type: IrType) {
+    let start_label = create_ir_ident(intern_string("_start"), 0);
+    rv.function_labels.insert(start_label);
+    push_riscv_label(rv, start_label);
+    push_riscv_jump(rv, RiscvOpcode::Jal, RiscvReg::Ra, main_label);
+
+    // NOTE(alexander): main without return value exits with code 0
+    if return_type == IrType::None {
+        push_riscv_li(rv, RiscvReg::A0, 0);
+    }
+
+    push_riscv_li(rv, RiscvReg::A7, RISCV_SYS_EXIT);
+    push_riscv_instruction(rv, RiscvOpcode::Ecall, RiscvReg::Zero, RiscvReg::Zero, RiscvReg::Zero, 0);
+}
+
+/***************************************************************************
+ * Register allocation
+ ***************************************************************************/
+
+/**
+ * Linear scan register allocation over the live intervals of the function, same as the x86 backend
+ * but only using callee-saved registers. Variables without register are stored in the stack frame
+ * below the saved registers, returns the size of the stack frame excluding outgoing arguments.
+ */
+fn allocate_riscv_registers(rv: &mut RiscvAssembler, insns: &[IrInstruction], bb: &IrBasicBlock) -> i32 {
+    rv.locations.clear();
+    rv.variable_types.clear();
+    rv.temp_slots.clear();
+
+    let mut address_taken: Vec<IrIdent> = Vec::new();
+    for insn in insns {
+        match (&insn.opcode, insn.op1, insn.op2) {
+            (IrOpcode::Alloca, IrOperand::Ident(ident), _) |
+            (IrOpcode::Param, IrOperand::Ident(ident), _) => {
+                rv.variable_types.entry(ident).or_insert(insn.ty);
+            }
+            (IrOpcode::CopyFromRef, _, IrOperand::Ident(ident)) if !address_taken.contains(&ident) => {
+                address_taken.push(ident);
+            }
+            _ => {}
+        }
+    }
+
+    let mut intervals: Vec<(IrIdent, IrLiveInterval)> = bb.live_intervals.iter()
+        .filter(|(ident, _)| !address_taken.contains(ident))
+        .map(|(ident, interval)| (*ident, interval.clone()))
+        .collect();
+    intervals.sort_by_cached_key(|(ident, interval)| (interval.begin, interval.end, ident.to_string()));
+
+    let mut spilled: Vec<IrIdent> = Vec::new();
+    let mut active: Vec<(IrIdent, usize, RiscvReg)> = Vec::new(); // identifier, end and register
+    for (ident, interval) in &intervals {
+        // NOTE(alexander): an interval ending where another one begins can share its register,
+        // every instruction reads all of its operands before the destination is written.
+        active.retain(|(_, end, _)| *end > interval.begin);
+
+        let free_reg = RISCV_ALLOCATABLE_REGISTERS.iter().copied().find(|reg| active.iter().all(|(_, _, r)| r != reg));
+        if let Some(reg) = free_reg {
+            rv.locations.insert(*ident, RiscvLocation::Register(reg));
+            active.push((*ident, interval.end, reg));
+            continue;
+        }
+
+        // Spill the interval that ends last, if it is not the current one then steal its register
+        let (index, (other, end, reg)) = active.iter().copied().enumerate().max_by_key(|(_, (_, end, _))| *end).unwrap();
+        if end > interval.end {
+            rv.locations.insert(*ident, RiscvLocation::Register(reg));
+            rv.locations.remove(&other);
+            spilled.push(other);
+            active[index] = (*ident, interval.end, reg);
+        } else {
+            spilled.push(*ident);
+        }
+    }
+
+    // NOTE(alexander): parameters after the argument registers are read from the caller's outgoing area
+    let params = insns.iter()
+        .take_while(|insn| insn.opcode != IrOpcode::AllocParams)
+        .filter(|insn| insn.opcode == IrOpcode::Param);
+    for (i, insn) in params.enumerate().skip(RISCV_ARGUMENT_REGISTERS.len()) {
+        let offset = (i - RISCV_ARGUMENT_REGISTERS.len()) as i32 * RISCV_ADDR_SIZE as i32;
+        rv.locations.insert(get_ir_ident(insn.op1), RiscvLocation::Stack(offset));
+    }
+
+    rv.saved_registers = RISCV_ALLOCATABLE_REGISTERS.iter()
+        .copied()
+        .filter(|reg| rv.locations.values().any(|location| *location == RiscvLocation::Register(*reg)))
+        .collect();
+
+    // Return address and frame pointer are saved first, followed by the callee-saved registers
+    let mut frame_size = (2 + rv.saved_registers.len() as i32) * RISCV_ADDR_SIZE as i32;
+    spilled.extend(address_taken);
+    for insn in insns {
+        for op in riscv_variable_operands(insn) {
+            if let IrOperand::Ident(ident) = op {
+                if !rv.locations.contains_key(&ident) && !spilled.contains(&ident) {
+                    spilled.push(ident);
+                }
+            }
+        }
+    }
+
+    for ident in spilled {
+        rv.locations.entry(ident).or_insert_with(|| {
+            frame_size += RISCV_ADDR_SIZE as i32;
+            RiscvLocation::Stack(-frame_size)
+        });
+    }
+
+    for (i, insn) in insns.iter().enumerate() {
+        if let (IrOpcode::CopyFromRef, IrOperand::Value(_)) = (&insn.opcode, insn.op2) {
+            frame_size += RISCV_ADDR_SIZE as i32;
+            rv.temp_slots.insert(i, -frame_size);
+        }
+    }
+
+    frame_size
+}
+
+/**
+ * Operands of the instruction that are variables, i.e. not labels or functions.
+ */
+fn riscv_variable_operands(insn: &IrInstruction) -> Vec<IrOperand> {
+    match insn.opcode {
+        IrOpcode::Nop         |
+        IrOpcode::AllocParams |
+        IrOpcode::Label       |
+        IrOpcode::Jump        |
+        IrOpcode::Prologue    |
+        IrOpcode::Epilogue    => vec![],
+
+        IrOpcode::IfLt |
+        IrOpcode::IfGt |
+        IrOpcode::IfLe |
+        IrOpcode::IfGe |
+        IrOpcode::IfEq |
+        IrOpcode::IfNe => vec![insn.op1, insn.op2],
+
+        IrOpcode::Call => vec![insn.op1],
+
+        _ => vec![insn.op1, insn.op2, insn.op3, insn.op4],
+    }
+}
+
+/***************************************************************************
+ * Instruction selection
+ ***************************************************************************/
+
+fn push_riscv_function(rv: &mut RiscvAssembler, ident: IrIdent, insns: &[IrInstruction], bb: &IrBasicBlock) {
+    let locals_size = allocate_riscv_registers(rv, insns, bb);
+
+    // Stack arguments of the calls are stored at the bottom of the stack frame
+    let mut outgoing_arguments_size = 0;
+    for insn in insns {
+        if let (IrOpcode::Call, IrOperand::Ident(_)) = (&insn.opcode, insn.op2) {
+            let num_stack_args = get_riscv_param_count(insn).saturating_sub(RISCV_ARGUMENT_REGISTERS.len());
+            outgoing_arguments_size = outgoing_arguments_size.max(num_stack_args as i32 * RISCV_ADDR_SIZE as i32);
+        }
+    }
+
+    // NOTE(alexander): the stack pointer is always 16-byte aligned
+    let frame_size = (locals_size + outgoing_arguments_size + 15) & !15;
+
+    // Prologue
+    push_riscv_label(rv, ident);
+    push_riscv_instruction(rv, RiscvOpcode::Addi, RiscvReg::Sp, RiscvReg::Sp, RiscvReg::Zero, -8);
+    push_riscv_instruction(rv, RiscvOpcode::Sw, RiscvReg::Zero, RiscvReg::Sp, RiscvReg::Ra, 4);
+    push_riscv_instruction(rv, RiscvOpcode::Sw, RiscvReg::Zero, RiscvReg::Sp, RiscvReg::S0, 0);
+    push_riscv_instruction(rv, RiscvOpcode::Addi, RiscvReg::S0, RiscvReg::Sp, RiscvReg::Zero, 8);
+    push_riscv_add_immediate(rv, RiscvReg::Sp, RiscvReg::Sp, -(frame_size - 8));
+    for (i, reg) in rv.saved_registers.clone().iter().enumerate() {
+        let offset = -(3 + i as i32) * RISCV_ADDR_SIZE as i32;
+        push_riscv_instruction(rv, RiscvOpcode::Sw, RiscvReg::Zero, RiscvReg::S0, *reg, offset);
+    }
+
+    // Function body
+    let mut params: Vec<IrOperand> = Vec::new();
+    let mut is_body = false;
+    for (i, insn) in insns.iter().enumerate() {
+        // NOTE(alexander): every register is 32-bit, the same as the wasm backend
+        if let IrType::I64 | IrType::U64 = insn.ty {
+            panic!("riscv: 64-bit integers are not supported by RV32IM, found `{}`", insn.ty);
+        }
+
+        let next_label = match insns.get(i + 1) {
+            Some(IrInstruction { opcode: IrOpcode::Label, op1: IrOperand::Ident(label), .. }) => Some(*label),
+            _ => None,
+        };
+
+        match insn.opcode {
+            IrOpcode::Nop => {
+                push_riscv_instruction(rv, RiscvOpcode::Addi, RiscvReg::Zero, RiscvReg::Zero, RiscvReg::Zero, 0);
+            }
+
+            IrOpcode::Alloca => {
+                // NOTE(alexander): stack space is reserved by the register allocator
+            }
+
+            IrOpcode::Param => {
+                if is_body {
+                    rv.arguments.push((insn.op1, insn.ty));
+                } else {
+                    params.push(insn.op1);
+                }
+            }
+
+            IrOpcode::AllocParams => {
+                // NOTE(alexander): argument registers are never allocated, so the moves cannot overlap
+                for (param, reg) in params.iter().zip(RISCV_ARGUMENT_REGISTERS.iter()) {
+                    push_riscv_store_operand(rv, *param, *reg);
+                }
+                is_body = true;
+            }
+
+            IrOpcode::Copy => {
+                match get_riscv_location(rv, insn.op1) {
+                    RiscvLocation::Register(dst) => push_riscv_load_operand_into(rv, insn.op2, dst),
+                    RiscvLocation::Stack(_) => {
+                        let src = push_riscv_load_operand(rv, insn.op2, RiscvReg::T0);
+                        push_riscv_store_operand(rv, insn.op1, src);
+                    }
+                }
+            }
+
+            IrOpcode::CopyFromRef => {
+                let offset = match insn.op2 {
+                    IrOperand::Ident(_) => match get_riscv_location(rv, insn.op2) {
+                        RiscvLocation::Stack(offset) => offset,
+                        RiscvLocation::Register(_) => panic!("riscv: referenced variable `{}` is not in memory", insn.op2),
+                    },
+
+                    op => {
+                        // Values are stored in a temporary stack slot to be able to reference them
+                        let offset = rv.temp_slots[&i];
+                        let src = push_riscv_load_operand(rv, op, RiscvReg::T0);
+                        let store = if insn.ty == IrType::PtrI8(1) { RiscvOpcode::Sb } else { RiscvOpcode::Sw };
+                        push_riscv_memory_access(rv, store, src, RiscvReg::S0, offset);
+                        offset
+                    }
+                };
+
+                let dst = get_riscv_destination(rv, insn.op1, RiscvReg::T0);
+                push_riscv_add_immediate(rv, dst, RiscvReg::S0, offset);
+                push_riscv_store_operand(rv, insn.op1, dst);
+            }
+
+            IrOpcode::CopyFromDeref => {
+                let addr = push_riscv_load_operand(rv, insn.op2, RiscvReg::T0);
+                let dst = get_riscv_destination(rv, insn.op1, RiscvReg::T1);
+                let load = if insn.ty == IrType::I8 { RiscvOpcode::Lbu } else { RiscvOpcode::Lw };
+                push_riscv_instruction(rv, load, dst, addr, RiscvReg::Zero, 0);
+                push_riscv_store_operand(rv, insn.op1, dst);
+            }
+
+            IrOpcode::CopyToDeref => {
+                let addr = push_riscv_load_operand(rv, insn.op1, RiscvReg::T0);
+                let src = push_riscv_load_operand(rv, insn.op2, RiscvReg::T1);
+                let store = if insn.ty == IrType::I8 { RiscvOpcode::Sb } else { RiscvOpcode::Sw };
+                push_riscv_instruction(rv, store, RiscvReg::Zero, addr, src, 0);
+            }
+
+            IrOpcode::Clear => {
+                push_riscv_store_operand(rv, insn.op1, RiscvReg::Zero);
+            }
+
+            IrOpcode::Add |
+            IrOpcode::Sub |
+            IrOpcode::Mul |
+            IrOpcode::Div |
+            IrOpcode::Mod |
+            IrOpcode::And |
+            IrOpcode::Or  |
+            IrOpcode::Xor => {
+                let is_unsigned = is_riscv_unsigned(insn.ty);
+                let (opcode, imm_opcode) = match insn.opcode {
+                    IrOpcode::Add => (RiscvOpcode::Add, Some(RiscvOpcode::Addi)),
+                    IrOpcode::Sub => (RiscvOpcode::Sub, None),
+                    IrOpcode::Mul => (RiscvOpcode::Mul, None),
+                    IrOpcode::Div if is_unsigned => (RiscvOpcode::Divu, None),
+                    IrOpcode::Div => (RiscvOpcode::Div, None),
+                    IrOpcode::Mod if is_unsigned => (RiscvOpcode::Remu, None),
+                    IrOpcode::Mod => (RiscvOpcode::Rem, None),
+                    IrOpcode::And => (RiscvOpcode::And, Some(RiscvOpcode::Andi)),
+                    IrOpcode::Or  => (RiscvOpcode::Or,  Some(RiscvOpcode::Ori)),
+                    _             => (RiscvOpcode::Xor, Some(RiscvOpcode::Xori)),
+                };
+
+                // Immediate operands are used when possible, subtraction adds the negated value
+                let (opcode, imm) = match (imm_opcode, get_riscv_immediate(insn.op3)) {
+                    (Some(imm_opcode), Some(imm)) => (imm_opcode, Some(imm)),
+                    (None, Some(imm)) if opcode == RiscvOpcode::Sub && is_riscv_immediate(-imm) => {
+                        (RiscvOpcode::Addi, Some(-imm))
+                    }
+                    _ => (opcode, None),
+                };
+
+                let lhs = push_riscv_load_operand(rv, insn.op2, RiscvReg::T0);
+                let dst = get_riscv_destination(rv, insn.op1, RiscvReg::T0);
+                match imm {
+                    Some(imm) => push_riscv_instruction(rv, opcode, dst, lhs, RiscvReg::Zero, imm),
+                    None => {
+                        let rhs = push_riscv_load_operand(rv, insn.op3, RiscvReg::T1);
+                        if matches!(insn.opcode, IrOpcode::Div | IrOpcode::Mod) {
+                            push_riscv_division_checks(rv, &insn.opcode, is_unsigned, lhs, rhs);
+                        }
+                        push_riscv_instruction(rv, opcode, dst, lhs, rhs, 0);
+                    }
+                }
+                push_riscv_store_operand(rv, insn.op1, dst);
+            }
+
+            IrOpcode::Pow => {
+                // NOTE(alexander): the exponent is treated as an unsigned integer, same as the interpreter
+                let exp = RiscvReg::T0;
+                let base = RiscvReg::T1;
+                let result = RiscvReg::T2;
+                push_riscv_load_operand_into(rv, insn.op3, exp);
+                push_riscv_load_operand_into(rv, insn.op2, base);
+                push_riscv_li(rv, result, 1);
+
+                // Exponentiation by squaring, multiply the result by the base for every bit set in the exponent
+                let loop_label = create_riscv_label(rv);
+                let skip_label = create_riscv_label(rv);
+                let exit_label = create_riscv_label(rv);
+                push_riscv_branch(rv, RiscvOpcode::Beq, exp, RiscvReg::Zero, exit_label);
+                push_riscv_label(rv, loop_label);
+                push_riscv_instruction(rv, RiscvOpcode::Andi, RiscvReg::T3, exp, RiscvReg::Zero, 1);
+                push_riscv_branch(rv, RiscvOpcode::Beq, RiscvReg::T3, RiscvReg::Zero, skip_label);
+                push_riscv_instruction(rv, RiscvOpcode::Mul, result, result, base, 0);
+                push_riscv_label(rv, skip_label);
+                push_riscv_instruction(rv, RiscvOpcode::Srli, exp, exp, RiscvReg::Zero, 1);
+                push_riscv_branch(rv, RiscvOpcode::Beq, exp, RiscvReg::Zero, exit_label);
+                push_riscv_instruction(rv, RiscvOpcode::Mul, base, base, base, 0);
+                push_riscv_jump(rv, RiscvOpcode::Jal, RiscvReg::Zero, loop_label);
+                push_riscv_label(rv, exit_label);
+                push_riscv_store_operand(rv, insn.op1, result);
+            }
+
+            IrOpcode::Lt |
+            IrOpcode::Le |
+            IrOpcode::Gt |
+            IrOpcode::Ge |
+            IrOpcode::Eq |
+            IrOpcode::Ne => {
+                let slt = if is_riscv_unsigned(insn.ty) { RiscvOpcode::Sltu } else { RiscvOpcode::Slt };
+                let lhs = push_riscv_load_operand(rv, insn.op2, RiscvReg::T0);
+                let rhs = push_riscv_load_operand(rv, insn.op3, RiscvReg::T1);
+                let dst = get_riscv_destination(rv, insn.op1, RiscvReg::T0);
+                match insn.opcode {
+                    IrOpcode::Lt => push_riscv_instruction(rv, slt, dst, lhs, rhs, 0),
+                    IrOpcode::Gt => push_riscv_instruction(rv, slt, dst, rhs, lhs, 0),
+                    IrOpcode::Le => {
+                        push_riscv_instruction(rv, slt, dst, rhs, lhs, 0);
+                        push_riscv_instruction(rv, RiscvOpcode::Xori, dst, dst, RiscvReg::Zero, 1);
+                    }
+                    IrOpcode::Ge => {
+                        push_riscv_instruction(rv, slt, dst, lhs, rhs, 0);
+                        push_riscv_instruction(rv, RiscvOpcode::Xori, dst, dst, RiscvReg::Zero, 1);
+                    }
+                    IrOpcode::Eq => {
+                        push_riscv_instruction(rv, RiscvOpcode::Xor, dst, lhs, rhs, 0);
+                        push_riscv_instruction(rv, RiscvOpcode::Sltiu, dst, dst, RiscvReg::Zero, 1);
+                    }
+                    _ => {
+                        push_riscv_instruction(rv, RiscvOpcode::Xor, dst, lhs, rhs, 0);
+                        push_riscv_instruction(rv, RiscvOpcode::Sltu, dst, RiscvReg::Zero, dst, 0);
+                    }
+                }
+                push_riscv_store_operand(rv, insn.op1, dst);
+            }
+
+            IrOpcode::Select => {
+                // Branchless select, dst = op4 ^ ((op3 ^ op4) & -op2) where op2 is either 0 or 1
+                let cond = push_riscv_load_operand(rv, insn.op2, RiscvReg::T0);
+                let lhs = push_riscv_load_operand(rv, insn.op3, RiscvReg::T1);
+                let rhs = push_riscv_load_operand(rv, insn.op4, RiscvReg::T2);
+                push_riscv_instruction(rv, RiscvOpcode::Sub, RiscvReg::T0, RiscvReg::Zero, cond, 0);
+                push_riscv_instruction(rv, RiscvOpcode::Xor, RiscvReg::T1, lhs, rhs, 0);
+                push_riscv_instruction(rv, RiscvOpcode::And, RiscvReg::T1, RiscvReg::T1, RiscvReg::T0, 0);
+                let dst = get_riscv_destination(rv, insn.op1, RiscvReg::T0);
+                push_riscv_instruction(rv, RiscvOpcode::Xor, dst, RiscvReg::T1, rhs, 0);
+                push_riscv_store_operand(rv, insn.op1, dst);
+            }
+
+            IrOpcode::IfLt |
+            IrOpcode::IfGt |
+            IrOpcode::IfLe |
+            IrOpcode::IfGe |
+            IrOpcode::IfEq |
+            IrOpcode::IfNe => {
+                let is_unsigned = is_riscv_unsigned(insn.ty);
+                let lhs = push_riscv_load_operand(rv, insn.op1, RiscvReg::T0);
+                let rhs = push_riscv_load_operand(rv, insn.op2, RiscvReg::T1);
+
+                // NOTE(alexander): there are only less than and greater or equal branches, swap the operands of the rest
+                let (opcode, lhs, rhs) = match insn.opcode {
+                    IrOpcode::IfLt => (if is_unsigned { RiscvOpcode::Bltu } else { RiscvOpcode::Blt }, lhs, rhs),
+                    IrOpcode::IfGt => (if is_unsigned { RiscvOpcode::Bltu } else { RiscvOpcode::Blt }, rhs, lhs),
+                    IrOpcode::IfLe => (if is_unsigned { RiscvOpcode::Bgeu } else { RiscvOpcode::Bge }, rhs, lhs),
+                    IrOpcode::IfGe => (if is_unsigned { RiscvOpcode::Bgeu } else { RiscvOpcode::Bge }, lhs, rhs),
+                    IrOpcode::IfEq => (RiscvOpcode::Beq, lhs, rhs),
+                    _              => (RiscvOpcode::Bne, lhs, rhs),
+                };
+                push_riscv_branch(rv, opcode, lhs, rhs, get_ir_ident(insn.op3));
+            }
+
+            IrOpcode::Jump => {
+                let label = get_ir_ident(insn.op1);
+                if next_label != Some(label) { // NOTE(alexander): falls through to the next label
+                    push_riscv_jump(rv, RiscvOpcode::Jal, RiscvReg::Zero, label);
+                }
+            }
+
+            IrOpcode::Label => {
+                push_riscv_label(rv, get_ir_ident(insn.op1));
+            }
+
+            IrOpcode::Call => {
+                let num_args = get_riscv_param_count(insn);
+                let args = rv.arguments.split_off(rv.arguments.len() - num_args);
+
+                match insn.op2 {
+                    IrOperand::Ident(func) if func.symbol == rv.debug_break_symbol => {
+                        push_riscv_instruction(rv, RiscvOpcode::Ebreak, RiscvReg::Zero, RiscvReg::Zero, RiscvReg::Zero, 0);
+                        continue;
+                    }
+
+                    IrOperand::Ident(func) => {
+                        for (i, (arg, _)) in args.iter().enumerate().skip(RISCV_ARGUMENT_REGISTERS.len()) {
+                            let src = push_riscv_load_operand(rv, *arg, RiscvReg::T0);
+                            let offset = (i - RISCV_ARGUMENT_REGISTERS.len()) as i32 * RISCV_ADDR_SIZE as i32;
+                            push_riscv_memory_access(rv, RiscvOpcode::Sw, src, RiscvReg::Sp, offset);
+                        }
+
+                        for ((arg, _), reg) in args.iter().zip(RISCV_ARGUMENT_REGISTERS.iter()) {
+                            push_riscv_load_operand_into(rv, *arg, *reg);
+                        }
+                        push_riscv_jump(rv, RiscvOpcode::Jal, RiscvReg::Ra, func);
+                    }
+
+                    IrOperand::Value(IrValue::U32(addr)) => {
+                        // NOTE(alexander): interpreter level intrinsics (e.g. trace) are not called
+                        let (func, address) = match rv.foreign_functions.get(&addr) {
+                            Some(foreign) => *foreign,
+                            None => continue,
+                        };
+
+                        if args.len() > RISCV_MAX_HOOK_ARGUMENTS {
+                            panic!("riscv: foreign function `{}` can take at most {} arguments, found {}",
+                                   func, RISCV_MAX_HOOK_ARGUMENTS, args.len());
+                        }
+
+                        for ((arg, _), reg) in args.iter().zip(RISCV_ARGUMENT_REGISTERS.iter()) {
+                            push_riscv_load_operand_into(rv, *arg, *reg);
+                        }
+
+                        let hook = get_riscv_ecall_hook(rv, func, address, insn);
+                        push_riscv_li(rv, RiscvReg::A7, hook);
+                        push_riscv_instruction(rv, RiscvOpcode::Ecall, RiscvReg::Zero, RiscvReg::Zero, RiscvReg::Zero, 0);
+                    }
+
+                    _ => panic!("riscv: cannot call `{}`, the IR has to be built with 32-bit addresses", insn.op2),
+                }
+
+                if insn.ty != IrType::None {
+                    push_riscv_store_operand(rv, insn.op1, RiscvReg::A0);
+                }
+            }
+
+            IrOpcode::Return => {
+                if insn.op1 != IrOperand::None {
+                    push_riscv_load_operand_into(rv, insn.op1, RiscvReg::A0);
+                }
+
+                let exit_label = bb.exit_label;
+                if next_label != Some(exit_label) {
+                    push_riscv_jump(rv, RiscvOpcode::Jal, RiscvReg::Zero, exit_label);
+                }
+            }
+
+            IrOpcode::Prologue |
+            IrOpcode::Epilogue => panic!("riscv: unexpected `{}` inside of function body", insn.opcode),
+
+            IrOpcode::Phi => panic!("riscv: phi instructions has to be removed before code generation"),
+
+            IrOpcode::AsmIn |
+            IrOpcode::Asm   |
+            IrOpcode::AsmOut => panic!("riscv: inline assembly is only supported by the x86 backend"),
+        }
+    }
+
+    // Epilogue
+    for (i, reg) in rv.saved_registers.clone().iter().enumerate() {
+        let offset = -(3 + i as i32) * RISCV_ADDR_SIZE as i32;
+        push_riscv_instruction(rv, RiscvOpcode::Lw, *reg, RiscvReg::S0, RiscvReg::Zero, offset);
+    }
+    push_riscv_instruction(rv, RiscvOpcode::Addi, RiscvReg::Sp, RiscvReg::S0, RiscvReg::Zero, -8);
+    push_riscv_instruction(rv, RiscvOpcode::Lw, RiscvReg::Ra, RiscvReg::Sp, RiscvReg::Zero, 4);
+    push_riscv_instruction(rv, RiscvOpcode::Lw, RiscvReg::S0, RiscvReg::Sp, RiscvReg::Zero, 0);
+    push_riscv_instruction(rv, RiscvOpcode::Addi, RiscvReg::Sp, RiscvReg::Sp, RiscvReg::Zero, 8);
+    push_riscv_instruction(rv, RiscvOpcode::Jalr, RiscvReg::Zero, RiscvReg::Ra, RiscvReg::Zero, 0);
+}
+
+/***************************************************************************
+ * Instruction selection helpers
+ ***************************************************************************/
+
+fn push_riscv_instruction(rv: &mut RiscvAssembler, opcode: RiscvOpcode, rd: RiscvReg, rs1: RiscvReg, rs2: RiscvReg, imm: i32) {
+    rv.instructions.push(RiscvInstruction {
+        opcode,
+        rd,
+        rs1,
+        rs2,
+        imm,
+        ..Default::default()
+    });
+}
+
+fn push_riscv_label(rv: &mut RiscvAssembler, label: IrIdent) {
+    rv.instructions.push(RiscvInstruction {
+        opcode: RiscvOpcode::Label,
+        label: Some(label),
+        ..Default::default()
+    });
+}
+
+fn push_riscv_branch(rv: &mut RiscvAssembler, opcode: RiscvOpcode, rs1: RiscvReg, rs2: RiscvReg, label: IrIdent) {
+    rv.instructions.push(RiscvInstruction {
+        opcode,
+        rs1,
+        rs2,
+        label: Some(label),
+        ..Default::default()
+    });
+}
+
+fn push_riscv_jump(rv: &mut RiscvAssembler, opcode: RiscvOpcode, rd: RiscvReg, label: IrIdent) {
+    rv.instructions.push(RiscvInstruction {
+        opcode,
+        rd,
+        label: Some(label),
+        ..Default::default()
+    });
+}
+
+fn create_riscv_label(rv: &mut RiscvAssembler) -> IrIdent {
+    let label = create_ir_ident(rv.local_label_symbol, rv.local_label_index);
+    rv.local_label_index += 1;
+    label
+}
+
+/**
+ * Loads the 32-bit constant, large values are split into lui and addi where
+ * the upper part is rounded since addi sign-extends the lower 12 bits.
+ */
+fn push_riscv_li(rv: &mut RiscvAssembler, rd: RiscvReg, value: i32) {
+    if is_riscv_immediate(value) {
+        push_riscv_instruction(rv, RiscvOpcode::Addi, rd, RiscvReg::Zero, RiscvReg::Zero, value);
+        return;
+    }
+
+    let lower = (value << 20) >> 20;
+    let upper = value.wrapping_sub(lower) >> 12;
+    push_riscv_instruction(rv, RiscvOpcode::Lui, rd, RiscvReg::Zero, RiscvReg::Zero, upper & 0xfffff);
+    if lower != 0 {
+        push_riscv_instruction(rv, RiscvOpcode::Addi, rd, rd, RiscvReg::Zero, lower);
+    }
+}
+
+/**
+ * Reports a runtime error if the divisor is zero or the signed division overflows, T2 is used as scratch.
+ */
+fn push_riscv_division_checks(rv: &mut RiscvAssembler, opcode: &IrOpcode, is_unsigned: bool, lhs: RiscvReg, rhs: RiscvReg) {
+    let nonzero_label = create_riscv_label(rv);
+    push_riscv_branch(rv, RiscvOpcode::Bne, rhs, RiscvReg::Zero, nonzero_label);
+    push_riscv_runtime_error(rv, 0);
+    push_riscv_label(rv, nonzero_label);
+
+    if !is_unsigned {
+        let exit_label = create_riscv_label(rv);
+        push_riscv_li(rv, RiscvReg::T2, -1);
+        push_riscv_branch(rv, RiscvOpcode::Bne, rhs, RiscvReg::T2, exit_label);
+        push_riscv_li(rv, RiscvReg::T2, i32::MIN);
+        push_riscv_branch(rv, RiscvOpcode::Bne, lhs, RiscvReg::T2, exit_label);
+        push_riscv_runtime_error(rv, if *opcode == IrOpcode::Div { 1 } else { 2 });
+        push_riscv_label(rv, exit_label);
+    }
+}
+
+/**
+ * Exits the program with the runtime error at the given index of `RISCV_RUNTIME_ERRORS`.
+ */
+fn push_riscv_runtime_error(rv: &mut RiscvAssembler, error: i32) {
+    push_riscv_li(rv, RiscvReg::A0, error);
+    push_riscv_li(rv, RiscvReg::A7, RISCV_ECALL_RUNTIME_ERROR);
+    push_riscv_instruction(rv, RiscvOpcode::Ecall, RiscvReg::Zero, RiscvReg::Zero, RiscvReg::Zero, 0);
+}
+
+/**
+ * Adds a constant to the register, T3 holds the constant if it does not fit in 12 bits.
+ */
+fn push_riscv_add_immediate(rv: &mut RiscvAssembler, rd: RiscvReg, rs1: RiscvReg, value: i32) {
+    if is_riscv_immediate(value) {
+        if rd != rs1 || value != 0 {
+            push_riscv_instruction(rv, RiscvOpcode::Addi, rd, rs1, RiscvReg::Zero, value);
+        }
+    } else {
+        push_riscv_li(rv, RiscvReg::T3, value);
+        push_riscv_instruction(rv, RiscvOpcode::Add, rd, rs1, RiscvReg::T3, 0);
+    }
+}
+
+/**
+ * Loads or stores the register at base + offset, T3 holds the address if the offset does not fit in 12 bits.
+ */
+fn push_riscv_memory_access(rv: &mut RiscvAssembler, opcode: RiscvOpcode, reg: RiscvReg, base: RiscvReg, offset: i32) {
+    let (base, offset) = if is_riscv_immediate(offset) {
+        (base, offset)
+    } else {
+        push_riscv_li(rv, RiscvReg::T3, offset);
+        push_riscv_instruction(rv, RiscvOpcode::Add, RiscvReg::T3, RiscvReg::T3, base, 0);
+        (RiscvReg::T3, 0)
+    };
+
+    match opcode {
+        RiscvOpcode::Sb | RiscvOpcode::Sh | RiscvOpcode::Sw => {
+            push_riscv_instruction(rv, opcode, RiscvReg::Zero, base, reg, offset)
+        }
+        _ => push_riscv_instruction(rv, opcode, reg, base, RiscvReg::Zero, offset),
+    }
+}
+
+/**
+ * Returns the register containing the value of the operand, the scratch register is only
+ * used if the operand is not already located in a register.
+ */
+fn push_riscv_load_operand(rv: &mut RiscvAssembler, op: IrOperand, scratch: RiscvReg) -> RiscvReg {
+    match op {
+        IrOperand::Ident(_) => match get_riscv_location(rv, op) {
+            RiscvLocation::Register(reg) => reg,
+            RiscvLocation::Stack(offset) => {
+                let (load, _) = get_riscv_memory_opcodes(rv, op);
+                push_riscv_memory_access(rv, load, scratch, RiscvReg::S0, offset);
+                scratch
+            }
+        },
+
+        IrOperand::Value(_) => match get_riscv_value(op) {
+            0 => RiscvReg::Zero,
+            value => {
+                push_riscv_li(rv, scratch, value);
+                scratch
+            }
+        },
+
+        IrOperand::None => panic!("riscv: unexpected empty operand"),
+    }
+}
+
+fn push_riscv_load_operand_into(rv: &mut RiscvAssembler, op: IrOperand, rd: RiscvReg) {
+    let reg = push_riscv_load_operand(rv, op, rd);
+    if reg != rd {
+        push_riscv_instruction(rv, RiscvOpcode::Addi, rd, reg, RiscvReg::Zero, 0);
+    }
+}
+
+/**
+ * Writes the register to the location of the operand.
+ */
+fn push_riscv_store_operand(rv: &mut RiscvAssembler, op: IrOperand, reg: RiscvReg) {
+    match get_riscv_location(rv, op) {
+        RiscvLocation::Register(dst) => {
+            if dst != reg {
+                push_riscv_instruction(rv, RiscvOpcode::Addi, dst, reg, RiscvReg::Zero, 0);
+            }
+        }
+
+        RiscvLocation::Stack(offset) => {
+            let (_, store) = get_riscv_memory_opcodes(rv, op);
+            push_riscv_memory_access(rv, store, reg, RiscvReg::S0, offset);
+        }
+    }
+}
+
+/**
+ * Register to compute the result in, the scratch register is used if the destination is on the stack.
+ */
+fn get_riscv_destination(rv: &RiscvAssembler, op: IrOperand, scratch: RiscvReg) -> RiscvReg {
+    match get_riscv_location(rv, op) {
+        RiscvLocation::Register(reg) => reg,
+        RiscvLocation::Stack(_) => scratch,
+    }
+}
+
+fn get_riscv_location(rv: &RiscvAssembler, op: IrOperand) -> RiscvLocation {
+    match op {
+        IrOperand::Ident(ident) => match rv.locations.get(&ident) {
+            Some(location) => *location,
+            None => panic!("riscv: `{}` has not been assigned a register or stack slot", ident),
+        },
+        _ => panic!("riscv: cannot assign to `{}`", op),
+    }
+}
+
+/**
+ * Booleans declared as locals are accessed using bytes, since they can be written through references.
+ */
+fn get_riscv_memory_opcodes(rv: &RiscvAssembler, op: IrOperand) -> (RiscvOpcode, RiscvOpcode) {
+    match op {
+        IrOperand::Ident(ident) if rv.variable_types.get(&ident) == Some(&IrType::I8) => {
+            (RiscvOpcode::Lbu, RiscvOpcode::Sb)
+        }
+        _ => (RiscvOpcode::Lw, RiscvOpcode::Sw),
+    }
+}
+
+#[inline]
+fn get_ir_ident(op: IrOperand) -> IrIdent {
+    if let IrOperand::Ident(ident) = op {
+        ident
+    } else {
+        panic!("riscv: expected an identifier as operand")
+    }
+}
+
+fn get_riscv_value(op: IrOperand) -> i32 {
+    match op {
+        IrOperand::Value(IrValue::I32(value)) => value,
+        IrOperand::Value(IrValue::U32(value)) => value as i32,
+        IrOperand::Value(IrValue::Bool(value)) => value as i32,
+        IrOperand::Value(_) => panic!("riscv: 64-bit integers are not supported by RV32IM, found `{}`", op),
+        _ => panic!("riscv: expected value, found `{}`", op),
+    }
+}
+
+/**
+ * Value of the operand if it can be encoded as a 12-bit immediate.
+ */
+fn get_riscv_immediate(op: IrOperand) -> Option<i32> {
+    match op {
+        IrOperand::Value(_) => Some(get_riscv_value(op)).filter(|value| is_riscv_immediate(*value)),
+        _ => None,
+    }
+}
+
+fn is_riscv_immediate(value: i32) -> bool {
+    (-2048..=2047).contains(&value)
+}
+
+fn is_riscv_unsigned(ty: IrType) -> bool {
+    matches!(ty, IrType::U32 | IrType::PtrI8(_) | IrType::PtrI32(_) | IrType::PtrI64(_) | IrType::PtrU64(_))
+}
+
+fn get_riscv_param_count(insn: &IrInstruction) -> usize {
+    match insn.op3 {
+        IrOperand::Value(IrValue::I32(count)) => count as usize,
+        _ => 0,
+    }
+}
+
+/**
+ * Hook number of the foreign function, the hooks are added the first time they are called.
+ */
+fn get_riscv_ecall_hook(rv: &mut RiscvAssembler, func: IrIdent, address: usize, insn: &IrInstruction) -> i32 {
+    if let Some(hook) = rv.hook_numbers.get(&func) {
+        return *hook;
+    }
+
+    let hook = RISCV_ECALL_HOOK_BASE + rv.hooks.len() as i32;
+    rv.hook_numbers.insert(func, hook);
+    rv.hooks.push(RiscvEcallHook {
+        name: resolve_symbol(func.symbol).to_string(),
+        address,
+        num_args: get_riscv_param_count(insn),
+        return_type: insn.ty,
+    });
+    hook
+}
+
+/***************************************************************************
+ * Machine code encoding
+ ***************************************************************************/
+
+/**
+ * Decides the position of every instruction, every branch starts out as a single instruction
+ * and branches with targets out of range (+-4KiB) are replaced by an inverted branch over a jump.
+ * Extending a branch moves the code after it, so the distances are recalculated until
+ * no more branches have to be extended.
+ */
+fn resolve_riscv_branches(insns: &mut [RiscvInstruction]) -> Vec<usize> {
+    loop {
+        let mut positions = Vec::with_capacity(insns.len());
+        let mut labels: HashMap<IrIdent, usize> = HashMap::new();
+        let mut pos = 0;
+        for insn in insns.iter() {
+            positions.push(pos);
+            match insn.opcode {
+                RiscvOpcode::Label => {
+                    labels.insert(insn.label.unwrap(), pos);
+                }
+                _ if insn.is_long_branch => pos += 8,
+                _ => pos += 4,
+            }
+        }
+
+        let mut extended = false;
+        for (insn, pos) in insns.iter_mut().zip(&positions) {
+            if !is_riscv_branch(insn.opcode) || insn.is_long_branch {
+                continue;
+            }
+
+            let target = get_riscv_label_position(&labels, insn.label.unwrap());
+            let offset = target as isize - *pos as isize;
+            if !(-4096..4096).contains(&offset) {
+                insn.is_long_branch = true;
+                extended = true;
+            }
+        }
+
+        if !extended {
+            return positions;
+        }
+    }
+}
+
+fn encode_riscv_instructions(rv: &RiscvAssembler, positions: &[usize]) -> Vec<u8> {
+    let labels = get_riscv_label_positions(&rv.instructions, positions);
+    let mut machine_code = Vec::new();
+    for (insn, pos) in rv.instructions.iter().zip(positions) {
+        if insn.opcode == RiscvOpcode::Label {
+            continue;
+        }
+
+        let mut insn = *insn;
+        if let Some(label) = insn.label {
+            let target = get_riscv_label_position(&labels, label);
+            if insn.is_long_branch {
+                // Skip over the jump if the inverted condition holds
+                let branch = RiscvInstruction {
+                    opcode: negate_riscv_branch(insn.opcode),
+                    imm: 8,
+                    label: None,
+                    is_long_branch: false,
+                    ..insn
+                };
+                machine_code.extend_from_slice(&encode_riscv_instruction(&branch).to_le_bytes());
+                insn = RiscvInstruction {
+                    opcode: RiscvOpcode::Jal,
+                    rd: RiscvReg::Zero,
+                    imm: target as i32 - (*pos as i32 + 4),
+                    ..Default::default()
+                };
+            } else {
+                insn.imm = target as i32 - *pos as i32;
+            }
+
+            if insn.opcode == RiscvOpcode::Jal && !(-(1 << 20)..(1 << 20)).contains(&insn.imm) {
+                panic!("riscv: jump to `{}` is out of range", label);
+            }
+        }
+
+        machine_code.extend_from_slice(&encode_riscv_instruction(&insn).to_le_bytes());
+    }
+    machine_code
+}
+
+/**
+ * Major opcode, funct3 and funct7 of the instruction.
+ */
+fn get_riscv_encoding(opcode: RiscvOpcode) -> (u32, u32, u32) {
+    match opcode {
+        RiscvOpcode::Label  => panic!("riscv: labels cannot be encoded"),
+        RiscvOpcode::Lui    => (0x37, 0, 0),
+        RiscvOpcode::Auipc  => (0x17, 0, 0),
+        RiscvOpcode::Jal    => (0x6f, 0, 0),
+        RiscvOpcode::Jalr   => (0x67, 0, 0),
+        RiscvOpcode::Beq    => (0x63, 0, 0),
+        RiscvOpcode::Bne    => (0x63, 1, 0),
+        RiscvOpcode::Blt    => (0x63, 4, 0),
+        RiscvOpcode::Bge    => (0x63, 5, 0),
+        RiscvOpcode::Bltu   => (0x63, 6, 0),
+        RiscvOpcode::Bgeu   => (0x63, 7, 0),
+        RiscvOpcode::Lb     => (0x03, 0, 0),
+        RiscvOpcode::Lh     => (0x03, 1, 0),
+        RiscvOpcode::Lw     => (0x03, 2, 0),
+        RiscvOpcode::Lbu    => (0x03, 4, 0),
+        RiscvOpcode::Lhu    => (0x03, 5, 0),
+        RiscvOpcode::Sb     => (0x23, 0, 0),
+        RiscvOpcode::Sh     => (0x23, 1, 0),
+        RiscvOpcode::Sw     => (0x23, 2, 0),
+        RiscvOpcode::Addi   => (0x13, 0, 0),
+        RiscvOpcode::Slti   => (0x13, 2, 0),
+        RiscvOpcode::Sltiu  => (0x13, 3, 0),
+        RiscvOpcode::Xori   => (0x13, 4, 0),
+        RiscvOpcode::Ori    => (0x13, 6, 0),
+        RiscvOpcode::Andi   => (0x13, 7, 0),
+        RiscvOpcode::Slli   => (0x13, 1, 0x00),
+        RiscvOpcode::Srli   => (0x13, 5, 0x00),
+        RiscvOpcode::Srai   => (0x13, 5, 0x20),
+        RiscvOpcode::Add    => (0x33, 0, 0x00),
+        RiscvOpcode::Sub    => (0x33, 0, 0x20),
+        RiscvOpcode::Sll    => (0x33, 1, 0x00),
+        RiscvOpcode::Slt    => (0x33, 2, 0x00),
+        RiscvOpcode::Sltu   => (0x33, 3, 0x00),
+        RiscvOpcode::Xor    => (0x33, 4, 0x00),
+        RiscvOpcode::Srl    => (0x33, 5, 0x00),
+        RiscvOpcode::Sra    => (0x33, 5, 0x20),
+        RiscvOpcode::Or     => (0x33, 6, 0x00),
+        RiscvOpcode::And    => (0x33, 7, 0x00),
+        RiscvOpcode::Mul    => (0x33, 0, 0x01),
+        RiscvOpcode::Mulh   => (0x33, 1, 0x01),
+        RiscvOpcode::Mulhsu => (0x33, 2, 0x01),
+        RiscvOpcode::Mulhu  => (0x33, 3, 0x01),
+        RiscvOpcode::Div    => (0x33, 4, 0x01),
+        RiscvOpcode::Divu   => (0x33, 5, 0x01),
+        RiscvOpcode::Rem    => (0x33, 6, 0x01),
+        RiscvOpcode::Remu   => (0x33, 7, 0x01),
+        RiscvOpcode::Fence  => (0x0f, 0, 0),
+        RiscvOpcode::Ecall  => (0x73, 0, 0),
+        RiscvOpcode::Ebreak => (0x73, 0, 0),
+    }
+}
+
+pub fn encode_riscv_instruction(insn: &RiscvInstruction) -> u32 {
+    let (opcode, funct3, funct7) = get_riscv_encoding(insn.opcode);
+    let rd = (insn.rd as u32) << 7;
+    let rs1 = (insn.rs1 as u32) << 15;
+    let rs2 = (insn.rs2 as u32) << 20;
+    let funct3 = funct3 << 12;
+    let imm = insn.imm as u32;
+
+    match opcode {
+        // U-type
+        0x37 | 0x17 => (imm << 12) | rd | opcode,
+
+        // J-type
+        0x6f => {
+            let imm = ((imm >> 20) & 1) << 31 | ((imm >> 1) & 0x3ff) << 21 | ((imm >> 11) & 1) << 20 | ((imm >> 12) & 0xff) << 12;
+            imm | rd | opcode
+        }
+
+        // B-type
+        0x63 => {
+            let imm = ((imm >> 12) & 1) << 31 | ((imm >> 5) & 0x3f) << 25 | ((imm >> 1) & 0xf) << 8 | ((imm >> 11) & 1) << 7;
+            imm | rs2 | rs1 | funct3 | opcode
+        }
+
+        // S-type
+        0x23 => ((imm >> 5) & 0x7f) << 25 | rs2 | rs1 | funct3 | (imm & 0x1f) << 7 | opcode,
+
+        // R-type
+        0x33 => funct7 << 25 | rs2 | rs1 | funct3 | rd | opcode,
+
+        // I-type, shifts store the shift amount and funct7 in the immediate
+        0x13 if insn.opcode == RiscvOpcode::Slli || insn.opcode == RiscvOpcode::Srli || insn.opcode == RiscvOpcode::Srai => {
+            funct7 << 25 | (imm & 0x1f) << 20 | rs1 | funct3 | rd | opcode
+        }
+        0x73 if insn.opcode == RiscvOpcode::Ebreak => 1 << 20 | opcode,
+        _ => (imm & 0xfff) << 20 | rs1 | funct3 | rd | opcode,
+    }
+}
+
+/**
+ * Decodes the machine code word, returns none if it is not a valid RV32IM instruction.
+ */
+pub fn decode_riscv_instruction(word: u32) -> Option<RiscvInstruction> {
+    let opcode = word & 0x7f;
+    let funct3 = (word >> 12) & 0x7;
+    let funct7 = word >> 25;
+    let rd = RISCV_REGISTERS[((word >> 7) & 0x1f) as usize];
+    let rs1 = RISCV_REGISTERS[((word >> 15) & 0x1f) as usize];
+    let rs2 = RISCV_REGISTERS[((word >> 20) & 0x1f) as usize];
+
+    let i_imm = (word as i32) >> 20;
+    let s_imm = ((word as i32) >> 25) << 5 | ((word >> 7) & 0x1f) as i32;
+    let b_imm = ((word as i32) >> 31) << 12 | (((word >> 7) & 1) << 11) as i32 |
+                (((word >> 25) & 0x3f) << 5) as i32 | (((word >> 8) & 0xf) << 1) as i32;
+    let u_imm = (word >> 12) as i32;
+    let j_imm = ((word as i32) >> 31) << 20 | (word & 0xff000) as i32 |
+                (((word >> 20) & 1) << 11) as i32 | (((word >> 21) & 0x3ff) << 1) as i32;
+
+    let (opcode, imm) = match (opcode, funct3, funct7) {
+        (0x37, _, _) => (RiscvOpcode::Lui, u_imm),
+        (0x17, _, _) => (RiscvOpcode::Auipc, u_imm),
+        (0x6f, _, _) => (RiscvOpcode::Jal, j_imm),
+        (0x67, 0, _) => (RiscvOpcode::Jalr, i_imm),
+        (0x63, 0, _) => (RiscvOpcode::Beq, b_imm),
+        (0x63, 1, _) => (RiscvOpcode::Bne, b_imm),
+        (0x63, 4, _) => (RiscvOpcode::Blt, b_imm),
+        (0x63, 5, _) => (RiscvOpcode::Bge, b_imm),
+        (0x63, 6, _) => (RiscvOpcode::Bltu, b_imm),
+        (0x63, 7, _) => (RiscvOpcode::Bgeu, b_imm),
+        (0x03, 0, _) => (RiscvOpcode::Lb, i_imm),
+        (0x03, 1, _) => (RiscvOpcode::Lh, i_imm),
+        (0x03, 2, _) => (RiscvOpcode::Lw, i_imm),
+        (0x03, 4, _) => (RiscvOpcode::Lbu, i_imm),
+        (0x03, 5, _) => (RiscvOpcode::Lhu, i_imm),
+        (0x23, 0, _) => (RiscvOpcode::Sb, s_imm),
+        (0x23, 1, _) => (RiscvOpcode::Sh, s_imm),
+        (0x23, 2, _) => (RiscvOpcode::Sw, s_imm),
+        (0x13, 0, _) => (RiscvOpcode::Addi, i_imm),
+        (0x13, 2, _) => (RiscvOpcode::Slti, i_imm),
+        (0x13, 3, _) => (RiscvOpcode::Sltiu, i_imm),
+        (0x13, 4, _) => (RiscvOpcode::Xori, i_imm),
+        (0x13, 6, _) => (RiscvOpcode::Ori, i_imm),
+        (0x13, 7, _) => (RiscvOpcode::Andi, i_imm),
+        (0x13, 1, 0x00) => (RiscvOpcode::Slli, i_imm & 0x1f),
+        (0x13, 5, 0x00) => (RiscvOpcode::Srli, i_imm & 0x1f),
+        (0x13, 5, 0x20) => (RiscvOpcode::Srai, i_imm & 0x1f),
+        (0x33, 0, 0x00) => (RiscvOpcode::Add, 0),
+        (0x33, 0, 0x20) => (RiscvOpcode::Sub, 0),
+        (0x33, 1, 0x00) => (RiscvOpcode::Sll, 0),
+        (0x33, 2, 0x00) => (RiscvOpcode::Slt, 0),
+        (0x33, 3, 0x00) => (RiscvOpcode::Sltu, 0),
+        (0x33, 4, 0x00) => (RiscvOpcode::Xor, 0),
+        (0x33, 5, 0x00) => (RiscvOpcode::Srl, 0),
+        (0x33, 5, 0x20) => (RiscvOpcode::Sra, 0),
+        (0x33, 6, 0x00) => (RiscvOpcode::Or, 0),
+        (0x33, 7, 0x00) => (RiscvOpcode::And, 0),
+        (0x33, 0, 0x01) => (RiscvOpcode::Mul, 0),
+        (0x33, 1, 0x01) => (RiscvOpcode::Mulh, 0),
+        (0x33, 2, 0x01) => (RiscvOpcode::Mulhsu, 0),
+        (0x33, 3, 0x01) => (RiscvOpcode::Mulhu, 0),
+        (0x33, 4, 0x01) => (RiscvOpcode::Div, 0),
+        (0x33, 5, 0x01) => (RiscvOpcode::Divu, 0),
+        (0x33, 6, 0x01) => (RiscvOpcode::Rem, 0),
+        (0x33, 7, 0x01) => (RiscvOpcode::Remu, 0),
+        (0x0f, _, _) => (RiscvOpcode::Fence, 0),
+        (0x73, 0, _) if word == 0x0000_0073 => (RiscvOpcode::Ecall, 0),
+        (0x73, 0, _) if word == 0x0010_0073 => (RiscvOpcode::Ebreak, 0),
+        _ => return None,
+    };
+
+    Some(RiscvInstruction {
+        opcode,
+        rd,
+        rs1,
+        rs2,
+        imm,
+        ..Default::default()
+    })
+}
+
+fn is_riscv_branch(opcode: RiscvOpcode) -> bool {
+    matches!(opcode, RiscvOpcode::Beq | RiscvOpcode::Bne | RiscvOpcode::Blt |
+             RiscvOpcode::Bge | RiscvOpcode::Bltu | RiscvOpcode::Bgeu)
+}
+
+fn negate_riscv_branch(opcode: RiscvOpcode) -> RiscvOpcode {
+    match opcode {
+        RiscvOpcode::Beq  => RiscvOpcode::Bne,
+        RiscvOpcode::Bne  => RiscvOpcode::Beq,
+        RiscvOpcode::Blt  => RiscvOpcode::Bge,
+        RiscvOpcode::Bge  => RiscvOpcode::Blt,
+        RiscvOpcode::Bltu => RiscvOpcode::Bgeu,
+        RiscvOpcode::Bgeu => RiscvOpcode::Bltu,
+        _ => panic!("riscv: `{}` is not a branch", opcode),
+    }
+}
+
+fn get_riscv_label_positions(insns: &[RiscvInstruction], positions: &[usize]) -> HashMap<IrIdent, usize> {
+    insns.iter().zip(positions)
+        .filter(|(insn, _)| insn.opcode == RiscvOpcode::Label)
+        .map(|(insn, pos)| (insn.label.unwrap(), *pos))
+        .collect()
+}
+
+fn get_riscv_label_position(labels: &HashMap<IrIdent, usize>, label: IrIdent) -> usize {
+    match labels.get(&label) {
+        Some(pos) => *pos,
+        None => panic!("riscv: jump to unknown label `{}`", label),
+    }
+}
+
+/***************************************************************************
+ * Assembly
+ ***************************************************************************/
+
+/**
+ * Formats the program using the assembler pseudo instructions where they apply, e.g. `li`, `mv` and `ret`.
+ */
+fn format_riscv_assembly(rv: &RiscvAssembler, positions: &[usize]) -> String {
+    let label_name = |label: IrIdent| -> String {
+        if rv.function_labels.contains(&label) {
+            resolve_symbol(label.symbol).to_string()
+        } else {
+            format!(".L{}.{}", resolve_symbol(label.symbol), label.index)
+        }
+    };
+
+    let mut assembly = String::new();
+    for (insn, pos) in rv.instructions.iter().zip(positions) {
+        if insn.opcode == RiscvOpcode::Label {
+            let label = insn.label.unwrap();
+            if rv.function_labels.contains(&label) && *pos > 0 {
+                assembly.push('\n');
+            }
+            assembly.push_str(&format!("{}:\n", label_name(label)));
+            continue;
+        }
+
+        let (opcode, operands) = match (insn.opcode, insn.label) {
+            (RiscvOpcode::Jal, Some(label)) if insn.rd == RiscvReg::Zero => (String::from("j"), label_name(label)),
+            (RiscvOpcode::Jal, Some(label)) if insn.rd == RiscvReg::Ra => (String::from("call"), label_name(label)),
+            (opcode, Some(label)) if insn.is_long_branch => {
+                assembly.push_str(&format!("    {:<5} {}, {}, 8\n", negate_riscv_branch(opcode), insn.rs1, insn.rs2));
+                (String::from("j"), label_name(label))
+            }
+            (opcode, Some(label)) => (opcode.to_string(), format!("{}, {}, {}", insn.rs1, insn.rs2, label_name(label))),
+            _ => format_riscv_instruction(insn),
+        };
+        assembly.push_str(format!("    {:<5} {}\n", opcode, operands).trim_end());
+        assembly.push('\n');
+    }
+    assembly
+}
+
+fn format_riscv_instruction(insn: &RiscvInstruction) -> (String, String) {
+    let opcode = insn.opcode.to_string();
+    match insn.opcode {
+        RiscvOpcode::Addi if insn.rd == RiscvReg::Zero && insn.rs1 == RiscvReg::Zero && insn.imm == 0 => {
+            (String::from("nop"), String::new())
+        }
+        RiscvOpcode::Addi if insn.rs1 == RiscvReg::Zero => (String::from("li"), format!("{}, {}", insn.rd, insn.imm)),
+        RiscvOpcode::Addi if insn.imm == 0 => (String::from("mv"), format!("{}, {}", insn.rd, insn.rs1)),
+        RiscvOpcode::Jalr if insn.rd == RiscvReg::Zero && insn.rs1 == RiscvReg::Ra && insn.imm == 0 => {
+            (String::from("ret"), String::new())
+        }
+        RiscvOpcode::Lui | RiscvOpcode::Auipc => (opcode, format!("{}, {:#x}", insn.rd, insn.imm)),
+        RiscvOpcode::Jal => (opcode, format!("{}, {}", insn.rd, insn.imm)),
+        RiscvOpcode::Jalr |
+        RiscvOpcode::Lb   |
+        RiscvOpcode::Lh   |
+        RiscvOpcode::Lw   |
+        RiscvOpcode::Lbu  |
+        RiscvOpcode::Lhu  => (opcode, format!("{}, {}({})", insn.rd, insn.imm, insn.rs1)),
+        RiscvOpcode::Sb   |
+        RiscvOpcode::Sh   |
+        RiscvOpcode::Sw   => (opcode, format!("{}, {}({})", insn.rs2, insn.imm, insn.rs1)),
+        RiscvOpcode::Beq  |
+        RiscvOpcode::Bne  |
+        RiscvOpcode::Blt  |
+        RiscvOpcode::Bge  |
+        RiscvOpcode::Bltu |
+        RiscvOpcode::Bgeu => (opcode, format!("{}, {}, {}", insn.rs1, insn.rs2, insn.imm)),
+        RiscvOpcode::Addi  |
+        RiscvOpcode::Slti  |
+        RiscvOpcode::Sltiu |
+        RiscvOpcode::Xori  |
+        RiscvOpcode::Ori   |
+        RiscvOpcode::Andi  |
+        RiscvOpcode::Slli  |
+        RiscvOpcode::Srli  |
+        RiscvOpcode::Srai  => (opcode, format!("{}, {}, {}", insn.rd, insn.rs1, insn.imm)),
+        RiscvOpcode::Label  |
+        RiscvOpcode::Fence  |
+        RiscvOpcode::Ecall  |
+        RiscvOpcode::Ebreak => (opcode, String::new()),
+        _ => (opcode, format!("{}, {}, {}", insn.rd, insn.rs1, insn.rs2)),
+    }
+}
+
+impl fmt::Display for RiscvInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (opcode, operands) = format_riscv_instruction(self);
+        if operands.is_empty() {
+            write!(f, "{}", opcode)
+        } else {
+            write!(f, "{} {}", opcode, operands)
+        }
+    }
+}
+
+impl fmt::Display for RiscvOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self).to_lowercase();
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for RiscvReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self).to_lowercase();
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,253 @@
+use crate::ir::IrType;
+use crate::ffi::call_foreign_function;
+use crate::riscv::*;
+
+/***************************************************************************
+ * RV32IM Simulator:
+ * - Executes programs compiled by the RISC-V backend, the machine code is
+ *   loaded at address zero and the stack starts at the end of memory.
+ * - `ecall` exits the program (a7 = 93), reports a runtime error (a7 = 1023)
+ *   or calls one of the ecall hooks of the program, which forwards a0-a6 to
+ *   the host implementation of the foreign function and writes the result to a0.
+ * - `ebreak` prints the registers and continues the execution.
+ ***************************************************************************/
+
+/**
+ * Size of the memory in bytes, holds both the machine code and the stack.
+ */
+pub const RVSIM_MEMORY_SIZE: usize = 1 << 20;
+
+pub struct RiscvSimulator<'a> {
+    program: &'a RiscvProgram,
+    code: Vec<Option<RiscvInstruction>>, // decoded machine code, indexed by pc / 4
+    registers: [u32; 32],
+    memory: Vec<u8>,
+    pc: u32,
+    pub instructions_executed: u64,
+}
+
+pub fn create_riscv_simulator(program: &RiscvProgram) -> RiscvSimulator<'_> {
+    if program.machine_code.len() > RVSIM_MEMORY_SIZE / 2 {
+        panic!("rvsim: program of {} bytes does not fit in memory", program.machine_code.len());
+    }
+
+    let mut memory = vec![0; RVSIM_MEMORY_SIZE];
+    memory[..program.machine_code.len()].copy_from_slice(&program.machine_code);
+
+    // NOTE(alexander): the code is decoded once up front, there is no self-modifying code
+    let code = program.machine_code
+        .chunks_exact(4)
+        .map(|word| decode_riscv_instruction(u32::from_le_bytes([word[0], word[1], word[2], word[3]])))
+        .collect();
+
+    let mut registers = [0; 32];
+    registers[RiscvReg::Sp as usize] = RVSIM_MEMORY_SIZE as u32;
+
+    RiscvSimulator {
+        program,
+        code,
+        registers,
+        memory,
+        pc: 0,
+        instructions_executed: 0,
+    }
+}
+
+/**
+ * Runs the program until it exits, returns the exit code or an error if the program traps.
+ */
+pub fn execute_riscv_simulator(sim: &mut RiscvSimulator) -> Result<i32, String> {
+    loop {
+        let insn = match sim.code.get(sim.pc as usize / 4) {
+            Some(Some(insn)) if sim.pc & 3 == 0 => *insn,
+            Some(None) => return Err(format!("illegal instruction at pc {:#x}", sim.pc)),
+            _ => return Err(format!("pc {:#x} is outside of the program", sim.pc)),
+        };
+
+        sim.instructions_executed += 1;
+        if let Some(code) = step_riscv_instruction(sim, &insn)? {
+            return Ok(code);
+        }
+        sim.registers[0] = 0;
+    }
+}
+
+/**
+ * Executes one instruction, returns the exit code if the program exited.
+ */
+fn step_riscv_instruction(sim: &mut RiscvSimulator, insn: &RiscvInstruction) -> Result<Option<i32>, String> {
+    let rs1 = sim.registers[insn.rs1 as usize];
+    let rs2 = sim.registers[insn.rs2 as usize];
+    let imm = insn.imm as u32;
+    let mut next_pc = sim.pc.wrapping_add(4);
+
+    let result = match insn.opcode {
+        RiscvOpcode::Lui   => Some(imm << 12),
+        RiscvOpcode::Auipc => Some(sim.pc.wrapping_add(imm << 12)),
+        RiscvOpcode::Jal => {
+            next_pc = sim.pc.wrapping_add(imm);
+            Some(sim.pc.wrapping_add(4))
+        }
+        RiscvOpcode::Jalr => {
+            next_pc = rs1.wrapping_add(imm) & !1;
+            Some(sim.pc.wrapping_add(4))
+        }
+
+        RiscvOpcode::Beq  |
+        RiscvOpcode::Bne  |
+        RiscvOpcode::Blt  |
+        RiscvOpcode::Bge  |
+        RiscvOpcode::Bltu |
+        RiscvOpcode::Bgeu => {
+            let taken = match insn.opcode {
+                RiscvOpcode::Beq  => rs1 == rs2,
+                RiscvOpcode::Bne  => rs1 != rs2,
+                RiscvOpcode::Blt  => (rs1 as i32) < (rs2 as i32),
+                RiscvOpcode::Bge  => (rs1 as i32) >= (rs2 as i32),
+                RiscvOpcode::Bltu => rs1 < rs2,
+                _                 => rs1 >= rs2,
+            };
+            if taken {
+                next_pc = sim.pc.wrapping_add(imm);
+            }
+            None
+        }
+
+        RiscvOpcode::Lb  => Some(load_riscv_memory(sim, rs1.wrapping_add(imm), 1)? as u8 as i8 as i32 as u32),
+        RiscvOpcode::Lh  => Some(load_riscv_memory(sim, rs1.wrapping_add(imm), 2)? as u16 as i16 as i32 as u32),
+        RiscvOpcode::Lw  => Some(load_riscv_memory(sim, rs1.wrapping_add(imm), 4)?),
+        RiscvOpcode::Lbu => Some(load_riscv_memory(sim, rs1.wrapping_add(imm), 1)?),
+        RiscvOpcode::Lhu => Some(load_riscv_memory(sim, rs1.wrapping_add(imm), 2)?),
+        RiscvOpcode::Sb  => store_riscv_memory(sim, rs1.wrapping_add(imm), rs2, 1).map(|_| None)?,
+        RiscvOpcode::Sh  => store_riscv_memory(sim, rs1.wrapping_add(imm), rs2, 2).map(|_| None)?,
+        RiscvOpcode::Sw  => store_riscv_memory(sim, rs1.wrapping_add(imm), rs2, 4).map(|_| None)?,
+
+        RiscvOpcode::Addi  => Some(rs1.wrapping_add(imm)),
+        RiscvOpcode::Slti  => Some(((rs1 as i32) < (imm as i32)) as u32),
+        RiscvOpcode::Sltiu => Some((rs1 < imm) as u32),
+        RiscvOpcode::Xori  => Some(rs1 ^ imm),
+        RiscvOpcode::Ori   => Some(rs1 | imm),
+        RiscvOpcode::Andi  => Some(rs1 & imm),
+        RiscvOpcode::Slli  => Some(rs1 << (imm & 0x1f)),
+        RiscvOpcode::Srli  => Some(rs1 >> (imm & 0x1f)),
+        RiscvOpcode::Srai  => Some(((rs1 as i32) >> (imm & 0x1f)) as u32),
+
+        RiscvOpcode::Add  => Some(rs1.wrapping_add(rs2)),
+        RiscvOpcode::Sub  => Some(rs1.wrapping_sub(rs2)),
+        RiscvOpcode::Sll  => Some(rs1 << (rs2 & 0x1f)),
+        RiscvOpcode::Slt  => Some(((rs1 as i32) < (rs2 as i32)) as u32),
+        RiscvOpcode::Sltu => Some((rs1 < rs2) as u32),
+        RiscvOpcode::Xor  => Some(rs1 ^ rs2),
+        RiscvOpcode::Srl  => Some(rs1 >> (rs2 & 0x1f)),
+        RiscvOpcode::Sra  => Some(((rs1 as i32) >> (rs2 & 0x1f)) as u32),
+        RiscvOpcode::Or   => Some(rs1 | rs2),
+        RiscvOpcode::And  => Some(rs1 & rs2),
+
+        // NOTE(alexander): division by zero and overflow do not trap, the results are defined by the M extension
+        RiscvOpcode::Mul    => Some(rs1.wrapping_mul(rs2)),
+        RiscvOpcode::Mulh   => Some(((rs1 as i32 as i64 * rs2 as i32 as i64) >> 32) as u32),
+        RiscvOpcode::Mulhsu => Some(((rs1 as i32 as i64).wrapping_mul(rs2 as i64) >> 32) as u32),
+        RiscvOpcode::Mulhu  => Some(((rs1 as u64 * rs2 as u64) >> 32) as u32),
+        RiscvOpcode::Div => Some(match (rs1 as i32, rs2 as i32) {
+            (_, 0) => u32::MAX,
+            (lhs, rhs) => lhs.wrapping_div(rhs) as u32,
+        }),
+        RiscvOpcode::Divu => Some(rs1.checked_div(rs2).unwrap_or(u32::MAX)),
+        RiscvOpcode::Rem => Some(match (rs1 as i32, rs2 as i32) {
+            (lhs, 0) => lhs as u32,
+            (lhs, rhs) => lhs.wrapping_rem(rhs) as u32,
+        }),
+        RiscvOpcode::Remu => Some(rs1.checked_rem(rs2).unwrap_or(rs1)),
+
+        RiscvOpcode::Fence => None,
+        RiscvOpcode::Ecall => {
+            if let Some(code) = call_riscv_ecall(sim)? {
+                return Ok(Some(code));
+            }
+            None
+        }
+        RiscvOpcode::Ebreak => {
+            print_riscv_registers(sim);
+            None
+        }
+
+        RiscvOpcode::Label => return Err(format!("illegal instruction at pc {:#x}", sim.pc)),
+    };
+
+    if let Some(value) = result {
+        sim.registers[insn.rd as usize] = value;
+    }
+    sim.pc = next_pc;
+    Ok(None)
+}
+
+/**
+ * Exits the program or calls the host function of the ecall hook given by a7.
+ */
+fn call_riscv_ecall(sim: &mut RiscvSimulator) -> Result<Option<i32>, String> {
+    let number = sim.registers[RiscvReg::A7 as usize] as i32;
+    if number == RISCV_SYS_EXIT {
+        return Ok(Some(sim.registers[RiscvReg::A0 as usize] as i32));
+    }
+
+    if number == RISCV_ECALL_RUNTIME_ERROR {
+        return match RISCV_RUNTIME_ERRORS.get(sim.registers[RiscvReg::A0 as usize] as usize) {
+            Some(msg) => Err(msg.to_string()),
+            None => Err(format!("unknown runtime error at pc {:#x}", sim.pc)),
+        };
+    }
+
+    let hook = match sim.program.hooks.get((number - RISCV_ECALL_HOOK_BASE) as usize) {
+        Some(hook) if number >= RISCV_ECALL_HOOK_BASE => hook,
+        _ => return Err(format!("unknown ecall {} at pc {:#x}", number, sim.pc)),
+    };
+
+    // NOTE(alexander): arguments are sign-extended, the same as the interpreter passes 32-bit integers
+    let args: Vec<i64> = sim.registers[RiscvReg::A0 as usize..RiscvReg::A0 as usize + hook.num_args]
+        .iter()
+        .map(|arg| *arg as i32 as i64)
+        .collect();
+
+    let result = match unsafe { call_foreign_function(hook.address, &args) } {
+        Ok(result) => result,
+        Err(msg) => return Err(format!("{} in `{}`", msg, hook.name)),
+    };
+
+    let a0 = &mut sim.registers[RiscvReg::A0 as usize];
+    match hook.return_type {
+        IrType::I8 => *a0 = (result as u8 != 0) as u32,
+        IrType::None => {}
+        _ => *a0 = result as u32,
+    }
+    Ok(None)
+}
+
+fn load_riscv_memory(sim: &RiscvSimulator, addr: u32, size: usize) -> Result<u32, String> {
+    let addr = addr as usize;
+    match sim.memory.get(addr..addr + size) {
+        Some(bytes) => Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32)),
+        None => Err(format!("load from invalid address {:#x} at pc {:#x}", addr, sim.pc)),
+    }
+}
+
+fn store_riscv_memory(sim: &mut RiscvSimulator, addr: u32, value: u32, size: usize) -> Result<(), String> {
+    let pc = sim.pc;
+    let addr = addr as usize;
+    match sim.memory.get_mut(addr..addr + size) {
+        Some(bytes) => {
+            bytes.copy_from_slice(&value.to_le_bytes()[..size]);
+            Ok(())
+        }
+        None => Err(format!("store to invalid address {:#x} at pc {:#x}", addr, pc)),
+    }
+}
+
+fn print_riscv_registers(sim: &RiscvSimulator) {
+    eprintln!("ebreak at pc {:#x}", sim.pc);
+    for (i, reg) in RISCV_REGISTERS.iter().enumerate() {
+        eprint!("{:>5}: {:#010x}", reg.to_string(), sim.registers[i]);
+        if i % 4 == 3 {
+            eprintln!();
+        }
+    }
+}
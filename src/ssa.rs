@@ -0,0 +1,1091 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::ast::{Symbol, intern_string};
+use crate::ir::*;
+
+/***************************************************************************
+ * Static Single Assignment:
+ * - Every function is split into basic blocks that forms a control-flow
+ *   graph, the dominator tree is computed using the iterative algorithm by
+ *   Cooper, Harvey and Kennedy, along with the dominance frontiers.
+ * - Phi instructions are placed at the iterated dominance frontier of the
+ *   blocks that assigns a variable, but only where the variable is alive
+ *   (pruned SSA). Variables are then renamed in a walk of the dominator tree.
+ * - Variables that are referenced are kept in memory and never renamed.
+ * - Natural loops are found from the back edges, i.e. edges to a block
+ *   that dominates the source of the edge.
+ * - Out-of-SSA replaces phi instructions by copies in the predecessors,
+ *   critical edges are split first. Live intervals are recomputed from
+ *   the resulting instructions since the variables have been renamed.
+ ***************************************************************************/
+
+/**
+ * Control-flow graph of a single function, the first block is the entry
+ * and the last block is labeled by the exit label of the function.
+ */
+pub struct IrControlFlowGraph {
+    pub blocks: Vec<IrBlock>,
+}
+
+/**
+ * Basic block is a sequence of instructions that is only entered at the beginning
+ * and ends with the first jump, conditional jump or return.
+ * NOTE(alexander): not to be confused with IrBasicBlock that describes an entire function.
+ */
+pub struct IrBlock {
+    pub label: Option<IrIdent>,
+    pub instructions: Vec<IrInstruction>, // excluding the label
+    pub position: usize, // index of the first instruction in the instructions the graph was built from
+    pub predecessors: Vec<usize>,
+    pub successors: Vec<usize>, // fall through block is always first
+    pub idom: Option<usize>, // immediate dominator, none for the entry and unreachable blocks
+    pub dominates: Vec<usize>, // children in the dominator tree
+    pub frontier: Vec<usize>,
+}
+
+/**
+ * Function in SSA form, stores the control-flow graph of the function body
+ * i.e. the instructions between the prologue and the epilogue.
+ */
+pub struct IrSsaFunction {
+    pub ident: IrIdent,
+    pub cfg: IrControlFlowGraph,
+    pub referenced: HashSet<IrIdent>, // variables stored in memory, these are not in SSA form
+}
+
+/**
+ * Natural loop defined by its header and the blocks that can reach a back edge
+ * without passing through the header, loops sharing the same header are merged.
+ */
+pub struct IrLoop {
+    pub header: usize,
+    pub latches: Vec<usize>, // sources of the back edges
+    pub blocks: HashSet<usize>, // including the header
+}
+
+/**
+ * Used for renaming variables, stores the current version of each variable.
+ */
+struct IrSsaRenamer {
+    promoted: HashSet<IrIdent>,
+    versions: HashMap<IrIdent, Vec<IrIdent>>,
+    next_index: HashMap<Symbol, u32>,
+}
+
+pub fn create_ir_block(label: Option<IrIdent>, position: usize) -> IrBlock {
+    IrBlock {
+        label,
+        instructions: Vec::new(),
+        position,
+        predecessors: Vec::new(),
+        successors: Vec::new(),
+        idom: None,
+        dominates: Vec::new(),
+        frontier: Vec::new(),
+    }
+}
+
+/**
+ * Converts every function defined in the program into SSA form.
+ */
+pub fn build_ssa_from_ir<'a>(ib: &mut IrBuilder<'a>) -> Vec<IrSsaFunction> {
+    let mut defined: Vec<(IrIdent, usize, usize)> = ib.functions.iter()
+        .filter(|(_, bb)| !bb.is_foreign)
+        .map(|(ident, bb)| (*ident, bb.prologue_index, bb.epilogue_index))
+        .collect();
+    defined.sort_by_key(|(_, prologue_index, _)| *prologue_index);
+
+    let mut functions = Vec::new();
+    for (ident, prologue_index, epilogue_index) in defined {
+        let mut cfg = build_ir_cfg(&ib.instructions[prologue_index + 1..epilogue_index]);
+
+        // NOTE(alexander): phi instructions refers to their predecessors by label
+        for block in &mut cfg.blocks {
+            if block.label.is_none() {
+                block.label = Some(create_unique_ir_label(ib));
+            }
+        }
+
+        compute_ir_dominators(&mut cfg);
+        let referenced = build_ssa_from_cfg(&mut cfg);
+        functions.push(IrSsaFunction { ident, cfg, referenced });
+    }
+    functions
+}
+
+/**
+ * Converts the functions back from SSA form, the instructions of the program
+ * are replaced and the live intervals of each function are recomputed.
+ */
+pub fn build_ir_from_ssa<'a>(ib: &mut IrBuilder<'a>, functions: Vec<IrSsaFunction>) {
+    let mut instructions: Vec<IrInstruction> = Vec::new();
+    for func in functions {
+        let (prologue_index, epilogue_index) = match ib.functions.get(&func.ident) {
+            Some(bb) => (bb.prologue_index, bb.epilogue_index),
+            None => panic!("`{}` is not a registered function", func.ident),
+        };
+        let body = build_ir_from_ssa_cfg(ib, func.cfg);
+
+        instructions.push(ib.instructions[prologue_index - 1].clone()); // enter label
+        let new_prologue_index = instructions.len();
+        instructions.push(ib.instructions[prologue_index].clone());
+
+        let live_intervals = compute_ir_live_intervals(&body, instructions.len());
+        instructions.extend(body);
+
+        let new_epilogue_index = instructions.len();
+        instructions.push(ib.instructions[epilogue_index].clone());
+
+        let bb = ib.functions.get_mut(&func.ident).unwrap();
+        bb.prologue_index = new_prologue_index;
+        bb.epilogue_index = new_epilogue_index;
+        bb.live_intervals = live_intervals;
+    }
+    ib.instructions = instructions;
+}
+
+/***************************************************************************
+ * Control-flow graph
+ ***************************************************************************/
+
+/**
+ * Splits the instructions into basic blocks and connects them, unreachable blocks are removed.
+ * The instructions are expected to end with the exit label of the function.
+ */
+pub fn build_ir_cfg(insns: &[IrInstruction]) -> IrControlFlowGraph {
+    let mut blocks: Vec<IrBlock> = Vec::new();
+    let mut current: Option<IrBlock> = None;
+    for (i, insn) in insns.iter().enumerate() {
+        if insn.opcode == IrOpcode::Label {
+            if let Some(block) = current.take() {
+                blocks.push(block);
+            }
+            current = Some(create_ir_block(Some(get_ir_label(insn.op1)), i + 1));
+            continue;
+        }
+
+        let block = current.get_or_insert_with(|| create_ir_block(None, i));
+        block.instructions.push(insn.clone());
+        if is_ir_terminator(&insn.opcode) {
+            blocks.push(current.take().unwrap());
+        }
+    }
+    if let Some(block) = current {
+        blocks.push(block);
+    }
+
+    let mut cfg = IrControlFlowGraph { blocks };
+    connect_ir_blocks(&mut cfg);
+    remove_unreachable_ir_blocks(&mut cfg);
+    cfg
+}
+
+/**
+ * Computes the predecessors and successors of every block from their last instruction,
+ * blocks that does not end with a jump or return falls through to the next block.
+ */
+pub fn connect_ir_blocks(cfg: &mut IrControlFlowGraph) {
+    let label_indices: HashMap<IrIdent, usize> = cfg.blocks.iter().enumerate()
+        .filter_map(|(i, block)| block.label.map(|label| (label, i)))
+        .collect();
+    let lookup = |label: IrIdent| -> usize {
+        match label_indices.get(&label) {
+            Some(index) => *index,
+            None => panic!("ssa: jump to unknown label `{}`", label),
+        }
+    };
+
+    let exit = cfg.blocks.len() - 1;
+    for i in 0..cfg.blocks.len() {
+        let successors = match cfg.blocks[i].instructions.last() {
+            Some(insn) if insn.opcode == IrOpcode::Jump => vec![lookup(get_ir_label(insn.op1))],
+            Some(insn) if insn.opcode == IrOpcode::Return => vec![exit],
+            Some(insn) if is_ir_conditional_jump(&insn.opcode) => {
+                let target = lookup(get_ir_label(insn.op3));
+                if target == i + 1 {
+                    vec![i + 1]
+                } else {
+                    vec![i + 1, target]
+                }
+            }
+            _ => if i < exit { vec![i + 1] } else { vec![] },
+        };
+        cfg.blocks[i].successors = successors;
+        cfg.blocks[i].predecessors.clear();
+    }
+
+    for b in 0..cfg.blocks.len() {
+        for s in cfg.blocks[b].successors.clone() {
+            cfg.blocks[s].predecessors.push(b);
+        }
+    }
+}
+
+/**
+ * Removes the blocks that cannot be reached from the entry, the exit block is always kept.
+ * Arguments of phi instructions coming from removed predecessors are also removed.
+ */
+pub fn remove_unreachable_ir_blocks(cfg: &mut IrControlFlowGraph) -> bool {
+    let exit = cfg.blocks.len() - 1;
+    let mut reachable = vec![false; cfg.blocks.len()];
+    let mut worklist = vec![0];
+    reachable[0] = true;
+    while let Some(b) = worklist.pop() {
+        for &s in &cfg.blocks[b].successors {
+            if !reachable[s] {
+                reachable[s] = true;
+                worklist.push(s);
+            }
+        }
+    }
+    reachable[exit] = true;
+
+    if reachable.iter().all(|is_reachable| *is_reachable) {
+        return false;
+    }
+
+    let mut index = 0;
+    cfg.blocks.retain(|_| {
+        index += 1;
+        reachable[index - 1]
+    });
+    connect_ir_blocks(cfg);
+    remove_stale_ir_phi_args(cfg);
+    true
+}
+
+/**
+ * Removes arguments of phi instructions whose block is no longer a predecessor.
+ */
+pub fn remove_stale_ir_phi_args(cfg: &mut IrControlFlowGraph) {
+    for b in 0..cfg.blocks.len() {
+        let labels: Vec<Option<IrIdent>> = cfg.blocks[b].predecessors.iter().map(|p| cfg.blocks[*p].label).collect();
+        for insn in &mut cfg.blocks[b].instructions {
+            if insn.opcode == IrOpcode::Phi {
+                insn.phi_args.retain(|(label, _)| labels.contains(&Some(*label)));
+            }
+        }
+    }
+}
+
+/**
+ * Computes the dominator tree and dominance frontiers, see
+ * "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy.
+ */
+pub fn compute_ir_dominators(cfg: &mut IrControlFlowGraph) {
+    let num_blocks = cfg.blocks.len();
+    let order = reverse_postorder(cfg);
+    let mut order_number = vec![usize::MAX; num_blocks];
+    for (i, b) in order.iter().enumerate() {
+        order_number[*b] = i;
+    }
+
+    fn intersect(idom: &[Option<usize>], order_number: &[usize], mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            while order_number[a] > order_number[b] {
+                a = idom[a].unwrap();
+            }
+            while order_number[b] > order_number[a] {
+                b = idom[b].unwrap();
+            }
+        }
+        a
+    }
+
+    let mut idom: Vec<Option<usize>> = vec![None; num_blocks];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in order.iter().skip(1) {
+            let mut new_idom: Option<usize> = None;
+            for &p in &cfg.blocks[b].predecessors {
+                if idom[p].is_some() {
+                    new_idom = match new_idom {
+                        Some(other) => Some(intersect(&idom, &order_number, p, other)),
+                        None => Some(p),
+                    };
+                }
+            }
+            if idom[b] != new_idom {
+                idom[b] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    for block in &mut cfg.blocks {
+        block.idom = None;
+        block.dominates.clear();
+        block.frontier.clear();
+    }
+
+    for (b, d) in idom.iter().enumerate().skip(1) {
+        if let Some(d) = *d {
+            cfg.blocks[b].idom = Some(d);
+            cfg.blocks[d].dominates.push(b);
+        }
+    }
+
+    // Dominance frontiers, only join points can be in a frontier
+    for b in 0..num_blocks {
+        if cfg.blocks[b].predecessors.len() < 2 || idom[b].is_none() {
+            continue;
+        }
+
+        for p in cfg.blocks[b].predecessors.clone() {
+            let mut runner = p;
+            while idom[runner].is_some() && Some(runner) != idom[b] {
+                if !cfg.blocks[runner].frontier.contains(&b) {
+                    cfg.blocks[runner].frontier.push(b);
+                }
+                if runner == 0 {
+                    break;
+                }
+                runner = idom[runner].unwrap();
+            }
+        }
+    }
+}
+
+/**
+ * Blocks reachable from the entry ordered in reverse postorder.
+ */
+pub fn reverse_postorder(cfg: &IrControlFlowGraph) -> Vec<usize> {
+    let mut order = Vec::with_capacity(cfg.blocks.len());
+    let mut visited = vec![false; cfg.blocks.len()];
+    let mut stack: Vec<(usize, usize)> = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((b, next)) = stack.pop() {
+        if let Some(&s) = cfg.blocks[b].successors.get(next) {
+            stack.push((b, next + 1));
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            order.push(b);
+        }
+    }
+    order.reverse();
+    order
+}
+
+/**
+ * True if every path from the entry to block b goes through block a.
+ */
+pub fn ir_dominates(cfg: &IrControlFlowGraph, a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match cfg.blocks[b].idom {
+            Some(d) => b = d,
+            None => return false,
+        }
+    }
+}
+
+/**
+ * Finds the natural loops of the function, inner loops are ordered before outer loops.
+ * The dominators has to be computed first.
+ */
+pub fn find_ir_loops(cfg: &IrControlFlowGraph) -> Vec<IrLoop> {
+    let mut loops: Vec<IrLoop> = Vec::new();
+    for b in 0..cfg.blocks.len() {
+        for &h in &cfg.blocks[b].successors {
+            if !ir_dominates(cfg, h, b) {
+                continue;
+            }
+
+            let index = match loops.iter().position(|lp| lp.header == h) {
+                Some(index) => index,
+                None => {
+                    loops.push(IrLoop { header: h, latches: Vec::new(), blocks: [h].iter().copied().collect() });
+                    loops.len() - 1
+                }
+            };
+
+            let lp = &mut loops[index];
+            lp.latches.push(b);
+            let mut worklist = vec![b];
+            while let Some(n) = worklist.pop() {
+                if lp.blocks.insert(n) {
+                    worklist.extend(&cfg.blocks[n].predecessors);
+                }
+            }
+        }
+    }
+
+    loops.sort_by_key(|lp| lp.blocks.len());
+    loops
+}
+
+/**
+ * Computes the variables that are alive when entering and leaving each block.
+ * Arguments of phi instructions are alive at the end of the corresponding predecessor.
+ */
+pub fn compute_ir_liveness(cfg: &IrControlFlowGraph) -> (Vec<HashSet<IrIdent>>, Vec<HashSet<IrIdent>>) {
+    let num_blocks = cfg.blocks.len();
+    let mut uses: Vec<HashSet<IrIdent>> = vec![HashSet::new(); num_blocks];
+    let mut defs: Vec<HashSet<IrIdent>> = vec![HashSet::new(); num_blocks];
+    let mut phi_uses: Vec<HashSet<IrIdent>> = vec![HashSet::new(); num_blocks];
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for insn in &block.instructions {
+            if insn.opcode == IrOpcode::Phi {
+                for (label, value) in &insn.phi_args {
+                    if let IrOperand::Ident(ident) = value {
+                        if let Some(p) = block.predecessors.iter().find(|p| cfg.blocks[**p].label == Some(*label)) {
+                            phi_uses[*p].insert(*ident);
+                        }
+                    }
+                }
+            } else {
+                for ident in ir_used_variables(insn) {
+                    if !defs[b].contains(&ident) {
+                        uses[b].insert(ident);
+                    }
+                }
+            }
+
+            if let Some(ident) = ir_defined_variable(insn) {
+                defs[b].insert(ident);
+            }
+        }
+    }
+
+    let mut live_in: Vec<HashSet<IrIdent>> = vec![HashSet::new(); num_blocks];
+    let mut live_out: Vec<HashSet<IrIdent>> = phi_uses;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..num_blocks).rev() {
+            let mut out = live_out[b].clone();
+            for s in &cfg.blocks[b].successors {
+                out.extend(live_in[*s].iter().copied());
+            }
+
+            let mut new_in = uses[b].clone();
+            new_in.extend(out.iter().filter(|ident| !defs[b].contains(ident)).copied());
+
+            if new_in.len() != live_in[b].len() || out.len() != live_out[b].len() {
+                changed = true;
+            }
+            live_in[b] = new_in;
+            live_out[b] = out;
+        }
+    }
+
+    (live_in, live_out)
+}
+
+/**
+ * Computes the live interval of each variable in the instructions of a function body,
+ * the intervals are indexed from the base index of the first instruction.
+ */
+pub fn compute_ir_live_intervals(insns: &[IrInstruction], base_index: usize) -> HashMap<IrIdent, IrLiveInterval> {
+    let cfg = build_ir_cfg(insns);
+    let (live_in, live_out) = compute_ir_liveness(&cfg);
+
+    let mut live_intervals: HashMap<IrIdent, IrLiveInterval> = HashMap::new();
+    let mut touch = |ident: IrIdent, index: usize| {
+        let live_interval = live_intervals.entry(ident).or_insert(IrLiveInterval { begin: index, end: index });
+        live_interval.begin = live_interval.begin.min(index);
+        live_interval.end = live_interval.end.max(index);
+    };
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let first = base_index + block.position;
+        let last = first + block.instructions.len().max(1) - 1;
+        for ident in &live_in[b] {
+            touch(*ident, first);
+        }
+        for ident in &live_out[b] {
+            touch(*ident, last);
+        }
+
+        for (i, insn) in block.instructions.iter().enumerate() {
+            for ident in ir_used_variables(insn) {
+                touch(ident, first + i);
+            }
+
+            // NOTE(alexander): dead definitions still needs a location to be written to
+            if let Some(ident) = ir_defined_variable(insn) {
+                touch(ident, first + i);
+                touch(ident, first + i + 1);
+            }
+        }
+    }
+
+    // NOTE(alexander): arguments are passed at the call, and parameters are all defined by alloc_params,
+    // the same goes for inline assembly inputs which are moved into their registers at the asm.
+    let mut arguments: Vec<IrIdent> = Vec::new();
+    for (i, insn) in insns.iter().enumerate() {
+        match insn.opcode {
+            IrOpcode::Param |
+            IrOpcode::AsmIn => if let IrOperand::Ident(ident) = insn.op1 {
+                arguments.push(ident);
+            }
+
+            IrOpcode::Call |
+            IrOpcode::Asm => {
+                for ident in arguments.drain(..) {
+                    touch(ident, base_index + i);
+                }
+            }
+
+            IrOpcode::AllocParams => {
+                for ident in arguments.drain(..) {
+                    touch(ident, base_index + i);
+                    touch(ident, base_index + i + 1);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    live_intervals
+}
+
+/***************************************************************************
+ * SSA construction
+ ***************************************************************************/
+
+/**
+ * Converts the control-flow graph into SSA form, requires that every block is labeled
+ * and that the dominators are computed. Returns the referenced variables that are not renamed.
+ */
+pub fn build_ssa_from_cfg(cfg: &mut IrControlFlowGraph) -> HashSet<IrIdent> {
+    // Referenced variables are stored in memory and cannot be renamed
+    let referenced = referenced_ir_variables(cfg);
+    let mut promoted: HashSet<IrIdent> = HashSet::new();
+    let mut types: HashMap<IrIdent, IrType> = HashMap::new();
+    let mut def_blocks: HashMap<IrIdent, Vec<usize>> = HashMap::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for insn in &block.instructions {
+            if insn.opcode == IrOpcode::Param && b == 0 {
+                if let IrOperand::Ident(ident) = insn.op1 {
+                    types.entry(ident).or_insert(insn.ty);
+                }
+            }
+
+            for ident in ir_used_variables(insn) {
+                promoted.insert(ident);
+            }
+
+            if let Some(ident) = ir_defined_variable(insn) {
+                promoted.insert(ident);
+                types.entry(ident).or_insert(ir_defined_type(insn));
+                let blocks = def_blocks.entry(ident).or_default();
+                if blocks.last() != Some(&b) {
+                    blocks.push(b);
+                }
+            }
+        }
+    }
+    promoted.retain(|ident| !referenced.contains(ident));
+
+    // Insert phi instructions at the iterated dominance frontier where the variable is alive
+    let (live_in, _) = compute_ir_liveness(cfg);
+    let mut variables: Vec<&IrIdent> = def_blocks.keys().filter(|ident| promoted.contains(ident)).collect();
+    variables.sort_by_key(|ident| (ident.symbol, ident.index));
+    for ident in variables {
+        let mut has_phi = vec![false; cfg.blocks.len()];
+        let mut worklist = def_blocks[ident].clone();
+        while let Some(b) = worklist.pop() {
+            for f in cfg.blocks[b].frontier.clone() {
+                if has_phi[f] || !live_in[f].contains(ident) {
+                    continue;
+                }
+                has_phi[f] = true;
+
+                let block = &cfg.blocks[f];
+                let phi_args = block.predecessors.iter()
+                    .map(|p| (cfg.blocks[*p].label.unwrap(), IrOperand::Ident(*ident)))
+                    .collect();
+                cfg.blocks[f].instructions.insert(0, IrInstruction {
+                    opcode: IrOpcode::Phi,
+                    op1: IrOperand::Ident(*ident),
+                    ty: types[ident],
+                    phi_args,
+                    ..Default::default()
+                });
+
+                if !def_blocks[ident].contains(&f) {
+                    worklist.push(f);
+                }
+            }
+        }
+    }
+
+    // NOTE(alexander): promoted variables are not stored in memory, alloca is not needed
+    for block in &mut cfg.blocks {
+        block.instructions.retain(|insn| !(insn.opcode == IrOpcode::Alloca &&
+                                           promoted.contains(&get_ir_label(insn.op1))));
+    }
+
+    // Rename the variables, the first version of each variable keeps its original name
+    let mut renamer = IrSsaRenamer {
+        promoted,
+        versions: HashMap::new(),
+        next_index: next_ir_ident_indices(cfg),
+    };
+    rename_ir_block(cfg, &mut renamer, 0);
+    referenced
+}
+
+/**
+ * Variables that are referenced are stored in memory, they can be modified through
+ * references and are therefore never renamed or assumed to keep their value.
+ */
+pub fn referenced_ir_variables(cfg: &IrControlFlowGraph) -> HashSet<IrIdent> {
+    let mut referenced: HashSet<IrIdent> = HashSet::new();
+    for block in &cfg.blocks {
+        for insn in &block.instructions {
+            if insn.opcode == IrOpcode::CopyFromRef {
+                if let IrOperand::Ident(ident) = insn.op2 {
+                    referenced.insert(ident);
+                }
+            }
+        }
+    }
+    referenced
+}
+
+fn rename_ir_block(cfg: &mut IrControlFlowGraph, renamer: &mut IrSsaRenamer, b: usize) {
+    let mut defined: Vec<IrIdent> = Vec::new();
+    for insn in &mut cfg.blocks[b].instructions {
+        if insn.opcode != IrOpcode::Phi {
+            for op in ir_used_operands(insn) {
+                if let IrOperand::Ident(ident) = *op {
+                    *op = IrOperand::Ident(current_ir_version(renamer, ident));
+                }
+            }
+        }
+
+        if let Some(ident) = ir_defined_variable(insn) {
+            if renamer.promoted.contains(&ident) {
+                let index = renamer.next_index.entry(ident.symbol).or_insert(1);
+                let version = create_ir_ident(ident.symbol, *index);
+                *index += 1;
+
+                renamer.versions.entry(ident).or_default().push(version);
+                defined.push(ident);
+                insn.op1 = IrOperand::Ident(version);
+            }
+        }
+    }
+
+    // Fill in the incoming values of the phi instructions in the successors
+    let label = cfg.blocks[b].label.unwrap();
+    for s in cfg.blocks[b].successors.clone() {
+        for insn in &mut cfg.blocks[s].instructions {
+            if insn.opcode != IrOpcode::Phi {
+                break;
+            }
+
+            for (pred_label, value) in &mut insn.phi_args {
+                if *pred_label == label {
+                    if let IrOperand::Ident(ident) = *value {
+                        *value = IrOperand::Ident(current_ir_version(renamer, ident));
+                    }
+                }
+            }
+        }
+    }
+
+    for child in cfg.blocks[b].dominates.clone() {
+        rename_ir_block(cfg, renamer, child);
+    }
+
+    for ident in defined {
+        renamer.versions.get_mut(&ident).unwrap().pop();
+    }
+}
+
+fn current_ir_version(renamer: &IrSsaRenamer, ident: IrIdent) -> IrIdent {
+    if !renamer.promoted.contains(&ident) {
+        return ident;
+    }
+
+    match renamer.versions.get(&ident).and_then(|versions| versions.last()) {
+        Some(version) => *version,
+        None => ident, // NOTE(alexander): parameters or used before being assigned
+    }
+}
+
+/**
+ * The next unused index of each symbol in the function, used to create new identifiers.
+ */
+pub fn next_ir_ident_indices(cfg: &IrControlFlowGraph) -> HashMap<Symbol, u32> {
+    let mut next_index: HashMap<Symbol, u32> = HashMap::new();
+    let mut update = |op: &IrOperand| {
+        if let IrOperand::Ident(ident) = op {
+            let index = next_index.entry(ident.symbol).or_insert(0);
+            *index = (*index).max(ident.index + 1);
+        }
+    };
+
+    for block in &cfg.blocks {
+        for insn in &block.instructions {
+            update(&insn.op1);
+            update(&insn.op2);
+            update(&insn.op3);
+            update(&insn.op4);
+            for (_, value) in &insn.phi_args {
+                update(value);
+            }
+        }
+    }
+    next_index
+}
+
+/***************************************************************************
+ * Out-of-SSA
+ ***************************************************************************/
+
+/**
+ * Replaces the phi instructions by copies and lays out the blocks as linear instructions.
+ * Copies on critical edges are placed in new blocks, edges that falls through gets
+ * its block placed directly after the predecessor, otherwise it is placed before the exit.
+ */
+fn build_ir_from_ssa_cfg<'a>(ib: &mut IrBuilder<'a>, mut cfg: IrControlFlowGraph) -> Vec<IrInstruction> {
+    let num_blocks = cfg.blocks.len();
+    let exit = num_blocks - 1;
+    let mut next_index = next_ir_ident_indices(&cfg);
+    let temp_symbol = intern_string("");
+
+    let mut fall_through_blocks: Vec<Vec<IrInstruction>> = vec![Vec::new(); num_blocks];
+    let mut split_blocks: Vec<IrInstruction> = Vec::new();
+
+    for b in 0..num_blocks {
+        let num_phis = cfg.blocks[b].instructions.iter().take_while(|insn| insn.opcode == IrOpcode::Phi).count();
+        if num_phis == 0 {
+            continue;
+        }
+
+        let phis: Vec<IrInstruction> = cfg.blocks[b].instructions.drain(..num_phis).collect();
+        let label = cfg.blocks[b].label.unwrap();
+        for p in cfg.blocks[b].predecessors.clone() {
+            let pred_label = cfg.blocks[p].label.unwrap();
+            let copies: Vec<(IrIdent, IrOperand, IrType)> = phis.iter()
+                .map(|phi| {
+                    let value = phi.phi_args.iter().find(|(l, _)| *l == pred_label).unwrap().1;
+                    (get_ir_label(phi.op1), value, phi.ty)
+                })
+                .collect();
+
+            let mut copy_insns: Vec<IrInstruction> = Vec::new();
+            push_ir_parallel_copies(&mut copy_insns, copies, temp_symbol, &mut next_index);
+            if copy_insns.is_empty() {
+                continue;
+            }
+
+            let pred = &mut cfg.blocks[p];
+            if pred.successors.len() == 1 {
+                // NOTE(alexander): conditional jump to the next block does nothing, but may read the copied variables
+                if matches!(pred.instructions.last(), Some(insn) if is_ir_conditional_jump(&insn.opcode)) {
+                    pred.instructions.pop();
+                }
+
+                let at = match pred.instructions.last() {
+                    Some(insn) if is_ir_terminator(&insn.opcode) => pred.instructions.len() - 1,
+                    _ => pred.instructions.len(),
+                };
+                pred.instructions.splice(at..at, copy_insns);
+            } else if pred.successors[0] == b {
+                fall_through_blocks[p].extend(copy_insns);
+            } else {
+                let split_label = create_unique_ir_label(ib);
+                pred.instructions.last_mut().unwrap().op3 = IrOperand::Ident(split_label);
+                split_blocks.push(IrInstruction {
+                    opcode: IrOpcode::Label,
+                    op1: IrOperand::Ident(split_label),
+                    ..Default::default()
+                });
+                split_blocks.extend(copy_insns);
+                split_blocks.push(IrInstruction {
+                    opcode: IrOpcode::Jump,
+                    op1: IrOperand::Ident(label),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    // Layout the blocks in their original order
+    let mut insns: Vec<IrInstruction> = Vec::new();
+    for (b, block) in cfg.blocks.into_iter().enumerate() {
+        if b == exit && !split_blocks.is_empty() {
+            let falls_through = match insns.last() {
+                Some(insn) => insn.opcode != IrOpcode::Jump && insn.opcode != IrOpcode::Return,
+                None => true,
+            };
+            if falls_through {
+                insns.push(IrInstruction {
+                    opcode: IrOpcode::Jump,
+                    op1: IrOperand::Ident(block.label.unwrap()),
+                    ..Default::default()
+                });
+            }
+            insns.append(&mut split_blocks);
+        }
+
+        if let Some(label) = block.label {
+            insns.push(IrInstruction {
+                opcode: IrOpcode::Label,
+                op1: IrOperand::Ident(label),
+                ..Default::default()
+            });
+        }
+        insns.extend(block.instructions);
+        insns.append(&mut fall_through_blocks[b]);
+    }
+
+    // Remove labels that are never jumped to, except the exit label
+    let exit_label = insns.last().unwrap().op1;
+    let mut targets: HashSet<IrIdent> = HashSet::new();
+    for insn in &insns {
+        if insn.opcode == IrOpcode::Jump {
+            targets.insert(get_ir_label(insn.op1));
+        } else if is_ir_conditional_jump(&insn.opcode) {
+            targets.insert(get_ir_label(insn.op3));
+        }
+    }
+    insns.retain(|insn| insn.opcode != IrOpcode::Label || insn.op1 == exit_label ||
+                 targets.contains(&get_ir_label(insn.op1)));
+    insns
+}
+
+/**
+ * Sequentializes copies that are performed in parallel, when a destination is
+ * also read by another copy the values are first copied into new temporaries.
+ */
+fn push_ir_parallel_copies(
+    insns: &mut Vec<IrInstruction>,
+    mut copies: Vec<(IrIdent, IrOperand, IrType)>,
+    temp_symbol: Symbol,
+    next_index: &mut HashMap<Symbol, u32>
+) {
+    copies.retain(|(dst, src, _)| *src != IrOperand::Ident(*dst));
+
+    let has_conflict = copies.iter().any(|(_, src, _)| {
+        copies.iter().any(|(dst, _, _)| *src == IrOperand::Ident(*dst))
+    });
+
+    if !has_conflict {
+        for (dst, src, ty) in copies {
+            insns.push(create_ir_copy(IrOperand::Ident(dst), src, ty));
+        }
+        return;
+    }
+
+    let mut temps: Vec<(IrIdent, IrIdent, IrType)> = Vec::new();
+    for (dst, src, ty) in copies {
+        let index = next_index.entry(temp_symbol).or_insert(0);
+        let temp = create_ir_ident(temp_symbol, *index);
+        *index += 1;
+        insns.push(create_ir_copy(IrOperand::Ident(temp), src, ty));
+        temps.push((dst, temp, ty));
+    }
+
+    for (dst, temp, ty) in temps {
+        insns.push(create_ir_copy(IrOperand::Ident(dst), IrOperand::Ident(temp), ty));
+    }
+}
+
+pub fn create_ir_copy(dst: IrOperand, src: IrOperand, ty: IrType) -> IrInstruction {
+    IrInstruction {
+        opcode: IrOpcode::Copy,
+        op1: dst,
+        op2: src,
+        ty,
+        ..Default::default()
+    }
+}
+
+/***************************************************************************
+ * Instruction helpers
+ ***************************************************************************/
+
+fn get_ir_label(op: IrOperand) -> IrIdent {
+    match op {
+        IrOperand::Ident(ident) => ident,
+        _ => panic!("ssa: expected identifier, found `{}`", op),
+    }
+}
+
+pub fn is_ir_conditional_jump(opcode: &IrOpcode) -> bool {
+    matches!(opcode,
+             IrOpcode::IfLt |
+             IrOpcode::IfGt |
+             IrOpcode::IfLe |
+             IrOpcode::IfGe |
+             IrOpcode::IfEq |
+             IrOpcode::IfNe)
+}
+
+/**
+ * Instructions that ends a basic block.
+ */
+pub fn is_ir_terminator(opcode: &IrOpcode) -> bool {
+    *opcode == IrOpcode::Jump || *opcode == IrOpcode::Return || is_ir_conditional_jump(opcode)
+}
+
+/**
+ * Which of the four operands that are read by the instruction,
+ * arguments of phi instructions are not included.
+ */
+fn ir_used_operand_mask(opcode: &IrOpcode) -> [bool; 4] {
+    match opcode {
+        IrOpcode::Copy          |
+        IrOpcode::CopyFromDeref => [false, true, false, false],
+
+        IrOpcode::CopyToDeref   |
+        IrOpcode::IfLt          |
+        IrOpcode::IfGt          |
+        IrOpcode::IfLe          |
+        IrOpcode::IfGe          |
+        IrOpcode::IfEq          |
+        IrOpcode::IfNe          => [true, true, false, false],
+
+        IrOpcode::Add           |
+        IrOpcode::Sub           |
+        IrOpcode::Mul           |
+        IrOpcode::Div           |
+        IrOpcode::Pow           |
+        IrOpcode::Mod           |
+        IrOpcode::And           |
+        IrOpcode::Or            |
+        IrOpcode::Xor           |
+        IrOpcode::Lt            |
+        IrOpcode::Le            |
+        IrOpcode::Gt            |
+        IrOpcode::Ge            |
+        IrOpcode::Eq            |
+        IrOpcode::Ne            => [false, true, true, false],
+
+        IrOpcode::Select        => [false, true, true, true],
+
+        IrOpcode::Param         |
+        IrOpcode::AsmIn         |
+        IrOpcode::Return        => [true, false, false, false],
+
+        _ => [false, false, false, false],
+    }
+}
+
+pub fn ir_used_operands(insn: &mut IrInstruction) -> Vec<&mut IrOperand> {
+    let mask = ir_used_operand_mask(&insn.opcode);
+    let mut operands = Vec::new();
+    for (used, op) in mask.iter().zip([&mut insn.op1, &mut insn.op2, &mut insn.op3, &mut insn.op4]) {
+        if *used {
+            operands.push(op);
+        }
+    }
+    operands
+}
+
+/**
+ * Variables that are read by the instruction, arguments of phi instructions are not included.
+ */
+pub fn ir_used_variables(insn: &IrInstruction) -> Vec<IrIdent> {
+    let mask = ir_used_operand_mask(&insn.opcode);
+    let mut variables = Vec::new();
+    for (used, op) in mask.iter().zip([insn.op1, insn.op2, insn.op3, insn.op4]) {
+        if let (true, IrOperand::Ident(ident)) = (used, op) {
+            variables.push(ident);
+        }
+    }
+    variables
+}
+
+/**
+ * Variable that is assigned by the instruction, if any.
+ */
+pub fn ir_defined_variable(insn: &IrInstruction) -> Option<IrIdent> {
+    match insn.opcode {
+        IrOpcode::Copy          |
+        IrOpcode::CopyFromDeref |
+        IrOpcode::CopyFromRef   |
+        IrOpcode::Clear         |
+        IrOpcode::Add           |
+        IrOpcode::Sub           |
+        IrOpcode::Mul           |
+        IrOpcode::Div           |
+        IrOpcode::Pow           |
+        IrOpcode::Mod           |
+        IrOpcode::And           |
+        IrOpcode::Or            |
+        IrOpcode::Xor           |
+        IrOpcode::Lt            |
+        IrOpcode::Le            |
+        IrOpcode::Gt            |
+        IrOpcode::Ge            |
+        IrOpcode::Eq            |
+        IrOpcode::Ne            |
+        IrOpcode::Select        |
+        IrOpcode::Call          |
+        IrOpcode::AsmOut        |
+        IrOpcode::Phi           => match insn.op1 {
+            IrOperand::Ident(ident) => Some(ident),
+            _ => None,
+        },
+
+        _ => None,
+    }
+}
+
+/**
+ * Type of the variable assigned by the instruction, comparisons always results in a boolean.
+ */
+pub fn ir_defined_type(insn: &IrInstruction) -> IrType {
+    match insn.opcode {
+        IrOpcode::Lt |
+        IrOpcode::Le |
+        IrOpcode::Gt |
+        IrOpcode::Ge |
+        IrOpcode::Eq |
+        IrOpcode::Ne => IrType::I8,
+        _ => insn.ty,
+    }
+}
+
+/***************************************************************************
+ * Printing help
+ ***************************************************************************/
+
+impl fmt::Display for IrSsaFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |b: usize| -> String {
+            match self.cfg.blocks[b].label {
+                Some(label) => format!("{}", label),
+                None => format!("#{}", b),
+            }
+        };
+        let labels = |blocks: &[usize]| -> String {
+            if blocks.is_empty() {
+                String::from("-")
+            } else {
+                blocks.iter().map(|b| label(*b)).collect::<Vec<String>>().join(", ")
+            }
+        };
+
+        writeln!(f, "{}:", self.ident)?;
+        for (b, block) in self.cfg.blocks.iter().enumerate() {
+            let idom = match block.idom {
+                Some(d) => label(d),
+                None => String::from("-"),
+            };
+            writeln!(f, "{}:{:<width$}; preds: {}, idom: {}, frontier: {}", label(b), "",
+                   labels(&block.predecessors), idom, labels(&block.frontier),
+                   width = 24usize.saturating_sub(label(b).len()))?;
+            for insn in &block.instructions {
+                writeln!(f, "    {}", insn)?;
+            }
+        }
+        Ok(())
+    }
+}
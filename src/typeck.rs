@@ -1,7 +1,9 @@
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::ast::*;
 use crate::error::*;
+use crate::ffi::{is_c_abi, lookup_symbol, MAX_FOREIGN_ARGUMENTS};
+use crate::native::lookup_native_function;
 
 pub struct TypeTable {
     types: HashMap<Symbol, Ty>,
@@ -13,15 +15,36 @@ pub struct TypeContext<'a> {
     pub locals: Vec<TypeTable>, // based on the call stack
     pub items: HashMap<Symbol, &'a Item>,
     pub current_item: Option<&'a Item>,
+    pub target: TypeTarget,
     pub error_count: u32,
 }
 
+/**
+ * Describes what the target of the compilation supports, programs that use
+ * anything else are reported here since the backends have no source locations.
+ */
+pub struct TypeTarget {
+    pub foreign_functions: Option<&'static [&'static str]>, // None if any foreign function can be called
+    pub inline_asm: Option<InlineAsmCheck>, // None if inline assembly is not supported
+    pub int64_unsupported: Option<&'static str>, // name of the target if 64-bit integers are not supported
+}
+
+/**
+ * Checks the template and registers of inline assembly, returns the error message and its location.
+ */
+pub type InlineAsmCheck = fn(&ExprAsm) -> Result<(), (Span, String)>;
+
 pub fn create_type_context<'a>() -> TypeContext<'a> {
     TypeContext {
         file: None,
         locals: Vec::new(),
         items: HashMap::new(),
         current_item: None,
+        target: TypeTarget {
+            foreign_functions: None,
+            inline_asm: None,
+            int64_unsupported: None,
+        },
         error_count: 0,
     }
 }
@@ -70,15 +93,91 @@ pub fn type_check_item<'a>(tc: &mut TypeContext<'a>, item: &'a Item) {
     tc.current_item = Some(item);
     match item {
         Item::Fn(func) => { type_check_function(tc, func); },
+        Item::ForeignMod(module) => {
+            for foreign_item in &module.items {
+                if let Item::ForeignFn(func) = foreign_item {
+                    if is_c_abi(&module.abi) {
+                        type_check_c_function(tc, func);
+                    } else {
+                        type_check_native_function(tc, func);
+                    }
+                }
+            }
+        },
         _ => { },
     };
 }
 
+/**
+ * Checks that a function declared in an `extern "Rust"` block, e.g. in the prelude,
+ * is a registered native function with the same signature.
+ */
+pub fn type_check_native_function<'a>(tc: &mut TypeContext<'a>, func: &'a ForeignFnItem) {
+    let native = match lookup_native_function(resolve_symbol(func.ident.sym)) {
+        Some(native) => native,
+        None => {
+            type_error(
+                tc,
+                func.ident.span,
+                &format!("cannot find native function `{}`", resolve_symbol(func.ident.sym)),
+                "no native function with this name is registered");
+            return;
+        }
+    };
+
+    let inputs_match = native.inputs.len() == func.decl.inputs.len() &&
+        native.inputs.iter().zip(&func.decl.inputs).all(|((_, ty), arg)| *ty == arg.ty.kind);
+    if !inputs_match || native.output != func.decl.output.kind {
+        type_error(
+            tc,
+            func.span,
+            &format!("declaration of `{}` does not match the native function signature", native.name),
+            "");
+    }
+}
+
+/**
+ * Checks that a function declared in an `extern "C"` block exists
+ * in the linked libraries and that its signature can be called.
+ */
+pub fn type_check_c_function<'a>(tc: &mut TypeContext<'a>, func: &'a ForeignFnItem) {
+    if lookup_symbol(resolve_symbol(func.ident.sym)).is_none() {
+        type_error(
+            tc,
+            func.ident.span,
+            &format!("cannot find foreign function `{}` in any linked library", resolve_symbol(func.ident.sym)),
+            "not found in libc or libraries given by `--link-lib`");
+    }
+
+    if func.decl.inputs.len() > MAX_FOREIGN_ARGUMENTS {
+        type_error(
+            tc,
+            func.decl.span,
+            &format!("foreign functions can take at most {} arguments", MAX_FOREIGN_ARGUMENTS),
+            "");
+    }
+
+    for arg in &func.decl.inputs {
+        if let TyKind::Ref(_) = arg.ty.kind {
+            type_error(tc, arg.ty.span, "references cannot be passed to foreign functions", "");
+        }
+        type_check_target_ty(tc, &arg.ty);
+    }
+    type_check_target_ty(tc, &func.decl.output);
+
+    if let TyKind::Ref(_) = func.decl.output.kind {
+        type_error(tc, func.decl.output.span, "references cannot be returned from foreign functions", "");
+    }
+}
+
 pub fn type_check_function<'a>(tc: &mut TypeContext<'a>, func: &'a FnItem) -> Ty {
+    type_check_attributes(tc, &func.attrs);
     tc.locals.push(create_type_table(false));
     
     let len = tc.locals.len();
+    type_check_target_ty(tc, &func.decl.output);
     for arg in &func.decl.inputs {
+        type_check_target_ty(tc, &arg.ty);
         let mut ty = arg.ty.clone();
         ty.mutable = arg.mutable;
         ty.sym = Some(arg.ident.sym);
@@ -157,6 +256,7 @@ pub fn type_check_block<'a>(tc: &mut TypeContext<'a>, block: &'a Block, inside_l
 pub fn type_check_stmt<'a>(tc: &mut TypeContext<'a>, stmt: &'a Stmt) -> Ty {
     match stmt {
         Stmt::Local(local) => {
+            type_check_target_ty(tc, &local.ty);
             let (ty, first_assigned_span) = match &*local.init {
                 Some(init) => {
                     let init_ty = type_check_expr(tc, init);
@@ -219,13 +319,18 @@ pub fn type_check_stmt<'a>(tc: &mut TypeContext<'a>, stmt: &'a Stmt) -> Ty {
 
 pub fn type_check_expr<'a>(tc: &mut TypeContext<'a>, expr: &'a Expr) -> Ty {
     match expr {
+        Expr::Asm       (e) => type_check_asm_expr(tc, e),
         Expr::Assign    (e) => type_check_assign_expr(tc, e),
         Expr::Binary    (e) => type_check_binary_expr(tc, e),
         Expr::Block     (e) => type_check_block(tc, &e.block, false),
         Expr::Call      (e) => type_check_call_expr(tc, e),
         Expr::Ident     (e) => type_check_ident_expr(tc, e),
         Expr::If        (e) => type_check_if_expr(tc, e),
-        Expr::Lit       (e) => type_check_literal_expr(e),
+        Expr::Lit       (e) => {
+            let ty = type_check_literal_expr(e);
+            type_check_target_ty(tc, &ty);
+            ty
+        }
         Expr::Paren     (e) => type_check_expr(tc, &e.expr),
         Expr::Reference (e) => type_check_reference_expr(tc, e),
         Expr::Return    (e) => type_check_return_expr(tc, e),
@@ -248,6 +353,77 @@ pub fn type_check_expr<'a>(tc: &mut TypeContext<'a>, expr: &'a Expr) -> Ty {
     }
 }
 
+/**
+ * Type checks the operands of inline assembly, outputs are assigned to local variables
+ * so these are checked the same way as assignments. The template and registers are
+ * checked by the target since only the x86 backend supports inline assembly.
+ */
+pub fn type_check_asm_expr<'a>(tc: &mut TypeContext<'a>, asm_expr: &'a ExprAsm) -> Ty {
+    match tc.target.inline_asm {
+        Some(check_inline_asm) => {
+            if let Err((span, message)) = check_inline_asm(asm_expr) {
+                type_error(tc, span, &message, "invalid inline assembly");
+            }
+        }
+        None => {
+            type_error(tc,
+                       asm_expr.span,
+                       "inline assembly is only supported by the x86 backend",
+                       "help: compile with `--backend x86`");
+        }
+    }
+
+    for operand in &asm_expr.operands {
+        let ty = match operand.dir {
+            AsmDir::In => type_check_expr(tc, &operand.expr),
+
+            AsmDir::Out | AsmDir::InOut => {
+                let ident = match &operand.expr {
+                    Expr::Ident(ident) => ident,
+                    _ => {
+                        type_error(tc,
+                                   operand.expr.get_span(),
+                                   "invalid inline assembly output",
+                                   "expected a local variable");
+                        continue;
+                    }
+                };
+
+                // NOTE(alexander): outputs are allowed to initialize variables
+                let ty = match operand.dir {
+                    AsmDir::InOut => type_check_ident_expr(tc, ident),
+                    _ => match tc.locals.iter().rev().find_map(|table| table.types.get(&ident.sym)) {
+                        Some(ty) => {
+                            let mut ty = ty.clone();
+                            ty.span = ident.span;
+                            ty
+                        }
+                        None => type_check_ident_expr(tc, ident),
+                    }
+                };
+
+                if let TyKind::Error = ty.kind {
+                    continue;
+                }
+                type_check_assigned_local(tc, &ty, operand.span);
+                ty
+            }
+        };
+
+        if ty.is_none() {
+            type_error(tc,
+                       operand.expr.get_span(),
+                       "cannot pass () type to inline assembly",
+                       "expected a typed value here, got nothing");
+        }
+    }
+
+    Ty {
+        span: asm_expr.span,
+        ..Default::default()
+    }
+}
+
 pub fn type_check_assign_expr<'a>(tc: &mut TypeContext<'a>, assign_expr: &'a ExprAssign) -> Ty {
 
     fn type_check_locator_expr<'a>(tc: &mut TypeContext<'a>, expr: &'a Expr, span: Span) -> Ty {
@@ -295,6 +471,20 @@ pub fn type_check_assign_expr<'a>(tc: &mut TypeContext<'a>, assign_expr: &'a Exp
         return lhs_ty;
     }
 
+    type_check_assigned_local(tc, &lhs_ty, assign_expr.span);
+
+    if lhs_ty != rhs_ty {
+        mismatched_types_error(tc, assign_expr.span, &lhs_ty.kind, &rhs_ty);
+    }
+
+    Ty::default()
+}
+
+/**
+ * Checks that the local variable can be assigned to and marks it as assigned,
+ * immutable variables are only allowed to be assigned once outside loops.
+ */
+fn type_check_assigned_local<'a>(tc: &mut TypeContext<'a>, lhs_ty: &Ty, span: Span) {
     let inside_loop = is_inside_loop(tc);
     if (lhs_ty.assigned || inside_loop) && !lhs_ty.mutable {
         if let Some(lhs_sym) = lhs_ty.sym {
@@ -302,7 +492,7 @@ pub fn type_check_assign_expr<'a>(tc: &mut TypeContext<'a>, assign_expr: &'a Exp
             let mut msg = create_error_msg(
                 tc,
                 ErrorLevel::Error,
-                assign_expr.span,
+                span,
                 &format!("cannot assign twice to immutable variable `{}`", var_str),
                 "cannot assign twice to immutable variable");
 
@@ -341,37 +531,31 @@ pub fn type_check_assign_expr<'a>(tc: &mut TypeContext<'a>, assign_expr: &'a Exp
             for table in tc.locals.iter_mut().rev() {
                 if let Some(ty) = table.types.get_mut(&sym) {
                     ty.assigned = true;
-                    ty.first_assigned_span = assign_expr.span;
+                    ty.first_assigned_span = span;
                 }
             }
         }
     }
-
-    if lhs_ty != rhs_ty {
-        mismatched_types_error(tc, assign_expr.span, &lhs_ty.kind, &rhs_ty);
-    }
-
-    Ty::default()
 }
 
 pub fn type_check_binary_expr<'a>(tc: &mut TypeContext<'a>, binary_expr: &'a ExprBinary) -> Ty {
     let lhs_ty = type_check_expr(tc, &binary_expr.left);
     let rhs_ty = type_check_expr(tc, &binary_expr.right);
     let ok = match binary_expr.op {
-        BinOp::Add => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Sub => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Mul => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Div => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Pow => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Mod => lhs_ty.is_int() && rhs_ty.is_int(),
+        BinOp::Add => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Sub => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Mul => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Div => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Pow => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Mod => lhs_ty.is_int() && lhs_ty == rhs_ty,
         BinOp::And => lhs_ty.is_bool() && rhs_ty.is_bool(),
         BinOp::Or  => lhs_ty.is_bool() && rhs_ty.is_bool(),
         BinOp::Eq  => lhs_ty == rhs_ty,
         BinOp::Ne  => lhs_ty == rhs_ty,
-        BinOp::Lt  => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Le  => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Gt  => lhs_ty.is_int() && rhs_ty.is_int(),
-        BinOp::Ge  => lhs_ty.is_int() && rhs_ty.is_int(),
+        BinOp::Lt  => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Le  => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Gt  => lhs_ty.is_int() && lhs_ty == rhs_ty,
+        BinOp::Ge  => lhs_ty.is_int() && lhs_ty == rhs_ty,
     };
 
     if !ok {
@@ -399,7 +583,19 @@ pub fn type_check_call_expr<'a>(tc: &mut TypeContext<'a>, call: &'a ExprCall) ->
     let fn_decl = match tc.items.get(&call.ident.sym) {
         Some(item) => match item {
             Item::Fn(func) => &func.decl,
-            Item::ForeignFn(func) => &func.decl,
+            Item::ForeignFn(func) => {
+                let name = resolve_symbol(func.ident.sym);
+                if let Some(foreign_functions) = tc.target.foreign_functions {
+                    if !foreign_functions.contains(&name) {
+                        type_error(
+                            tc,
+                            call.span,
+                            &format!("function `{}` is not available in executables", name),
+                            "executables are not linked with libc");
+                    }
+                }
+                &func.decl
+            }
             _ => panic!("compiler bug"),
         }
 
@@ -485,9 +681,30 @@ pub fn type_check_if_expr<'a>(tc: &mut TypeContext<'a>, if_expr: &'a ExprIf) ->
     then_ty
 }
 
+/**
+ * Checks that the type written in the source is supported by the target,
+ * e.g. the riscv backend only has 32-bit registers.
+ */
+fn type_check_target_ty<'a>(tc: &mut TypeContext<'a>, ty: &Ty) {
+    match &ty.kind {
+        TyKind::Int64 | TyKind::UInt64 => {
+            if let Some(target) = tc.target.int64_unsupported {
+                type_error(tc,
+                           ty.span,
+                           &format!("64-bit integers are not supported by {}", target),
+                           "help: use `i32` instead");
+            }
+        }
+        TyKind::Ref(type_ref) => type_check_target_ty(tc, &type_ref.elem),
+        _ => {}
+    }
+}
+
 pub fn type_check_literal_expr<'a>(literal: &'a ExprLit) -> Ty {
     match literal.lit {
         Lit::Int(_) => Ty::new(TyKind::Int, literal.span),
+        Lit::Int64(_) => Ty::new(TyKind::Int64, literal.span),
+        Lit::UInt64(_) => Ty::new(TyKind::UInt64, literal.span),
         Lit::Bool(_) => Ty::new(TyKind::Bool, literal.span),
     }
 }
@@ -514,6 +731,10 @@ pub fn type_check_reference_expr<'a>(tc: &mut TypeContext<'a>, reference_expr: &
 }
 
 pub fn type_check_return_expr<'a>(tc: &mut TypeContext<'a>, return_expr: &'a ExprReturn) -> Ty {
+    if return_expr.is_become {
+        type_check_become_expr(tc, return_expr);
+    }
+
     let ret_ty = match &*return_expr.expr {
         Some(expr) => type_check_expr(tc, &expr),
         None => {
@@ -544,10 +765,58 @@ pub fn type_check_return_expr<'a>(tc: &mut TypeContext<'a>, return_expr: &'a Exp
     }
 }
 
+/**
+ * Checks that the value of `become` is a call that can be turned into a tail call,
+ * i.e. a call to the function itself that cannot pass references into its own frame.
+ */
+pub fn type_check_become_expr<'a>(tc: &mut TypeContext<'a>, return_expr: &'a ExprReturn) {
+    let mut expr = match &*return_expr.expr {
+        Some(expr) => expr,
+        None => {
+            type_error(tc, return_expr.span, "`become` requires a function call", "help: use `return` instead");
+            return;
+        }
+    };
+    while let Expr::Paren(paren) = expr {
+        expr = &paren.expr;
+    }
+
+    let call = match expr {
+        Expr::Call(call) => call,
+        _ => {
+            type_error(tc, expr.get_span(), "call is not in tail position",
+                       "help: `become` requires the returned value to be a function call");
+            return;
+        }
+    };
+
+    if !call.is_tail {
+        let func = match tc.current_item {
+            Some(Item::Fn(func)) => func,
+            _ => panic!("compiler bug: not analysing any function"),
+        };
+
+        if call.ident.sym != func.ident.sym {
+            type_error(tc, call.span, "cannot perform tail call to another function",
+                       &format!("help: only calls to `{}` can be tail calls", resolve_symbol(func.ident.sym)));
+            return;
+        }
+
+        let mut locals = HashSet::new();
+        collect_locals_in_block(&func.block, &mut locals);
+        let span = match find_frame_reference_argument(call, &func.decl.inputs, &locals) {
+            Some(index) => call.args[index].get_span(),
+            None => call.span,
+        };
+        type_error(tc, span, "reference may point into the frame reused by the tail call",
+                   "help: only reference parameters can be passed on unchanged");
+    }
+}
+
 pub fn type_check_unary_expr<'a>(tc: &mut TypeContext<'a>, unary_expr: &'a ExprUnary) -> Ty {
     let mut ty = type_check_expr(tc, &unary_expr.expr);
     let ok = match unary_expr.op {
-        UnOp::Neg   => ty.kind == TyKind::Int,
+        UnOp::Neg   => ty.kind == TyKind::Int || ty.kind == TyKind::Int64,
         UnOp::Not   => ty.kind == TyKind::Bool,
         UnOp::Deref => match ty.get_ref() {
             Some(r) => {
@@ -588,13 +857,16 @@ fn create_error_msg<'a>(
     label: &str
 ) -> ErrorMsg {
     match tc.file {
-        Some(file) => create_error_msg_from_span(level,
-                                                 &file.lines,
-                                                 span,
-                                                 &file.filename,
-                                                 &file.source,
-                                                 message,
-                                                 label),
+        Some(file) => {
+            let file = get_span_file(file, span);
+            create_error_msg_from_span(level,
+                                       &file.lines,
+                                       span,
+                                       &file.filename,
+                                       &file.source,
+                                       message,
+                                       label)
+        }
 
         None => ErrorMsg {
             level: level,
@@ -610,6 +882,27 @@ fn create_error_msg<'a>(
     }
 }
 
+pub fn type_check_attributes<'a>(tc: &mut TypeContext<'a>, attrs: &'a [Attribute]) {
+    for attr in attrs {
+        match resolve_symbol(attr.ident.sym) {
+            "inline" => {
+                if attr.args.len() > 1 {
+                    type_error(tc, attr.span, "malformed `inline` attribute", "expected at most one argument");
+                }
+
+                for arg in &attr.args {
+                    match resolve_symbol(arg.sym) {
+                        "always" | "never" => {},
+                        _ => type_error(tc, arg.span, "invalid argument", "expected `always` or `never`"),
+                    }
+                }
+            }
+
+            name => type_error(tc, attr.ident.span, &format!("cannot find attribute `{}` in this scope", name), ""),
+        }
+    }
+}
+
 fn type_error<'a>(tc: &mut TypeContext<'a>, span: Span, message: &str, label: &str) {
     let msg = create_error_msg(
         tc,
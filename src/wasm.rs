@@ -0,0 +1,1699 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use crate::ast::resolve_symbol;
+use crate::ir::*;
+use crate::ssa::{
+    IrControlFlowGraph, build_ir_cfg, compute_ir_dominators, ir_defined_variable,
+    ir_dominates, is_ir_conditional_jump, is_ir_terminator, reverse_postorder,
+};
+
+/***************************************************************************
+ * WebAssembly Backend:
+ * - Compiles the IR into a WebAssembly module (MVP), the module can be
+ *   encoded into the binary `.wasm` format or printed as `.wat` text.
+ * - Jumps in the IR are lowered to structured control flow using the dominator tree,
+ *   loop headers start a `loop` and blocks with several predecessors are placed
+ *   after the end of a `block`, other blocks are nested in an `if` or placed inline.
+ *   Irreducible control flow falls back to a dispatcher `loop` that uses `br_table`
+ *   to enter the basic block stored in the `$.pc` local.
+ * - Locals and registers are stored in wasm locals, except for locals that
+ *   are referenced, these are stored in the stack frame in linear memory.
+ * - 64-bit integers are stored as i64, every other value (including booleans
+ *   and pointers) is stored as i32.
+ * - Native and foreign functions are imported from the `env` module,
+ *   see `src/libstd/runtime.js` for a runtime running on node.
+ ***************************************************************************/
+
+/**
+ * Size of the linear memory in pages of 64KiB, the stack starts at the end and grows downwards.
+ */
+const WASM_MEMORY_PAGES: u32 = 1;
+const WASM_PAGE_SIZE: i32 = 65536;
+const WASM_IMPORT_MODULE: &str = "env";
+const WASM_POW_FUNCTION: &str = "sqrrl.pow";
+const WASM_POW_I64_FUNCTION: &str = "sqrrl.pow_i64";
+
+pub struct WasmModule {
+    pub types: Vec<WasmFuncType>,
+    pub imports: Vec<WasmImport>,
+    pub functions: Vec<WasmFunction>,
+    pub memory_pages: u32,
+    pub stack_pointer: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WasmValType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmFuncType {
+    pub params: Vec<WasmValType>,
+    pub result: Option<WasmValType>,
+}
+
+pub struct WasmImport {
+    pub module: String,
+    pub name: String,
+    pub type_index: u32,
+}
+
+pub struct WasmFunction {
+    pub name: String,
+    pub type_index: u32,
+    pub is_exported: bool,
+    pub params: Vec<String>,
+    pub locals: Vec<(String, WasmValType)>,
+    pub body: Vec<WasmInstruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WasmInstruction {
+    Unreachable,
+    Nop,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load(u32), // offset
+    I32Store(u32),
+    I32Const(i32),
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32GtS,
+    I32LeS,
+    I32GeS,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivS,
+    I32RemS,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32ShrU,
+    I64Load(u32), // offset
+    I64Store(u32),
+    I64Const(i64),
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64ShrU,
+}
+
+/**
+ * Location of IR variables, referenced variables live in linear memory.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WasmVariable {
+    Local(u32),
+    Memory(u32), // offset from the frame pointer
+}
+
+struct WasmFunctionBuilder<'a> {
+    body: Vec<WasmInstruction>,
+    variables: HashMap<IrIdent, WasmVariable>,
+    types: HashMap<IrIdent, WasmValType>,
+    temp_slots: HashMap<usize, u32>, // memory for references to values, e.g. `&mut 5`
+    param_slots: Vec<(u32, u32, WasmValType)>, // referenced parameters are copied to the stack frame
+    block_indices: HashMap<IrIdent, usize>,
+    arguments: Vec<(IrOperand, WasmValType)>,
+    function_indices: &'a HashMap<IrIdent, u32>,
+    foreign_addresses: &'a HashMap<u64, IrIdent>,
+    pow_indices: &'a HashMap<WasmValType, u32>,
+    alloc_params_index: usize,
+    num_blocks: usize,
+    curr_block: usize,
+    frame_size: u32,
+    pc_local: u32,
+    fp_local: u32,
+    result: Option<WasmValType>,
+}
+
+/**
+ * Control flow graph of a function with reducible control flow, these are lowered to structured control flow.
+ */
+struct WasmControlFlow {
+    cfg: IrControlFlowGraph,
+    order: Vec<usize>, // reverse postorder number of every block
+    context: Vec<WasmContext>, // enclosing structured control flow, innermost last
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WasmContext {
+    IfThenElse,
+    LoopHeadedBy(usize),
+    BlockFollowedBy(usize),
+}
+
+/**
+ * Global index of the stack pointer.
+ */
+const WASM_STACK_POINTER: u32 = 0;
+
+pub fn compile_ir_to_wasm_module(
+    instructions: Vec<IrInstruction>,
+    functions: HashMap<IrIdent, IrBasicBlock>
+) -> WasmModule {
+    let mut module = WasmModule {
+        types: Vec::new(),
+        imports: Vec::new(),
+        functions: Vec::new(),
+        memory_pages: WASM_MEMORY_PAGES,
+        stack_pointer: WASM_PAGE_SIZE * WASM_MEMORY_PAGES as i32,
+    };
+
+    // NOTE(alexander): the IR calls foreign functions by address, map them back to their names
+    let mut foreign_addresses: HashMap<u64, IrIdent> = HashMap::new();
+    for (ident, bb) in &functions {
+        if let Some(addr) = bb.func_address {
+            if addr != 0 {
+                foreign_addresses.insert(addr as u64, *ident);
+            }
+        }
+    }
+
+    // Functions are compiled in the order they are defined in
+    let mut defined: Vec<(&IrIdent, &IrBasicBlock)> = functions.iter().filter(|(_, bb)| !bb.is_foreign).collect();
+    defined.sort_by_key(|(_, bb)| bb.prologue_index);
+
+    // Foreign functions that are called are imported, these come first in the function index space
+    let mut function_indices: HashMap<IrIdent, u32> = HashMap::new();
+    let mut pow_types: Vec<WasmValType> = Vec::new();
+    let mut argument_types: Vec<WasmValType> = Vec::new();
+    for insn in &instructions {
+        match insn.opcode {
+            IrOpcode::Pow => {
+                let ty = to_wasm_type(insn.ty);
+                if !pow_types.contains(&ty) {
+                    pow_types.push(ty);
+                }
+            }
+
+            // NOTE(alexander): params before alloc_params are the parameters of the function itself
+            IrOpcode::Param => argument_types.push(to_wasm_type(insn.ty)),
+            IrOpcode::AllocParams => argument_types.clear(),
+
+            IrOpcode::Call => {
+                let params = argument_types.split_off(argument_types.len() - get_ir_param_count(insn));
+                let ident = match insn.op2 {
+                    IrOperand::Value(IrValue::U32(addr)) => foreign_addresses.get(&(addr as u64)),
+                    IrOperand::Value(IrValue::U64(addr)) => foreign_addresses.get(&addr),
+                    _ => None,
+                };
+
+                if let Some(ident) = ident {
+                    if !function_indices.contains_key(ident) {
+                        let func_type = WasmFuncType {
+                            params,
+                            result: to_wasm_result_type(insn.ty),
+                        };
+                        let type_index = push_wasm_type(&mut module, func_type);
+                        function_indices.insert(*ident, module.imports.len() as u32);
+                        module.imports.push(WasmImport {
+                            module: WASM_IMPORT_MODULE.to_string(),
+                            name: resolve_symbol(ident.symbol).to_string(),
+                            type_index,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (i, (ident, _)) in defined.iter().enumerate() {
+        function_indices.insert(**ident, (module.imports.len() + i) as u32);
+    }
+
+    // The pow functions are placed after the defined functions, one per type of the pow instructions
+    let mut pow_indices: HashMap<WasmValType, u32> = HashMap::new();
+    for (i, ty) in pow_types.iter().enumerate() {
+        pow_indices.insert(*ty, (module.imports.len() + defined.len() + i) as u32);
+    }
+
+    for (ident, bb) in &defined {
+        let insns = &instructions[bb.prologue_index..=bb.epilogue_index];
+        let func = build_wasm_function(&mut module, insns, bb, **ident, &function_indices,
+                                       &foreign_addresses, &pow_indices);
+        module.functions.push(func);
+    }
+
+    for ty in pow_types {
+        let func = build_wasm_pow_function(&mut module, ty);
+        module.functions.push(func);
+    }
+
+    module
+}
+
+fn build_wasm_function(
+    module: &mut WasmModule,
+    insns: &[IrInstruction],
+    bb: &IrBasicBlock,
+    ident: IrIdent,
+    function_indices: &HashMap<IrIdent, u32>,
+    foreign_addresses: &HashMap<u64, IrIdent>,
+    pow_indices: &HashMap<WasmValType, u32>,
+) -> WasmFunction {
+    let mut wb = WasmFunctionBuilder {
+        body: Vec::new(),
+        variables: HashMap::new(),
+        types: HashMap::new(),
+        temp_slots: HashMap::new(),
+        param_slots: Vec::new(),
+        block_indices: HashMap::new(),
+        arguments: Vec::new(),
+        function_indices,
+        foreign_addresses,
+        pow_indices,
+        alloc_params_index: 0,
+        num_blocks: 1,
+        curr_block: 0,
+        frame_size: 0,
+        pc_local: 0,
+        fp_local: 0,
+        result: to_wasm_result_type(bb.return_type),
+    };
+
+    // Parameters are defined before alloc_params, these are the first wasm locals,
+    // the rest of the param instructions are arguments to function calls.
+    wb.alloc_params_index = insns.iter().position(|insn| insn.opcode == IrOpcode::AllocParams).unwrap_or(0);
+    let mut params = Vec::new();
+    let mut param_types = Vec::new();
+    for insn in &insns[..wb.alloc_params_index] {
+        if let (IrOpcode::Param, IrOperand::Ident(param)) = (&insn.opcode, insn.op1) {
+            wb.variables.insert(param, WasmVariable::Local(params.len() as u32));
+            wb.types.insert(param, to_wasm_type(insn.ty));
+            params.push(param);
+            param_types.push(to_wasm_type(insn.ty));
+        }
+    }
+
+    // The type of the other variables is given by the instructions defining them
+    for insn in insns {
+        let var = match insn.opcode {
+            IrOpcode::Alloca => match insn.op1 {
+                IrOperand::Ident(var) => Some(var),
+                _ => None,
+            },
+            _ => ir_defined_variable(insn),
+        };
+
+        if let Some(var) = var {
+            let ty = match insn.opcode {
+                IrOpcode::Lt | IrOpcode::Le | IrOpcode::Gt |
+                IrOpcode::Ge | IrOpcode::Eq | IrOpcode::Ne => WasmValType::I32,
+                _ => to_wasm_type(insn.ty),
+            };
+            wb.types.entry(var).or_insert(ty);
+        }
+    }
+
+    // Referenced variables are allocated in the stack frame
+    let mut referenced = Vec::new();
+    for (i, insn) in insns.iter().enumerate() {
+        if insn.opcode == IrOpcode::CopyFromRef {
+            match insn.op2 {
+                IrOperand::Ident(var) if !referenced.contains(&var) => referenced.push(var),
+                IrOperand::Value(value) => {
+                    let offset = allocate_wasm_frame_slot(&mut wb, to_wasm_value_type(value));
+                    wb.temp_slots.insert(i, offset);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for var in referenced {
+        let ty = get_wasm_variable_type(&wb, var);
+        let offset = allocate_wasm_frame_slot(&mut wb, ty);
+        if let Some(WasmVariable::Local(index)) = wb.variables.get(&var) {
+            wb.param_slots.push((*index, offset, ty));
+        }
+        wb.variables.insert(var, WasmVariable::Memory(offset));
+    }
+
+    // NOTE(alexander): the control flow graph is built from the instructions between the prologue and the epilogue
+    let mut cfg = build_ir_cfg(&insns[1..insns.len() - 1]);
+    compute_ir_dominators(&mut cfg);
+    let control_flow = create_wasm_control_flow(cfg);
+
+    // The remaining variables are stored in wasm locals, `$.pc` is only needed by the dispatcher
+    let mut locals = Vec::new();
+    if control_flow.is_none() {
+        wb.pc_local = params.len() as u32;
+        locals.push((String::from(".pc"), WasmValType::I32));
+    }
+    wb.fp_local = (params.len() + locals.len()) as u32;
+    locals.push((String::from(".fp"), WasmValType::I32));
+    for insn in insns {
+        let operands = match insn.opcode {
+            IrOpcode::Label | IrOpcode::Jump => vec![],
+            IrOpcode::Call => vec![insn.op1],
+            IrOpcode::IfLt | IrOpcode::IfGt | IrOpcode::IfLe |
+            IrOpcode::IfGe | IrOpcode::IfEq | IrOpcode::IfNe => vec![insn.op1, insn.op2],
+            _ => vec![insn.op1, insn.op2, insn.op3, insn.op4],
+        };
+
+        for op in operands {
+            if let IrOperand::Ident(var) = op {
+                if let Entry::Vacant(entry) = wb.variables.entry(var) {
+                    entry.insert(WasmVariable::Local((params.len() + locals.len()) as u32));
+                    locals.push((format!("{}", var), get_wasm_variable_type(&wb, var)));
+                }
+            }
+        }
+    }
+
+    match control_flow {
+        Some(mut wc) => {
+            build_wasm_instruction(&mut wb, 0, &insns[0]);
+            push_wasm_tree(&mut wb, &mut wc, insns, 0);
+
+            // NOTE(alexander): the end of the body is only reachable if it does not end with a return
+            if wb.body.last() != Some(&WasmInstruction::Return) {
+                wb.body.push(WasmInstruction::Unreachable);
+            }
+        }
+        None => push_wasm_dispatcher(&mut wb, insns),
+    }
+
+    let func_type = WasmFuncType {
+        params: param_types,
+        result: wb.result,
+    };
+
+    WasmFunction {
+        name: resolve_symbol(ident.symbol).to_string(),
+        type_index: push_wasm_type(module, func_type),
+        is_exported: resolve_symbol(ident.symbol) == "main",
+        params: params.iter().map(|param| format!("{}", param)).collect(),
+        locals,
+        body: wb.body,
+    }
+}
+
+/**
+ * Lowers irreducible control flow, each basic block is placed after the end of a nested `block`
+ * and jumps set the `$.pc` local and branch back to a dispatcher `loop` that uses `br_table`
+ * to enter the target basic block.
+ */
+fn push_wasm_dispatcher(wb: &mut WasmFunctionBuilder, insns: &[IrInstruction]) {
+    // Every label starts a new basic block
+    for insn in insns {
+        if insn.opcode == IrOpcode::Label {
+            if let IrOperand::Ident(label) = insn.op1 {
+                wb.block_indices.insert(label, wb.num_blocks);
+                wb.num_blocks += 1;
+            }
+        }
+    }
+
+    // Setup the dispatcher, br_table enters the basic block stored in `$.pc`
+    wb.body.push(WasmInstruction::Loop);
+    for _ in 0..wb.num_blocks {
+        wb.body.push(WasmInstruction::Block);
+    }
+    wb.body.push(WasmInstruction::LocalGet(wb.pc_local));
+    let targets: Vec<u32> = (0..wb.num_blocks as u32).collect();
+    wb.body.push(WasmInstruction::BrTable(targets, wb.num_blocks as u32 - 1));
+    wb.body.push(WasmInstruction::End);
+
+    for (i, insn) in insns.iter().enumerate() {
+        match insn.opcode {
+            IrOpcode::IfLt |
+            IrOpcode::IfGt |
+            IrOpcode::IfLe |
+            IrOpcode::IfGe |
+            IrOpcode::IfEq |
+            IrOpcode::IfNe => {
+                // NOTE(alexander): `$.pc` is only read by the dispatcher, so it is safe to set before br_if
+                let target = get_wasm_block_index(wb, insn.op3);
+                wb.body.push(WasmInstruction::I32Const(target as i32));
+                wb.body.push(WasmInstruction::LocalSet(wb.pc_local));
+                push_wasm_condition(wb, insn);
+                wb.body.push(WasmInstruction::BrIf(get_wasm_dispatch_depth(wb)));
+            }
+
+            IrOpcode::Jump => {
+                let target = get_wasm_block_index(wb, insn.op1);
+                if target != wb.curr_block + 1 { // NOTE(alexander): falls through to the next block
+                    wb.body.push(WasmInstruction::I32Const(target as i32));
+                    wb.body.push(WasmInstruction::LocalSet(wb.pc_local));
+                    wb.body.push(WasmInstruction::Br(get_wasm_dispatch_depth(wb)));
+                }
+            }
+
+            IrOpcode::Label => {
+                wb.body.push(WasmInstruction::End);
+                wb.curr_block += 1;
+            }
+
+            _ => build_wasm_instruction(wb, i, insn),
+        }
+    }
+
+    wb.body.push(WasmInstruction::End);
+    wb.body.push(WasmInstruction::Unreachable);
+}
+
+/**
+ * Lowers reducible control flow to structured control flow, see "Beyond Relooper: recursive
+ * translation of unstructured control flow to structured control flow" by Norman Ramsey.
+ * The code of a block is followed by the code of the blocks it immediately dominates,
+ * loop headers are wrapped in a `loop` and every merge node is placed after the end of a `block`
+ * so that the blocks jumping to it can branch out of the `block`.
+ */
+fn push_wasm_tree(wb: &mut WasmFunctionBuilder, wc: &mut WasmControlFlow, insns: &[IrInstruction], b: usize) {
+    let mut merges: Vec<usize> = wc.cfg.blocks[b].dominates.iter()
+        .copied()
+        .filter(|&child| is_wasm_merge_node(wc, child))
+        .collect();
+    merges.sort_by_key(|&child| wc.order[child]);
+
+    if is_wasm_loop_header(wc, b) {
+        wb.body.push(WasmInstruction::Loop);
+        wc.context.push(WasmContext::LoopHeadedBy(b));
+        push_wasm_node_within(wb, wc, insns, b, merges);
+        wc.context.pop();
+        wb.body.push(WasmInstruction::End);
+    } else {
+        push_wasm_node_within(wb, wc, insns, b, merges);
+    }
+}
+
+/**
+ * Merge nodes are ordered by their reverse postorder number,
+ * the last merge node gets the outermost `block` and is placed last.
+ */
+fn push_wasm_node_within(
+    wb: &mut WasmFunctionBuilder,
+    wc: &mut WasmControlFlow,
+    insns: &[IrInstruction],
+    b: usize,
+    mut merges: Vec<usize>,
+) {
+    if let Some(merge) = merges.pop() {
+        wb.body.push(WasmInstruction::Block);
+        wc.context.push(WasmContext::BlockFollowedBy(merge));
+        push_wasm_node_within(wb, wc, insns, b, merges);
+        wc.context.pop();
+        wb.body.push(WasmInstruction::End);
+        push_wasm_tree(wb, wc, insns, merge);
+        return;
+    }
+
+    // NOTE(alexander): the instructions in the graph exclude the label, the prologue is at index 0
+    let position = 1 + wc.cfg.blocks[b].position;
+    let num_insns = wc.cfg.blocks[b].instructions.len();
+    for (i, insn) in insns.iter().enumerate().skip(position).take(num_insns) {
+        if !is_ir_terminator(&insn.opcode) || insn.opcode == IrOpcode::Return {
+            build_wasm_instruction(wb, i, insn);
+        }
+    }
+
+    let exit = wc.cfg.blocks.len() - 1;
+    if b == exit {
+        build_wasm_instruction(wb, insns.len() - 1, &insns[insns.len() - 1]);
+        return;
+    }
+
+    let successors = wc.cfg.blocks[b].successors.clone();
+    match insns[position..position + num_insns].last() {
+        Some(last) if last.opcode == IrOpcode::Return => {}
+
+        Some(last) if is_ir_conditional_jump(&last.opcode) && successors.len() == 2 => {
+            let (next, target) = (successors[0], successors[1]);
+            push_wasm_condition(wb, last);
+            if is_wasm_branch(wc, b, target) {
+                wb.body.push(WasmInstruction::BrIf(get_wasm_branch_depth(wc, b, target)));
+                push_wasm_branch(wb, wc, insns, b, next);
+            } else {
+                wb.body.push(WasmInstruction::If);
+                wc.context.push(WasmContext::IfThenElse);
+                push_wasm_branch(wb, wc, insns, b, target);
+                wb.body.push(WasmInstruction::Else);
+                push_wasm_branch(wb, wc, insns, b, next);
+                wc.context.pop();
+                wb.body.push(WasmInstruction::End);
+            }
+        }
+
+        // NOTE(alexander): jumps, falls through or conditionally jumps to the next block
+        _ => push_wasm_branch(wb, wc, insns, b, successors[0]),
+    }
+}
+
+/**
+ * Backward edges branch to the start of the `loop` and edges to merge nodes branch out of the `block`,
+ * the other successors are only entered from this block and are placed inline.
+ */
+fn push_wasm_branch(
+    wb: &mut WasmFunctionBuilder,
+    wc: &mut WasmControlFlow,
+    insns: &[IrInstruction],
+    from: usize,
+    to: usize,
+) {
+    if is_wasm_branch(wc, from, to) {
+        // NOTE(alexander): the target is placed directly after the end of the innermost block
+        if wc.context.last() != Some(&WasmContext::BlockFollowedBy(to)) {
+            wb.body.push(WasmInstruction::Br(get_wasm_branch_depth(wc, from, to)));
+        }
+    } else {
+        push_wasm_tree(wb, wc, insns, to);
+    }
+}
+
+/**
+ * Per-instruction lowering of everything except for the control flow.
+ */
+fn build_wasm_instruction(wb: &mut WasmFunctionBuilder, i: usize, insn: &IrInstruction) {
+    let ty = to_wasm_type(insn.ty);
+    match insn.opcode {
+        IrOpcode::Nop |
+        IrOpcode::Alloca => {}
+
+        IrOpcode::Param => {
+            if i > wb.alloc_params_index {
+                wb.arguments.push((insn.op1, ty));
+            }
+        }
+
+        IrOpcode::Prologue => {
+            if wb.frame_size > 0 {
+                wb.body.push(WasmInstruction::GlobalGet(WASM_STACK_POINTER));
+                wb.body.push(WasmInstruction::I32Const(wb.frame_size as i32));
+                wb.body.push(WasmInstruction::I32Sub);
+                wb.body.push(WasmInstruction::LocalTee(wb.fp_local));
+                wb.body.push(WasmInstruction::GlobalSet(WASM_STACK_POINTER));
+            }
+        }
+
+        IrOpcode::AllocParams => {
+            for (index, offset, ty) in wb.param_slots.clone() {
+                wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+                wb.body.push(WasmInstruction::LocalGet(index));
+                wb.body.push(get_wasm_store_instruction(ty, offset));
+            }
+        }
+
+        IrOpcode::Copy => {
+            push_wasm_set_begin(wb, insn.op1);
+            push_wasm_get(wb, insn.op2, ty);
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::CopyFromDeref => {
+            push_wasm_set_begin(wb, insn.op1);
+            push_wasm_get(wb, insn.op2, WasmValType::I32);
+            wb.body.push(get_wasm_load_instruction(ty, 0));
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::CopyFromRef => {
+            let offset = match insn.op2 {
+                IrOperand::Ident(var) => match wb.variables.get(&var) {
+                    Some(WasmVariable::Memory(offset)) => *offset,
+                    _ => panic!("wasm: referenced variable `{}` is not in memory", var),
+                },
+
+                IrOperand::Value(value) => {
+                    // Values are stored in a temporary memory slot to be able to reference them
+                    let offset = wb.temp_slots[&i];
+                    let value_ty = to_wasm_value_type(value);
+                    wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+                    push_wasm_get(wb, insn.op2, value_ty);
+                    wb.body.push(get_wasm_store_instruction(value_ty, offset));
+                    offset
+                }
+
+                IrOperand::None => panic!("wasm: cannot reference empty operand"),
+            };
+
+            push_wasm_set_begin(wb, insn.op1);
+            wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+            wb.body.push(WasmInstruction::I32Const(offset as i32));
+            wb.body.push(WasmInstruction::I32Add);
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::CopyToDeref => {
+            push_wasm_get(wb, insn.op1, WasmValType::I32);
+            push_wasm_get(wb, insn.op2, ty);
+            wb.body.push(get_wasm_store_instruction(ty, 0));
+        }
+
+        IrOpcode::Clear => {
+            push_wasm_set_begin(wb, insn.op1);
+            push_wasm_get(wb, IrOperand::None, ty);
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::Add |
+        IrOpcode::Sub |
+        IrOpcode::Mul |
+        IrOpcode::Div |
+        IrOpcode::Mod |
+        IrOpcode::Pow |
+        IrOpcode::And |
+        IrOpcode::Or  |
+        IrOpcode::Xor |
+        IrOpcode::Lt  |
+        IrOpcode::Le  |
+        IrOpcode::Gt  |
+        IrOpcode::Ge  |
+        IrOpcode::Eq  |
+        IrOpcode::Ne  => {
+            let op = match insn.opcode {
+                IrOpcode::Pow => WasmInstruction::Call(wb.pow_indices[&ty]),
+                _ => get_wasm_binary_instruction(&insn.opcode, insn.ty),
+            };
+
+            // NOTE(alexander): rem_s does not trap on overflow, the division traps the same as the interpreter
+            if insn.opcode == IrOpcode::Mod && insn.ty != IrType::U64 {
+                push_wasm_get(wb, insn.op2, ty);
+                push_wasm_get(wb, insn.op3, ty);
+                wb.body.push(get_wasm_binary_instruction(&IrOpcode::Div, insn.ty));
+                wb.body.push(WasmInstruction::Drop);
+            }
+
+            push_wasm_set_begin(wb, insn.op1);
+            push_wasm_get(wb, insn.op2, ty);
+            push_wasm_get(wb, insn.op3, ty);
+            wb.body.push(op);
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::Select => {
+            // NOTE(alexander): select takes the condition last
+            push_wasm_set_begin(wb, insn.op1);
+            push_wasm_get(wb, insn.op3, ty);
+            push_wasm_get(wb, insn.op4, ty);
+            push_wasm_get(wb, insn.op2, WasmValType::I32);
+            wb.body.push(WasmInstruction::Select);
+            push_wasm_set_end(wb, insn.op1);
+        }
+
+        IrOpcode::Call => {
+            let num_args = get_ir_param_count(insn);
+            let args = wb.arguments.split_off(wb.arguments.len() - num_args);
+
+            let func_index = match insn.op2 {
+                IrOperand::Ident(func) => wb.function_indices.get(&func).copied(),
+                IrOperand::Value(IrValue::U32(addr)) => wb.foreign_addresses.get(&(addr as u64))
+                    .and_then(|func| wb.function_indices.get(func).copied()),
+                IrOperand::Value(IrValue::U64(addr)) => wb.foreign_addresses.get(&addr)
+                    .and_then(|func| wb.function_indices.get(func).copied()),
+                _ => None,
+            };
+
+            // NOTE(alexander): interpreter level intrinsics (e.g. trace) and debug_break are not called
+            if let Some(func_index) = func_index {
+                if insn.ty != IrType::None {
+                    push_wasm_set_begin(wb, insn.op1);
+                }
+                for (arg, arg_ty) in args {
+                    push_wasm_get(wb, arg, arg_ty);
+                }
+                wb.body.push(WasmInstruction::Call(func_index));
+                if insn.ty != IrType::None {
+                    push_wasm_set_end(wb, insn.op1);
+                }
+            }
+        }
+
+        IrOpcode::Return => {
+            if let Some(result) = wb.result {
+                push_wasm_get(wb, insn.op1, result);
+            }
+            push_wasm_return(wb);
+        }
+
+        IrOpcode::Epilogue => {
+            // NOTE(alexander): reached when a function with return value has no return, e.g. main
+            if let Some(result) = wb.result {
+                push_wasm_get(wb, IrOperand::None, result);
+            }
+            push_wasm_return(wb);
+        }
+
+        IrOpcode::IfLt |
+        IrOpcode::IfGt |
+        IrOpcode::IfLe |
+        IrOpcode::IfGe |
+        IrOpcode::IfEq |
+        IrOpcode::IfNe |
+        IrOpcode::Jump |
+        IrOpcode::Label => panic!("wasm: control flow has to be lowered by the caller"),
+
+        IrOpcode::Phi => panic!("wasm: phi instructions has to be removed before code generation"),
+
+        IrOpcode::AsmIn |
+        IrOpcode::Asm   |
+        IrOpcode::AsmOut => panic!("wasm: inline assembly is only supported by the x86 backend"),
+    }
+}
+
+/**
+ * Integer power function used by the pow instructions of the given type, computed by squaring.
+ * NOTE(alexander): the exponent is treated as an unsigned 32-bit integer the same as the interpreter.
+ */
+fn build_wasm_pow_function(module: &mut WasmModule, ty: WasmValType) -> WasmFunction {
+    use WasmInstruction::*;
+
+    let (name, one, zero, eq, ne, and, mul, shr) = match ty {
+        WasmValType::I32 => (WASM_POW_FUNCTION, I32Const(1), I32Const(0), I32Eq, I32Ne, I32And, I32Mul, I32ShrU),
+        WasmValType::I64 => (WASM_POW_I64_FUNCTION, I64Const(1), I64Const(0), I64Eq, I64Ne, I64And, I64Mul, I64ShrU),
+    };
+
+    let mut body = Vec::new();
+    if ty == WasmValType::I64 {
+        body.extend([LocalGet(1), I64Const(u32::MAX as i64), I64And, LocalSet(1)]);
+    }
+    body.extend([
+        one.clone(), LocalSet(2),
+        Block,
+        Loop,
+        LocalGet(1), zero.clone(), eq, BrIf(1),
+        LocalGet(1), one.clone(), and, zero, ne,
+        If,
+        LocalGet(2), LocalGet(0), mul.clone(), LocalSet(2),
+        End,
+        LocalGet(0), LocalGet(0), mul, LocalSet(0),
+        LocalGet(1), one, shr, LocalSet(1),
+        Br(0),
+        End,
+        End,
+        LocalGet(2),
+    ]);
+
+    let type_index = push_wasm_type(module, WasmFuncType { params: vec![ty, ty], result: Some(ty) });
+    WasmFunction {
+        name: name.to_string(),
+        type_index,
+        is_exported: false,
+        params: vec![String::from("base"), String::from("exp")],
+        locals: vec![(String::from("result"), ty)],
+        body,
+    }
+}
+
+/***************************************************************************
+ * WebAssembly function builder helpers
+ ***************************************************************************/
+
+/**
+ * Pushes the value of the operand, constants are created with the given type.
+ */
+fn push_wasm_get(wb: &mut WasmFunctionBuilder, op: IrOperand, ty: WasmValType) {
+    match op {
+        IrOperand::Ident(var) => match wb.variables.get(&var) {
+            Some(WasmVariable::Local(index)) => wb.body.push(WasmInstruction::LocalGet(*index)),
+            Some(WasmVariable::Memory(offset)) => {
+                let offset = *offset;
+                wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+                wb.body.push(get_wasm_load_instruction(get_wasm_variable_type(wb, var), offset));
+            }
+            None => panic!("wasm: unknown variable `{}`", var),
+        },
+
+        IrOperand::Value(value) => {
+            let value = match value {
+                IrValue::I32(v) => v as i64,
+                IrValue::I64(v) => v,
+                IrValue::U32(v) => v as i64,
+                IrValue::U64(v) => v as i64,
+                IrValue::Bool(v) => v as i64,
+            };
+            match ty {
+                WasmValType::I32 => wb.body.push(WasmInstruction::I32Const(value as i32)),
+                WasmValType::I64 => wb.body.push(WasmInstruction::I64Const(value)),
+            }
+        }
+
+        IrOperand::None => push_wasm_get(wb, IrOperand::Value(IrValue::I32(0)), ty),
+    }
+}
+
+/**
+ * Stores to memory need the address below the value on the stack,
+ * so the frame pointer is pushed before the value is computed.
+ */
+fn push_wasm_set_begin(wb: &mut WasmFunctionBuilder, op: IrOperand) {
+    if let IrOperand::Ident(var) = op {
+        if let Some(WasmVariable::Memory(_)) = wb.variables.get(&var) {
+            wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+        }
+    }
+}
+
+fn push_wasm_set_end(wb: &mut WasmFunctionBuilder, op: IrOperand) {
+    match op {
+        IrOperand::Ident(var) => match wb.variables.get(&var) {
+            Some(WasmVariable::Local(index)) => wb.body.push(WasmInstruction::LocalSet(*index)),
+            Some(WasmVariable::Memory(offset)) => {
+                let store = get_wasm_store_instruction(get_wasm_variable_type(wb, var), *offset);
+                wb.body.push(store);
+            }
+            None => panic!("wasm: unknown variable `{}`", var),
+        },
+        _ => panic!("wasm: cannot assign to `{}`", op),
+    }
+}
+
+fn push_wasm_return(wb: &mut WasmFunctionBuilder) {
+    if wb.frame_size > 0 {
+        wb.body.push(WasmInstruction::LocalGet(wb.fp_local));
+        wb.body.push(WasmInstruction::I32Const(wb.frame_size as i32));
+        wb.body.push(WasmInstruction::I32Add);
+        wb.body.push(WasmInstruction::GlobalSet(WASM_STACK_POINTER));
+    }
+    wb.body.push(WasmInstruction::Return);
+}
+
+/**
+ * Allocates a slot in the stack frame aligned to the size of the type.
+ */
+fn allocate_wasm_frame_slot(wb: &mut WasmFunctionBuilder, ty: WasmValType) -> u32 {
+    let size = match ty {
+        WasmValType::I32 => 4,
+        WasmValType::I64 => 8,
+    };
+    let offset = wb.frame_size.div_ceil(size) * size;
+    wb.frame_size = offset + size;
+    offset
+}
+
+fn get_wasm_variable_type(wb: &WasmFunctionBuilder, var: IrIdent) -> WasmValType {
+    wb.types.get(&var).copied().unwrap_or(WasmValType::I32)
+}
+
+fn get_wasm_load_instruction(ty: WasmValType, offset: u32) -> WasmInstruction {
+    match ty {
+        WasmValType::I32 => WasmInstruction::I32Load(offset),
+        WasmValType::I64 => WasmInstruction::I64Load(offset),
+    }
+}
+
+fn get_wasm_store_instruction(ty: WasmValType, offset: u32) -> WasmInstruction {
+    match ty {
+        WasmValType::I32 => WasmInstruction::I32Store(offset),
+        WasmValType::I64 => WasmInstruction::I64Store(offset),
+    }
+}
+
+/**
+ * Arithmetic and comparison instructions, unsigned 64-bit integers use the unsigned variants.
+ */
+fn get_wasm_binary_instruction(opcode: &IrOpcode, ty: IrType) -> WasmInstruction {
+    use WasmInstruction::*;
+
+    match (to_wasm_type(ty), ty == IrType::U64, opcode) {
+        (WasmValType::I32, _, IrOpcode::Add) => I32Add,
+        (WasmValType::I32, _, IrOpcode::Sub) => I32Sub,
+        (WasmValType::I32, _, IrOpcode::Mul) => I32Mul,
+        (WasmValType::I32, _, IrOpcode::Div) => I32DivS,
+        (WasmValType::I32, _, IrOpcode::Mod) => I32RemS,
+        (WasmValType::I32, _, IrOpcode::And) => I32And,
+        (WasmValType::I32, _, IrOpcode::Or)  => I32Or,
+        (WasmValType::I32, _, IrOpcode::Xor) => I32Xor,
+        (WasmValType::I32, _, IrOpcode::Lt)  => I32LtS,
+        (WasmValType::I32, _, IrOpcode::Le)  => I32LeS,
+        (WasmValType::I32, _, IrOpcode::Gt)  => I32GtS,
+        (WasmValType::I32, _, IrOpcode::Ge)  => I32GeS,
+        (WasmValType::I32, _, IrOpcode::Eq)  => I32Eq,
+        (WasmValType::I32, _, IrOpcode::Ne)  => I32Ne,
+
+        (WasmValType::I64, _,     IrOpcode::Add) => I64Add,
+        (WasmValType::I64, _,     IrOpcode::Sub) => I64Sub,
+        (WasmValType::I64, _,     IrOpcode::Mul) => I64Mul,
+        (WasmValType::I64, false, IrOpcode::Div) => I64DivS,
+        (WasmValType::I64, true,  IrOpcode::Div) => I64DivU,
+        (WasmValType::I64, false, IrOpcode::Mod) => I64RemS,
+        (WasmValType::I64, true,  IrOpcode::Mod) => I64RemU,
+        (WasmValType::I64, _,     IrOpcode::And) => I64And,
+        (WasmValType::I64, _,     IrOpcode::Or)  => I64Or,
+        (WasmValType::I64, _,     IrOpcode::Xor) => I64Xor,
+        (WasmValType::I64, false, IrOpcode::Lt)  => I64LtS,
+        (WasmValType::I64, true,  IrOpcode::Lt)  => I64LtU,
+        (WasmValType::I64, false, IrOpcode::Le)  => I64LeS,
+        (WasmValType::I64, true,  IrOpcode::Le)  => I64LeU,
+        (WasmValType::I64, false, IrOpcode::Gt)  => I64GtS,
+        (WasmValType::I64, true,  IrOpcode::Gt)  => I64GtU,
+        (WasmValType::I64, false, IrOpcode::Ge)  => I64GeS,
+        (WasmValType::I64, true,  IrOpcode::Ge)  => I64GeU,
+        (WasmValType::I64, _,     IrOpcode::Eq)  => I64Eq,
+        (WasmValType::I64, _,     IrOpcode::Ne)  => I64Ne,
+
+        _ => panic!("wasm: `{}` is not a binary instruction", opcode),
+    }
+}
+
+/**
+ * 64-bit integers are the only values that are not represented using i32.
+ */
+fn to_wasm_type(ty: IrType) -> WasmValType {
+    match ty {
+        IrType::I64 | IrType::U64 => WasmValType::I64,
+        _ => WasmValType::I32,
+    }
+}
+
+fn to_wasm_result_type(ty: IrType) -> Option<WasmValType> {
+    match ty {
+        IrType::None => None,
+        _ => Some(to_wasm_type(ty)),
+    }
+}
+
+fn to_wasm_value_type(value: IrValue) -> WasmValType {
+    match value {
+        IrValue::I64(_) | IrValue::U64(_) => WasmValType::I64,
+        _ => WasmValType::I32,
+    }
+}
+
+fn push_wasm_type(module: &mut WasmModule, func_type: WasmFuncType) -> u32 {
+    match module.types.iter().position(|ty| *ty == func_type) {
+        Some(index) => index as u32,
+        None => {
+            module.types.push(func_type);
+            module.types.len() as u32 - 1
+        }
+    }
+}
+
+fn get_wasm_block_index(wb: &WasmFunctionBuilder, label: IrOperand) -> usize {
+    match label {
+        IrOperand::Ident(label) => match wb.block_indices.get(&label) {
+            Some(index) => *index,
+            None => panic!("wasm: jump to unknown label `{}`", label),
+        },
+        _ => panic!("wasm: expected label"),
+    }
+}
+
+/**
+ * Relative depth of the dispatcher loop, every following basic block is still an open block.
+ */
+fn get_wasm_dispatch_depth(wb: &WasmFunctionBuilder) -> u32 {
+    (wb.num_blocks - 1 - wb.curr_block) as u32
+}
+
+/**
+ * Checks that the target of every backward edge dominates its source, i.e. that every loop has a single entry.
+ */
+fn create_wasm_control_flow(cfg: IrControlFlowGraph) -> Option<WasmControlFlow> {
+    let mut order = vec![usize::MAX; cfg.blocks.len()];
+    for (i, b) in reverse_postorder(&cfg).iter().enumerate() {
+        order[*b] = i;
+    }
+
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for &s in &block.successors {
+            if order[s] <= order[b] && !ir_dominates(&cfg, s, b) {
+                return None;
+            }
+        }
+    }
+
+    Some(WasmControlFlow { cfg, order, context: Vec::new() })
+}
+
+fn is_wasm_loop_header(wc: &WasmControlFlow, b: usize) -> bool {
+    wc.cfg.blocks[b].predecessors.iter().any(|&p| wc.order[b] <= wc.order[p])
+}
+
+/**
+ * Blocks entered by more than one forward edge, returns are not counted as they don't branch to the exit.
+ */
+fn is_wasm_merge_node(wc: &WasmControlFlow, b: usize) -> bool {
+    let num_forward = wc.cfg.blocks[b].predecessors.iter()
+        .filter(|&&p| wc.order[p] < wc.order[b])
+        .filter(|&&p| wc.cfg.blocks[p].instructions.last().map(|insn| insn.opcode != IrOpcode::Return).unwrap_or(true))
+        .count();
+    num_forward > 1
+}
+
+/**
+ * True if the edge is lowered to a branch, otherwise the target is placed inline.
+ */
+fn is_wasm_branch(wc: &WasmControlFlow, from: usize, to: usize) -> bool {
+    wc.order[to] <= wc.order[from] || is_wasm_merge_node(wc, to)
+}
+
+/**
+ * Relative depth of the `loop` of a backward edge or the `block` followed by a merge node.
+ */
+fn get_wasm_branch_depth(wc: &WasmControlFlow, from: usize, to: usize) -> u32 {
+    let target = if wc.order[to] <= wc.order[from] {
+        WasmContext::LoopHeadedBy(to)
+    } else {
+        WasmContext::BlockFollowedBy(to)
+    };
+
+    match wc.context.iter().rev().position(|context| *context == target) {
+        Some(depth) => depth as u32,
+        None => panic!("wasm: branch target `{:?}` is not enclosing the branch", target),
+    }
+}
+
+/**
+ * Pushes the condition of a conditional jump.
+ */
+fn push_wasm_condition(wb: &mut WasmFunctionBuilder, insn: &IrInstruction) {
+    let op = match insn.opcode {
+        IrOpcode::IfLt => get_wasm_binary_instruction(&IrOpcode::Lt, insn.ty),
+        IrOpcode::IfGt => get_wasm_binary_instruction(&IrOpcode::Gt, insn.ty),
+        IrOpcode::IfLe => get_wasm_binary_instruction(&IrOpcode::Le, insn.ty),
+        IrOpcode::IfGe => get_wasm_binary_instruction(&IrOpcode::Ge, insn.ty),
+        IrOpcode::IfEq => get_wasm_binary_instruction(&IrOpcode::Eq, insn.ty),
+        _              => get_wasm_binary_instruction(&IrOpcode::Ne, insn.ty),
+    };
+
+    let ty = to_wasm_type(insn.ty);
+    push_wasm_get(wb, insn.op1, ty);
+    push_wasm_get(wb, insn.op2, ty);
+    wb.body.push(op);
+}
+
+fn get_ir_param_count(insn: &IrInstruction) -> usize {
+    match insn.op3 {
+        IrOperand::Value(IrValue::I32(count)) => count as usize,
+        _ => 0,
+    }
+}
+
+/***************************************************************************
+ * WebAssembly binary encoding
+ ***************************************************************************/
+
+const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+const WASM_VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+const WASM_TYPE_I32: u8 = 0x7f;
+const WASM_TYPE_I64: u8 = 0x7e;
+const WASM_TYPE_FUNC: u8 = 0x60;
+const WASM_BLOCK_TYPE_EMPTY: u8 = 0x40;
+
+const WASM_SECTION_TYPE: u8 = 1;
+const WASM_SECTION_IMPORT: u8 = 2;
+const WASM_SECTION_FUNCTION: u8 = 3;
+const WASM_SECTION_MEMORY: u8 = 5;
+const WASM_SECTION_GLOBAL: u8 = 6;
+const WASM_SECTION_EXPORT: u8 = 7;
+const WASM_SECTION_CODE: u8 = 10;
+
+const WASM_EXTERNAL_FUNCTION: u8 = 0x00;
+const WASM_EXTERNAL_MEMORY: u8 = 0x02;
+
+/**
+ * Encodes the module using the WebAssembly binary format.
+ */
+pub fn encode_wasm_module(module: &WasmModule) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&WASM_MAGIC);
+    bytes.extend_from_slice(&WASM_VERSION);
+
+    let mut section = Vec::new();
+    push_uleb128(&mut section, module.types.len() as u64);
+    for func_type in &module.types {
+        section.push(WASM_TYPE_FUNC);
+        push_uleb128(&mut section, func_type.params.len() as u64);
+        section.extend(func_type.params.iter().map(|ty| encode_wasm_type(*ty)));
+        match func_type.result {
+            Some(ty) => section.extend_from_slice(&[1, encode_wasm_type(ty)]),
+            None => section.push(0),
+        }
+    }
+    push_wasm_section(&mut bytes, WASM_SECTION_TYPE, &section);
+
+    if !module.imports.is_empty() {
+        let mut section = Vec::new();
+        push_uleb128(&mut section, module.imports.len() as u64);
+        for import in &module.imports {
+            push_wasm_name(&mut section, &import.module);
+            push_wasm_name(&mut section, &import.name);
+            section.push(WASM_EXTERNAL_FUNCTION);
+            push_uleb128(&mut section, import.type_index as u64);
+        }
+        push_wasm_section(&mut bytes, WASM_SECTION_IMPORT, &section);
+    }
+
+    let mut section = Vec::new();
+    push_uleb128(&mut section, module.functions.len() as u64);
+    for func in &module.functions {
+        push_uleb128(&mut section, func.type_index as u64);
+    }
+    push_wasm_section(&mut bytes, WASM_SECTION_FUNCTION, &section);
+
+    let mut section = Vec::new();
+    push_uleb128(&mut section, 1);
+    section.push(0x00); // limits without maximum
+    push_uleb128(&mut section, module.memory_pages as u64);
+    push_wasm_section(&mut bytes, WASM_SECTION_MEMORY, &section);
+
+    let mut section = Vec::new();
+    push_uleb128(&mut section, 1);
+    section.push(WASM_TYPE_I32);
+    section.push(0x01); // mutable
+    push_wasm_instruction(&mut section, &WasmInstruction::I32Const(module.stack_pointer));
+    push_wasm_instruction(&mut section, &WasmInstruction::End);
+    push_wasm_section(&mut bytes, WASM_SECTION_GLOBAL, &section);
+
+    let mut exports = Vec::new();
+    exports.push((String::from("memory"), WASM_EXTERNAL_MEMORY, 0));
+    for (i, func) in module.functions.iter().enumerate() {
+        if func.is_exported {
+            exports.push((func.name.clone(), WASM_EXTERNAL_FUNCTION, (module.imports.len() + i) as u64));
+        }
+    }
+    let mut section = Vec::new();
+    push_uleb128(&mut section, exports.len() as u64);
+    for (name, kind, index) in exports {
+        push_wasm_name(&mut section, &name);
+        section.push(kind);
+        push_uleb128(&mut section, index);
+    }
+    push_wasm_section(&mut bytes, WASM_SECTION_EXPORT, &section);
+
+    let mut section = Vec::new();
+    push_uleb128(&mut section, module.functions.len() as u64);
+    for func in &module.functions {
+        let mut code = Vec::new();
+        push_wasm_locals(&mut code, &func.locals);
+        for insn in &func.body {
+            push_wasm_instruction(&mut code, insn);
+        }
+        push_wasm_instruction(&mut code, &WasmInstruction::End);
+
+        push_uleb128(&mut section, code.len() as u64);
+        section.extend_from_slice(&code);
+    }
+    push_wasm_section(&mut bytes, WASM_SECTION_CODE, &section);
+
+    bytes
+}
+
+/**
+ * Locals are declared in runs of consecutive locals with the same type.
+ */
+fn push_wasm_locals(bytes: &mut Vec<u8>, locals: &[(String, WasmValType)]) {
+    let mut runs: Vec<(u64, WasmValType)> = Vec::new();
+    for (_, ty) in locals {
+        match runs.last_mut() {
+            Some((count, run_ty)) if run_ty == ty => *count += 1,
+            _ => runs.push((1, *ty)),
+        }
+    }
+
+    push_uleb128(bytes, runs.len() as u64);
+    for (count, ty) in runs {
+        push_uleb128(bytes, count);
+        bytes.push(encode_wasm_type(ty));
+    }
+}
+
+fn encode_wasm_type(ty: WasmValType) -> u8 {
+    match ty {
+        WasmValType::I32 => WASM_TYPE_I32,
+        WasmValType::I64 => WASM_TYPE_I64,
+    }
+}
+
+fn push_wasm_section(bytes: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    bytes.push(id);
+    push_uleb128(bytes, contents.len() as u64);
+    bytes.extend_from_slice(contents);
+}
+
+fn push_wasm_name(bytes: &mut Vec<u8>, name: &str) {
+    push_uleb128(bytes, name.len() as u64);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn push_wasm_instruction(bytes: &mut Vec<u8>, insn: &WasmInstruction) {
+    match insn {
+        WasmInstruction::Unreachable => bytes.push(0x00),
+        WasmInstruction::Nop         => bytes.push(0x01),
+        WasmInstruction::Block       => bytes.extend_from_slice(&[0x02, WASM_BLOCK_TYPE_EMPTY]),
+        WasmInstruction::Loop        => bytes.extend_from_slice(&[0x03, WASM_BLOCK_TYPE_EMPTY]),
+        WasmInstruction::If          => bytes.extend_from_slice(&[0x04, WASM_BLOCK_TYPE_EMPTY]),
+        WasmInstruction::Else        => bytes.push(0x05),
+        WasmInstruction::End         => bytes.push(0x0b),
+        WasmInstruction::Br(depth) => {
+            bytes.push(0x0c);
+            push_uleb128(bytes, *depth as u64);
+        }
+        WasmInstruction::BrIf(depth) => {
+            bytes.push(0x0d);
+            push_uleb128(bytes, *depth as u64);
+        }
+        WasmInstruction::BrTable(targets, default) => {
+            bytes.push(0x0e);
+            push_uleb128(bytes, targets.len() as u64);
+            for target in targets {
+                push_uleb128(bytes, *target as u64);
+            }
+            push_uleb128(bytes, *default as u64);
+        }
+        WasmInstruction::Return => bytes.push(0x0f),
+        WasmInstruction::Call(index) => {
+            bytes.push(0x10);
+            push_uleb128(bytes, *index as u64);
+        }
+        WasmInstruction::Drop   => bytes.push(0x1a),
+        WasmInstruction::Select => bytes.push(0x1b),
+        WasmInstruction::LocalGet(index) |
+        WasmInstruction::LocalSet(index) |
+        WasmInstruction::LocalTee(index) |
+        WasmInstruction::GlobalGet(index) |
+        WasmInstruction::GlobalSet(index) => {
+            bytes.push(match insn {
+                WasmInstruction::LocalGet(_) => 0x20,
+                WasmInstruction::LocalSet(_) => 0x21,
+                WasmInstruction::LocalTee(_) => 0x22,
+                WasmInstruction::GlobalGet(_) => 0x23,
+                _ => 0x24,
+            });
+            push_uleb128(bytes, *index as u64);
+        }
+        WasmInstruction::I32Load(offset) |
+        WasmInstruction::I32Store(offset) => {
+            bytes.push(if let WasmInstruction::I32Load(_) = insn { 0x28 } else { 0x36 });
+            push_uleb128(bytes, 2); // alignment 2^2 bytes
+            push_uleb128(bytes, *offset as u64);
+        }
+        WasmInstruction::I64Load(offset) |
+        WasmInstruction::I64Store(offset) => {
+            bytes.push(if let WasmInstruction::I64Load(_) = insn { 0x29 } else { 0x37 });
+            push_uleb128(bytes, 3); // alignment 2^3 bytes
+            push_uleb128(bytes, *offset as u64);
+        }
+        WasmInstruction::I32Const(value) => {
+            bytes.push(0x41);
+            push_sleb128(bytes, *value as i64);
+        }
+        WasmInstruction::I64Const(value) => {
+            bytes.push(0x42);
+            push_sleb128(bytes, *value);
+        }
+        WasmInstruction::I32Eq   => bytes.push(0x46),
+        WasmInstruction::I32Ne   => bytes.push(0x47),
+        WasmInstruction::I32LtS  => bytes.push(0x48),
+        WasmInstruction::I32GtS  => bytes.push(0x4a),
+        WasmInstruction::I32LeS  => bytes.push(0x4c),
+        WasmInstruction::I32GeS  => bytes.push(0x4e),
+        WasmInstruction::I32Add  => bytes.push(0x6a),
+        WasmInstruction::I32Sub  => bytes.push(0x6b),
+        WasmInstruction::I32Mul  => bytes.push(0x6c),
+        WasmInstruction::I32DivS => bytes.push(0x6d),
+        WasmInstruction::I32RemS => bytes.push(0x6f),
+        WasmInstruction::I32And  => bytes.push(0x71),
+        WasmInstruction::I32Or   => bytes.push(0x72),
+        WasmInstruction::I32Xor  => bytes.push(0x73),
+        WasmInstruction::I32ShrU => bytes.push(0x76),
+        WasmInstruction::I64Eq   => bytes.push(0x51),
+        WasmInstruction::I64Ne   => bytes.push(0x52),
+        WasmInstruction::I64LtS  => bytes.push(0x53),
+        WasmInstruction::I64LtU  => bytes.push(0x54),
+        WasmInstruction::I64GtS  => bytes.push(0x55),
+        WasmInstruction::I64GtU  => bytes.push(0x56),
+        WasmInstruction::I64LeS  => bytes.push(0x57),
+        WasmInstruction::I64LeU  => bytes.push(0x58),
+        WasmInstruction::I64GeS  => bytes.push(0x59),
+        WasmInstruction::I64GeU  => bytes.push(0x5a),
+        WasmInstruction::I64Add  => bytes.push(0x7c),
+        WasmInstruction::I64Sub  => bytes.push(0x7d),
+        WasmInstruction::I64Mul  => bytes.push(0x7e),
+        WasmInstruction::I64DivS => bytes.push(0x7f),
+        WasmInstruction::I64DivU => bytes.push(0x80),
+        WasmInstruction::I64RemS => bytes.push(0x81),
+        WasmInstruction::I64RemU => bytes.push(0x82),
+        WasmInstruction::I64And  => bytes.push(0x83),
+        WasmInstruction::I64Or   => bytes.push(0x84),
+        WasmInstruction::I64Xor  => bytes.push(0x85),
+        WasmInstruction::I64ShrU => bytes.push(0x88),
+    }
+}
+
+fn push_uleb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn push_sleb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let is_done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if is_done {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/***************************************************************************
+ * WebAssembly text format
+ ***************************************************************************/
+
+impl fmt::Display for WasmModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "(module")?;
+        for (i, func_type) in self.types.iter().enumerate() {
+            writeln!(f, "  (type (;{};) (func{}))", i, func_type)?;
+        }
+
+        for import in &self.imports {
+            writeln!(f, "  (import \"{}\" \"{}\" (func ${} (type {})))",
+                     import.module, import.name, import.name, import.type_index)?;
+        }
+
+        writeln!(f, "  (memory (export \"memory\") {})", self.memory_pages)?;
+        writeln!(f, "  (global $.sp (mut i32) (i32.const {}))", self.stack_pointer)?;
+
+        for func in &self.functions {
+            write!(f, "  (func ${}", func.name)?;
+            if func.is_exported {
+                write!(f, " (export \"{}\")", func.name)?;
+            }
+            write!(f, " (type {})", func.type_index)?;
+            let func_type = &self.types[func.type_index as usize];
+            for (param, ty) in func.params.iter().zip(&func_type.params) {
+                write!(f, " (param ${} {})", param, ty)?;
+            }
+            if let Some(ty) = func_type.result {
+                write!(f, " (result {})", ty)?;
+            }
+            writeln!(f)?;
+
+            for (local, ty) in &func.locals {
+                writeln!(f, "    (local ${} {})", local, ty)?;
+            }
+
+            // NOTE(alexander): functions, locals and globals are referred to by name for readability
+            let local_names: Vec<&String> = func.params.iter().chain(func.locals.iter().map(|(name, _)| name)).collect();
+            let mut depth = 2;
+            for insn in &func.body {
+                if let WasmInstruction::Else | WasmInstruction::End = insn {
+                    depth -= 1;
+                }
+                write!(f, "{}", "  ".repeat(depth))?;
+                match insn {
+                    WasmInstruction::Call(index) => {
+                        let index = *index as usize;
+                        if index < self.imports.len() {
+                            writeln!(f, "call ${}", self.imports[index].name)?;
+                        } else {
+                            writeln!(f, "call ${}", self.functions[index - self.imports.len()].name)?;
+                        }
+                    }
+                    WasmInstruction::LocalGet(index)  => writeln!(f, "local.get ${}", local_names[*index as usize])?,
+                    WasmInstruction::LocalSet(index)  => writeln!(f, "local.set ${}", local_names[*index as usize])?,
+                    WasmInstruction::LocalTee(index)  => writeln!(f, "local.tee ${}", local_names[*index as usize])?,
+                    WasmInstruction::GlobalGet(_)     => writeln!(f, "global.get $.sp")?,
+                    WasmInstruction::GlobalSet(_)     => writeln!(f, "global.set $.sp")?,
+                    _ => writeln!(f, "{}", insn)?,
+                }
+                if let WasmInstruction::Block | WasmInstruction::Loop | WasmInstruction::If | WasmInstruction::Else = insn {
+                    depth += 1;
+                }
+            }
+            writeln!(f, "  )")?;
+        }
+        writeln!(f, ")")
+    }
+}
+
+impl fmt::Display for WasmFuncType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.params.is_empty() {
+            write!(f, " (param")?;
+            for ty in &self.params {
+                write!(f, " {}", ty)?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(ty) = self.result {
+            write!(f, " (result {})", ty)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WasmValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmValType::I32 => write!(f, "i32"),
+            WasmValType::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+impl fmt::Display for WasmInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmInstruction::Unreachable      => write!(f, "unreachable"),
+            WasmInstruction::Nop              => write!(f, "nop"),
+            WasmInstruction::Block            => write!(f, "block"),
+            WasmInstruction::Loop             => write!(f, "loop"),
+            WasmInstruction::If               => write!(f, "if"),
+            WasmInstruction::Else             => write!(f, "else"),
+            WasmInstruction::End              => write!(f, "end"),
+            WasmInstruction::Br(depth)        => write!(f, "br {}", depth),
+            WasmInstruction::BrIf(depth)      => write!(f, "br_if {}", depth),
+            WasmInstruction::BrTable(targets, default) => {
+                write!(f, "br_table")?;
+                for target in targets {
+                    write!(f, " {}", target)?;
+                }
+                write!(f, " {}", default)
+            }
+            WasmInstruction::Return           => write!(f, "return"),
+            WasmInstruction::Call(index)      => write!(f, "call {}", index),
+            WasmInstruction::Drop             => write!(f, "drop"),
+            WasmInstruction::Select           => write!(f, "select"),
+            WasmInstruction::LocalGet(index)  => write!(f, "local.get {}", index),
+            WasmInstruction::LocalSet(index)  => write!(f, "local.set {}", index),
+            WasmInstruction::LocalTee(index)  => write!(f, "local.tee {}", index),
+            WasmInstruction::GlobalGet(index) => write!(f, "global.get {}", index),
+            WasmInstruction::GlobalSet(index) => write!(f, "global.set {}", index),
+            WasmInstruction::I32Load(0)       => write!(f, "i32.load"),
+            WasmInstruction::I32Load(offset)  => write!(f, "i32.load offset={}", offset),
+            WasmInstruction::I32Store(0)      => write!(f, "i32.store"),
+            WasmInstruction::I32Store(offset) => write!(f, "i32.store offset={}", offset),
+            WasmInstruction::I32Const(value)  => write!(f, "i32.const {}", value),
+            WasmInstruction::I32Eq            => write!(f, "i32.eq"),
+            WasmInstruction::I32Ne            => write!(f, "i32.ne"),
+            WasmInstruction::I32LtS           => write!(f, "i32.lt_s"),
+            WasmInstruction::I32GtS           => write!(f, "i32.gt_s"),
+            WasmInstruction::I32LeS           => write!(f, "i32.le_s"),
+            WasmInstruction::I32GeS           => write!(f, "i32.ge_s"),
+            WasmInstruction::I32Add           => write!(f, "i32.add"),
+            WasmInstruction::I32Sub           => write!(f, "i32.sub"),
+            WasmInstruction::I32Mul           => write!(f, "i32.mul"),
+            WasmInstruction::I32DivS          => write!(f, "i32.div_s"),
+            WasmInstruction::I32RemS          => write!(f, "i32.rem_s"),
+            WasmInstruction::I32And           => write!(f, "i32.and"),
+            WasmInstruction::I32Or            => write!(f, "i32.or"),
+            WasmInstruction::I32Xor           => write!(f, "i32.xor"),
+            WasmInstruction::I32ShrU          => write!(f, "i32.shr_u"),
+            WasmInstruction::I64Load(0)       => write!(f, "i64.load"),
+            WasmInstruction::I64Load(offset)  => write!(f, "i64.load offset={}", offset),
+            WasmInstruction::I64Store(0)      => write!(f, "i64.store"),
+            WasmInstruction::I64Store(offset) => write!(f, "i64.store offset={}", offset),
+            WasmInstruction::I64Const(value)  => write!(f, "i64.const {}", value),
+            WasmInstruction::I64Eq            => write!(f, "i64.eq"),
+            WasmInstruction::I64Ne            => write!(f, "i64.ne"),
+            WasmInstruction::I64LtS           => write!(f, "i64.lt_s"),
+            WasmInstruction::I64LtU           => write!(f, "i64.lt_u"),
+            WasmInstruction::I64GtS           => write!(f, "i64.gt_s"),
+            WasmInstruction::I64GtU           => write!(f, "i64.gt_u"),
+            WasmInstruction::I64LeS           => write!(f, "i64.le_s"),
+            WasmInstruction::I64LeU           => write!(f, "i64.le_u"),
+            WasmInstruction::I64GeS           => write!(f, "i64.ge_s"),
+            WasmInstruction::I64GeU           => write!(f, "i64.ge_u"),
+            WasmInstruction::I64Add           => write!(f, "i64.add"),
+            WasmInstruction::I64Sub           => write!(f, "i64.sub"),
+            WasmInstruction::I64Mul           => write!(f, "i64.mul"),
+            WasmInstruction::I64DivS          => write!(f, "i64.div_s"),
+            WasmInstruction::I64DivU          => write!(f, "i64.div_u"),
+            WasmInstruction::I64RemS          => write!(f, "i64.rem_s"),
+            WasmInstruction::I64RemU          => write!(f, "i64.rem_u"),
+            WasmInstruction::I64And           => write!(f, "i64.and"),
+            WasmInstruction::I64Or            => write!(f, "i64.or"),
+            WasmInstruction::I64Xor           => write!(f, "i64.xor"),
+            WasmInstruction::I64ShrU          => write!(f, "i64.shr_u"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_leb128() {
+        let mut bytes = Vec::new();
+        push_uleb128(&mut bytes, 624485);
+        assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+
+        bytes.clear();
+        push_sleb128(&mut bytes, -123456);
+        assert_eq!(bytes, [0xc0, 0xbb, 0x78]);
+
+        bytes.clear();
+        push_sleb128(&mut bytes, 64);
+        assert_eq!(bytes, [0xc0, 0x00]);
+    }
+
+    #[test]
+    fn encode_locals() {
+        let locals = vec![
+            (String::from(".pc"), WasmValType::I32),
+            (String::from(".fp"), WasmValType::I32),
+            (String::from("a"), WasmValType::I64),
+            (String::from("b"), WasmValType::I32),
+        ];
+        let mut bytes = Vec::new();
+        push_wasm_locals(&mut bytes, &locals);
+        assert_eq!(bytes, [0x03, 0x02, 0x7f, 0x01, 0x7e, 0x01, 0x7f]);
+
+        bytes.clear();
+        push_wasm_locals(&mut bytes, &[]);
+        assert_eq!(bytes, [0x00]);
+
+        bytes.clear();
+        push_wasm_instruction(&mut bytes, &WasmInstruction::I64Const(-5000000000));
+        push_wasm_instruction(&mut bytes, &WasmInstruction::I64Store(8));
+        assert_eq!(bytes, [0x42, 0x80, 0x9c, 0xe8, 0xaf, 0x6d, 0x37, 0x03, 0x08]);
+    }
+
+    #[test]
+    fn encode_module() {
+        // Counts down from n to 1 printing every number, then returns its unused local
+        let module = WasmModule {
+            types: vec![
+                WasmFuncType { params: vec![WasmValType::I32], result: None },
+                WasmFuncType { params: vec![WasmValType::I32], result: Some(WasmValType::I32) },
+            ],
+            imports: vec![WasmImport {
+                module: String::from("env"),
+                name: String::from("print_int"),
+                type_index: 0,
+            }],
+            functions: vec![WasmFunction {
+                name: String::from("count"),
+                type_index: 1,
+                is_exported: true,
+                params: vec![String::from("n")],
+                locals: vec![(String::from("i"), WasmValType::I32)],
+                body: vec![
+                    WasmInstruction::Block,
+                    WasmInstruction::Loop,
+                    WasmInstruction::LocalGet(0),
+                    WasmInstruction::I32Const(0),
+                    WasmInstruction::I32Eq,
+                    WasmInstruction::BrIf(1),
+                    WasmInstruction::LocalGet(0),
+                    WasmInstruction::Call(0),
+                    WasmInstruction::LocalGet(0),
+                    WasmInstruction::I32Const(1),
+                    WasmInstruction::I32Sub,
+                    WasmInstruction::LocalSet(0),
+                    WasmInstruction::Br(0),
+                    WasmInstruction::End,
+                    WasmInstruction::End,
+                    WasmInstruction::LocalGet(1),
+                ],
+            }],
+            memory_pages: 1,
+            stack_pointer: WASM_PAGE_SIZE,
+        };
+
+        let expected: Vec<u8> = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // type section: (i32) -> (), (i32) -> i32
+            0x01, 0x0a, 0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+            // import section: env.print_int with type 0
+            0x02, 0x11, 0x01, 0x03, b'e', b'n', b'v',
+            0x09, b'p', b'r', b'i', b'n', b't', b'_', b'i', b'n', b't', 0x00, 0x00,
+            // function section
+            0x03, 0x02, 0x01, 0x01,
+            // memory section: one page without maximum
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            // global section: mutable i32 stack pointer initialized to 65536
+            0x06, 0x08, 0x01, 0x7f, 0x01, 0x41, 0x80, 0x80, 0x04, 0x0b,
+            // export section: memory and count (function index 1 after the import)
+            0x07, 0x12, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+            0x05, b'c', b'o', b'u', b'n', b't', 0x00, 0x01,
+            // code section
+            0x0a, 0x22, 0x01, 0x20,
+            0x01, 0x01, 0x7f,       // one local of type i32
+            0x02, 0x40,             // block
+            0x03, 0x40,             // loop
+            0x20, 0x00,             // local.get 0
+            0x41, 0x00,             // i32.const 0
+            0x46,                   // i32.eq
+            0x0d, 0x01,             // br_if 1
+            0x20, 0x00,             // local.get 0
+            0x10, 0x00,             // call 0
+            0x20, 0x00,             // local.get 0
+            0x41, 0x01,             // i32.const 1
+            0x6b,                   // i32.sub
+            0x21, 0x00,             // local.set 0
+            0x0c, 0x00,             // br 0
+            0x0b,                   // end loop
+            0x0b,                   // end block
+            0x20, 0x01,             // local.get 1
+            0x0b,                   // end function
+        ];
+
+        assert_eq!(encode_wasm_module(&module), expected);
+    }
+}
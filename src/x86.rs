@@ -1,25 +1,75 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::VecDeque;
 use std::fmt;
-use crate::ast::{Symbol, intern_string};
+use crate::ast::{AsmDir, ExprAsm, Span, Symbol, intern_string, resolve_symbol};
 use crate::ir::*;
+use crate::ssa::{ir_defined_variable, ir_used_variables};
 
 struct X86Assembler {
     machine_code: Vec<u8>,
+    instructions: Vec<X86Instruction>, // selected for the current function, encoded afterwards
+    peephole: bool, // run the peephole optimizer before encoding
     label_byte_pos: HashMap<IrIdent, usize>, // position in machine_code to each label
     relative_jumps: Vec<X86RelJump>,
-    local_variables: HashMap<IrIdent, (X86Operand, IrType)>,
-    allocated_registers: VecDeque<(X86Reg, Option<IrIdent>)>,
-    free_registers: VecDeque<X86Reg>,
+    local_variables: HashMap<IrIdent, X86Operand>, // location decided by the register allocator
+    scratch_registers: [X86Reg; 2], // never allocated, the second one is used by push_instruction
     argument_stack: VecDeque<(IrOperand, IrType)>, // ordered left-to-right
     curr_stack_offset: isize,
     max_stack_requirement: isize,
+    outgoing_arguments_size: isize, // stack arguments of the calls, stored at the bottom of the stack frame
     temp_variable_symbol: Symbol,
     debug_break_symbol: Symbol,
+    local_label_symbol: Symbol,
+    local_label_index: u32, // labels created by the backend e.g. loops in the pow instruction
     assembly: String,
     print_assembly: bool,
     addr_size: isize,
     x64_mode: bool,
+    output: X86Output,
+    foreign_functions: HashMap<u64, IrIdent>, // function address to foreign function
+    relocations: Vec<(usize, IrIdent)>, // position in machine_code to rel32 call target
+    inserted_bytes: Vec<(usize, usize)>, // bytes inserted when extending jumps
+    runtime_routines: Vec<IrIdent>, // foreign functions emitted by the backend
+    function_labels: HashSet<IrIdent>, // labels that keep their name in the assembly
+}
+
+/**
+ * Where the compiled machine code ends up, decides how foreign functions are called.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum X86Output {
+    Jit,        // absolute address of the foreign function
+    Object,     // relocation resolved by the linker
+    Executable, // routine emitted by the backend, no libc available
+}
+
+/**
+ * Machine code compiled ahead of time, where calls to foreign functions
+ * are left to be resolved by the linker.
+ */
+pub struct X86Object {
+    pub machine_code: Vec<u8>,
+    pub assembly: String,
+    pub symbols: Vec<X86Symbol>,
+    pub relocations: Vec<X86Relocation>,
+}
+
+/**
+ * Function defined in the machine code.
+ */
+pub struct X86Symbol {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub is_global: bool,
+}
+
+/**
+ * Relative 32-bit call to a foreign function located at the given offset.
+ */
+pub struct X86Relocation {
+    pub offset: usize,
+    pub symbol: String,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -32,20 +82,55 @@ struct X86RelJump {
     is_long_jump: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+/**
+ * Symbolic x86 instruction, the instruction selection produces these for one function at a time
+ * which then goes through the peephole optimizer before being encoded into machine code.
+ * Memory operands are represented by `X86Operand::Stack` using any register as base.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct X86Instruction {
+    opcode: X86Opcode,
+    ty: IrType,
+    op1: Option<X86Operand>,
+    op2: Option<X86Operand>,
+    op3: Option<X86Operand>, // only used by imul with an immediate
+    label: Option<IrIdent>, // target of jumps and calls, or the label placed by LABEL
+    volatile: bool, // written by inline assembly, never rewritten by the peephole optimizer
+}
+
+impl Default for X86Instruction {
+    fn default() -> Self {
+        X86Instruction {
+            opcode: X86Opcode::NOP,
+            ty: IrType::None,
+            op1: None,
+            op2: None,
+            op3: None,
+            label: None,
+            volatile: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum X86Opcode {
+    LABEL, // pseudo instruction marking the position of a label
     NOP,
     MOV,
+    MOVABS,
     MOVSX,
     LEA,
     ADD,
     SUB,
     IMUL,
     IDIV,
+    DIV,
     AND,
     OR,
     XOR,
+    SHR,
     CDQ,
+    CQO,
     CMP,
     TEST,
     SETL,
@@ -54,23 +139,45 @@ enum X86Opcode {
     SETGE,
     SETE,
     SETNE,
+    SETB,
+    SETA,
+    SETBE,
+    SETAE,
+    CMOVL,
+    CMOVG,
+    CMOVLE,
+    CMOVGE,
+    CMOVE,
+    CMOVNE,
+    CMOVB,
+    CMOVA,
+    CMOVBE,
+    CMOVAE,
     JL,
     JLE,
     JG,
     JGE,
     JE,
     JNE,
+    JB,
+    JBE,
+    JA,
+    JAE,
     JMP,
     PUSH,
     POP,
     CALL,
     RET,
     INT3,
+    SYSCALL,
+    RDTSC,
+    CPUID,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum X86Operand {
     Stack(X86Reg, isize),
+    Indexed(X86Reg, X86Reg, u8, isize), // base + index * scale + disp, only used by lea
     Register(X86Reg),
     Value(X86Value),
 }
@@ -106,426 +213,495 @@ macro_rules! sprint_asm {
     ($x86:expr, $str:expr) => {
         if $x86.print_assembly {
             $x86.assembly.push_str($str);
-            // $x86.assembly.push_str(&format!("free_registers: {:?}\n\n", $x86.free_registers));
         }
     };
 
     ($x86:expr, $fmt:expr, $( $arg:expr ),* ) => {
         if $x86.print_assembly {
             $x86.assembly.push_str(&format!($fmt $(, $arg)*));
-            // $x86.assembly.push_str(&format!("free_registers: {:?}\n\n", $x86.free_registers));
         }
     }
 }
 
+/**
+ * Wraps the assembly listing with the directives needed by the GNU assembler,
+ * assembling the result gives the exact same machine code.
+ */
+pub fn build_x86_assembly_file(filename: &str, assembly: &str) -> String {
+    let mut file = String::new();
+    file.push_str(&format!("# Generated by firstc from {}\n", filename));
+    file.push_str("    .intel_syntax noprefix\n");
+    file.push_str("    .text\n\n");
+    file.push_str(assembly);
+    file
+}
+
+/**
+ * Compiles to machine code that can be jitted, foreign functions are called by their address.
+ * The peephole optimizer rewrites the selected instructions of each function before encoding.
+ */
 pub fn compile_ir_to_x86_machine_code(
-    instructions: Vec<IrInstruction>,
-    functions: HashMap<IrIdent, IrBasicBlock>
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    peephole: bool
 ) -> (Vec<u8>, String) {
-    let mut x86 = X86Assembler {
+    let x86 = compile_ir_to_x86(instructions, functions, X86Output::Jit, peephole);
+    (x86.machine_code, x86.assembly)
+}
+
+/**
+ * Compiles to an object with one symbol per function, the entry point
+ * is exported as `main` so it can be linked using the system C compiler.
+ */
+pub fn compile_ir_to_x86_object(
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    peephole: bool
+) -> X86Object {
+    let x86 = compile_ir_to_x86(instructions, functions, X86Output::Object, peephole);
+    let symbols = collect_x86_symbols(&x86, functions, "main", "sqrrl.main");
+    let relocations = x86.relocations.iter().map(|(pos, ident)| X86Relocation {
+        offset: adjusted_byte_pos(&x86, *pos),
+        symbol: resolve_symbol(ident.symbol).to_string(),
+    }).collect();
+
+    X86Object {
+        machine_code: x86.machine_code,
+        assembly: x86.assembly,
+        symbols,
+        relocations,
+    }
+}
+
+/**
+ * Compiles a standalone program that does not depend on libc, the entry point `_start`
+ * is placed first and exits using the return value of main. Foreign functions
+ * are only supported if the backend has a runtime routine for them.
+ */
+pub fn compile_ir_to_x86_executable(
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    peephole: bool
+) -> X86Object {
+    let x86 = compile_ir_to_x86(instructions, functions, X86Output::Executable, peephole);
+    let symbols = collect_x86_symbols(&x86, functions, "_start", "main");
+
+    X86Object {
+        machine_code: x86.machine_code,
+        assembly: x86.assembly,
+        symbols,
+        relocations: Vec::new(),
+    }
+}
+
+/**
+ * Creates one symbol per compiled function (including the entry point and runtime routines),
+ * each symbol extends until the start of the next one.
+ */
+fn collect_x86_symbols(
+    x86: &X86Assembler,
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    entry_name: &str,
+    main_name: &str
+) -> Vec<X86Symbol> {
+    if !x86.x64_mode {
+        panic!("x86: object files and executables are only supported in 64-bit mode");
+    }
+
+    let main_ident = create_ir_ident(intern_string("main"), 0);
+    let mut offsets: Vec<(String, usize, bool)> = Vec::new();
+    offsets.push((String::from(entry_name), 0, true));
+    for (ident, bb) in functions {
+        if bb.is_foreign {
+            continue;
+        }
+
+        let name = if *ident == main_ident {
+            String::from(main_name)
+        } else {
+            resolve_symbol(ident.symbol).to_string()
+        };
+        let pos = *x86.label_byte_pos.get(&bb.enter_label).unwrap();
+        offsets.push((name, adjusted_byte_pos(x86, pos), false));
+    }
+
+    for ident in &x86.runtime_routines {
+        let pos = *x86.label_byte_pos.get(ident).unwrap();
+        offsets.push((resolve_symbol(ident.symbol).to_string(), adjusted_byte_pos(x86, pos), false));
+    }
+    offsets.sort_by_key(|(_, offset, _)| *offset);
+
+    let mut symbols = Vec::new();
+    for (i, (name, offset, is_global)) in offsets.iter().enumerate() {
+        let end = match offsets.get(i + 1) {
+            Some((_, next, _)) => *next,
+            None => x86.machine_code.len(),
+        };
+
+        symbols.push(X86Symbol {
+            name: name.clone(),
+            offset: *offset,
+            size: end - offset,
+            is_global: *is_global,
+        });
+    }
+    symbols
+}
+
+fn create_x86_assembler(
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    output: X86Output,
+    peephole: bool
+) -> X86Assembler {
+    let x64_mode = cfg!(target_arch="x86_64");
+    X86Assembler {
         machine_code: Vec::new(),
+        instructions: Vec::new(),
+        peephole,
         label_byte_pos: HashMap::new(),
         relative_jumps: Vec::new(),
         local_variables: HashMap::new(),
-        allocated_registers: VecDeque::new(),
-        free_registers: VecDeque::new(),
+        scratch_registers: x86_scratch_registers(x64_mode),
         argument_stack: VecDeque::new(),
         curr_stack_offset: 0,
         max_stack_requirement: 0,
+        outgoing_arguments_size: 0,
         temp_variable_symbol: intern_string(""), // NOTE(alexander): maybe parameterize this?
         debug_break_symbol: intern_string("debug_break"),
+        local_label_symbol: intern_string(".x86"),
+        local_label_index: 0,
         assembly: String::new(),
         print_assembly: true,
         addr_size: std::mem::size_of::<usize>() as isize,
-        x64_mode: cfg!(target_arch="x86_64"),
-    };
+        x64_mode,
+        output,
+        foreign_functions: HashMap::new(),
+        relocations: Vec::new(),
+        inserted_bytes: Vec::new(),
+        runtime_routines: Vec::new(),
+        function_labels: functions.keys().copied().collect(),
+    }
+}
+
+fn x86_scratch_registers(x64_mode: bool) -> [X86Reg; 2] {
+    if x64_mode {
+        [X86Reg::R10, X86Reg::R11]
+    } else {
+        [X86Reg::RSI, X86Reg::RDI]
+    }
+}
+
+fn compile_ir_to_x86(
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>,
+    output: X86Output,
+    peephole: bool
+) -> X86Assembler {
+    let mut x86 = create_x86_assembler(functions, output, peephole);
+
+    // NOTE(alexander): the IR calls foreign functions by address, map them back to their names
+    for (ident, bb) in functions {
+        if let Some(addr) = bb.func_address {
+            if addr != 0 {
+                x86.foreign_functions.insert(addr as u64, *ident);
+            }
+        }
+    }
 
-    // Compile first main function
+    // Compile first the entry point followed by the main function
     let main_ident = create_ir_ident(intern_string("main"), 0);
     if let Some(bb) = functions.get(&main_ident) {
+        if output == X86Output::Executable {
+            push_start_stub(&mut x86, main_ident, bb.return_type);
+        } else {
+            push_entry_point(&mut x86, main_ident, bb.return_type);
+        }
         let insns = &instructions[bb.prologue_index + 1..=bb.epilogue_index];
         push_function(&mut x86, insns, bb);
     } else {
         panic!("x86: failed to locate `main` function");
     }
 
-    // Compile the rest of the functions
-    for (ident, bb) in &functions {
+    // Compile the rest of the functions in the order they are defined in
+    let mut defined: Vec<(&IrIdent, &IrBasicBlock)> = functions.iter().collect();
+    defined.sort_by_key(|(_, bb)| bb.prologue_index);
+    for (ident, bb) in defined {
         if *ident != main_ident && !bb.is_foreign {
             let insns = &instructions[bb.prologue_index + 1..bb.epilogue_index];
             push_function(&mut x86, insns, bb);
         }
     }
 
+    // Executables have no libc to link against, append the runtime routines that are called
+    for ident in x86.runtime_routines.clone() {
+        push_runtime_routine(&mut x86, ident);
+    }
+
     // Before calculating jump distances make sure all jumps have its target set
     for jmp in x86.relative_jumps.iter_mut() {
         jmp.target = *x86.label_byte_pos.get(&jmp.ident).unwrap();
     }
 
-    fn calculate_jump_distance(
-        calculated_jumps: &mut Vec<(usize, Vec<u8>, u8)>,
-        active_jumps: &mut Vec<X86RelJump>,
-        curr_pos: usize,
-        x64_mode: bool
-    ) -> Vec<bool> {
-        // Sort all active jumps by their position in decreasing order
-        active_jumps.sort_by(|a, b| b.pos.cmp(&a.pos));
-
-        let mut bytes_added = 0;
-        let mut keep_active_jumps: Vec<bool> = Vec::new();
-        for jmp in active_jumps.iter_mut() {
-            let exit_pos = jmp.pos.min(jmp.target);
-            jmp.target += bytes_added;
-
-            if curr_pos <= exit_pos {
-                let mut jmp_code = Vec::new();
-                let dist = jmp.target as isize - jmp.next_pos as isize;
-                let pre_allocated_bytes = if jmp.is_long_jump {
-                    5
-                } else {
-                    2
-                };
-                let is_long_jump = dist < -128 || dist > 127 || jmp.is_long_jump;
-
-                match (jmp.opcode, is_long_jump) {
-                    (X86Opcode::JL, false) => jmp_code.push(0x7c),
-                    (X86Opcode::JL, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x8c);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JLE, false) => jmp_code.push(0x7e),
-                    (X86Opcode::JLE, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x8e);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JG, false) => jmp_code.push(0x7f),
-                    (X86Opcode::JG, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x8f);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JGE, false) => jmp_code.push(0x7d),
-                    (X86Opcode::JGE, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x8d);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JE, false) => jmp_code.push(0x74),
-                    (X86Opcode::JE, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x84);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JNE, false) => jmp_code.push(0x75),
-                    (X86Opcode::JNE, true)  => {
-                        jmp_code.push(0x0f);
-                        jmp_code.push(0x85);
-                        bytes_added += 4;
-                    }
-
-                    (X86Opcode::JMP, false) => jmp_code.push(0xeb),
-                    (X86Opcode::JMP, true)  => {
-                        jmp_code.push(0xe9);
-                        bytes_added += 3;
-                    }
-                    (X86Opcode::CALL, true) => {
-                        jmp_code.push(0xe8);
-                    }
-
-                    _ => panic!("x86: invalid relative jump instruction"),
-                }
-
-                if is_long_jump {
-                    if x64_mode {
-                        let v = dist as i32;
-                        jmp_code.push(( v        & 0xFFi32) as u8);
-                        jmp_code.push(((v >> 8)  & 0xFFi32) as u8);
-                        jmp_code.push(((v >> 16) & 0xFFi32) as u8);
-                        jmp_code.push(((v >> 24) & 0xFFi32) as u8);
-
-                    } else {
-                        let v = dist as i16;
-                        jmp_code.push(( v        & 0xFFi16) as u8);
-                        jmp_code.push(((v >> 8)  & 0xFFi16) as u8);
-                    }
-
-                } else {
-                    let v = dist as i8;
-                    jmp_code.push(v as u8);
-                }
+    // Every jump starts out as a short jump (calls are always long), jumps with distances
+    // that doesn't fit in 8-bits are extended. Extending a jump moves the code after it,
+    // so the distances are recalculated until no more jumps have to be extended.
+    x86.relative_jumps.sort_by_key(|jmp| jmp.pos);
+    let disp_size = if x86.x64_mode { 4 } else { 2 };
+    let long_jump_size = |opcode: X86Opcode| -> usize {
+        match opcode {
+            X86Opcode::JMP | X86Opcode::CALL => 1 + disp_size,
+            _ => 2 + disp_size,
+        }
+    };
 
-                calculated_jumps.push((jmp.pos, jmp_code, pre_allocated_bytes));
-                keep_active_jumps.push(false);
-            } else {
-                keep_active_jumps.push(true);
+    let mut is_long_jump: Vec<bool> = x86.relative_jumps.iter().map(|jmp| jmp.is_long_jump).collect();
+    let mut final_pos: Vec<usize> = Vec::new();
+    loop {
+        // NOTE(alexander): bytes are inserted directly after the pre-allocated bytes of the jump
+        let mut inserted: Vec<(usize, usize)> = Vec::new();
+        for (jmp, is_long) in x86.relative_jumps.iter().zip(&is_long_jump) {
+            if *is_long && !jmp.is_long_jump {
+                inserted.push((jmp.next_pos, long_jump_size(jmp.opcode) - (jmp.next_pos - jmp.pos)));
             }
         }
+        let adjusted = |pos: usize| -> usize {
+            pos + inserted.iter().filter(|(index, _)| *index <= pos).map(|(_, count)| count).sum::<usize>()
+        };
 
-        return keep_active_jumps;
-    }
-
-    // Iterate in reverse order of jumps and jump targets, calculate the
-    // relative jump distance, extend 8-bit jump to 32-bits if necessary.
-    // Makes sure that overlapping jumps are accounted for when extending bits.
-    // Loop invariant: all jump distances calulated so far are correct,
-    //                 since adding bytes before the jump wont affect the
-    //                 actual jump distance since they are relative.
-    x86.relative_jumps.sort_by(|a, b| b.pos.max(b.target).cmp(&a.pos.max(a.target)));
-    let mut calculated_jumps: Vec<(usize, Vec<u8>, u8)> = Vec::new();
-    let mut active_jumps: Vec<X86RelJump> = Vec::new();
-    for next_jmp in &x86.relative_jumps {
-        let curr_pos = next_jmp.pos.max(next_jmp.target);
-        let keep_active_jumps = calculate_jump_distance(&mut calculated_jumps,
-                                                        &mut active_jumps,
-                                                        curr_pos,
-                                                        x86.x64_mode);
+        let mut extended = false;
+        final_pos.clear();
+        for (jmp, is_long) in x86.relative_jumps.iter().zip(is_long_jump.iter_mut()) {
+            let next_pos = adjusted(jmp.next_pos);
+            let dist = adjusted(jmp.target) as isize - next_pos as isize;
+            if !*is_long && !(-128..=127).contains(&dist) {
+                *is_long = true;
+                extended = true;
+            }
+            final_pos.push(next_pos);
+        }
 
-        let mut i = 0;
-        active_jumps.retain(|_| (keep_active_jumps[i], i += 1).0);
-        active_jumps.push(*next_jmp);
+        if !extended {
+            x86.inserted_bytes = inserted;
+            break;
+        }
     }
 
-    // Any remaining active jumps needs to be calcuated also
-    calculate_jump_distance(&mut calculated_jumps, &mut active_jumps, 0, x86.x64_mode);
-
     // Now write the calculated jump distances, from highest index to lowest
-    calculated_jumps.sort_by(|a, b| b.0.cmp(&a.0));
-    for (index, bytes, pre_allocated_bytes) in calculated_jumps {
-        // println!("index = {}, bytes = {:#?}, pre_allocated_bytes = {}", index, bytes, pre_allocated_bytes);
-        let mut i = 0;
-        for b in &bytes[..pre_allocated_bytes as usize] {
-            x86.machine_code[index + i] = *b;
-            i += 1;
+    for (i, jmp) in x86.relative_jumps.iter().enumerate().rev() {
+        let dist = adjusted_byte_pos(&x86, jmp.target) as isize - final_pos[i] as isize;
+        let mut jmp_code = Vec::new();
+        match (jmp.opcode, is_long_jump[i]) {
+            (X86Opcode::JL, false)  => jmp_code.push(0x7c),
+            (X86Opcode::JL, true)   => jmp_code.extend(&[0x0f, 0x8c]),
+            (X86Opcode::JLE, false) => jmp_code.push(0x7e),
+            (X86Opcode::JLE, true)  => jmp_code.extend(&[0x0f, 0x8e]),
+            (X86Opcode::JG, false)  => jmp_code.push(0x7f),
+            (X86Opcode::JG, true)   => jmp_code.extend(&[0x0f, 0x8f]),
+            (X86Opcode::JGE, false) => jmp_code.push(0x7d),
+            (X86Opcode::JGE, true)  => jmp_code.extend(&[0x0f, 0x8d]),
+            (X86Opcode::JE, false)  => jmp_code.push(0x74),
+            (X86Opcode::JE, true)   => jmp_code.extend(&[0x0f, 0x84]),
+            (X86Opcode::JNE, false) => jmp_code.push(0x75),
+            (X86Opcode::JNE, true)  => jmp_code.extend(&[0x0f, 0x85]),
+            (X86Opcode::JB, false)  => jmp_code.push(0x72),
+            (X86Opcode::JB, true)   => jmp_code.extend(&[0x0f, 0x82]),
+            (X86Opcode::JBE, false) => jmp_code.push(0x76),
+            (X86Opcode::JBE, true)  => jmp_code.extend(&[0x0f, 0x86]),
+            (X86Opcode::JA, false)  => jmp_code.push(0x77),
+            (X86Opcode::JA, true)   => jmp_code.extend(&[0x0f, 0x87]),
+            (X86Opcode::JAE, false) => jmp_code.push(0x73),
+            (X86Opcode::JAE, true)  => jmp_code.extend(&[0x0f, 0x83]),
+            (X86Opcode::JMP, false) => jmp_code.push(0xeb),
+            (X86Opcode::JMP, true)  => jmp_code.push(0xe9),
+            (X86Opcode::CALL, true) => jmp_code.push(0xe8),
+            _ => panic!("x86: invalid relative jump instruction"),
         }
-        // println!("i = {} => {:#?}", i, &bytes[pre_allocated_bytes as usize..]);
 
-        for b in &bytes[pre_allocated_bytes as usize..] {
-            x86.machine_code.insert(index + i, *b);
-            i += 1;
+        if !is_long_jump[i] {
+            jmp_code.push(dist as i8 as u8);
+        } else if x86.x64_mode {
+            jmp_code.extend(&(dist as i32).to_le_bytes());
+        } else {
+            jmp_code.extend(&(dist as i16).to_le_bytes());
+        }
+
+        let pre_allocated_bytes = jmp.next_pos - jmp.pos;
+        x86.machine_code[jmp.pos..jmp.next_pos].copy_from_slice(&jmp_code[..pre_allocated_bytes]);
+        for (j, b) in jmp_code[pre_allocated_bytes..].iter().enumerate() {
+            x86.machine_code.insert(jmp.next_pos + j, *b);
         }
     }
 
-    (x86.machine_code, x86.assembly)
+    x86
+}
+
+/**
+ * Maps a position recorded during compilation to its final position,
+ * after the extended jumps have inserted their extra bytes.
+ */
+fn adjusted_byte_pos(x86: &X86Assembler, pos: usize) -> usize {
+    let mut adjusted = pos;
+    for (index, count) in &x86.inserted_bytes {
+        if *index <= pos {
+            adjusted += count;
+        }
+    }
+    adjusted
 }
 
 fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBlock) {
-    // Setup the x86 state
-    x86.curr_stack_offset = 0;
-    x86.local_variables.clear();
-    x86.free_registers.clear();
-    x86.free_registers.push_back(X86Reg::RAX);
-    x86.free_registers.push_back(X86Reg::RBX);
-    x86.free_registers.push_back(X86Reg::RCX);
-    x86.free_registers.push_back(X86Reg::RDX);
-    if x86.x64_mode {
-        x86.free_registers.push_back(X86Reg::R10);
-        x86.free_registers.push_back(X86Reg::R11);
-        x86.free_registers.push_back(X86Reg::R13);
-        x86.free_registers.push_back(X86Reg::R14);
-        x86.free_registers.push_back(X86Reg::R15);
-        x86.free_registers.push_back(X86Reg::R8); // NOTE(alexander): this regsiters are usually needed for
-        x86.free_registers.push_back(X86Reg::R9); // calling functions to use those if really needed
-        // NOTE(alexander): R12 is not included due to it requiring SIB to be used!
-    }
-    x86.free_registers.push_back(X86Reg::RSI);
-    x86.free_registers.push_back(X86Reg::RDI);
-
-    // Set enter label pos
-    let base_pos = x86.machine_code.len();
-    x86.label_byte_pos.insert(bb.enter_label, base_pos);
-
-    // x86.machine_code.push(0xcc); // FIXME: debugging remove this
+    // Decide where every variable is stored before generating any code
+    let allocation = allocate_x86_registers(x86, insns, bb);
 
-    // Prologue
-    sprint_asm!(x86, "{}:\n", bb.enter_label);
+    // Setup the x86 state
+    x86.curr_stack_offset = allocation.stack_offset;
+    x86.max_stack_requirement = 0;
+    x86.outgoing_arguments_size = 0;
+    x86.local_variables = allocation.locations;
+    x86.instructions.clear();
+    let callee_saved_registers = allocation.callee_saved_registers;
+    let scratch_reg = x86.scratch_registers[0];
+
+    // The parameters after the argument registers are read from the stack slots above the return address and saved rbp
+    let mut stack_offset = 2 * x86.addr_size;
+    let params = insns.iter()
+        .take_while(|insn| insn.opcode != IrOpcode::AllocParams)
+        .filter(|insn| insn.opcode == IrOpcode::Param);
+    for insn in params.skip(X86_WINDOWS_ARGUMENT_REGISTERS.len()) {
+        x86.local_variables.insert(get_ir_ident(insn.op1), X86Operand::Stack(X86Reg::RBP, stack_offset));
+        stack_offset += x86.addr_size;
+    }
 
-    // push rbp
-    x86.machine_code.push(0xff);
-    x86.machine_code.push(modrm(6, reg_id(X86Reg::RBP)));
-    sprint_asm!(x86, "    push  rbp\n");
+    // Instructions that are selected together, decided after the locations are known
+    let patterns = select_x86_patterns(x86, insns);
 
-    // mov rbp rsp
+    // Prologue
+    push_label(x86, bb.enter_label);
+    push_unary_instruction(x86, X86Opcode::PUSH, IrType::I64, X86Operand::Register(X86Reg::RBP));
     push_instruction(x86,
                      X86Opcode::MOV,
                      IrType::I64,
                      X86Operand::Register(X86Reg::RBP),
                      X86Operand::Register(X86Reg::RSP));
 
-    // sub rsp x (gets filled in later, if needed)
-    let sub_rsp_byte_pos = x86.machine_code.len();
-    let sub_rsp_asm_byte_pos = x86.assembly.len();
+    // Save the callee-saved registers used by this function
+    for reg in &callee_saved_registers {
+        push_unary_instruction(x86, X86Opcode::PUSH, IrType::I64, X86Operand::Register(*reg));
+    }
+
+    // sub rsp x (gets inserted later, if needed)
+    let sub_rsp_index = x86.instructions.len();
 
     // Function body
     let mut require_stack_frame = false;
 
     let num_insns = insns.len();
 
-    fn alloca(x86: &mut X86Assembler, ty: IrType, ir_dst: IrOperand) -> X86Operand {
-        x86.curr_stack_offset -= size_of_ir_type(ty, x86.addr_size);
-        let dst = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-        insert_variable(x86, ty, ir_dst, dst);
-        dst
-    }
-
     for (i, insn) in insns.iter().enumerate() {
-        let insn_index = bb.prologue_index + i + 1;
+        match patterns[i] {
+            Some(X86Pattern::Folded) => continue,
+
+            Some(X86Pattern::Address(addr)) => {
+                let dst = to_x86_operand(x86, insn.op1);
+                let reg = register_or_scratch(dst, scratch_reg);
+                let src = to_x86_address(x86, addr);
+                push_instruction(x86, X86Opcode::LEA, insn.ty, X86Operand::Register(reg), src);
+                push_store_register(x86, dst, reg, insn.ty);
+                continue;
+            }
+
+            _ => {}
+        }
 
         match insn.opcode {
             IrOpcode::Nop => {
-                x86.machine_code.push(0x90);
-                sprint_asm!(x86, "    nop\n");
+                push_opcode_instruction(x86, X86Opcode::NOP);
             }
 
             IrOpcode::Alloca => {
-                alloca(x86, insn.ty, insn.op1);
+                // NOTE(alexander): stack space is reserved by the register allocator
             }
 
             IrOpcode::AllocParams => {
-                // NOTE(alexander): internally we always use windows calling convention.
-                let dst_reg: [X86Reg; 4] = [X86Reg::RCX, X86Reg::RDX, X86Reg::R8, X86Reg::R9];
-                for i in 0..4 {
+                // NOTE(alexander): internally we always use windows argument registers, see `internal_calling_convention`.
+                let mut param_moves: Vec<(X86Operand, X86Operand, IrType)> = Vec::new();
+                for src_reg in X86_WINDOWS_ARGUMENT_REGISTERS.iter() {
                     if let Some((op, ty)) = x86.argument_stack.pop_front() {
-                        let dst = alloca(x86, ty, op);
-                        let src = X86Operand::Register(dst_reg[i]);
-                        push_instruction(x86, X86Opcode::MOV, ty, dst, src);
+                        let dst = to_x86_operand(x86, op);
+                        param_moves.push((dst, X86Operand::Register(*src_reg), ty));
                     } else {
                         break;
                     }
                 }
+                push_parallel_moves(x86, param_moves);
 
-                let mut arg_moves: Vec<(IrType, IrOperand, X86Operand)> = Vec::new();
-                let mut stack_offset = 0isize;
-                for (op, ty) in x86.argument_stack.iter().rev() {
-                    arg_moves.push((*ty, *op, X86Operand::Stack(X86Reg::RBP, stack_offset)));
-                    stack_offset += size_of_ir_type(*ty, x86.addr_size);
-                }
-
-                for (ty, dst, src) in arg_moves {
-                    insert_variable(x86, ty, dst, src);
-                }
-
+                // NOTE(alexander): the remaining parameters are already located on the stack
                 x86.argument_stack.clear();
             }
 
             IrOpcode::Copy => {
-                let dst = to_x86_operand(x86, insn.op1, insn.ty);
-                let src = to_x86_operand(x86, insn.op2, insn.ty);
-                push_instruction(x86, X86Opcode::MOV, insn.ty, dst, src);
-                insert_variable(x86, insn.ty, insn.op1, dst);
+                let dst = to_x86_operand(x86, insn.op1);
+                let src = to_x86_operand(x86, insn.op2);
+                if dst != src {
+                    push_instruction(x86, X86Opcode::MOV, insn.ty, dst, src);
+                }
             }
 
             IrOpcode::CopyFromDeref => {
                 let ref_ty = to_ref_type(insn.ty);
-                let dst = to_x86_operand(x86, insn.op1, insn.ty);
-                let src = to_x86_operand(x86, insn.op2, ref_ty);
+                let dst = to_x86_operand(x86, insn.op1);
+                let src = to_x86_operand(x86, insn.op2);
 
                 // Make sure that we access the data from register rather than stack.
-                let ident = maybe_get_ir_ident(insn.op2);
-                let (src_reg, src) = move_operand_to_register(x86, src, ident, ref_ty);
-
-                // NOTE(alexander): will always be a regsiter
-                let dst_reg = if let X86Operand::Register(reg) = dst {
-                    reg
-                } else {
-                    panic!("x86: copy_from_deref expects register as first operand");
-                };
+                let src_reg = move_operand_to_register(x86, src, ref_ty, x86.scratch_registers[1]);
+                let dst_reg = register_or_scratch(dst, scratch_reg);
 
                 // mov dst, x ptr [src]
-                push_rex_prefix(x86, Some(dst_reg), Some(src_reg), insn.ty);
-                x86.machine_code.push(0x8b); // RM
-                x86.machine_code.push(modrm_disp(reg_id(dst_reg), reg_id(src_reg), 0));
-                x86.machine_code.push(0); // NOTE(alexander): use no displacement
-                print_instruction(x86, X86Opcode::MOV, insn.ty, dst, false, src, true);
-                insert_variable(x86, insn.ty, insn.op1, dst);
+                push_instruction(x86, X86Opcode::MOV, insn.ty, X86Operand::Register(dst_reg), X86Operand::Stack(src_reg, 0));
+                push_store_register(x86, dst, dst_reg, insn.ty);
             }
 
             IrOpcode::CopyFromRef => {
-                let ref_ty = to_ref_type(insn.ty);
-                let dst = to_x86_operand(x86, insn.op1, ref_ty);
-                let src = to_x86_operand(x86, insn.op2, insn.ty);
+                let dst = to_x86_operand(x86, insn.op1);
+                let src = to_x86_operand(x86, insn.op2);
 
                 // Make sure the src data is stored in memory (only supports stack, no support for heap allocs)
-                let (disp, src) = move_operand_to_stack(x86, src, insn.ty);
-
-                // NOTE(alexander): will always be a regsiter
-                let reg = if let X86Operand::Register(reg) = dst {
-                    reg
-                } else {
-                    panic!("x86: copy_from_ref expects register as first operand");
-                };
+                let (_, src) = move_operand_to_stack(x86, src, insn.ty);
+                let reg = register_or_scratch(dst, scratch_reg);
 
                 // lea dst, src
-                push_rex_prefix(x86, Some(reg), None, insn.ty);
-                x86.machine_code.push(0x8d);
-                x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(X86Reg::RBP), disp));
-                push_displacement(x86, disp);
-                print_instruction(x86, X86Opcode::LEA, insn.ty, dst, false, src, false);
-
-                insert_variable(x86, ref_ty, insn.op1, dst);
+                push_instruction(x86, X86Opcode::LEA, insn.ty, X86Operand::Register(reg), src);
+                push_store_register(x86, dst, reg, insn.ty);
             }
 
             IrOpcode::CopyToDeref => {
                 let ref_ty = to_ref_type(insn.ty);
-                let dst = to_x86_operand(x86, insn.op1, ref_ty);
-                let src = to_x86_operand(x86, insn.op2, insn.ty);
+                let dst = to_x86_operand(x86, insn.op1);
+                let src = to_x86_operand(x86, insn.op2);
 
                 // Make sure that we access the data from register rather than stack.
-                let ident = maybe_get_ir_ident(insn.op1);
-                let (dst_reg, dst) = move_operand_to_register(x86, dst, ident, ref_ty);
+                let dst_reg = move_operand_to_register(x86, dst, ref_ty, scratch_reg);
+                let dst = X86Operand::Stack(dst_reg, 0);
 
-                // Has to be register op otherwise use auxiliary register (make sure to free it later)
+                // Has to be register op otherwise use the second scratch register
                 match src {
-                    X86Operand::Value(val) => {
-                        push_rex_prefix(x86, None, Some(dst_reg), insn.ty);
-                        x86.machine_code.push(0xc7); // MI
-                        x86.machine_code.push(modrm_disp(0, reg_id(dst_reg), 0));
-                        x86.machine_code.push(0); // NOTE(alexander): use no displacement
-                        push_immediate(x86, val);
-                        print_instruction(x86, X86Opcode::MOV, insn.ty, dst, true, src, false);
-                    }
-
-                    X86Operand::Stack(sreg, disp) => {
-                        // Move first source into auxiliary register
-                        let src_reg = allocate_register(x86, None);
-                        push_rex_prefix(x86, Some(src_reg), None, insn.ty);
-                        x86.machine_code.push(0x8b);
-                        x86.machine_code.push(modrm_disp(reg_id(src_reg), reg_id(sreg), disp));
-                        push_displacement(x86, disp);
-                        print_instruction(x86, X86Opcode::MOV, insn.ty, X86Operand::Register(src_reg),
-                                          false, src, false);
-
-                        push_rex_prefix(x86, Some(src_reg), Some(dst_reg), insn.ty);
-                        x86.machine_code.push(0x89); // MR
-                        x86.machine_code.push(modrm_disp(reg_id(src_reg), reg_id(dst_reg), 0));
-                        x86.machine_code.push(0); // NOTE(alexander): use no displacement
-                        print_instruction(x86, X86Opcode::MOV, insn.ty, dst,
-                                          true, X86Operand::Register(src_reg), false);
-
-                        free_register(x86, src_reg);
+                    X86Operand::Value(_) => {
+                        push_instruction(x86, X86Opcode::MOV, insn.ty, dst, src);
                     }
 
-                    X86Operand::Register(src_reg) => {
+                    X86Operand::Stack(_, _) |
+                    X86Operand::Indexed(_, _, _, _) |
+                    X86Operand::Register(_) => {
                         // mov x ptr [dst], src
-                        push_rex_prefix(x86, Some(src_reg), Some(dst_reg), insn.ty);
-                        x86.machine_code.push(0x89); // MR
-                        x86.machine_code.push(modrm_disp(reg_id(src_reg), reg_id(dst_reg), 0));
-                        x86.machine_code.push(0); // NOTE(alexander): use no displacement
-                        print_instruction(x86, X86Opcode::MOV, insn.ty, dst, true, src, false);
+                        let src_reg = move_operand_to_register(x86, src, insn.ty, x86.scratch_registers[1]);
+                        push_instruction(x86, X86Opcode::MOV, insn.ty, dst, X86Operand::Register(src_reg));
                     }
                 }
             }
 
             IrOpcode::Clear => {
-                let op = to_x86_operand(x86, insn.op1, insn.ty);
+                let op = to_x86_operand(x86, insn.op1);
                 push_instruction(x86, X86Opcode::XOR, insn.ty, op, op);
-                insert_variable(x86, insn.ty, insn.op1, op);
             }
 
             IrOpcode::Add |
@@ -533,12 +709,9 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
             IrOpcode::And |
             IrOpcode::Or  |
             IrOpcode::Xor => {
-                let dst = to_x86_operand(x86, insn.op1, insn.ty);
-                let lhs = to_x86_operand(x86, insn.op2, insn.ty);
-                let rhs = to_x86_operand(x86, insn.op3, insn.ty);
-                if dst != lhs {
-                    push_instruction(x86, X86Opcode::MOV, insn.ty, dst, lhs);
-                }
+                let dst = to_x86_operand(x86, insn.op1);
+                let lhs = to_x86_operand(x86, insn.op2);
+                let rhs = to_x86_operand(x86, insn.op3);
 
                 let opcode = match insn.opcode {
                     IrOpcode::Add => X86Opcode::ADD,
@@ -548,133 +721,163 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
                     IrOpcode::Xor => X86Opcode::XOR,
                     _ => unreachable!(),
                 };
-                push_instruction(x86, opcode, insn.ty, dst, rhs);
-                insert_variable(x86, insn.ty, insn.op1, dst);
-            }
-
-            IrOpcode::Mul => {
-                let lhs = to_x86_operand(x86, insn.op2, insn.ty);
-                let rhs = to_x86_operand(x86, insn.op3, insn.ty);
 
-                // Make sure the left hand side is stored in register
-                let ident = maybe_get_ir_ident(insn.op2);
-                let (reg, lhs) = move_operand_to_register(x86, lhs, ident, insn.ty);
-
-                match rhs {
-                    X86Operand::Value(val) => {
-                        push_rex_prefix(x86, Some(reg), Some(reg), insn.ty);
-                        x86.machine_code.push(0x69); // RMI
-                        x86.machine_code.push(modrm(reg_id(reg), reg_id(reg)));
-                        push_immediate(x86, val);
-                    }
+                // NOTE(alexander): the destination may share location with the right-hand side,
+                // swap the operands if possible otherwise compute the result in scratch register.
+                let (lhs, rhs) = if dst == rhs && dst != lhs && insn.opcode != IrOpcode::Sub {
+                    (rhs, lhs)
+                } else {
+                    (lhs, rhs)
+                };
 
-                    X86Operand::Stack(sreg, disp) => {
-                        push_rex_prefix(x86, Some(reg), None, insn.ty);
-                        x86.machine_code.push(0x0f); // RM
-                        x86.machine_code.push(0xaf);
-                        x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(sreg), disp));
-                        push_displacement(x86, disp);
+                if dst == rhs && dst != lhs {
+                    let scratch = X86Operand::Register(scratch_reg);
+                    push_instruction(x86, X86Opcode::MOV, insn.ty, scratch, lhs);
+                    push_instruction(x86, opcode, insn.ty, scratch, rhs);
+                    push_instruction(x86, X86Opcode::MOV, insn.ty, dst, scratch);
+                } else {
+                    if dst != lhs {
+                        push_instruction(x86, X86Opcode::MOV, insn.ty, dst, lhs);
                     }
+                    push_instruction(x86, opcode, insn.ty, dst, rhs);
+                }
+            }
 
-                    X86Operand::Register(rm) => {
-                        push_rex_prefix(x86, Some(reg), Some(rm), insn.ty);
-                        x86.machine_code.push(0x0f); // RM
-                        x86.machine_code.push(0xaf);
-                        x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
-                    }
+            IrOpcode::Mul => {
+                let dst = to_x86_operand(x86, insn.op1);
+                let lhs = to_x86_operand(x86, insn.op2);
+                let rhs = to_x86_operand(x86, insn.op3);
+
+                // Multiply in the destination register, imul is commutative so
+                // make sure the right hand side is not overwritten first.
+                let reg = register_or_scratch(dst, scratch_reg);
+                let (lhs, rhs) = if rhs == X86Operand::Register(reg) {
+                    (rhs, lhs)
+                } else {
+                    (lhs, rhs)
+                };
+                let ty = push_widening_move(x86, insn.ty, reg, lhs);
+                let lhs = X86Operand::Register(reg);
+
+                if let (X86Operand::Value(val), true) = (rhs, is_x86_imm64(x86, ty, rhs)) {
+                    // movabs aux, imm64; imul reg, aux
+                    let aux = X86Operand::Register(x86.scratch_registers[1]);
+                    push_instruction(x86, X86Opcode::MOV, ty, aux, X86Operand::Value(val));
+                    push_instruction(x86, X86Opcode::IMUL, ty, lhs, aux);
+                } else if let X86Operand::Value(val) = rhs {
+                    // imul reg, reg, imm
+                    push_x86_instruction(x86, X86Instruction {
+                        opcode: X86Opcode::IMUL,
+                        ty,
+                        op1: Some(lhs),
+                        op2: Some(lhs),
+                        op3: Some(X86Operand::Value(X86Value::Int32(x86_value_to_i64(val) as i32))),
+                        ..Default::default()
+                    });
+                } else if ty != insn.ty {
+                    let rhs_reg = x86.scratch_registers[1];
+                    push_widening_move(x86, insn.ty, rhs_reg, rhs);
+                    push_instruction(x86, X86Opcode::IMUL, ty, lhs, X86Operand::Register(rhs_reg));
+                } else {
+                    push_instruction(x86, X86Opcode::IMUL, ty, lhs, rhs);
                 }
 
-                print_instruction(x86, X86Opcode::IMUL, insn.ty, lhs, false, rhs, false);
-                insert_variable(x86, insn.ty, insn.op1, lhs);
+                push_store_register(x86, dst, reg, insn.ty);
             }
 
             IrOpcode::Div |
             IrOpcode::Mod => {
-                // Make sure RDX and RAX are stored somewhere safe!
-                allocate_specific_register(x86, X86Reg::RAX);
-                allocate_specific_register(x86, X86Reg::RDX);
-
-                let mut dst = to_x86_operand(x86, insn.op1, insn.ty);
-                let lhs = to_x86_operand(x86, insn.op2, insn.ty);
-                let rhs = to_x86_operand(x86, insn.op3, insn.ty);
+                // NOTE(alexander): the register allocator keeps values alive across idiv out of RAX and RDX,
+                // the right-hand side is never assigned to them either.
+                let dst = to_x86_operand(x86, insn.op1);
+                let lhs = to_x86_operand(x86, insn.op2);
+                let rhs = to_x86_operand(x86, insn.op3);
 
                 // Make sure the left-hand side is stored in RAX
-                let mut lhs_is_rax = false;
-                if let X86Operand::Register(reg) = lhs {
-                    if let X86Reg::RAX = reg { lhs_is_rax = true; }
-                }
-                if !lhs_is_rax {
-                    let dst = X86Operand::Register(X86Reg::RAX);
-                    push_instruction(x86, X86Opcode::MOV, insn.ty, dst, lhs);
+                let ty = push_widening_move(x86, insn.ty, X86Reg::RAX, lhs);
+                let is_unsigned = is_unsigned_ir_type(ty);
+
+                if is_unsigned {
+                    // xor edx, edx (unsigned division of EDX:EAX)
+                    let rdx = X86Operand::Register(X86Reg::RDX);
+                    push_instruction(x86, X86Opcode::XOR, IrType::I32, rdx, rdx);
+                } else if size_of_ir_type(ty, x86.addr_size) == 8 {
+                    // cqo (sign extends RAX to RDX:RAX)
+                    push_opcode_instruction(x86, X86Opcode::CQO);
+                } else {
+                    // cdq (sign extends EAX to EDX:EAX)
+                    push_opcode_instruction(x86, X86Opcode::CDQ);
                 }
 
-                x86.machine_code.push(0x99); // cdq (sign extends EAX to EDX:EAX)
-                sprint_asm!(x86, "    cdq\n");
-
-                match rhs {
-                    X86Operand::Value(_) => {
-                        // Move first to auxiliary register
-                        let reg = allocate_register(x86, None);
-                        push_instruction(x86, X86Opcode::MOV, insn.ty, X86Operand::Register(reg), rhs);
-
-                        push_rex_prefix(x86, None, Some(reg), insn.ty);
-                        x86.machine_code.push(0xf7); // M
-                        x86.machine_code.push(modrm(7, reg_id(reg)));
-                        free_register(x86, reg);
-                        sprint_asm!(x86, "    idiv  {}\n", reg);
-                    }
-
-                    X86Operand::Stack(sreg, disp) => {
-                        push_rex_prefix(x86, None, None, insn.ty);
-                        x86.machine_code.push(0xf7); // M
-                        x86.machine_code.push(modrm_disp(7, reg_id(sreg), disp));
-                        push_displacement(x86, disp);
-                        sprint_asm!(x86, "    idiv  dword ptr {}\n", rhs);
-                    }
-
-                    X86Operand::Register(reg) => {
-                        push_rex_prefix(x86, None, Some(reg), insn.ty);
-                        x86.machine_code.push(0xf7); // M
-                        x86.machine_code.push(modrm(7, reg_id(reg)));
-                        sprint_asm!(x86, "    idiv  {}\n", reg);
+                // Immediates and bytes are moved to the scratch register first
+                let rhs = match rhs {
+                    X86Operand::Register(_) |
+                    X86Operand::Stack(_, _) if ty == insn.ty => rhs,
+                    _ => {
+                        push_widening_move(x86, insn.ty, scratch_reg, rhs);
+                        X86Operand::Register(scratch_reg)
                     }
-                }
+                };
+                let opcode = if is_unsigned { X86Opcode::DIV } else { X86Opcode::IDIV };
+                push_unary_instruction(x86, opcode, ty, rhs);
 
                 // Save the result to the the destination (first operand)
-                match insn.opcode {
-                    IrOpcode::Div => {
-                        let mut dst_is_rax = false;
-                        if let X86Operand::Register(_) = lhs {
-                            dst = X86Operand::Register(X86Reg::RAX);
-                            dst_is_rax = true;
-                        }
-                        if !dst_is_rax {
-                            push_instruction(x86, X86Opcode::MOV, insn.ty, dst, X86Operand::Register(X86Reg::RAX));
-                            insert_variable(x86, insn.ty, insn.op1, dst);
-                        }
-
-                    }
-                    IrOpcode::Mod => {
-                        let mut dst_is_rdx = false;
-                        if let X86Operand::Register(_) = lhs {
-                            dst = X86Operand::Register(X86Reg::RDX);
-                            dst_is_rdx = true;
-                        }
-                        if !dst_is_rdx {
-                            push_instruction(x86, X86Opcode::MOV, insn.ty, dst, X86Operand::Register(X86Reg::RDX));
-                            insert_variable(x86, insn.ty, insn.op1, dst);
-                        }
-                    }
+                let result = match insn.opcode {
+                    IrOpcode::Div => X86Reg::RAX,
+                    IrOpcode::Mod => X86Reg::RDX,
                     _ => unreachable!(),
-                }
-
-                // Make sure that we give back the original registers, and free the new ones
-                // free_specific_register(x86, X86Reg::RAX, rax_state);
-                // free_specific_register(x86, X86Reg::RDX, rdx_state);
+                };
+                push_store_register(x86, dst, result, insn.ty);
             }
 
             IrOpcode::Pow => {
-                unimplemented!(); // TODO implement this.
+                // NOTE(alexander): RAX and RDX are reserved the same way as idiv, the exponent
+                // is treated as a 32-bit unsigned integer so it behaves the same as the interpreter.
+                let dst = to_x86_operand(x86, insn.op1);
+                let lhs = to_x86_operand(x86, insn.op2);
+                let rhs = to_x86_operand(x86, insn.op3);
+
+                let exp = X86Operand::Register(scratch_reg);
+                let base = X86Operand::Register(X86Reg::RDX);
+                let result = X86Operand::Register(X86Reg::RAX);
+
+                // The exponent is read first since it may be stored in RAX or RDX
+                let exp_ty = match (insn.ty, rhs) {
+                    (IrType::I64, X86Operand::Value(val)) |
+                    (IrType::U64, X86Operand::Value(val)) => {
+                        let val = X86Operand::Value(X86Value::Int32(x86_value_to_i64(val) as i32));
+                        push_instruction(x86, X86Opcode::MOV, IrType::I32, exp, val);
+                        IrType::I32
+                    }
+
+                    (IrType::I64, _) |
+                    (IrType::U64, _) => {
+                        push_instruction(x86, X86Opcode::MOV, IrType::I32, exp, rhs);
+                        IrType::I32
+                    }
+
+                    _ => push_widening_move(x86, insn.ty, scratch_reg, rhs),
+                };
+                let ty = push_widening_move(x86, insn.ty, X86Reg::RDX, lhs);
+                push_instruction(x86, X86Opcode::MOV, ty, result, X86Operand::Value(X86Value::Int32(1)));
+
+                // Exponentiation by squaring, multiply the result by the base for every bit set in the exponent
+                let loop_label = create_x86_label(x86);
+                let skip_label = create_x86_label(x86);
+                let exit_label = create_x86_label(x86);
+                push_instruction(x86, X86Opcode::TEST, exp_ty, exp, exp);
+                push_relative_jump(x86, exit_label, X86Opcode::JE);
+                push_label(x86, loop_label);
+                push_instruction(x86, X86Opcode::TEST, exp_ty, exp, X86Operand::Value(X86Value::Int32(1)));
+                push_relative_jump(x86, skip_label, X86Opcode::JE);
+                push_instruction(x86, X86Opcode::IMUL, ty, result, base);
+                push_label(x86, skip_label);
+                push_instruction(x86, X86Opcode::IMUL, ty, base, base);
+                push_instruction(x86, X86Opcode::SHR, exp_ty, exp, X86Operand::Value(X86Value::Int8(1)));
+                push_relative_jump(x86, loop_label, X86Opcode::JNE);
+                push_label(x86, exit_label);
+
+                push_store_register(x86, dst, X86Reg::RAX, insn.ty);
             }
 
             IrOpcode::Lt |
@@ -683,58 +886,68 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
             IrOpcode::Ge |
             IrOpcode::Eq |
             IrOpcode::Ne => {
-                let dst = to_x86_operand(x86, insn.op1, IrType::I8);
-                let lhs = to_x86_operand(x86, insn.op2, insn.ty);
-                let rhs = to_x86_operand(x86, insn.op3, insn.ty);
+                let dst = to_x86_operand(x86, insn.op1);
+                let lhs = to_x86_operand(x86, insn.op2);
+                let rhs = to_x86_operand(x86, insn.op3);
                 push_instruction(x86, X86Opcode::CMP, insn.ty, lhs, rhs);
-                match insn.opcode {
-                    IrOpcode::Lt => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x9c);
-                        sprint_asm!(x86, "    setl  {}\n", dst);
-                    },
-
-                    IrOpcode::Le => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x9e);
-                        sprint_asm!(x86, "    setle {}\n", dst);
-                    },
-
-                    IrOpcode::Gt => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x9f);
-                        sprint_asm!(x86, "    setg  {}\n", dst);
-                    },
-
-                    IrOpcode::Ge => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x9d);
-                        sprint_asm!(x86, "    setge {}\n", dst);
-                    },
-
-                    IrOpcode::Eq => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x94);
-                        sprint_asm!(x86, "    sete  {}\n", dst);
-                    },
-
-                    IrOpcode::Ne => {
-                        x86.machine_code.push(0x0f);
-                        x86.machine_code.push(0x95);
-                        sprint_asm!(x86, "    setne {}\n", dst);
-                    },
+                let opcode = match (&insn.opcode, is_unsigned_ir_type(insn.ty)) {
+                    (IrOpcode::Lt, false) => X86Opcode::SETL,
+                    (IrOpcode::Le, false) => X86Opcode::SETLE,
+                    (IrOpcode::Gt, false) => X86Opcode::SETG,
+                    (IrOpcode::Ge, false) => X86Opcode::SETGE,
+                    (IrOpcode::Lt, true)  => X86Opcode::SETB,
+                    (IrOpcode::Le, true)  => X86Opcode::SETBE,
+                    (IrOpcode::Gt, true)  => X86Opcode::SETA,
+                    (IrOpcode::Ge, true)  => X86Opcode::SETAE,
+                    (IrOpcode::Eq, _)     => X86Opcode::SETE,
+                    (IrOpcode::Ne, _)     => X86Opcode::SETNE,
                     _ => unreachable!(),
+                };
+
+                if let X86Operand::Value(_) = dst {
+                    panic!("x86: cannot assign to value");
                 }
+                push_unary_instruction(x86, opcode, IrType::I8, dst);
+            }
 
-                match dst {
-                    X86Operand::Value(_) => panic!("x86: cannot assign to value"),
-                    X86Operand::Register(reg) => x86.machine_code.push(modrm(0, reg_id(reg))),
-                    X86Operand::Stack(sreg, disp) => {
-                        x86.machine_code.push(modrm_disp(0, reg_id(sreg), disp));
-                        push_displacement(x86, disp);
+            IrOpcode::Select => {
+                // NOTE(alexander): there is no byte form of cmov, booleans are selected as 32-bit values
+                let ty = if insn.ty == IrType::I8 { IrType::I32 } else { insn.ty };
+                let dst = to_x86_operand(x86, insn.op1);
+                let mut src_true = to_x86_operand(x86, insn.op3);
+                let mut src_false = to_x86_operand(x86, insn.op4);
+                let dst_reg = register_or_scratch(dst, scratch_reg);
+
+                // The condition is tested first since the destination may share register with it
+                let cc = match patterns[i] {
+                    Some(X86Pattern::Compare(compare)) => push_comparison(x86, &insns[compare]),
+                    _ => {
+                        let cond = to_x86_operand(x86, insn.op2);
+                        push_instruction(x86, X86Opcode::CMP, IrType::I8, cond, X86Operand::Value(X86Value::Int8(0)));
+                        X86Opcode::JNE
                     }
+                };
+
+                // Start from the value that is already in the destination, it would be overwritten otherwise
+                let mut opcode = x86_jump_to_cmov(cc);
+                if src_true == X86Operand::Register(dst_reg) {
+                    std::mem::swap(&mut src_true, &mut src_false);
+                    opcode = x86_jump_to_cmov(negate_x86_jump(cc));
                 }
-                insert_variable(x86, IrType::I8, insn.op1, dst);
+                push_widening_move(x86, insn.ty, dst_reg, src_false);
+
+                // cmov only takes register or memory sources (of at least 16-bits)
+                let src = match src_true {
+                    X86Operand::Register(_) => src_true,
+                    X86Operand::Stack(_, _) if insn.ty != IrType::I8 => src_true,
+                    _ => {
+                        let aux = x86.scratch_registers[1];
+                        push_widening_move(x86, insn.ty, aux, src_true);
+                        X86Operand::Register(aux)
+                    }
+                };
+                push_instruction(x86, opcode, ty, X86Operand::Register(dst_reg), src);
+                push_store_register(x86, dst, dst_reg, insn.ty);
             }
 
             IrOpcode::IfLt |
@@ -743,110 +956,88 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
             IrOpcode::IfGe |
             IrOpcode::IfEq |
             IrOpcode::IfNe => {
-                let lhs = to_x86_operand(x86, insn.op1, insn.ty);
-                let rhs = to_x86_operand(x86, insn.op2, insn.ty);
-
                 let label = get_ir_ident(insn.op3);
 
-                push_instruction(x86, X86Opcode::CMP, insn.ty, lhs, rhs);
-                let opcode = match insn.opcode {
-                    IrOpcode::IfLt => {
-                        sprint_asm!(x86, "    jl    {}\n", label);
-                        X86Opcode::JL
-                    },
-
-                    IrOpcode::IfLe => {
-                        sprint_asm!(x86, "    jle   {}\n", label);
-                        X86Opcode::JLE
-                    },
-
-                    IrOpcode::IfGt => {
-                        sprint_asm!(x86, "    jg    {}\n", label);
-                        X86Opcode::JG
-                    },
-
-                    IrOpcode::IfGe => {
-                        sprint_asm!(x86, "    jge   {}\n", label);
-                        X86Opcode::JGE
-                    },
-
-                    IrOpcode::IfEq => {
-                        sprint_asm!(x86, "    je    {}\n", label);
-                        X86Opcode::JE
-                    },
-
-                    IrOpcode::IfNe => {
-                        sprint_asm!(x86, "    jne   {}\n", label);
-                        X86Opcode::JNE
-                    },
-                    _ => unreachable!(),
+                let opcode = match patterns[i] {
+                    Some(X86Pattern::Compare(compare)) => {
+                        // Jump on the comparison directly rather than on the boolean computed by it
+                        let cc = push_comparison(x86, &insns[compare]);
+                        let is_true = insn.op2 == IrOperand::Value(IrValue::Bool(true));
+                        if (insn.opcode == IrOpcode::IfEq) == is_true {
+                            cc
+                        } else {
+                            negate_x86_jump(cc)
+                        }
+                    }
+                    _ => push_comparison(x86, insn),
                 };
 
-                push_relative_jump(x86, label, opcode, false);
+                push_relative_jump(x86, label, opcode);
             }
 
             IrOpcode::Jump => {
                 let label = get_ir_ident(insn.op1);
-                push_relative_jump(x86, label, X86Opcode::JMP, false);
-                sprint_asm!(x86, "    jmp   {}\n", label);
+                push_relative_jump(x86, label, X86Opcode::JMP);
             }
 
             IrOpcode::Label => {
+                // NOTE(alexander): the exit label is placed by the epilogue
                 let label = get_ir_ident(insn.op1);
-                let insn_pos = x86.machine_code.len();
-                x86.label_byte_pos.insert(label, insn_pos);
-                sprint_asm!(x86, "{}:\n", label);
+                if label != bb.exit_label {
+                    push_label(x86, label);
+                }
             }
 
             IrOpcode::Param => {
                 x86.argument_stack.push_back((insn.op1, insn.ty));
             }
 
-            IrOpcode::Call => {
-                // Make sure temporary registers are saved to stack
-                let mut arg_moves: Vec<(IrType, IrIdent, X86Operand, X86Operand)> = Vec::new();
-                let mut reg_alloc_moved_to_stack: Vec<bool> = Vec::new();
-                for (reg, maybe_ident) in &x86.allocated_registers {
-                    if let Some(ident) = maybe_ident {
-                        let var = x86.local_variables.get(ident);
-                        if let Some((src, ty)) = var {
-                            if let X86Operand::Register(src_reg) = src {
-                                if reg == src_reg {
-                                    x86.curr_stack_offset -= size_of_ir_type(*ty, x86.addr_size);
-                                    let dst = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-                                    arg_moves.push((*ty, *ident, dst, *src));
-                                    reg_alloc_moved_to_stack.push(true);
-                                    continue;
-                                }
-                            }
-                        }
-                    }
-                    
-                    reg_alloc_moved_to_stack.push(false);
+            IrOpcode::AsmIn |
+            IrOpcode::AsmOut => {
+                // NOTE(alexander): the operands are moved by the inline assembly instruction
+            }
+
+            IrOpcode::Asm => {
+                let asm = parse_x86_inline_asm(x86, insn);
+
+                let mut input_moves: Vec<(X86Operand, X86Operand, IrType)> = Vec::new();
+                for input in insns[..i].iter().rev().take_while(|insn| insn.opcode == IrOpcode::AsmIn) {
+                    let reg = asm.operands[ir_asm_operand_index(input)];
+                    let src = to_x86_operand(x86, input.op1);
+                    input_moves.push((X86Operand::Register(reg), src, input.ty));
                 }
+                push_parallel_moves(x86, input_moves);
 
-                // Remove any register allocations that moved to stack
-                let mut i = 0;
-                x86.allocated_registers.retain(|_| (!reg_alloc_moved_to_stack[i], i += 1).0);
+                for asm_insn in asm.instructions {
+                    push_x86_instruction(x86, asm_insn);
+                }
 
-                for (ty, ident, dst, src) in &arg_moves {
-                    push_instruction(x86, X86Opcode::MOV, *ty, *dst, *src);
-                    x86.local_variables.insert(*ident, (*dst, *ty));
+                let mut output_moves: Vec<(X86Operand, X86Operand, IrType)> = Vec::new();
+                for output in insns[i + 1..].iter().take_while(|insn| insn.opcode == IrOpcode::AsmOut) {
+                    let reg = asm.operands[ir_asm_operand_index(output)];
+                    let dst = to_x86_operand(x86, output.op1);
+                    output_moves.push((dst, X86Operand::Register(reg), output.ty));
                 }
+                push_parallel_moves(x86, output_moves);
+
+                // NOTE(alexander): push and pop may write below rsp where the locals would otherwise be
+                require_stack_frame = true;
+            }
 
+            IrOpcode::Call => {
+                // NOTE(alexander): values alive across the call are never assigned to caller-saved registers
                 let return_op = match insn.op2 {
                     IrOperand::Ident(ident) => {
                         if ident.symbol == x86.debug_break_symbol {
-                            x86.machine_code.push(0xcc);
+                            push_opcode_instruction(x86, X86Opcode::INT3);
                             continue;
                         }
 
-                        // Setup based arguments, windows x64 calling convention
-                        let return_op = windows_calling_convention(x86);
+                        // Setup based arguments, windows argument registers without shadow space
+                        let return_op = internal_calling_convention(x86);
 
                         // Perform the call
-                        push_relative_jump(x86, ident, X86Opcode::CALL, true);
-                        sprint_asm!(x86, "    call  {}\n", ident);
+                        push_relative_jump(x86, ident, X86Opcode::CALL);
                         return_op
                     }
 
@@ -862,22 +1053,41 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
                             panic!("x64: unknown os, cannot select calling convention");
                         };
 
-                        if x86.x64_mode {
-                            x86.machine_code.push(REX_W);
-                        }
-
-                        x86.machine_code.push(0xb8 + reg_id(reg));
-                        let val = match func_address {
-                            IrValue::U32(v) => X86Value::Int32(v as i32),
-                            IrValue::U64(v) => X86Value::Int64(v as i64),
+                        let addr = match func_address {
+                            IrValue::U32(v) => v as u64,
+                            IrValue::U64(v) => v,
                             _ => panic!("x86: unexpected func address value"),
                         };
-                        push_immediate(x86, val);
-                        sprint_asm!(x86, "    mov   {}, {}\n", dst, val);
 
-                        x86.machine_code.push(0xff);
-                        x86.machine_code.push(modrm(2, reg_id(reg)));
-                        sprint_asm!(x86, "    call  {}\n", reg);
+                        // NOTE(alexander): functions without address are interpreter level intrinsics e.g. trace
+                        let foreign_ident = x86.foreign_functions.get(&addr).copied();
+
+                        if x86.output == X86Output::Object {
+                            // NOTE(alexander): the call is encoded with a relocation for the linker
+                            if let Some(ident) = foreign_ident {
+                                push_relative_jump(x86, ident, X86Opcode::CALL);
+                            }
+                        } else if x86.output == X86Output::Executable {
+                            if let Some(ident) = foreign_ident {
+                                if !x86.runtime_routines.contains(&ident) {
+                                    x86.runtime_routines.push(ident);
+                                }
+                                push_relative_jump(x86, ident, X86Opcode::CALL);
+                            }
+                        } else {
+                            match func_address {
+                                IrValue::U32(v) => {
+                                    let val = X86Operand::Value(X86Value::Int32(v as i32));
+                                    push_instruction(x86, X86Opcode::MOV, IrType::I32, dst, val);
+                                }
+                                IrValue::U64(v) => {
+                                    let val = X86Operand::Value(X86Value::Int64(v as i64));
+                                    push_instruction(x86, X86Opcode::MOVABS, IrType::I64, dst, val);
+                                }
+                                _ => panic!("x86: unexpected func address value"),
+                            };
+                            push_unary_instruction(x86, X86Opcode::CALL, IrType::I64, dst);
+                        }
 
                         return_op
                     }
@@ -885,21 +1095,23 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
                     _ => panic!("x86: expected identifier or value as second operand to Call"),
                 };
 
-                // Restore previous registers
-                // for (ty, src, dst) in &arg_moves {
-                    // push_instruction(x86, X86Opcode::MOV, *ty, *dst, *src);
-                // }
-
                 require_stack_frame = true;
                 x86.argument_stack.clear();
-                insert_variable(x86, insn.ty, insn.op1, return_op);
+
+                // NOTE(alexander): functions without return value has nothing to store
+                if insn.ty != IrType::None {
+                    let dst = to_x86_operand(x86, insn.op1);
+                    if dst != return_op {
+                        push_instruction(x86, X86Opcode::MOV, insn.ty, dst, return_op);
+                    }
+                }
             }
 
             IrOpcode::Return => {
                 // Store return value in RAX
                 if let IrOperand::None = insn.op1 {
                 } else {
-                    let src = to_x86_operand(x86, insn.op1, insn.ty);
+                    let src = to_x86_operand(x86, insn.op1);
                     if let X86Operand::Register(X86Reg::RAX) = src {
                     } else {
                         push_instruction(x86, X86Opcode::MOV, insn.ty, X86Operand::Register(X86Reg::RAX), src);
@@ -908,20 +1120,18 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
 
                 // Jump to the end of the function
                 if i < num_insns - 1 {
-                    push_relative_jump(x86, bb.exit_label, X86Opcode::JMP, false);
-                    sprint_asm!(x86, "    jmp   {}\n", bb.exit_label);
+                    push_relative_jump(x86, bb.exit_label, X86Opcode::JMP);
                 }
             }
 
+            IrOpcode::Phi => panic!("x86: phi instructions has to be removed before code generation"),
+
             _ => {},
         }
-
-        free_dead_registers(x86, insn_index, bb);
     }
 
-    // Setup return label byte pos
-    let return_pos = x86.machine_code.len();
-    x86.label_byte_pos.insert(bb.exit_label, return_pos);
+    // Setup return label
+    push_label(x86, bb.exit_label);
 
     // Epilogue
 
@@ -931,466 +1141,2847 @@ fn push_function(x86: &mut X86Assembler, insns: &[IrInstruction], bb: &IrBasicBl
             x86.max_stack_requirement = x86.curr_stack_offset;
         }
 
-        if x86.max_stack_requirement != 0 || true {
-            // Align 16-bytes
-            let stack_misalignment = x86.max_stack_requirement % 16;
-            if stack_misalignment < 0 {
-                x86.max_stack_requirement -= 16 + stack_misalignment;
-            }
-
-            // sub rbp, stackspace
-            let v = -x86.max_stack_requirement as i32;
-            x86.machine_code.insert(sub_rsp_byte_pos, ((v >> 24) & 0xFFi32) as u8);
-            x86.machine_code.insert(sub_rsp_byte_pos, ((v >> 16) & 0xFFi32) as u8);
-            x86.machine_code.insert(sub_rsp_byte_pos, ((v >> 8)  & 0xFFi32) as u8);
-            x86.machine_code.insert(sub_rsp_byte_pos, ( v        & 0xFFi32) as u8);
-            x86.machine_code.insert(sub_rsp_byte_pos, modrm(5, reg_id(X86Reg::RSP)));
-            x86.machine_code.insert(sub_rsp_byte_pos, 0x81);
+        // The stack arguments are stored below the local variables, at rsp when the call is made
+        x86.max_stack_requirement -= x86.outgoing_arguments_size;
 
-            let bytes_added: usize;
-            if x86.x64_mode {
-                x86.machine_code.insert(sub_rsp_byte_pos, REX_W);
-                bytes_added = 7;
-            } else {
-                bytes_added = 6;
-            }
-            if x86.print_assembly {
-                x86.assembly.insert_str(sub_rsp_asm_byte_pos, &format!("    sub   rsp, {}\n", -x86.max_stack_requirement));
-            }
-
-            // 6-bytes have been inserted make sure to update all
-            // stored indices pointing after sub_rsp_byte_pos
-            for (_, pos) in x86.label_byte_pos.iter_mut() {
-                if *pos >= sub_rsp_byte_pos {
-                    *pos += bytes_added;
-                }
-            }
-
-            for rel_jmp in x86.relative_jumps.iter_mut() {
-                if rel_jmp.pos >= sub_rsp_byte_pos {
-                    rel_jmp.pos += bytes_added;
-                }
-                if rel_jmp.next_pos >= sub_rsp_byte_pos {
-                    rel_jmp.next_pos += bytes_added;
-                }
-            }
+        // Align 16-bytes, the saved callee-saved registers are part of the stack frame
+        let stack_misalignment = x86.max_stack_requirement % 16;
+        if stack_misalignment < 0 {
+            x86.max_stack_requirement -= 16 + stack_misalignment;
+        }
 
-            // sub rbp, stackspace
-            if x86.x64_mode {
-                x86.machine_code.push(REX_W);
-            }
-            push_instruction(x86,
-                             X86Opcode::ADD,
-                             IrType::I32,
-                             X86Operand::Register(X86Reg::RSP),
-                             X86Operand::Value(X86Value::Int32(v)));
+        let saved_registers_size = callee_saved_registers.len() as isize * x86.addr_size;
+        let v = (-x86.max_stack_requirement - saved_registers_size) as i32;
+        if v != 0 {
+            // sub rsp, stackspace (inserted into the prologue now that the size is known)
+            let rsp = X86Operand::Register(X86Reg::RSP);
+            let stack_space = X86Operand::Value(X86Value::Int32(v));
+            x86.instructions.insert(sub_rsp_index, X86Instruction {
+                opcode: X86Opcode::SUB,
+                ty: IrType::I64,
+                op1: Some(rsp),
+                op2: Some(stack_space),
+                ..Default::default()
+            });
+
+            // add rsp, stackspace
+            push_instruction(x86, X86Opcode::ADD, IrType::I64, rsp, stack_space);
         }
     }
 
+    // Restore the callee-saved registers
+    for reg in callee_saved_registers.iter().rev() {
+        push_unary_instruction(x86, X86Opcode::POP, IrType::I64, X86Operand::Register(*reg));
+    }
+
     // pop rbp
-    x86.machine_code.push(0x8f);
-    x86.machine_code.push(modrm(0, reg_id(X86Reg::RBP)));
-    sprint_asm!(x86, "    pop    rbp\n");
+    push_unary_instruction(x86, X86Opcode::POP, IrType::I64, X86Operand::Register(X86Reg::RBP));
 
     // ret
-    x86.machine_code.push(0xc3);
-    sprint_asm!(x86, "    ret\n");
-}
-
-fn free_dead_registers(x86: &mut X86Assembler, insn_index: usize, bb: &IrBasicBlock) {
-    // NOTE(alexander) Free registers that are nolonger in use
-    for (ident, interval) in &bb.live_intervals {
-        if insn_index == interval.end {
-            let opt_reg = match x86.local_variables.get(ident) {
-                Some((operand, _)) => if let X86Operand::Register(reg) = operand {
-                    Some(reg)
-                } else {
-                    None
-                }
+    push_opcode_instruction(x86, X86Opcode::RET);
 
-                None => None,
-            };
-            if let Some(reg) = opt_reg {
-                let r = *reg;
-                // x86.assembly.push_str(&format!("dropped: {} from `{}`\n", reg, ident));
-                free_register(x86, r);
-                x86.local_variables.remove(ident);
-            }
-        }
+    if x86.peephole {
+        optimize_x86_instructions(&mut x86.instructions);
     }
+    encode_instructions(x86);
 }
 
-/***************************************************************************
- * Machine code builder helpers
- ***************************************************************************/
-
+/**
+ * The entry point is called by the host, generated functions preserve the callee-saved
+ * registers themselves so the entry point only has to keep the stack aligned.
+ */
+fn push_entry_point(x86: &mut X86Assembler, main_label: IrIdent, return_type: IrType) {
+    if !x86.x64_mode {
+        return;
+    }
+
+    // NOTE(alexander): the return address misaligns the stack, realign it before the call
+    let rsp = X86Operand::Register(X86Reg::RSP);
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rsp, X86Operand::Value(X86Value::Int32(8)));
+    push_relative_jump(x86, main_label, X86Opcode::CALL);
+
+    // NOTE(alexander): main without return value exits with code 0
+    if return_type == IrType::None {
+        push_instruction(x86, X86Opcode::XOR, IrType::I32, X86Operand::Register(X86Reg::RAX), X86Operand::Register(X86Reg::RAX));
+    }
+
+    push_instruction(x86, X86Opcode::ADD, IrType::I64, rsp, X86Operand::Value(X86Value::Int32(8)));
+    push_opcode_instruction(x86, X86Opcode::RET);
+    encode_instructions(x86);
+    sprint_asm!(x86, "\n");
+}
+
+/**
+ * Entry point of executables, the kernel jumps here with the stack 16-byte aligned.
+ * There is nothing to return to so the result of main is passed to the exit syscall.
+ */
+fn push_start_stub(x86: &mut X86Assembler, main_label: IrIdent, return_type: IrType) {
+    sprint_asm!(x86, "_start:\n");
+    push_relative_jump(x86, main_label, X86Opcode::CALL);
+
+    // NOTE(alexander): main without return value exits with code 0
+    let code = X86Operand::Register(X86Reg::RDI);
+    if return_type == IrType::None {
+        push_instruction(x86, X86Opcode::XOR, IrType::I32, code, code);
+    } else {
+        push_instruction(x86, X86Opcode::MOV, IrType::I32, code, X86Operand::Register(X86Reg::RAX));
+    }
+
+    // mov eax, SYS_exit
+    let sys_exit = X86Operand::Value(X86Value::Int32(X86_SYS_EXIT));
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, X86Operand::Register(X86Reg::RAX), sys_exit);
+    push_opcode_instruction(x86, X86Opcode::SYSCALL);
+    encode_instructions(x86);
+    sprint_asm!(x86, "\n");
+}
+
+/**
+ * Appends the backend implementation of a foreign function, these are selected
+ * and encoded the same way as functions and only use the write and exit syscalls.
+ */
+fn push_runtime_routine(x86: &mut X86Assembler, ident: IrIdent) {
+    push_label(x86, ident);
+    match resolve_symbol(ident.symbol) {
+        "print_int" => push_print_integer_routine(x86, IrType::I32),
+        "print_i64" => push_print_integer_routine(x86, IrType::I64),
+        "print_u64" => push_print_integer_routine(x86, IrType::U64),
+        "print_bool" => push_print_bool_routine(x86),
+        "assert" => push_assert_routine(x86, IrType::I8, false),
+        "assert_eq_int" => push_assert_routine(x86, IrType::I32, true),
+        "assert_eq_bool" => push_assert_routine(x86, IrType::I8, true),
+        name => panic!("x86: foreign function `{}` is not available in executables", name),
+    }
+    encode_instructions(x86);
+    sprint_asm!(x86, "\n");
+}
+
+/***************************************************************************
+ * Register allocation
+ ***************************************************************************/
+
+/**
+ * Location of every variable in a function, decided before any code is generated.
+ */
+struct X86RegisterAllocation {
+    locations: HashMap<IrIdent, X86Operand>,
+    intervals: Vec<(IrIdent, IrLiveInterval)>, // sorted by increasing start point
+    callee_saved_registers: Vec<X86Reg>, // used by the function, saved in the prologue
+    stack_offset: isize, // lowest stack offset used by the saved registers and spilled variables
+}
+
+/**
+ * Pre-colored registers, registers that are overwritten by some instruction
+ * cannot be assigned to variables that are alive across that instruction.
+ */
+struct X86RegisterConstraints {
+    clobbers: Vec<(usize, X86Reg, Option<IrIdent>)>, // instruction, register overwritten and its destination
+    forbidden: HashMap<IrIdent, Vec<X86Reg>>,
+    hints: HashMap<IrIdent, X86Reg>,
+    address_taken: HashSet<IrIdent>, // always stored on the stack
+}
+
+const X86_WINDOWS_ARGUMENT_REGISTERS: [X86Reg; 4] = [X86Reg::RCX, X86Reg::RDX, X86Reg::R8, X86Reg::R9];
+const X86_SYSV_ARGUMENT_REGISTERS: [X86Reg; 6] = [X86Reg::RDI, X86Reg::RSI, X86Reg::RDX, X86Reg::RCX, X86Reg::R8, X86Reg::R9];
+const X86_WINDOWS_SHADOW_SPACE: isize = 32; // reserved by the caller for the four register arguments
+
+/**
+ * Formats the register assigned to each variable of every function (excluding foreign functions).
+ */
+pub fn format_x86_register_allocation(
+    instructions: &[IrInstruction],
+    functions: &HashMap<IrIdent, IrBasicBlock>
+) -> String {
+    let x86 = create_x86_assembler(functions, X86Output::Jit, false);
+    let mut defined: Vec<(&IrIdent, &IrBasicBlock)> = functions.iter().filter(|(_, bb)| !bb.is_foreign).collect();
+    defined.sort_by_key(|(_, bb)| bb.prologue_index);
+
+    let mut result = String::new();
+    for (ident, bb) in defined {
+        let insns = &instructions[bb.prologue_index + 1..bb.epilogue_index];
+        let allocation = allocate_x86_registers(&x86, insns, bb);
+
+        result.push_str(&format!("{}:\n", ident));
+        for (ident, interval) in &allocation.intervals {
+            let location = match allocation.locations.get(ident) {
+                Some(X86Operand::Register(reg)) => format!("{}", reg),
+                Some(op) => format!("{}", op),
+                None => String::from("-"),
+            };
+            result.push_str(&format!("    {:<12} {:<16} {}\n",
+                                     format!("%{}", ident),
+                                     format!("[{}, {}]", interval.begin, interval.end),
+                                     location));
+        }
+
+        // Variables without live interval, e.g. address taken variables
+        let mut stack_variables: Vec<(String, String)> = allocation.locations.iter()
+            .filter(|(ident, _)| !allocation.intervals.iter().any(|(i, _)| i == *ident))
+            .map(|(ident, op)| (format!("%{}", ident), format!("{}", op)))
+            .collect();
+        stack_variables.sort();
+        for (ident, op) in stack_variables {
+            result.push_str(&format!("    {:<12} {:<16} {}\n", ident, "-", op));
+        }
+
+        let saved: Vec<String> = allocation.callee_saved_registers.iter().map(|reg| format!("{}", reg)).collect();
+        if !saved.is_empty() {
+            result.push_str(&format!("    callee-saved: {}\n", saved.join(", ")));
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/**
+ * Registers handed out by the register allocator in order of preference, caller-saved
+ * registers comes first since they do not need to be saved in the prologue.
+ * The scratch registers are never allocated.
+ */
+fn allocatable_registers(x86: &X86Assembler) -> Vec<X86Reg> {
+    if !x86.x64_mode {
+        vec![X86Reg::RAX, X86Reg::RCX, X86Reg::RDX, X86Reg::RBX]
+    } else if cfg!(windows) {
+        vec![X86Reg::RAX, X86Reg::RCX, X86Reg::RDX, X86Reg::R8,  X86Reg::R9,  X86Reg::RBX,
+             X86Reg::RSI, X86Reg::RDI, X86Reg::R12, X86Reg::R13, X86Reg::R14, X86Reg::R15]
+    } else {
+        vec![X86Reg::RAX, X86Reg::RCX, X86Reg::RDX, X86Reg::RSI, X86Reg::RDI, X86Reg::R8,
+             X86Reg::R9,  X86Reg::RBX, X86Reg::R12, X86Reg::R13, X86Reg::R14, X86Reg::R15]
+    }
+}
+
+/**
+ * Registers that a function has to restore before returning, the same
+ * registers are used internally as the host calling convention.
+ */
+fn is_callee_saved_register(reg: X86Reg) -> bool {
+    match reg {
+        X86Reg::RBX |
+        X86Reg::RSP |
+        X86Reg::RBP |
+        X86Reg::R12 |
+        X86Reg::R13 |
+        X86Reg::R14 |
+        X86Reg::R15 => true,
+        X86Reg::RSI |
+        X86Reg::RDI => cfg!(windows),
+        _ => false,
+    }
+}
+
+/**
+ * Linear scan register allocation (Poletto and Sarkar) over the live intervals of the function.
+ * Intervals are visited in order of increasing start point and are assigned a free register,
+ * if there are none left the interval ending furthest away is spilled to the stack.
+ */
+fn allocate_x86_registers(x86: &X86Assembler, insns: &[IrInstruction], bb: &IrBasicBlock) -> X86RegisterAllocation {
+    let registers = allocatable_registers(x86);
+    let constraints = collect_x86_register_constraints(x86, insns, bb, &registers);
+
+    let mut intervals: Vec<(IrIdent, IrLiveInterval)> = bb.live_intervals.iter()
+        .filter(|(ident, _)| !constraints.address_taken.contains(ident))
+        .map(|(ident, interval)| (*ident, interval.clone()))
+        .collect();
+    intervals.sort_by_cached_key(|(ident, interval)| (interval.begin, interval.end, ident.to_string()));
+
+    let mut assigned: HashMap<IrIdent, X86Reg> = HashMap::new();
+    let mut spilled: Vec<IrIdent> = Vec::new();
+    let mut active: Vec<(IrIdent, usize, X86Reg)> = Vec::new(); // identifier, end and register
+    for (ident, interval) in &intervals {
+        // Expire old intervals, NOTE(alexander): an interval ending where another one begins can share its
+        // register since instructions read all their operands before the destination is written.
+        active.retain(|(_, end, _)| *end > interval.begin);
+
+        let is_allowed = |reg: X86Reg| is_register_allowed(&constraints, reg, *ident, interval);
+        let is_free = |reg: X86Reg| active.iter().all(|(_, _, r)| *r != reg);
+        let hint = constraints.hints.get(ident).copied().filter(|reg| is_free(*reg) && is_allowed(*reg));
+        let free_reg = hint.or_else(|| registers.iter().copied().find(|reg| is_free(*reg) && is_allowed(*reg)));
+
+        if let Some(reg) = free_reg {
+            assigned.insert(*ident, reg);
+            active.push((*ident, interval.end, reg));
+            continue;
+        }
+
+        // Spill the interval that ends last, if it is not the current one then steal its register
+        let spill_candidate = active.iter()
+            .enumerate()
+            .filter(|(_, (_, _, reg))| is_allowed(*reg))
+            .max_by_key(|(_, (_, end, _))| *end)
+            .map(|(index, (spill_ident, end, reg))| (index, *spill_ident, *end, *reg));
+
+        match spill_candidate {
+            Some((index, spill_ident, end, reg)) if end > interval.end => {
+                assigned.remove(&spill_ident);
+                spilled.push(spill_ident);
+                active.remove(index);
+                assigned.insert(*ident, reg);
+                active.push((*ident, interval.end, reg));
+            }
+
+            _ => spilled.push(*ident),
+        }
+    }
+
+    // NOTE(alexander): inline assembly can also clobber callee-saved registers e.g. rbx by cpuid
+    let is_clobbered = |reg: X86Reg| constraints.clobbers.iter().any(|(_, r, _)| *r == reg);
+    let mut callee_saved_registers: Vec<X86Reg> = registers.iter()
+        .copied()
+        .filter(|reg| is_callee_saved_register(*reg) && (assigned.values().any(|r| r == reg) || is_clobbered(*reg)))
+        .collect();
+    for reg in X86_REGISTERS.iter() {
+        if is_callee_saved_register(*reg) && is_clobbered(*reg) && !callee_saved_registers.contains(reg) {
+            callee_saved_registers.push(*reg);
+        }
+    }
+
+    let mut locations: HashMap<IrIdent, X86Operand> = HashMap::new();
+    for (ident, reg) in &assigned {
+        locations.insert(*ident, X86Operand::Register(*reg));
+    }
+
+    // Spilled variables, variables that has their address taken and variables
+    // without live interval are stored on the stack below the saved registers.
+    let mut address_taken: Vec<IrIdent> = constraints.address_taken.iter().copied().collect();
+    address_taken.sort_by_cached_key(|ident| ident.to_string());
+    spilled.extend(address_taken);
+    for insn in insns {
+        for ident in ir_variable_operands(insn) {
+            if !bb.live_intervals.contains_key(&ident) && !spilled.contains(&ident) {
+                spilled.push(ident);
+            }
+        }
+    }
+
+    let mut stack_offset = -(callee_saved_registers.len() as isize) * x86.addr_size;
+    for ident in spilled {
+        stack_offset -= x86.addr_size;
+        locations.insert(ident, X86Operand::Stack(X86Reg::RBP, stack_offset));
+    }
+
+    X86RegisterAllocation {
+        locations,
+        intervals,
+        callee_saved_registers,
+        stack_offset,
+    }
+}
+
+/**
+ * Calls overwrite all the caller-saved registers and idiv overwrites RAX and RDX.
+ * Call arguments, return values and the idiv operands are hinted to be allocated
+ * in the registers they are expected in, which avoids most moves.
+ */
+fn collect_x86_register_constraints(
+    x86: &X86Assembler,
+    insns: &[IrInstruction],
+    bb: &IrBasicBlock,
+    registers: &[X86Reg]
+) -> X86RegisterConstraints {
+    let mut constraints = X86RegisterConstraints {
+        clobbers: Vec::new(),
+        forbidden: HashMap::new(),
+        hints: HashMap::new(),
+        address_taken: HashSet::new(),
+    };
+
+    fn hint(constraints: &mut X86RegisterConstraints, op: IrOperand, reg: X86Reg) {
+        if let Some(ident) = maybe_get_ir_ident(op) {
+            constraints.hints.entry(ident).or_insert(reg);
+        }
+    }
+
+    let host_argument_registers: &[X86Reg] = if cfg!(windows) {
+        &X86_WINDOWS_ARGUMENT_REGISTERS
+    } else {
+        &X86_SYSV_ARGUMENT_REGISTERS
+    };
+
+    let mut params: Vec<IrOperand> = Vec::new();
+    for (i, insn) in insns.iter().enumerate() {
+        let insn_index = bb.prologue_index + i + 1;
+        match insn.opcode {
+            IrOpcode::Param => params.push(insn.op1),
+
+            IrOpcode::AllocParams => {
+                for (op, reg) in params.drain(..).zip(X86_WINDOWS_ARGUMENT_REGISTERS.iter()) {
+                    hint(&mut constraints, op, *reg);
+                }
+            }
+
+            IrOpcode::Call => {
+                let argument_registers = match insn.op2 {
+                    IrOperand::Ident(ident) if ident.symbol == x86.debug_break_symbol => {
+                        params.clear();
+                        continue;
+                    }
+                    IrOperand::Value(_) => host_argument_registers,
+                    _ => &X86_WINDOWS_ARGUMENT_REGISTERS,
+                };
+
+                for (op, reg) in params.drain(..).zip(argument_registers.iter()) {
+                    hint(&mut constraints, op, *reg);
+                }
+
+                let dst = maybe_get_ir_ident(insn.op1);
+                for reg in registers {
+                    if !is_callee_saved_register(*reg) {
+                        constraints.clobbers.push((insn_index, *reg, dst));
+                    }
+                }
+                hint(&mut constraints, insn.op1, X86Reg::RAX);
+            }
+
+            IrOpcode::Pow => {
+                let dst = maybe_get_ir_ident(insn.op1);
+                constraints.clobbers.push((insn_index, X86Reg::RAX, dst));
+                constraints.clobbers.push((insn_index, X86Reg::RDX, dst));
+                hint(&mut constraints, insn.op1, X86Reg::RAX);
+            }
+
+            IrOpcode::Div |
+            IrOpcode::Mod => {
+                let dst = maybe_get_ir_ident(insn.op1);
+                constraints.clobbers.push((insn_index, X86Reg::RAX, dst));
+                constraints.clobbers.push((insn_index, X86Reg::RDX, dst));
+                if let Some(ident) = maybe_get_ir_ident(insn.op3) {
+                    constraints.forbidden.insert(ident, vec![X86Reg::RAX, X86Reg::RDX]);
+                }
+
+                let result = if insn.opcode == IrOpcode::Div { X86Reg::RAX } else { X86Reg::RDX };
+                hint(&mut constraints, insn.op1, result);
+                hint(&mut constraints, insn.op2, X86Reg::RAX);
+            }
+
+            IrOpcode::Asm => {
+                // NOTE(alexander): inputs and outputs are hinted to the registers they are moved from and into
+                let asm = parse_x86_inline_asm(x86, insn);
+                let inputs = insns[..i].iter().rev().take_while(|insn| insn.opcode == IrOpcode::AsmIn);
+                let outputs: Vec<&IrInstruction> = insns[i + 1..].iter()
+                    .take_while(|insn| insn.opcode == IrOpcode::AsmOut)
+                    .collect();
+
+                for input in inputs {
+                    hint(&mut constraints, input.op1, asm.operands[ir_asm_operand_index(input)]);
+                }
+
+                // NOTE(alexander): the inputs are also overwritten when they are moved into their registers
+                let mut overwritten = asm.clobbers.clone();
+                overwritten.extend(asm.operands.iter().filter(|reg| !asm.clobbers.contains(reg)));
+                for reg in &overwritten {
+                    let dst = outputs.iter()
+                        .find(|output| asm.operands[ir_asm_operand_index(output)] == *reg)
+                        .and_then(|output| maybe_get_ir_ident(output.op1));
+                    constraints.clobbers.push((insn_index, *reg, dst));
+                }
+
+                for output in outputs {
+                    hint(&mut constraints, output.op1, asm.operands[ir_asm_operand_index(output)]);
+                }
+            }
+
+            IrOpcode::Return => hint(&mut constraints, insn.op1, X86Reg::RAX),
+
+            IrOpcode::CopyFromRef => {
+                if let Some(ident) = maybe_get_ir_ident(insn.op2) {
+                    constraints.address_taken.insert(ident);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    constraints
+}
+
+fn is_register_allowed(
+    constraints: &X86RegisterConstraints,
+    reg: X86Reg,
+    ident: IrIdent,
+    interval: &IrLiveInterval
+) -> bool {
+    if let Some(forbidden) = constraints.forbidden.get(&ident) {
+        if forbidden.contains(&reg) {
+            return false;
+        }
+    }
+
+    // NOTE(alexander): the destination is written after the register is overwritten
+    constraints.clobbers.iter().all(|(insn_index, r, dst)| {
+        *r != reg || *dst == Some(ident) || *insn_index < interval.begin || *insn_index >= interval.end
+    })
+}
+
+/**
+ * Identifiers used as variables by the instruction, i.e. not labels or functions.
+ */
+fn ir_variable_operands(insn: &IrInstruction) -> Vec<IrIdent> {
+    let ops = match insn.opcode {
+        IrOpcode::Nop         |
+        IrOpcode::AllocParams |
+        IrOpcode::Label       |
+        IrOpcode::Jump        |
+        IrOpcode::Prologue    |
+        IrOpcode::Epilogue    => vec![],
+
+        IrOpcode::IfLt |
+        IrOpcode::IfGt |
+        IrOpcode::IfLe |
+        IrOpcode::IfGe |
+        IrOpcode::IfEq |
+        IrOpcode::IfNe => vec![insn.op1, insn.op2],
+
+        IrOpcode::Call => vec![insn.op1],
+
+        _ => vec![insn.op1, insn.op2, insn.op3, insn.op4],
+    };
+    ops.into_iter().filter_map(maybe_get_ir_ident).collect()
+}
+
+/***************************************************************************
+ * Instruction patterns
+ ***************************************************************************/
+
+/**
+ * Instructions that are selected together with the instruction using their result,
+ * decided for the entire function before any code is selected.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum X86Pattern {
+    Folded,              // selected as part of a later instruction
+    Address(X86Address), // the expression tree is computed by a single lea
+    Compare(usize),      // branches or selects directly on the comparison at the given index
+}
+
+/**
+ * Value computed by lea as `base + index * scale + disp`, the registers are given by the variables.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct X86Address {
+    base: Option<IrIdent>,
+    index: Option<IrIdent>,
+    scale: u8,
+    disp: i64,
+}
+
+/**
+ * Covers the expression trees of the function with patterns that are selected as a single x86
+ * instruction. The inner nodes of a tree are temporaries used once, by the instructions directly
+ * following their definitions. No code is selected in between so the registers of the other
+ * operands are still valid when the entire tree is selected:
+ *
+ * %1 = mul i32 %b, 4
+ * %2 = add i32 %a, %1      =>   lea   eax, [rcx + rdx*4 + 8]
+ * %3 = add i32 %2, 8
+ *
+ * Loads used once are accessed as a memory operand of the instruction using them
+ * and comparisons that are only branched on are fused with the branch.
+ */
+fn select_x86_patterns(x86: &mut X86Assembler, insns: &[IrInstruction]) -> Vec<Option<X86Pattern>> {
+    let mut uses: HashMap<IrIdent, usize> = HashMap::new();
+    let mut definitions: HashMap<IrIdent, usize> = HashMap::new();
+    for insn in insns {
+        for ident in ir_used_variables(insn) {
+            *uses.entry(ident).or_insert(0) += 1;
+        }
+        if let Some(ident) = ir_defined_variable(insn) {
+            *definitions.entry(ident).or_insert(0) += 1;
+        }
+    }
+    let is_temporary = |ident: IrIdent| uses.get(&ident) == Some(&1) && definitions.get(&ident) == Some(&1);
+
+    // Trees are matched from their root, the later instructions are visited first so the largest tree is covered
+    let mut patterns = vec![None; insns.len()];
+    for i in (0..insns.len()).rev() {
+        if patterns[i].is_some() {
+            continue;
+        }
+
+        if let Some((addr, first)) = match_x86_address(x86, insns, &is_temporary, i) {
+            patterns[i] = Some(X86Pattern::Address(addr));
+            for pattern in &mut patterns[first..i] {
+                *pattern = Some(X86Pattern::Folded);
+            }
+            continue;
+        }
+
+        let mut root = i;
+        if let Some(compare) = match_x86_compare(insns, &is_temporary, i) {
+            patterns[i] = Some(X86Pattern::Compare(compare));
+            patterns[compare] = Some(X86Pattern::Folded);
+            root = compare;
+        }
+
+        if fold_x86_load(x86, insns, &is_temporary, root) {
+            patterns[root - 1] = Some(X86Pattern::Folded);
+        }
+    }
+    patterns
+}
+
+/**
+ * Matches the addition, subtraction or multiplication at the given index and the instructions
+ * folded into it against lea. Returns the address and the index of the first folded instruction.
+ */
+fn match_x86_address(
+    x86: &X86Assembler,
+    insns: &[IrInstruction],
+    is_temporary: &dyn Fn(IrIdent) -> bool,
+    index: usize
+) -> Option<(X86Address, usize)> {
+    let insn = &insns[index];
+    if !matches!(insn.ty, IrType::I32 | IrType::U32 | IrType::I64 | IrType::U64) {
+        return None;
+    }
+
+    let empty = X86Address { base: None, index: None, scale: 1, disp: 0 };
+    let (mut addr, first) = fold_x86_address(insns, is_temporary, index, insn.ty, empty)?;
+
+    // Indices without base are only encodable with a 32-bit displacement, `x * 2` is computed as `x + x` instead
+    if addr.base.is_none() {
+        match addr.scale {
+            1 => {
+                addr.base = addr.index.take();
+            }
+            2 => {
+                addr.base = addr.index;
+                addr.scale = 1;
+            }
+            _ => return None,
+        }
+    }
+
+    let location = |ident: IrIdent| x86.local_variables.get(&ident).copied();
+    let is_register = |ident: Option<IrIdent>| match ident {
+        Some(ident) => matches!(location(ident), Some(X86Operand::Register(_))),
+        None => true,
+    };
+    if addr.base.is_none() || !is_register(addr.base) || !is_register(addr.index) {
+        return None;
+    }
+
+    // NOTE(alexander): 32-bit results only depend on the lower half of the displacement
+    if let IrType::I32 | IrType::U32 = insn.ty {
+        addr.disp = addr.disp as i32 as i64;
+    }
+    if addr.disp < i32::MIN as i64 || addr.disp > i32::MAX as i64 {
+        return None;
+    }
+
+    // A single addition is only worth it if it saves moving one of the operands into the destination
+    if first == index && insn.opcode != IrOpcode::Mul {
+        let dst = location(get_ir_ident(insn.op1));
+        let in_place = [insn.op2, insn.op3].iter()
+            .filter_map(|op| maybe_get_ir_ident(*op))
+            .any(|ident| location(ident) == dst);
+        if in_place {
+            return None;
+        }
+    }
+
+    Some((addr, first))
+}
+
+/**
+ * Adds the value computed by the instruction at the given index to the address,
+ * returns the address and the index of the first folded instruction.
+ */
+fn fold_x86_address(
+    insns: &[IrInstruction],
+    is_temporary: &dyn Fn(IrIdent) -> bool,
+    index: usize,
+    ty: IrType,
+    addr: X86Address
+) -> Option<(X86Address, usize)> {
+    let insn = &insns[index];
+    if insn.ty != ty {
+        return None;
+    }
+
+    match (&insn.opcode, insn.op2, insn.op3) {
+        (IrOpcode::Add, lhs, rhs) => {
+            // NOTE(alexander): the right-hand side is computed last, i.e. directly before the addition
+            let (addr, first) = fold_x86_address_operand(insns, is_temporary, index, ty, rhs, addr)?;
+            fold_x86_address_operand(insns, is_temporary, first, ty, lhs, addr)
+        }
+
+        (IrOpcode::Sub, lhs, IrOperand::Value(val)) => {
+            let addr = X86Address { disp: addr.disp.wrapping_sub(ir_integer_value(val)?), ..addr };
+            fold_x86_address_operand(insns, is_temporary, index, ty, lhs, addr)
+        }
+
+        (IrOpcode::Mul, IrOperand::Ident(ident), IrOperand::Value(val)) |
+        (IrOpcode::Mul, IrOperand::Value(val), IrOperand::Ident(ident)) => match ir_integer_value(val)? {
+            scale @ (1 | 2 | 4 | 8) if addr.index.is_none() => {
+                Some((X86Address { index: Some(ident), scale: scale as u8, ..addr }, index))
+            }
+            scale @ (3 | 5 | 9) if addr.base.is_none() && addr.index.is_none() => {
+                Some((X86Address { base: Some(ident), index: Some(ident), scale: scale as u8 - 1, ..addr }, index))
+            }
+            _ => None,
+        }
+
+        _ => None,
+    }
+}
+
+/**
+ * Adds the operand to the address, it is folded if it is a temporary computed directly
+ * before the instructions folded so far (starting at `first`) otherwise it is used as register.
+ */
+fn fold_x86_address_operand(
+    insns: &[IrInstruction],
+    is_temporary: &dyn Fn(IrIdent) -> bool,
+    first: usize,
+    ty: IrType,
+    op: IrOperand,
+    addr: X86Address
+) -> Option<(X86Address, usize)> {
+    let ident = match op {
+        IrOperand::Ident(ident) => ident,
+        IrOperand::Value(val) => return Some((X86Address { disp: addr.disp.wrapping_add(ir_integer_value(val)?), ..addr }, first)),
+        IrOperand::None => return None,
+    };
+
+    if first > 0 && is_temporary(ident) && ir_defined_variable(&insns[first - 1]) == Some(ident) {
+        if let Some(folded) = fold_x86_address(insns, is_temporary, first - 1, ty, addr) {
+            return Some(folded);
+        }
+    }
+
+    if addr.base.is_none() {
+        Some((X86Address { base: Some(ident), ..addr }, first))
+    } else if addr.index.is_none() {
+        Some((X86Address { index: Some(ident), scale: 1, ..addr }, first))
+    } else {
+        None
+    }
+}
+
+/**
+ * Matches a branch or select on the boolean computed by the comparison directly before it,
+ * returns the index of the comparison.
+ */
+fn match_x86_compare(insns: &[IrInstruction], is_temporary: &dyn Fn(IrIdent) -> bool, index: usize) -> Option<usize> {
+    let insn = &insns[index];
+    let cond = match (&insn.opcode, insn.op1, insn.op2) {
+        (IrOpcode::IfEq, IrOperand::Ident(ident), IrOperand::Value(IrValue::Bool(_))) |
+        (IrOpcode::IfNe, IrOperand::Ident(ident), IrOperand::Value(IrValue::Bool(_))) |
+        (IrOpcode::Select, _, IrOperand::Ident(ident)) => ident,
+        _ => return None,
+    };
+
+    let compare = index.checked_sub(1)?;
+    let is_comparison = matches!(insns[compare].opcode, IrOpcode::Lt | IrOpcode::Le | IrOpcode::Gt |
+                                 IrOpcode::Ge | IrOpcode::Eq | IrOpcode::Ne);
+    if is_comparison && is_temporary(cond) && ir_defined_variable(&insns[compare]) == Some(cond) {
+        Some(compare)
+    } else {
+        None
+    }
+}
+
+/**
+ * Loads used once by the instruction directly after it are accessed as a memory operand, i.e.
+ * the loaded variable is located at the dereferenced pointer rather than in a register.
+ */
+fn fold_x86_load(
+    x86: &mut X86Assembler,
+    insns: &[IrInstruction],
+    is_temporary: &dyn Fn(IrIdent) -> bool,
+    index: usize
+) -> bool {
+    if index == 0 {
+        return false;
+    }
+
+    let load = &insns[index - 1];
+    let insn = &insns[index];
+    let (ident, ptr) = match (&load.opcode, load.op1, load.op2) {
+        (IrOpcode::CopyFromDeref, IrOperand::Ident(ident), IrOperand::Ident(ptr)) => (ident, ptr),
+        _ => return false,
+    };
+
+    // NOTE(alexander): bytes are widened by some of the instructions, these are not folded
+    if load.ty != insn.ty || load.ty == IrType::I8 || !is_temporary(ident) {
+        return false;
+    }
+
+    let ptr_reg = match x86.local_variables.get(&ptr) {
+        Some(X86Operand::Register(reg)) => *reg,
+        _ => return false,
+    };
+
+    // The destination may be written before the memory is read, it cannot overwrite the pointer
+    let operands = match insn.opcode {
+        IrOpcode::Add |
+        IrOpcode::Sub |
+        IrOpcode::And |
+        IrOpcode::Or  |
+        IrOpcode::Xor |
+        IrOpcode::Mul => {
+            let dst = maybe_get_ir_ident(insn.op1).and_then(|dst| x86.local_variables.get(&dst));
+            if dst == Some(&X86Operand::Register(ptr_reg)) {
+                return false;
+            }
+            [insn.op2, insn.op3]
+        }
+
+        IrOpcode::Lt |
+        IrOpcode::Le |
+        IrOpcode::Gt |
+        IrOpcode::Ge |
+        IrOpcode::Eq |
+        IrOpcode::Ne => [insn.op2, insn.op3],
+
+        IrOpcode::IfLt |
+        IrOpcode::IfLe |
+        IrOpcode::IfGt |
+        IrOpcode::IfGe |
+        IrOpcode::IfEq |
+        IrOpcode::IfNe => [insn.op1, insn.op2],
+
+        _ => return false,
+    };
+
+    if !operands.contains(&IrOperand::Ident(ident)) {
+        return false;
+    }
+    x86.local_variables.insert(ident, X86Operand::Stack(ptr_reg, 0));
+    true
+}
+
+fn ir_integer_value(val: IrValue) -> Option<i64> {
+    match val {
+        IrValue::I32(v) => Some(v as i64),
+        IrValue::I64(v) => Some(v),
+        IrValue::U32(v) => Some(v as i64),
+        IrValue::U64(v) => Some(v as i64),
+        IrValue::Bool(_) => None,
+    }
+}
+
+/***************************************************************************
+ * Inline assembly
+ ***************************************************************************/
+
+/**
+ * Inline assembly parsed into x86 instructions, the registers are given per operand
+ * in the same order as the operands of the asm expression.
+ */
+struct X86InlineAsm {
+    instructions: Vec<X86Instruction>,
+    operands: Vec<X86Reg>,
+    clobbers: Vec<X86Reg>, // every register written by the template, including the outputs
+}
+
+const X86_REGISTERS: [X86Reg; 16] = [
+    X86Reg::RAX, X86Reg::RCX, X86Reg::RDX, X86Reg::RBX, X86Reg::RSP, X86Reg::RBP, X86Reg::RSI, X86Reg::RDI,
+    X86Reg::R8,  X86Reg::R9,  X86Reg::R10, X86Reg::R11, X86Reg::R12, X86Reg::R13, X86Reg::R14, X86Reg::R15,
+];
+
+/**
+ * Instructions that can be written in inline assembly, control flow is not supported
+ * since the template is not able to refer to labels.
+ */
+const X86_INLINE_ASM_OPCODES: [X86Opcode; 47] = [
+    X86Opcode::NOP,    X86Opcode::MOV,    X86Opcode::MOVABS, X86Opcode::MOVSX,  X86Opcode::LEA,
+    X86Opcode::ADD,    X86Opcode::SUB,    X86Opcode::IMUL,   X86Opcode::IDIV,   X86Opcode::DIV,
+    X86Opcode::AND,    X86Opcode::OR,     X86Opcode::XOR,    X86Opcode::SHR,    X86Opcode::CDQ,
+    X86Opcode::CQO,    X86Opcode::CMP,    X86Opcode::TEST,   X86Opcode::SETL,   X86Opcode::SETG,
+    X86Opcode::SETLE,  X86Opcode::SETGE,  X86Opcode::SETE,   X86Opcode::SETNE,  X86Opcode::SETB,
+    X86Opcode::SETA,   X86Opcode::SETBE,  X86Opcode::SETAE,  X86Opcode::CMOVL,  X86Opcode::CMOVG,
+    X86Opcode::CMOVLE, X86Opcode::CMOVGE, X86Opcode::CMOVE,  X86Opcode::CMOVNE, X86Opcode::CMOVB,
+    X86Opcode::CMOVA,  X86Opcode::CMOVBE, X86Opcode::CMOVAE, X86Opcode::PUSH,   X86Opcode::POP,
+    X86Opcode::INT3,   X86Opcode::SYSCALL, X86Opcode::RDTSC, X86Opcode::CPUID,  X86Opcode::JMP,
+    X86Opcode::CALL,   X86Opcode::RET,
+];
+
+/**
+ * Parses the template and registers of the inline assembly instruction. The template is
+ * written in Intel syntax with one instruction per line (or separated by `;`), e.g.
+ *
+ * asm!("mov eax, ecx", "add eax, dword ptr [rdx + 8]", in("ecx") a, in("rdx") p, out("eax") b)
+ *
+ * Every register that the template refers to or writes has to be an operand or clobbered,
+ * except rsp and rbp which are only allowed to be read (and adjusted by balanced push and pop).
+ */
+fn parse_x86_inline_asm(x86: &X86Assembler, insn: &IrInstruction) -> X86InlineAsm {
+    let asm = match &insn.asm {
+        Some(asm) => asm,
+        None => panic!("x86: expected inline assembly"),
+    };
+
+    // NOTE(alexander): the type checker reports these with source locations, see check_x86_inline_asm
+    match parse_x86_inline_asm_template(x86.x64_mode, asm) {
+        Ok(asm) => asm,
+        Err(message) => panic!("x86: {}", message),
+    }
+}
+
+/**
+ * Checks that the inline assembly is accepted by this backend, so that the errors
+ * can be reported by the type checker instead of during code generation.
+ */
+pub fn check_x86_inline_asm(asm_expr: &ExprAsm) -> Result<(), (Span, String)> {
+    let x64_mode = cfg!(target_arch="x86_64");
+    let registers = asm_expr.operands.iter().map(|operand| &operand.reg).chain(asm_expr.clobbers.iter());
+    for reg in registers {
+        parse_x86_asm_register(x64_mode, &reg.name).map_err(|message| (reg.span, message))?;
+    }
+
+    let asm = IrInlineAsm {
+        template: asm_expr.template.clone(),
+        operands: asm_expr.operands.iter().map(|operand| IrAsmOperand {
+            reg: operand.reg.name.clone(),
+            is_output: operand.dir != AsmDir::In,
+        }).collect(),
+        clobbers: asm_expr.clobbers.iter().map(|reg| reg.name.clone()).collect(),
+    };
+    match parse_x86_inline_asm_template(x64_mode, &asm) {
+        Ok(_) => Ok(()),
+        Err(message) => Err((asm_expr.span, message)),
+    }
+}
+
+/**
+ * Parses the template and registers of inline assembly, see parse_x86_inline_asm.
+ */
+fn parse_x86_inline_asm_template(x64_mode: bool, asm: &IrInlineAsm) -> Result<X86InlineAsm, String> {
+    let scratch_registers = x86_scratch_registers(x64_mode);
+    let mut operands = Vec::new();
+    let mut clobbers = Vec::new();
+    for operand in &asm.operands {
+        let reg = parse_x86_asm_register(x64_mode, &operand.reg)?;
+        if scratch_registers.contains(&reg) {
+            return Err(format!("`{}` is reserved by the backend and can only be clobbered", operand.reg));
+        }
+
+        // NOTE(alexander): use inout when the same register is both read and written
+        if operands.contains(&reg) {
+            return Err(format!("register `{}` is used by more than one inline assembly operand", operand.reg));
+        }
+
+        if operand.is_output {
+            clobbers.push(reg);
+        }
+        operands.push(reg);
+    }
+
+    for name in &asm.clobbers {
+        let reg = parse_x86_asm_register(x64_mode, name)?;
+        if !clobbers.contains(&reg) {
+            clobbers.push(reg);
+        }
+    }
+
+    let mut instructions = Vec::new();
+    for line in asm.template.split(&['\n', ';'][..]) {
+        let line = line.trim();
+        if !line.is_empty() {
+            instructions.push(parse_x86_asm_instruction(x64_mode, line)?);
+        }
+    }
+
+    // Check that the registers used by the template are declared
+    let is_frame_register = |reg: X86Reg| reg == X86Reg::RSP || reg == X86Reg::RBP;
+    for insn in &instructions {
+        for op in [insn.op1, insn.op2, insn.op3].iter().flatten() {
+            for reg in X86_REGISTERS.iter() {
+                if x86_operand_uses_register(*op, *reg) && !is_frame_register(*reg) &&
+                    !operands.contains(reg) && !clobbers.contains(reg) {
+                    return Err(format!("register `{}` is used by `{}` but is not an inline assembly operand or clobber",
+                                       reg, insn.opcode));
+                }
+            }
+        }
+
+        for reg in x86_written_registers(insn) {
+            if is_frame_register(reg) {
+                if insn.opcode != X86Opcode::PUSH && insn.opcode != X86Opcode::POP {
+                    return Err(format!("`{}` cannot be modified by inline assembly", reg));
+                }
+            } else if !clobbers.contains(&reg) {
+                return Err(format!("`{}` writes to `{}` which has to be an inline assembly output or clobber", insn.opcode, reg));
+            }
+        }
+    }
+
+    Ok(X86InlineAsm {
+        instructions,
+        operands,
+        clobbers,
+    })
+}
+
+/**
+ * Finds the register named by an inline assembly operand or clobber.
+ */
+fn parse_x86_asm_register(x64_mode: bool, name: &str) -> Result<X86Reg, String> {
+    match parse_x86_register(name) {
+        Some((reg, _)) if !x64_mode && is_reg_x64_only(reg) => {
+            Err(format!("register `{}` is only available in 64-bit mode", name))
+        }
+        Some((X86Reg::RSP, _)) | Some((X86Reg::RBP, _)) => {
+            Err(format!("`{}` is used by the stack frame and cannot be an inline assembly operand or clobber", name))
+        }
+        Some((reg, _)) => Ok(reg),
+        None => Err(format!("unknown register `{}` in inline assembly", name)),
+    }
+}
+
+/**
+ * Parses one line of the inline assembly template, the operand size is given by the
+ * registers or the `ptr` prefix of memory operands e.g. `add dword ptr [rcx], 1`.
+ */
+fn parse_x86_asm_instruction(x64_mode: bool, line: &str) -> Result<X86Instruction, String> {
+    let (mnemonic, rest) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+    };
+
+    let mnemonic = mnemonic.to_lowercase();
+    let mut opcode = match X86_INLINE_ASM_OPCODES.iter().find(|opcode| opcode.to_string() == mnemonic) {
+        Some(X86Opcode::JMP) | Some(X86Opcode::CALL) | Some(X86Opcode::RET) => {
+            return Err(format!("`{}` is not supported in inline assembly, the template cannot change the control flow", mnemonic))
+        }
+        Some(opcode) => *opcode,
+        None => return Err(format!("unknown instruction `{}` in inline assembly", mnemonic)),
+    };
+
+    let mut operands = Vec::new();
+    if !rest.is_empty() {
+        for op in rest.split(',') {
+            operands.push(parse_x86_asm_operand(x64_mode, op.trim(), line)?);
+        }
+    }
+
+    let num_operands = match opcode {
+        X86Opcode::NOP     |
+        X86Opcode::CDQ     |
+        X86Opcode::CQO     |
+        X86Opcode::INT3    |
+        X86Opcode::SYSCALL |
+        X86Opcode::RDTSC   |
+        X86Opcode::CPUID => 0..=0,
+        X86Opcode::IDIV    |
+        X86Opcode::DIV     |
+        X86Opcode::PUSH    |
+        X86Opcode::POP     |
+        X86Opcode::SETL    |
+        X86Opcode::SETG    |
+        X86Opcode::SETLE   |
+        X86Opcode::SETGE   |
+        X86Opcode::SETE    |
+        X86Opcode::SETNE   |
+        X86Opcode::SETB    |
+        X86Opcode::SETA    |
+        X86Opcode::SETBE   |
+        X86Opcode::SETAE => 1..=1,
+        X86Opcode::IMUL => 2..=3,
+        _ => 2..=2,
+    };
+    if !num_operands.contains(&operands.len()) {
+        return Err(format!("invalid number of operands to `{}` in `{}`", mnemonic, line));
+    }
+
+    // The operand size is decided by the destination if the operands have different sizes
+    let sizes: Vec<IrType> = operands.iter().filter_map(|(_, ty)| *ty).collect();
+    let ty = match opcode {
+        _ if operands.is_empty() => IrType::None,
+        X86Opcode::PUSH | X86Opcode::POP => IrType::I64,
+        X86Opcode::MOVSX | X86Opcode::LEA | X86Opcode::SHR => match operands[0].1 {
+            Some(ty) => ty,
+            None => return Err(format!("expected register as destination of `{}` in `{}`", mnemonic, line)),
+        }
+        _ => match sizes.first() {
+            Some(ty) if sizes.iter().all(|other| other == ty) => *ty,
+            Some(_) => return Err(format!("operand size mismatch in `{}`", line)),
+            None => return Err(format!("cannot infer the operand size of `{}`, use e.g. `dword ptr`", line)),
+        }
+    };
+
+    match opcode {
+        X86Opcode::MOVSX if operands[1].1.is_some() && operands[1].1 != Some(IrType::I8) => {
+            return Err(format!("expected byte source operand to `movsx` in `{}`", line))
+        }
+        X86Opcode::SETL | X86Opcode::SETG | X86Opcode::SETLE | X86Opcode::SETGE | X86Opcode::SETE |
+        X86Opcode::SETNE | X86Opcode::SETB | X86Opcode::SETA | X86Opcode::SETBE | X86Opcode::SETAE if ty != IrType::I8 => {
+            return Err(format!("expected byte operand to `{}` in `{}`", mnemonic, line))
+        }
+        _ => {}
+    }
+
+    let mut ops = operands.iter().map(|(op, _)| *op);
+    let op1 = ops.next();
+    let mut op2 = ops.next();
+    let op3 = ops.next();
+
+    for op in [op1, op2, op3].iter().flatten() {
+        if let (X86Operand::Indexed(_, _, _, _), false) = (op, opcode == X86Opcode::LEA) {
+            return Err(format!("indexed memory operands are only supported by `lea`, found `{}`", line));
+        }
+    }
+
+    // NOTE(alexander): immediates are sign-extended from 32-bit except moves into 64-bit registers
+    if let Some(X86Operand::Value(val)) = op2 {
+        let imm = x86_value_to_i64(val);
+        let fits_i32 = imm >= i32::MIN as i64 && imm <= i32::MAX as i64;
+        let is_quad = ty == IrType::I64;
+        if !fits_i32 && opcode == X86Opcode::MOV && is_quad {
+            opcode = X86Opcode::MOVABS;
+        } else if !fits_i32 && (is_quad || imm > u32::MAX as i64 || imm < i32::MIN as i64) {
+            return Err(format!("immediate `{}` does not fit in 32 bits in `{}`", imm, line));
+        } else if !fits_i32 {
+            op2 = Some(X86Operand::Value(X86Value::Int32(imm as u32 as i32)));
+        }
+    }
+
+    Ok(X86Instruction {
+        opcode,
+        ty,
+        op1,
+        op2,
+        op3,
+        volatile: true,
+        ..Default::default()
+    })
+}
+
+/**
+ * Parses a register, immediate or memory operand and its size if it is known.
+ */
+fn parse_x86_asm_operand(x64_mode: bool, op: &str, line: &str) -> Result<(X86Operand, Option<IrType>), String> {
+    let lower = op.to_lowercase();
+    if let Some((reg, ty)) = parse_x86_register(&lower) {
+        if !x64_mode && is_reg_x64_only(reg) {
+            return Err(format!("register `{}` is only available in 64-bit mode", lower));
+        }
+        return Ok((X86Operand::Register(reg), Some(ty)));
+    }
+
+    if let Some(imm) = parse_x86_immediate(&lower) {
+        let val = if imm >= i32::MIN as i64 && imm <= i32::MAX as i64 {
+            X86Value::Int32(imm as i32)
+        } else {
+            X86Value::Int64(imm)
+        };
+        return Ok((X86Operand::Value(val), None));
+    }
+
+    let (ty, mem) = if let Some(mem) = lower.strip_prefix("byte ptr") {
+        (Some(IrType::I8), mem.trim())
+    } else if let Some(mem) = lower.strip_prefix("dword ptr") {
+        (Some(IrType::I32), mem.trim())
+    } else if let Some(mem) = lower.strip_prefix("qword ptr") {
+        (Some(IrType::I64), mem.trim())
+    } else {
+        (None, lower.as_str())
+    };
+
+    let inner = match mem.strip_prefix('[').and_then(|mem| mem.strip_suffix(']')) {
+        Some(inner) => inner.replace(' ', ""),
+        None => return Err(format!("invalid operand `{}` in `{}`", op, line)),
+    };
+
+    // Memory operands are written as `[base + index*scale + disp]`, where every part is optional
+    let mut base: Option<X86Reg> = None;
+    let mut index: Option<(X86Reg, u8)> = None;
+    let mut disp: i64 = 0;
+    let addr_ty = if x64_mode { IrType::I64 } else { IrType::I32 };
+    let to_address_register = |name: &str| match parse_x86_register(name) {
+        Some((reg, ty)) if ty == addr_ty => Ok(Some(reg)),
+        Some(_) => Err(format!("expected {}-bit address register, found `{}` in `{}`", if x64_mode { 64 } else { 32 }, name, line)),
+        None => Ok(None),
+    };
+
+    for term in inner.replace('-', "+-").split('+').filter(|term| !term.is_empty()) {
+        if let Some((lhs, rhs)) = term.split_once('*') {
+            let (reg, scale) = match (to_address_register(lhs)?, to_address_register(rhs)?) {
+                (Some(reg), None) => (reg, rhs),
+                (None, Some(reg)) => (reg, lhs),
+                _ => return Err(format!("invalid index `{}` in `{}`", term, line)),
+            };
+            match parse_x86_immediate(scale) {
+                Some(scale @ (1 | 2 | 4 | 8)) if index.is_none() => index = Some((reg, scale as u8)),
+                _ => return Err(format!("invalid index `{}` in `{}`", term, line)),
+            }
+        } else if let Some(reg) = to_address_register(term)? {
+            if base.is_none() {
+                base = Some(reg);
+            } else if index.is_none() {
+                index = Some((reg, 1));
+            } else {
+                return Err(format!("too many registers in memory operand `{}`", op));
+            }
+        } else if let Some(imm) = parse_x86_immediate(term) {
+            disp = disp.wrapping_add(imm);
+        } else {
+            return Err(format!("invalid memory operand `{}` in `{}`", op, line));
+        }
+    }
+
+    if disp < i32::MIN as i64 || disp > i32::MAX as i64 {
+        return Err(format!("displacement `{}` does not fit in 32 bits in `{}`", disp, line));
+    }
+
+    let operand = match (base, index) {
+        (Some(base), None) => X86Operand::Stack(base, disp as isize),
+        (Some(base), Some((index, scale))) => X86Operand::Indexed(base, index, scale, disp as isize),
+        _ => return Err(format!("memory operand `{}` requires a base register", op)),
+    };
+    Ok((operand, ty))
+}
+
+/**
+ * Index of the inline assembly operand that is moved by asm_in or asm_out.
+ */
+fn ir_asm_operand_index(insn: &IrInstruction) -> usize {
+    match insn.op2 {
+        IrOperand::Value(IrValue::I32(index)) => index as usize,
+        _ => panic!("x86: expected inline assembly operand index"),
+    }
+}
+
+/**
+ * Finds the register and its size by name, e.g. `ecx` is the 32-bit part of rcx.
+ */
+fn parse_x86_register(name: &str) -> Option<(X86Reg, IrType)> {
+    for reg in X86_REGISTERS.iter() {
+        for ty in [IrType::I8, IrType::I32, IrType::I64].iter() {
+            if sized_register_name(*reg, *ty) == name {
+                return Some((*reg, *ty));
+            }
+        }
+    }
+    None
+}
+
+fn parse_x86_immediate(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None if !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit()) => digits.parse::<u64>().ok()?,
+        None => return None,
+    };
+
+    let value = value as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+/**
+ * Registers written by the instruction, including the registers written implicitly.
+ */
+fn x86_written_registers(insn: &X86Instruction) -> Vec<X86Reg> {
+    let mut written = match insn.opcode {
+        X86Opcode::IDIV | X86Opcode::DIV => vec![X86Reg::RAX, X86Reg::RDX],
+        X86Opcode::CDQ | X86Opcode::CQO => vec![X86Reg::RDX],
+        X86Opcode::RDTSC => vec![X86Reg::RAX, X86Reg::RDX],
+        X86Opcode::CPUID => vec![X86Reg::RAX, X86Reg::RBX, X86Reg::RCX, X86Reg::RDX],
+        X86Opcode::SYSCALL => vec![X86Reg::RAX, X86Reg::RCX, X86Reg::R11],
+        X86Opcode::PUSH => vec![X86Reg::RSP],
+        X86Opcode::POP => vec![X86Reg::RSP],
+        _ => Vec::new(),
+    };
+
+    let writes_destination = !matches!(insn.opcode,
+        X86Opcode::CMP | X86Opcode::TEST | X86Opcode::PUSH | X86Opcode::IDIV | X86Opcode::DIV);
+    if let (true, Some(X86Operand::Register(reg))) = (writes_destination, insn.op1) {
+        written.push(reg);
+    }
+    written
+}
+
+/***************************************************************************
+ * Instruction selection helpers
+ ***************************************************************************/
+
+/**
+ * Selects a two operand instruction, operand combinations that cannot be encoded
+ * e.g. memory to memory go through the second scratch register.
+ */
 fn push_instruction(x86: &mut X86Assembler, opcode: X86Opcode, ty: IrType, dst: X86Operand, src: X86Operand) {
-    let opcode_offset = match ty {
-        IrType::I8 => 1,
-        _ => 0,
+    match (dst, src) {
+        (X86Operand::Stack(_, _), X86Operand::Stack(_, _)) if opcode != X86Opcode::LEA => {
+            // Move first source into auxiliary register
+            let aux_src = X86Operand::Register(x86.scratch_registers[1]);
+            push_x86_instruction(x86, X86Instruction {
+                opcode: X86Opcode::MOV,
+                ty,
+                op1: Some(aux_src),
+                op2: Some(src),
+                ..Default::default()
+            });
+
+            // Now try again, next time it should enter another case
+            push_instruction(x86, opcode, ty, dst, aux_src);
+        }
+
+        (X86Operand::Value(_), _) => {
+            // NOTE(alexander): should only be used by CMP and TEST instructions, e.g. MOV makes no sense here!
+            // Move first destination into auxiliary register, the second one is needed if the source is a 64-bit immediate.
+            let aux_dst = if is_x86_imm64(x86, ty, src) {
+                X86Operand::Register(x86.scratch_registers[0])
+            } else {
+                X86Operand::Register(x86.scratch_registers[1])
+            };
+            push_x86_instruction(x86, X86Instruction {
+                opcode: X86Opcode::MOV,
+                ty,
+                op1: Some(aux_dst),
+                op2: Some(dst),
+                ..Default::default()
+            });
+
+            // Now try again, next time it should enter another case
+            push_instruction(x86, opcode, ty, aux_dst, src);
+        }
+
+        (X86Operand::Register(_), X86Operand::Value(_)) if opcode == X86Opcode::MOV && is_x86_imm64(x86, ty, src) => {
+            // movabs reg, imm64
+            push_x86_instruction(x86, X86Instruction {
+                opcode: X86Opcode::MOVABS,
+                ty,
+                op1: Some(dst),
+                op2: Some(src),
+                ..Default::default()
+            });
+        }
+
+        (_, X86Operand::Value(_)) if opcode != X86Opcode::MOVABS && is_x86_imm64(x86, ty, src) => {
+            // NOTE(alexander): other instructions only take sign-extended 32-bit immediates
+            let aux_src = X86Operand::Register(x86.scratch_registers[1]);
+            push_x86_instruction(x86, X86Instruction {
+                opcode: X86Opcode::MOVABS,
+                ty,
+                op1: Some(aux_src),
+                op2: Some(src),
+                ..Default::default()
+            });
+
+            push_instruction(x86, opcode, ty, dst, aux_src);
+        }
+
+        _ => {
+            push_x86_instruction(x86, X86Instruction {
+                opcode,
+                ty,
+                op1: Some(dst),
+                op2: Some(src),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+fn push_unary_instruction(x86: &mut X86Assembler, opcode: X86Opcode, ty: IrType, op: X86Operand) {
+    push_x86_instruction(x86, X86Instruction {
+        opcode,
+        ty,
+        op1: Some(op),
+        ..Default::default()
+    });
+}
+
+fn push_opcode_instruction(x86: &mut X86Assembler, opcode: X86Opcode) {
+    push_x86_instruction(x86, X86Instruction {
+        opcode,
+        ..Default::default()
+    });
+}
+
+fn push_label(x86: &mut X86Assembler, ident: IrIdent) {
+    push_x86_instruction(x86, X86Instruction {
+        opcode: X86Opcode::LABEL,
+        label: Some(ident),
+        ..Default::default()
+    });
+}
+
+/**
+ * Selects a jump or call to the label, the distance is calculated after the entire program is encoded.
+ */
+fn push_relative_jump(x86: &mut X86Assembler, ident: IrIdent, opcode: X86Opcode) {
+    push_x86_instruction(x86, X86Instruction {
+        opcode,
+        label: Some(ident),
+        ..Default::default()
+    });
+}
+
+#[inline]
+fn push_x86_instruction(x86: &mut X86Assembler, insn: X86Instruction) {
+    x86.instructions.push(insn);
+}
+
+/**
+ * Moves the operand into the register, bytes are sign-extended to 32-bit since imul and idiv
+ * are used without byte forms. Returns the type the value has in the register.
+ */
+fn push_widening_move(x86: &mut X86Assembler, ty: IrType, reg: X86Reg, src: X86Operand) -> IrType {
+    let dst = X86Operand::Register(reg);
+    match (ty, src) {
+        (IrType::I8, X86Operand::Value(val)) => {
+            let val = X86Operand::Value(X86Value::Int32(x86_value_to_i64(val) as i32));
+            push_instruction(x86, X86Opcode::MOV, IrType::I32, dst, val);
+            IrType::I32
+        }
+
+        (IrType::I8, _) => {
+            push_instruction(x86, X86Opcode::MOVSX, IrType::I32, dst, src);
+            IrType::I32
+        }
+
+        _ => {
+            if src != dst {
+                push_instruction(x86, X86Opcode::MOV, ty, dst, src);
+            }
+            ty
+        }
+    }
+}
+
+/**
+ * Selects the comparison of a conditional jump or comparison instruction,
+ * returns the jump that is taken if the condition holds.
+ */
+fn push_comparison(x86: &mut X86Assembler, insn: &IrInstruction) -> X86Opcode {
+    let (lhs, rhs) = match insn.opcode {
+        IrOpcode::Lt |
+        IrOpcode::Le |
+        IrOpcode::Gt |
+        IrOpcode::Ge |
+        IrOpcode::Eq |
+        IrOpcode::Ne => (insn.op2, insn.op3),
+        _ => (insn.op1, insn.op2),
+    };
+    let lhs = to_x86_operand(x86, lhs);
+    let rhs = to_x86_operand(x86, rhs);
+    push_instruction(x86, X86Opcode::CMP, insn.ty, lhs, rhs);
+
+    match (&insn.opcode, is_unsigned_ir_type(insn.ty)) {
+        (IrOpcode::IfLt, false) | (IrOpcode::Lt, false) => X86Opcode::JL,
+        (IrOpcode::IfLe, false) | (IrOpcode::Le, false) => X86Opcode::JLE,
+        (IrOpcode::IfGt, false) | (IrOpcode::Gt, false) => X86Opcode::JG,
+        (IrOpcode::IfGe, false) | (IrOpcode::Ge, false) => X86Opcode::JGE,
+        (IrOpcode::IfLt, true)  | (IrOpcode::Lt, true)  => X86Opcode::JB,
+        (IrOpcode::IfLe, true)  | (IrOpcode::Le, true)  => X86Opcode::JBE,
+        (IrOpcode::IfGt, true)  | (IrOpcode::Gt, true)  => X86Opcode::JA,
+        (IrOpcode::IfGe, true)  | (IrOpcode::Ge, true)  => X86Opcode::JAE,
+        (IrOpcode::IfEq, _)     | (IrOpcode::Eq, _)     => X86Opcode::JE,
+        (IrOpcode::IfNe, _)     | (IrOpcode::Ne, _)     => X86Opcode::JNE,
+        _ => panic!("x86: expected comparison instruction"),
+    }
+}
+
+/**
+ * Returns true if the operand is an immediate that cannot be encoded as a sign-extended
+ * 32-bit immediate of a 64-bit instruction, only `movabs` can encode these.
+ */
+fn is_x86_imm64(x86: &X86Assembler, ty: IrType, op: X86Operand) -> bool {
+    match op {
+        X86Operand::Value(val) => {
+            let imm = x86_value_to_i64(val);
+            x86.x64_mode && size_of_ir_type(ty, x86.addr_size) == 8 && (imm < i32::MIN as i64 || imm > i32::MAX as i64)
+        }
+        _ => false,
+    }
+}
+
+#[inline]
+fn is_unsigned_ir_type(ty: IrType) -> bool {
+    matches!(ty, IrType::U32 | IrType::U64)
+}
+
+/**
+ * Creates a label local to the backend, used by instructions that are selected as loops.
+ */
+fn create_x86_label(x86: &mut X86Assembler) -> IrIdent {
+    let label = create_ir_ident(x86.local_label_symbol, x86.local_label_index);
+    x86.local_label_index += 1;
+    label
+}
+
+/**
+ * Calls between generated functions, the first four arguments are passed in the windows
+ * argument registers and the rest on the stack without reserving any shadow space.
+ */
+fn internal_calling_convention(x86: &mut X86Assembler) -> X86Operand {
+    push_call_arguments(x86, &X86_WINDOWS_ARGUMENT_REGISTERS, 0)
+}
+
+fn windows_calling_convention(x86: &mut X86Assembler) -> X86Operand {
+    push_call_arguments(x86, &X86_WINDOWS_ARGUMENT_REGISTERS, X86_WINDOWS_SHADOW_SPACE)
+}
+
+fn sysv64_calling_convention(x86: &mut X86Assembler) -> X86Operand {
+    push_call_arguments(x86, &X86_SYSV_ARGUMENT_REGISTERS, 0)
+}
+
+/**
+ * Moves the arguments into the argument registers followed by the stack, the stack arguments
+ * are stored first since the register moves may overwrite registers they are read from.
+ * Stack arguments are stored left-to-right in 8-byte slots starting at `[rsp + shadow_space]`,
+ * the stack frame reserves space for them and keeps rsp 16-byte aligned at the call.
+ */
+fn push_call_arguments(x86: &mut X86Assembler, argument_registers: &[X86Reg], shadow_space: isize) -> X86Operand {
+    let mut arg_moves: Vec<(X86Operand, X86Operand, IrType)> = Vec::new();
+    for dst_reg in argument_registers {
+        if let Some((op, ty)) = x86.argument_stack.pop_front() {
+            let src_op = to_x86_operand(x86, op);
+            arg_moves.push((X86Operand::Register(*dst_reg), src_op, ty));
+        } else {
+            break;
+        }
+    }
+
+    let mut stack_offset = shadow_space;
+    while let Some((op, ty)) = x86.argument_stack.pop_front() {
+        let src_op = to_x86_operand(x86, op);
+        push_instruction(x86, X86Opcode::MOV, ty, X86Operand::Stack(X86Reg::RSP, stack_offset), src_op);
+        stack_offset += x86.addr_size;
+    }
+    if stack_offset > x86.outgoing_arguments_size {
+        x86.outgoing_arguments_size = stack_offset;
+    }
+
+    push_parallel_moves(x86, arg_moves);
+    X86Operand::Register(X86Reg::RAX)
+}
+
+/**
+ * Performs the moves as if they all happened at the same time, i.e. a register is not
+ * overwritten until every move reading from it is done. Cycles are broken by
+ * saving one of the registers in the first scratch register.
+ */
+fn push_parallel_moves(x86: &mut X86Assembler, mut moves: Vec<(X86Operand, X86Operand, IrType)>) {
+    moves.retain(|(dst, src, _)| dst != src);
+
+    while !moves.is_empty() {
+        let is_read = |moves: &Vec<(X86Operand, X86Operand, IrType)>, dst: X86Operand| {
+            if let X86Operand::Register(_) = dst {
+                moves.iter().any(|(_, src, _)| *src == dst)
+            } else {
+                false
+            }
+        };
+
+        match moves.iter().position(|(dst, _, _)| !is_read(&moves, *dst)) {
+            Some(index) => {
+                let (dst, src, ty) = moves.remove(index);
+                push_instruction(x86, X86Opcode::MOV, ty, dst, src);
+            }
+
+            None => {
+                // Every destination is read by another move, break the cycle
+                let (dst, _, ty) = moves[0];
+                let scratch = X86Operand::Register(x86.scratch_registers[0]);
+                push_instruction(x86, X86Opcode::MOV, ty, scratch, dst);
+                for (_, src, _) in moves.iter_mut() {
+                    if *src == dst {
+                        *src = scratch;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn to_x86_operand(x86: &mut X86Assembler, op: IrOperand) -> X86Operand {
+    match op {
+        IrOperand::Ident(ident) => match x86.local_variables.get(&ident) {
+            Some(operand) => *operand,
+            None => panic!("x86: `{}` has not been assigned a register or stack slot", ident),
+        }
+
+        IrOperand::Value(value) => match value {
+            IrValue::I32(v)  => X86Operand::Value(X86Value::Int32(v)),
+            IrValue::I64(v)  => X86Operand::Value(X86Value::Int64(v)),
+            IrValue::U32(v)  => X86Operand::Value(X86Value::Int32(v as i32)),
+            IrValue::U64(v)  => X86Operand::Value(X86Value::Int64(v as i64)),
+            IrValue::Bool(v) => X86Operand::Value(X86Value::Int8(v as i8)),
+        }
+
+        IrOperand::None => panic!("x86: unexpected empty operand"),
+    }
+}
+
+/**
+ * Memory operand addressing the value computed by lea, the variables are all stored in registers.
+ */
+fn to_x86_address(x86: &mut X86Assembler, addr: X86Address) -> X86Operand {
+    let to_register = |x86: &mut X86Assembler, ident: IrIdent| match to_x86_operand(x86, IrOperand::Ident(ident)) {
+        X86Operand::Register(reg) => reg,
+        _ => panic!("x86: `{}` has to be stored in a register to be used as address", ident),
     };
 
-    match (dst, src) {
-        (X86Operand::Stack(sreg1, disp1), X86Operand::Stack(sreg2, disp2)) => {
-            // Move first source into auxiliary register
-            let reg = allocate_register(x86, None);
-            push_rex_prefix(x86, None, None, ty);
-            x86.machine_code.push(get_rm_opcode(X86Opcode::MOV, opcode_offset));
-            x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(sreg2), disp2));
-            push_displacement(x86, disp2);
-            print_instruction(x86, X86Opcode::MOV, ty, X86Operand::Register(reg), false, src, false);
-
-            // Move the auxiliary register to the destination
-            push_rex_prefix(x86, None, None, ty);
-            x86.machine_code.push(get_mr_opcode(opcode, opcode_offset));
-            x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(sreg1), disp1));
-            push_displacement(x86, disp1);
-            print_instruction(x86, opcode, ty, dst, false, X86Operand::Register(reg), false);
+    let base = to_register(x86, addr.base.unwrap());
+    match addr.index {
+        Some(index) => {
+            let index = to_register(x86, index);
+            X86Operand::Indexed(base, index, addr.scale, addr.disp as isize)
+        }
+        None => X86Operand::Stack(base, addr.disp as isize),
+    }
+}
+
+fn to_ref_type(ty: IrType) -> IrType {
+    match ty {
+        IrType::I8 => IrType::PtrI8(1),
+        IrType::I32 => IrType::PtrI32(1),
+        IrType::I64 => IrType::PtrI64(1),
+        IrType::U64 => IrType::PtrU64(1),
+        IrType::PtrI8(i) => IrType::PtrI8(i + 1),
+        IrType::PtrI32(i) => IrType::PtrI32(i + 1),
+        IrType::PtrI64(i) => IrType::PtrI64(i + 1),
+        IrType::PtrU64(i) => IrType::PtrU64(i + 1),
+        // NOTE(alexander): only the size of the pointer matters to the x86 backend
+        IrType::U32 | IrType::None => IrType::PtrI32(1),
+    }
+}
+
+/**
+ * Operands that are not already stored in a register are moved into the given scratch register.
+ */
+fn move_operand_to_register(x86: &mut X86Assembler, op: X86Operand, ty: IrType, scratch: X86Reg) -> X86Reg {
+    if let X86Operand::Register(reg) = op {
+        reg
+    } else {
+        push_instruction(x86, X86Opcode::MOV, ty, X86Operand::Register(scratch), op);
+        scratch
+    }
+}
+
+/**
+ * The register to compute a result in, destinations that are spilled are computed
+ * in the scratch register and stored afterwards using `push_store_register`.
+ */
+#[inline]
+fn register_or_scratch(dst: X86Operand, scratch: X86Reg) -> X86Reg {
+    if let X86Operand::Register(reg) = dst {
+        reg
+    } else {
+        scratch
+    }
+}
+
+fn push_store_register(x86: &mut X86Assembler, dst: X86Operand, reg: X86Reg, ty: IrType) {
+    let src = X86Operand::Register(reg);
+    if dst != src {
+        push_instruction(x86, X86Opcode::MOV, ty, dst, src);
+    }
+}
+
+fn move_operand_to_stack(x86: &mut X86Assembler, op: X86Operand, ty: IrType) -> (isize, X86Operand) {
+    if let X86Operand::Stack(_, disp) = op {
+        (disp, op)
+    } else {
+        x86.curr_stack_offset -= size_of_ir_type(ty, x86.addr_size);
+        let stack_op = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
+        push_instruction(x86, X86Opcode::MOV, ty, stack_op, op);
+        (x86.curr_stack_offset, stack_op)
+    }
+}
+
+#[inline]
+fn get_ir_ident(op: IrOperand) -> IrIdent {
+    if let IrOperand::Ident(ident) = op {
+        ident
+    } else {
+        panic!("x86: expected an identifier as operand")
+    }
+}
+
+#[inline]
+fn maybe_get_ir_ident(op: IrOperand) -> Option<IrIdent> {
+    if let IrOperand::Ident(ident) = op {
+        Some(ident)
+    } else {
+        None
+    }
+}
+
+/***************************************************************************
+ * Peephole optimizer
+ ***************************************************************************/
+
+/**
+ * How an instruction accesses a register or the flags, writes that only
+ * modify part of a register (e.g. `mov al, 1`) are not considered writes.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum X86Access {
+    Read,
+    Write,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum X86Resource {
+    Register(X86Reg),
+    Flags,
+}
+
+/**
+ * Rewrites the selected instructions of a function into shorter sequences
+ * doing the same thing, the patterns are applied until none of them matches.
+ */
+fn optimize_x86_instructions(insns: &mut Vec<X86Instruction>) {
+    loop {
+        let mut changed = false;
+        changed |= remove_redundant_moves(insns);
+        changed |= thread_jumps(insns);
+        changed |= remove_unreachable_instructions(insns);
+        changed |= fuse_setcc_branches(insns);
+        changed |= coalesce_move_operation_move(insns);
+        changed |= zero_registers_using_xor(insns);
+        if !changed {
+            break;
+        }
+    }
+}
+
+/**
+ * Removes moves of a register to itself and replaces reloads of a stack slot
+ * that was just stored with a register move (or nothing if it is the same register).
+ */
+fn remove_redundant_moves(insns: &mut Vec<X86Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < insns.len() {
+        let insn = insns[i];
+        if insn.opcode != X86Opcode::MOV || insn.volatile {
+            i += 1;
+            continue;
+        }
+
+        // mov reg, reg
+        // NOTE(alexander): 32-bit moves also clears the upper half, nothing depends on this.
+        if let (Some(X86Operand::Register(dst)), Some(X86Operand::Register(src))) = (insn.op1, insn.op2) {
+            if dst == src {
+                insns.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+
+        if i + 1 < insns.len() && insns[i + 1].opcode == X86Opcode::MOV && insns[i + 1].ty == insn.ty && !insns[i + 1].volatile {
+            let next = insns[i + 1];
+            match (insn.op1, insn.op2, next.op1, next.op2) {
+                // mov [mem], reg1; mov reg2, [mem]
+                (Some(mem @ X86Operand::Stack(base, _)), Some(X86Operand::Register(src)),
+                 Some(X86Operand::Register(dst)), Some(next_src)) if mem == next_src && base != src => {
+                    if dst == src {
+                        insns.remove(i + 1);
+                    } else {
+                        insns[i + 1].op2 = Some(X86Operand::Register(src));
+                    }
+                    changed = true;
+                    continue;
+                }
+
+                // mov reg, [mem]; mov [mem], reg
+                (Some(X86Operand::Register(dst)), Some(mem @ X86Operand::Stack(base, _)),
+                 Some(next_dst), Some(X86Operand::Register(src))) if mem == next_dst && dst == src && base != dst => {
+                    insns.remove(i + 1);
+                    changed = true;
+                    continue;
+                }
 
-            free_register(x86, reg);
+                _ => {},
+            }
         }
 
-        (X86Operand::Stack(sreg, disp), X86Operand::Register(reg)) => {
-            push_rex_prefix(x86, Some(reg), None, ty);
-            x86.machine_code.push(get_mr_opcode(opcode, opcode_offset));
-            x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(sreg), disp));
-            push_displacement(x86, disp);
-            print_instruction(x86, opcode, ty, dst, false, src, false);
+        i += 1;
+    }
+    changed
+}
+
+/**
+ * Jumps to another jump are redirected to its target and jumps to the instruction
+ * directly following it are removed.
+ */
+fn thread_jumps(insns: &mut Vec<X86Instruction>) -> bool {
+    let mut changed = false;
+    let mut labels = x86_label_positions(insns);
+    let mut i = 0;
+    while i < insns.len() {
+        if !is_x86_jump(insns[i].opcode) {
+            i += 1;
+            continue;
         }
 
-        (X86Operand::Stack(sreg, disp), X86Operand::Value(val)) => {
-            let (opcode_byte, opcode_reg) = get_mi_opcode(opcode, opcode_offset);
-            push_rex_prefix(x86, None, None, ty);
-            x86.machine_code.push(opcode_byte);
-            x86.machine_code.push(modrm_disp(opcode_reg, reg_id(sreg), disp));
-            push_displacement(x86, disp);
-            push_immediate(x86, val);
-            print_instruction(x86, opcode, ty, dst, false, src, false);
+        // Follow the chain of unconditional jumps, stopping at cycles
+        let mut target = insns[i].label.unwrap();
+        let mut visited = HashSet::new();
+        visited.insert(target);
+        while let Some(&pos) = labels.get(&target) {
+            match insns[pos..].iter().find(|insn| insn.opcode != X86Opcode::LABEL) {
+                Some(X86Instruction { opcode: X86Opcode::JMP, label: Some(next), .. }) if visited.insert(*next) => {
+                    target = *next;
+                }
+                _ => break,
+            }
         }
 
-        (X86Operand::Register(reg), X86Operand::Stack(sreg, disp)) => {
-            push_rex_prefix(x86, Some(reg), None, ty);
-            x86.machine_code.push(get_rm_opcode(opcode, opcode_offset));
-            x86.machine_code.push(modrm_disp(reg_id(reg), reg_id(sreg), disp));
-            push_displacement(x86, disp);
-            print_instruction(x86, opcode, ty, dst, false, src, false);
+        if insns[i].label != Some(target) {
+            insns[i].label = Some(target);
+            changed = true;
         }
 
-        (X86Operand::Register(reg1), X86Operand::Register(reg2)) => {
-            push_rex_prefix(x86, Some(reg1), Some(reg2), ty);
-            x86.machine_code.push(get_rm_opcode(opcode, opcode_offset));
-            x86.machine_code.push(modrm(reg_id(reg1), reg_id(reg2)));
-            print_instruction(x86, opcode, ty, dst, false, src, false);
+        // jmp .L; .L:
+        let falls_through = insns[i + 1..].iter()
+            .take_while(|insn| insn.opcode == X86Opcode::LABEL)
+            .any(|insn| insn.label == Some(target));
+        if falls_through {
+            insns.remove(i);
+            labels = x86_label_positions(insns);
+            changed = true;
+            continue;
         }
 
-        (X86Operand::Register(reg), X86Operand::Value(val)) => {
-            let (opcode_byte, opcode_reg) = get_mi_opcode(opcode, opcode_offset);
-            push_rex_prefix(x86, None, Some(reg), ty);
-            x86.machine_code.push(opcode_byte);
-            x86.machine_code.push(modrm(opcode_reg, reg_id(reg)));
-            push_immediate(x86, val);
-            print_instruction(x86, opcode, ty, dst, false, src, false);
+        i += 1;
+    }
+    changed
+}
+
+/**
+ * Removes instructions following an unconditional jump or return until the next
+ * label that is jumped to, other labels are also removed (except the function label).
+ */
+fn remove_unreachable_instructions(insns: &mut Vec<X86Instruction>) -> bool {
+    let targets: HashSet<IrIdent> = insns.iter()
+        .filter(|insn| is_x86_jump(insn.opcode))
+        .filter_map(|insn| insn.label)
+        .collect();
+
+    let len = insns.len();
+    let mut reachable = true;
+    let mut index = 0;
+    insns.retain(|insn| {
+        let is_first = index == 0;
+        index += 1;
+        if insn.opcode == X86Opcode::LABEL {
+            if is_first || targets.contains(&insn.label.unwrap()) {
+                reachable = true;
+                return true;
+            }
+            return false;
         }
 
-        (X86Operand::Value(val), _) => {
-            // NOTE(alexander): should only be used by CMP and TEST instructions, e.g. MOV makes no sense here!
-            // Move first destination into auxiliary register
-            let reg = allocate_register(x86, None);
-            let aux_dst = X86Operand::Register(reg);
-            let (opcode_byte, opcode_reg) = get_mi_opcode(X86Opcode::MOV, opcode_offset);
-            push_rex_prefix(x86, None, Some(reg), ty);
-            x86.machine_code.push(opcode_byte);
-            x86.machine_code.push(modrm(opcode_reg, reg_id(reg)));
-            push_immediate(x86, val);
-            print_instruction(x86, X86Opcode::MOV, ty, aux_dst, false, dst, false);
+        let keep = reachable;
+        if let X86Opcode::JMP | X86Opcode::RET = insn.opcode {
+            reachable = false;
+        }
+        keep
+    });
+    insns.len() != len
+}
 
-            // Now try again, next time it should enter another case
-            push_instruction(x86, opcode, ty, aux_dst, src);
+/**
+ * Boolean conditions are computed using setcc and branched on by comparing it with zero,
+ * the jump is instead made directly on the original comparison:
+ *
+ * cmp   eax, ecx          cmp   eax, ecx
+ * setl  dl           =>   jge   .L
+ * cmp   dl, 0
+ * je    .L
+ *
+ * Conditional moves are fused in the same way, these may be preceded by moves of the
+ * selected values since they do not modify the flags. The setcc is only kept if the
+ * boolean is used afterwards.
+ */
+fn fuse_setcc_branches(insns: &mut Vec<X86Instruction>) -> bool {
+    let mut changed = false;
+    let mut labels = x86_label_positions(insns);
+    let mut i = 0;
+    while i + 2 < insns.len() {
+        let setcc = insns[i];
+        let cmp = insns[i + 1];
+        if setcc.volatile || cmp.volatile {
+            i += 1;
+            continue;
+        }
 
-            free_register(x86, reg);
+        let cc = match setcc.opcode {
+            X86Opcode::SETL  => X86Opcode::JL,
+            X86Opcode::SETLE => X86Opcode::JLE,
+            X86Opcode::SETG  => X86Opcode::JG,
+            X86Opcode::SETGE => X86Opcode::JGE,
+            X86Opcode::SETE  => X86Opcode::JE,
+            X86Opcode::SETNE => X86Opcode::JNE,
+            X86Opcode::SETB  => X86Opcode::JB,
+            X86Opcode::SETBE => X86Opcode::JBE,
+            X86Opcode::SETA  => X86Opcode::JA,
+            X86Opcode::SETAE => X86Opcode::JAE,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let is_zero_test = cmp.ty == setcc.ty && match (cmp.opcode, cmp.op1, cmp.op2) {
+            (X86Opcode::CMP, Some(op), Some(X86Operand::Value(val))) => op == setcc.op1.unwrap() && x86_value_to_i64(val) == 0,
+            (X86Opcode::TEST, Some(op @ X86Operand::Register(_)), Some(other)) => op == setcc.op1.unwrap() && op == other,
+            _ => false,
+        };
+
+        // Skip the moves in between, none of these modify the flags
+        let mut j = i + 2;
+        while j < insns.len() && matches!(insns[j].opcode, X86Opcode::MOV | X86Opcode::MOVSX | X86Opcode::MOVABS) {
+            j += 1;
+        }
+
+        let opcode = match insns.get(j).map(|insn| insn.opcode) {
+            Some(X86Opcode::JNE) if j == i + 2 => cc,
+            Some(X86Opcode::JE) if j == i + 2  => negate_x86_jump(cc),
+            Some(X86Opcode::CMOVNE) => x86_jump_to_cmov(cc),
+            Some(X86Opcode::CMOVE)  => x86_jump_to_cmov(negate_x86_jump(cc)),
+            _ => X86Opcode::NOP,
+        };
+
+        if !is_zero_test || opcode == X86Opcode::NOP || insns[j].volatile ||
+            is_x86_live_after(insns, &labels, j, X86Resource::Flags) {
+            i += 1;
+            continue;
         }
+
+        insns[j].opcode = opcode;
+        insns.remove(i + 1);
+        labels = x86_label_positions(insns);
+        changed = true;
     }
-}
 
-fn windows_calling_convention(x86: &mut X86Assembler) -> X86Operand {
-    let dst_reg: [X86Reg; 4] = [X86Reg::RCX, X86Reg::RDX, X86Reg::R8, X86Reg::R9];
-    for i in 0..4 {
-        if let Some((op, ty)) = x86.argument_stack.pop_front() {
-            let src_op = to_x86_operand(x86, op, ty);
-            let dst_op = X86Operand::Register(dst_reg[i]);
-            push_instruction(x86, X86Opcode::MOV, ty, dst_op, src_op);
-        } else {
-            break;
+    // Remove the setcc instructions whose boolean is no longer used
+    let mut i = 0;
+    while i < insns.len() {
+        let is_setcc = matches!(insns[i].opcode, X86Opcode::SETL | X86Opcode::SETLE | X86Opcode::SETG |
+                                X86Opcode::SETGE | X86Opcode::SETE | X86Opcode::SETNE | X86Opcode::SETB |
+                                X86Opcode::SETBE | X86Opcode::SETA | X86Opcode::SETAE);
+        if let (true, false, Some(X86Operand::Register(reg))) = (is_setcc, insns[i].volatile, insns[i].op1) {
+            if !is_x86_live_after(insns, &labels, i, X86Resource::Register(reg)) {
+                insns.remove(i);
+                labels = x86_label_positions(insns);
+                changed = true;
+                continue;
+            }
         }
+        i += 1;
     }
+    changed
+}
+
+/**
+ * Operations computed in a temporary register that is only moved into the destination
+ * are computed in the destination directly:
+ *
+ * mov   eax, ebx          mov   ecx, ebx
+ * add   eax, 1       =>   add   ecx, 1
+ * mov   ecx, eax
+ */
+fn coalesce_move_operation_move(insns: &mut Vec<X86Instruction>) -> bool {
+    let mut changed = false;
+    let mut labels = x86_label_positions(insns);
+    let mut i = 0;
+    while i + 2 < insns.len() {
+        let first = insns[i];
+        let op = insns[i + 1];
+        let last = insns[i + 2];
+        if first.volatile || op.volatile || last.volatile {
+            i += 1;
+            continue;
+        }
+
+        let is_operation = match op.opcode {
+            X86Opcode::ADD |
+            X86Opcode::SUB |
+            X86Opcode::AND |
+            X86Opcode::OR  |
+            X86Opcode::XOR => true,
+            X86Opcode::IMUL => op.op3.is_none(),
+            _ => false,
+        };
+
+        let regs = match (first.op1, op.op1, last.op1, last.op2) {
+            (Some(X86Operand::Register(tmp)), Some(X86Operand::Register(op_dst)),
+             Some(X86Operand::Register(dst)), Some(X86Operand::Register(src))) if tmp == op_dst && tmp == src && tmp != dst => {
+                Some((tmp, dst))
+            }
+            _ => None,
+        };
+
+        let (tmp, dst) = match regs {
+            Some(regs) if is_operation &&
+                first.opcode == X86Opcode::MOV &&
+                last.opcode == X86Opcode::MOV &&
+                first.ty == op.ty && op.ty == last.ty && op.ty != IrType::I8 => regs,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let is_frame_register = |reg| reg == X86Reg::RSP || reg == X86Reg::RBP;
+        if is_frame_register(tmp) || is_frame_register(dst) ||
+            x86_operand_uses_register(op.op2.unwrap(), dst) ||
+            is_x86_live_after(insns, &labels, i + 2, X86Resource::Register(tmp)) {
+            i += 1;
+            continue;
+        }
 
-    let prev_stack_offset = x86.curr_stack_offset;
-    let mut arg_moves: Vec<(IrType, X86Operand, IrOperand)> = Vec::new();
-    for (op, ty) in x86.argument_stack.iter().rev() {
-        let dst_op = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-        arg_moves.push((*ty, dst_op, *op));
-        x86.curr_stack_offset += size_of_ir_type(*ty, x86.addr_size);
+        insns[i].op1 = Some(X86Operand::Register(dst));
+        insns[i + 1].op1 = Some(X86Operand::Register(dst));
+        insns[i + 1].op2 = Some(replace_x86_register(op.op2.unwrap(), tmp, dst));
+        insns.remove(i + 2);
+        labels = x86_label_positions(insns);
+        changed = true;
     }
+    changed
+}
 
-    for (ty, dst, src) in arg_moves {
-        let src_op = to_x86_operand(x86, src, ty);
-        push_instruction(x86, X86Opcode::MOV, ty, dst, src_op);
+/**
+ * Replaces `mov reg, 0` with the shorter `xor reg, reg` when the flags it overwrites are not used.
+ */
+fn zero_registers_using_xor(insns: &mut [X86Instruction]) -> bool {
+    let mut changed = false;
+    let labels = x86_label_positions(insns);
+    for i in 0..insns.len() {
+        let insn = insns[i];
+        if let (X86Opcode::MOV, Some(X86Operand::Register(reg)), Some(X86Operand::Value(val))) = (insn.opcode, insn.op1, insn.op2) {
+            // NOTE(alexander): byte registers are not cleared since the encoding is equally long
+            if insn.ty != IrType::I8 && !insn.volatile && x86_value_to_i64(val) == 0 &&
+                !is_x86_live_after(insns, &labels, i, X86Resource::Flags) {
+                insns[i].opcode = X86Opcode::XOR;
+                insns[i].op2 = Some(X86Operand::Register(reg));
+                changed = true;
+            }
+        }
     }
-    if x86.curr_stack_offset < x86.max_stack_requirement {
-        x86.max_stack_requirement = x86.curr_stack_offset;
+    changed
+}
+
+/**
+ * Checks if the value of the resource after the instruction at the given index may be read,
+ * i.e. it is read on some path before being overwritten. Paths leaving the instructions
+ * e.g. jumps to unknown labels are assumed to read it.
+ */
+fn is_x86_live_after(
+    insns: &[X86Instruction],
+    labels: &HashMap<IrIdent, usize>,
+    index: usize,
+    resource: X86Resource
+) -> bool {
+    let mut visited = HashSet::new();
+    let mut worklist = x86_successors(insns, labels, index);
+    while let Some(next) = worklist.pop() {
+        let pos = match next {
+            Some(pos) if pos < insns.len() => pos,
+            _ => return true,
+        };
+
+        if !visited.insert(pos) {
+            continue;
+        }
+
+        let access = match resource {
+            X86Resource::Register(reg) => x86_register_access(&insns[pos], reg),
+            X86Resource::Flags => x86_flags_access(&insns[pos]),
+        };
+
+        match access {
+            X86Access::Read => return true,
+            X86Access::Write => {},
+            X86Access::None => worklist.extend(x86_successors(insns, labels, pos)),
+        }
     }
-    x86.curr_stack_offset = prev_stack_offset;
+    false
+}
 
-    X86Operand::Register(X86Reg::RAX)
+/**
+ * Instructions that may execute after the given one, `None` is used for unknown targets.
+ */
+fn x86_successors(insns: &[X86Instruction], labels: &HashMap<IrIdent, usize>, index: usize) -> Vec<Option<usize>> {
+    let insn = &insns[index];
+    let target = || insn.label.and_then(|label| labels.get(&label).copied());
+    match insn.opcode {
+        X86Opcode::RET => Vec::new(),
+        X86Opcode::JMP => vec![target()],
+        opcode if is_x86_jump(opcode) => vec![Some(index + 1), target()],
+        _ => vec![Some(index + 1)],
+    }
 }
 
-fn sysv64_calling_convention(x86: &mut X86Assembler) -> X86Operand {
-    let dst_reg: [X86Reg; 6] = [X86Reg::RDI, X86Reg::RSI, X86Reg::RDX, X86Reg::RCX, X86Reg::R8, X86Reg::R9];
-    for i in 0..6 {
-        if let Some((op, ty)) = x86.argument_stack.pop_front() {
-            let src_op = to_x86_operand(x86, op, ty);
-            let dst_op = X86Operand::Register(dst_reg[i]);
-            push_instruction(x86, X86Opcode::MOV, ty, dst_op, src_op);
+fn x86_register_access(insn: &X86Instruction, reg: X86Reg) -> X86Access {
+    let operands = [insn.op1, insn.op2, insn.op3];
+    let is_reg = |op: Option<X86Operand>| op == Some(X86Operand::Register(reg));
+    let is_full_write = insn.ty != IrType::I8;
+
+    // NOTE(alexander): registers used to address memory are always read
+    if operands.iter().flatten().any(|op| match op {
+        X86Operand::Stack(base, _) => *base == reg,
+        X86Operand::Indexed(base, index, _, _) => *base == reg || *index == reg,
+        _ => false,
+    }) {
+        return X86Access::Read;
+    }
+
+    match insn.opcode {
+        X86Opcode::LABEL |
+        X86Opcode::NOP   |
+        X86Opcode::JL    |
+        X86Opcode::JLE   |
+        X86Opcode::JG    |
+        X86Opcode::JGE   |
+        X86Opcode::JE    |
+        X86Opcode::JNE   |
+        X86Opcode::JB    |
+        X86Opcode::JBE   |
+        X86Opcode::JA    |
+        X86Opcode::JAE   |
+        X86Opcode::JMP => X86Access::None,
+
+        X86Opcode::MOV    |
+        X86Opcode::MOVABS |
+        X86Opcode::MOVSX  |
+        X86Opcode::LEA => if is_reg(insn.op2) {
+            X86Access::Read
+        } else if is_reg(insn.op1) && is_full_write {
+            X86Access::Write
         } else {
-            break;
+            X86Access::None
         }
-    }
 
-    let prev_stack_offset = x86.curr_stack_offset;
-    let mut arg_moves: Vec<(IrType, X86Operand, IrOperand)> = Vec::new();
-    for (op, ty) in x86.argument_stack.iter().rev() {
-        let dst_op = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-        arg_moves.push((*ty, dst_op, *op));
-        x86.curr_stack_offset += size_of_ir_type(*ty, x86.addr_size);
-    }
+        X86Opcode::IMUL if insn.op3.is_some() => if is_reg(insn.op2) {
+            X86Access::Read
+        } else if is_reg(insn.op1) && is_full_write {
+            X86Access::Write
+        } else {
+            X86Access::None
+        }
+
+        // xor reg, reg
+        X86Opcode::XOR if is_reg(insn.op1) && is_reg(insn.op2) && is_full_write => X86Access::Write,
+
+        X86Opcode::ADD  |
+        X86Opcode::SUB  |
+        X86Opcode::IMUL |
+        X86Opcode::AND  |
+        X86Opcode::OR   |
+        X86Opcode::XOR  |
+        X86Opcode::SHR  |
+        X86Opcode::CMP  |
+        X86Opcode::TEST |
+        X86Opcode::PUSH => if is_reg(insn.op1) || is_reg(insn.op2) {
+            X86Access::Read
+        } else {
+            X86Access::None
+        }
+
+        // NOTE(alexander): the destination keeps its value if the condition is false
+        X86Opcode::CMOVL  |
+        X86Opcode::CMOVLE |
+        X86Opcode::CMOVG  |
+        X86Opcode::CMOVGE |
+        X86Opcode::CMOVE  |
+        X86Opcode::CMOVNE |
+        X86Opcode::CMOVB  |
+        X86Opcode::CMOVBE |
+        X86Opcode::CMOVA  |
+        X86Opcode::CMOVAE => if is_reg(insn.op1) || is_reg(insn.op2) {
+            X86Access::Read
+        } else {
+            X86Access::None
+        }
 
-    for (ty, dst, src) in arg_moves {
-        let src_op = to_x86_operand(x86, src, ty);
-        push_instruction(x86, X86Opcode::MOV, ty, dst, src_op);
+        X86Opcode::SETL  |
+        X86Opcode::SETLE |
+        X86Opcode::SETG  |
+        X86Opcode::SETGE |
+        X86Opcode::SETE  |
+        X86Opcode::SETNE |
+        X86Opcode::SETB  |
+        X86Opcode::SETBE |
+        X86Opcode::SETA  |
+        X86Opcode::SETAE => X86Access::None,
+
+        X86Opcode::POP => if is_reg(insn.op1) {
+            X86Access::Write
+        } else {
+            X86Access::None
+        }
+
+        X86Opcode::IDIV |
+        X86Opcode::DIV => if reg == X86Reg::RAX || reg == X86Reg::RDX || is_reg(insn.op1) {
+            X86Access::Read
+        } else {
+            X86Access::None
+        }
+
+        X86Opcode::CDQ |
+        X86Opcode::CQO => match reg {
+            X86Reg::RAX => X86Access::Read,
+            X86Reg::RDX => X86Access::Write,
+            _ => X86Access::None,
+        }
+
+        X86Opcode::RDTSC => match reg {
+            X86Reg::RAX | X86Reg::RDX => X86Access::Write,
+            _ => X86Access::None,
+        }
+
+        // NOTE(alexander): the leaf and subleaf are given in eax and ecx
+        X86Opcode::CPUID => match reg {
+            X86Reg::RAX | X86Reg::RCX => X86Access::Read,
+            X86Reg::RBX | X86Reg::RDX => X86Access::Write,
+            _ => X86Access::None,
+        }
+
+        // NOTE(alexander): the return value and callee-saved registers are used by the caller
+        X86Opcode::RET => if reg == X86Reg::RAX || reg == X86Reg::RSP || is_callee_saved_register(reg) {
+            X86Access::Read
+        } else {
+            X86Access::Write
+        }
+
+        // NOTE(alexander): calls may read any register e.g. arguments
+        X86Opcode::CALL |
+        X86Opcode::INT3 |
+        X86Opcode::SYSCALL => X86Access::Read,
     }
-    if x86.curr_stack_offset < x86.max_stack_requirement {
-        x86.max_stack_requirement = x86.curr_stack_offset;
+}
+
+fn x86_flags_access(insn: &X86Instruction) -> X86Access {
+    match insn.opcode {
+        X86Opcode::JL    |
+        X86Opcode::JLE   |
+        X86Opcode::JG    |
+        X86Opcode::JGE   |
+        X86Opcode::JE    |
+        X86Opcode::JNE   |
+        X86Opcode::JB    |
+        X86Opcode::JBE   |
+        X86Opcode::JA    |
+        X86Opcode::JAE   |
+        X86Opcode::SETL  |
+        X86Opcode::SETLE |
+        X86Opcode::SETG  |
+        X86Opcode::SETGE |
+        X86Opcode::SETE  |
+        X86Opcode::SETNE |
+        X86Opcode::SETB  |
+        X86Opcode::SETBE |
+        X86Opcode::SETA  |
+        X86Opcode::SETAE |
+        X86Opcode::CMOVL  |
+        X86Opcode::CMOVLE |
+        X86Opcode::CMOVG  |
+        X86Opcode::CMOVGE |
+        X86Opcode::CMOVE  |
+        X86Opcode::CMOVNE |
+        X86Opcode::CMOVB  |
+        X86Opcode::CMOVBE |
+        X86Opcode::CMOVA  |
+        X86Opcode::CMOVAE |
+        X86Opcode::INT3 => X86Access::Read,
+
+        X86Opcode::ADD     |
+        X86Opcode::SUB     |
+        X86Opcode::IMUL    |
+        X86Opcode::IDIV    |
+        X86Opcode::DIV     |
+        X86Opcode::AND     |
+        X86Opcode::OR      |
+        X86Opcode::XOR     |
+        X86Opcode::SHR     |
+        X86Opcode::CMP     |
+        X86Opcode::TEST    |
+        X86Opcode::CALL    |
+        X86Opcode::RET     |
+        X86Opcode::SYSCALL => X86Access::Write,
+
+        _ => X86Access::None,
     }
-    x86.curr_stack_offset = prev_stack_offset;
+}
 
-    X86Operand::Register(X86Reg::RAX)
+fn x86_label_positions(insns: &[X86Instruction]) -> HashMap<IrIdent, usize> {
+    insns.iter().enumerate()
+        .filter(|(_, insn)| insn.opcode == X86Opcode::LABEL)
+        .map(|(i, insn)| (insn.label.unwrap(), i))
+        .collect()
 }
 
-fn get_mr_opcode(opcode: X86Opcode, opcode_offset: u8) -> u8{
+fn is_x86_jump(opcode: X86Opcode) -> bool {
+    matches!(opcode, X86Opcode::JL | X86Opcode::JLE | X86Opcode::JG | X86Opcode::JGE |
+             X86Opcode::JE | X86Opcode::JNE | X86Opcode::JB | X86Opcode::JBE |
+             X86Opcode::JA | X86Opcode::JAE | X86Opcode::JMP)
+}
+
+fn negate_x86_jump(opcode: X86Opcode) -> X86Opcode {
     match opcode {
-        X86Opcode::MOV  => 0x89 - opcode_offset,
-        X86Opcode::ADD  => 0x01 - opcode_offset,
-        X86Opcode::SUB  => 0x29 - opcode_offset,
-        X86Opcode::AND  => 0x20 - opcode_offset,
-        X86Opcode::OR   => 0x08 - opcode_offset,
-        X86Opcode::XOR  => 0x31 - opcode_offset,
-        X86Opcode::CMP  => 0x39 - opcode_offset,
-        X86Opcode::TEST => 0x84 - opcode_offset,
-        _ => unimplemented!(),
+        X86Opcode::JL  => X86Opcode::JGE,
+        X86Opcode::JLE => X86Opcode::JG,
+        X86Opcode::JG  => X86Opcode::JLE,
+        X86Opcode::JGE => X86Opcode::JL,
+        X86Opcode::JE  => X86Opcode::JNE,
+        X86Opcode::JNE => X86Opcode::JE,
+        X86Opcode::JB  => X86Opcode::JAE,
+        X86Opcode::JBE => X86Opcode::JA,
+        X86Opcode::JA  => X86Opcode::JBE,
+        X86Opcode::JAE => X86Opcode::JB,
+        _ => panic!("x86: cannot negate {}", opcode),
     }
 }
 
-fn get_rm_opcode(opcode: X86Opcode, opcode_offset: u8) -> u8 {
+/**
+ * Returns the conditional move taken on the same condition as the given jump.
+ */
+fn x86_jump_to_cmov(opcode: X86Opcode) -> X86Opcode {
     match opcode {
-        X86Opcode::MOV  => 0x8b - opcode_offset,
-        X86Opcode::ADD  => 0x03 - opcode_offset,
-        X86Opcode::SUB  => 0x2b - opcode_offset,
-        X86Opcode::AND  => 0x22 - opcode_offset,
-        X86Opcode::OR   => 0x0a - opcode_offset,
-        X86Opcode::XOR  => 0x33 - opcode_offset,
-        X86Opcode::CMP  => 0x3b - opcode_offset,
-        X86Opcode::TEST => 0x84 - opcode_offset,
-        _ => unimplemented!(),
+        X86Opcode::JL  => X86Opcode::CMOVL,
+        X86Opcode::JLE => X86Opcode::CMOVLE,
+        X86Opcode::JG  => X86Opcode::CMOVG,
+        X86Opcode::JGE => X86Opcode::CMOVGE,
+        X86Opcode::JE  => X86Opcode::CMOVE,
+        X86Opcode::JNE => X86Opcode::CMOVNE,
+        X86Opcode::JB  => X86Opcode::CMOVB,
+        X86Opcode::JBE => X86Opcode::CMOVBE,
+        X86Opcode::JA  => X86Opcode::CMOVA,
+        X86Opcode::JAE => X86Opcode::CMOVAE,
+        _ => panic!("x86: {} is not a conditional jump", opcode),
     }
 }
 
-fn get_mi_opcode(opcode: X86Opcode, opcode_offset: u8) -> (u8, u8) {
-    match opcode {
-        X86Opcode::MOV  => (0xc7 - opcode_offset, 0),
-        X86Opcode::ADD  => (0x81 - opcode_offset, 0),
-        X86Opcode::SUB  => (0x81 - opcode_offset, 5),
-        X86Opcode::AND  => (0x81 - opcode_offset, 4),
-        X86Opcode::OR   => (0x81 - opcode_offset, 1),
-        X86Opcode::XOR  => (0x81 - opcode_offset, 6),
-        X86Opcode::CMP  => (0x81 - opcode_offset, 7),
-        X86Opcode::TEST => (0xf6 - opcode_offset, 0),
-        _ => unimplemented!(),
-    }
-}
-
-fn allocate_register(x86: &mut X86Assembler, ident: Option<IrIdent>) -> X86Reg {
-    if x86.free_registers.is_empty() {
-        let (reg, opt_ident) = x86.allocated_registers.pop_front().unwrap();
-        if let Some(prev_ident) = opt_ident {
-            match x86.local_variables.get(&prev_ident) {
-                Some(prev_variable) => {
-                    // Spill out to stack
-                    let (prev_operand, prev_ty) = *prev_variable;
-                    x86.curr_stack_offset -= size_of_ir_type(prev_ty, x86.addr_size);
-                    let stack_op = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-                    x86.local_variables.insert(prev_ident, (stack_op, prev_ty));
-                    push_instruction(x86, X86Opcode::MOV, prev_ty, stack_op, prev_operand);
+fn x86_operand_uses_register(op: X86Operand, reg: X86Reg) -> bool {
+    match op {
+        X86Operand::Stack(base, _) => base == reg,
+        X86Operand::Indexed(base, index, _, _) => base == reg || index == reg,
+        X86Operand::Register(r) => r == reg,
+        X86Operand::Value(_) => false,
+    }
+}
+
+fn replace_x86_register(op: X86Operand, from: X86Reg, to: X86Reg) -> X86Operand {
+    match op {
+        X86Operand::Stack(base, disp) if base == from => X86Operand::Stack(to, disp),
+        X86Operand::Indexed(base, index, scale, disp) if base == from || index == from => {
+            let replace = |reg: X86Reg| if reg == from { to } else { reg };
+            X86Operand::Indexed(replace(base), replace(index), scale, disp)
+        }
+        X86Operand::Register(reg) if reg == from => X86Operand::Register(to),
+        _ => op,
+    }
+}
+
+/***************************************************************************
+ * Machine code encoding
+ ***************************************************************************/
+
+/**
+ * Encodes the selected instructions into machine code and clears them,
+ * the assembly is printed at the same time.
+ */
+fn encode_instructions(x86: &mut X86Assembler) {
+    let insns = std::mem::take(&mut x86.instructions);
+    for insn in &insns {
+        print_instruction(x86, insn);
+        encode_instruction(x86, insn);
+    }
+}
+
+fn encode_instruction(x86: &mut X86Assembler, insn: &X86Instruction) {
+    let ty = insn.ty;
+    match insn.opcode {
+        X86Opcode::LABEL => {
+            let label = insn.label.unwrap();
+            x86.label_byte_pos.insert(label, x86.machine_code.len());
+        }
+
+        X86Opcode::JL  |
+        X86Opcode::JLE |
+        X86Opcode::JG  |
+        X86Opcode::JGE |
+        X86Opcode::JE  |
+        X86Opcode::JNE |
+        X86Opcode::JB  |
+        X86Opcode::JBE |
+        X86Opcode::JA  |
+        X86Opcode::JAE |
+        X86Opcode::JMP => {
+            encode_relative_jump(x86, insn.label.unwrap(), insn.opcode, false);
+        }
+
+        X86Opcode::CALL => match (insn.op1, insn.label) {
+            (Some(X86Operand::Register(reg)), _) => {
+                if is_reg_x64_only(reg) {
+                    x86.machine_code.push(REX_B);
+                }
+                x86.machine_code.push(0xff);
+                x86.machine_code.push(modrm(2, reg_id(reg)));
+            }
+
+            (None, Some(label)) => {
+                let is_foreign = x86.foreign_functions.values().any(|ident| *ident == label);
+                if x86.output == X86Output::Object && is_foreign {
+                    x86.machine_code.push(0xe8);
+                    x86.relocations.push((x86.machine_code.len(), label));
+                    push_immediate(x86, X86Value::Int32(0));
+                } else {
+                    encode_relative_jump(x86, label, X86Opcode::CALL, true);
                 }
+            }
+
+            _ => panic!("x86: expected register or label as call target"),
+        }
 
-                None => {}
+        X86Opcode::PUSH |
+        X86Opcode::POP => {
+            let reg = match insn.op1 {
+                Some(X86Operand::Register(reg)) => reg,
+                _ => panic!("x86: expected register operand to `{}`", insn.opcode),
+            };
+            if is_reg_x64_only(reg) {
+                x86.machine_code.push(REX_B);
+            }
+            let base = if let X86Opcode::PUSH = insn.opcode { 0x50 } else { 0x58 };
+            x86.machine_code.push(base + reg_id(reg));
+        }
+
+        X86Opcode::NOP     => x86.machine_code.push(0x90),
+        X86Opcode::CDQ     => x86.machine_code.push(0x99), // sign extends EAX to EDX:EAX
+        X86Opcode::CQO     => x86.machine_code.extend_from_slice(&[REX_W, 0x99]), // sign extends RAX to RDX:RAX
+        X86Opcode::RET     => x86.machine_code.push(0xc3),
+        X86Opcode::INT3    => x86.machine_code.push(0xcc),
+        X86Opcode::SYSCALL => x86.machine_code.extend_from_slice(&[0x0f, 0x05]),
+        X86Opcode::RDTSC   => x86.machine_code.extend_from_slice(&[0x0f, 0x31]), // EDX:EAX = time-stamp counter
+        X86Opcode::CPUID   => x86.machine_code.extend_from_slice(&[0x0f, 0xa2]),
+
+        X86Opcode::MOVABS => match (insn.op1, insn.op2) {
+            (Some(X86Operand::Register(reg)), Some(X86Operand::Value(val))) => {
+                push_rex_prefix(x86, None, Some(reg), IrType::I64);
+                x86.machine_code.push(0xb8 + reg_id(reg));
+                push_immediate(x86, X86Value::Int64(x86_value_to_i64(val)));
+            }
+            _ => panic!("x86: expected register and immediate operands to `movabs`"),
+        }
+
+        X86Opcode::LEA => match (insn.op1, insn.op2) {
+            (Some(X86Operand::Register(reg)), Some(X86Operand::Stack(base, disp))) => {
+                push_memory_rex_prefix(x86, Some(reg), base, ty);
+                x86.machine_code.push(0x8d);
+                push_memory_operand(x86, reg_id(reg), base, disp);
+            }
+            (Some(X86Operand::Register(reg)), Some(X86Operand::Indexed(base, index, scale, disp))) => {
+                push_indexed_rex_prefix(x86, reg, base, index, ty);
+                x86.machine_code.push(0x8d);
+                push_indexed_memory_operand(x86, reg_id(reg), base, index, scale, disp);
+            }
+            _ => panic!("x86: expected register and memory operands to `lea`"),
+        }
+
+        X86Opcode::IMUL => {
+            let reg = match insn.op1 {
+                Some(X86Operand::Register(reg)) => reg,
+                _ => panic!("x86: expected register as destination of `imul`"),
+            };
+
+            match (insn.op2.unwrap(), insn.op3) {
+                (X86Operand::Register(rm), Some(X86Operand::Value(val))) => {
+                    let imm = x86_value_to_i64(val);
+                    push_rex_prefix(x86, Some(reg), Some(rm), ty);
+                    if imm >= i8::MIN as i64 && imm <= i8::MAX as i64 {
+                        x86.machine_code.push(0x6b); // RMI, imm8
+                        x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
+                        push_immediate(x86, X86Value::Int8(imm as i8));
+                    } else {
+                        x86.machine_code.push(0x69); // RMI
+                        x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
+                        push_immediate(x86, X86Value::Int32(imm as i32));
+                    }
+                }
+
+                (X86Operand::Stack(sreg, disp), None) => {
+                    push_memory_rex_prefix(x86, Some(reg), sreg, ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(0xaf);
+                    push_memory_operand(x86, reg_id(reg), sreg, disp);
+                }
+
+                (X86Operand::Register(rm), None) => {
+                    push_rex_prefix(x86, Some(reg), Some(rm), ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(0xaf);
+                    x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
+                }
+
+                _ => panic!("x86: invalid operands to `imul`"),
+            }
+        }
+
+        X86Opcode::IDIV |
+        X86Opcode::DIV => {
+            let opcode_reg = if insn.opcode == X86Opcode::IDIV { 7 } else { 6 };
+            match insn.op1 {
+                Some(X86Operand::Stack(sreg, disp)) => {
+                    push_memory_rex_prefix(x86, None, sreg, ty);
+                    x86.machine_code.push(0xf7); // M
+                    push_memory_operand(x86, opcode_reg, sreg, disp);
+                }
+
+                Some(X86Operand::Register(reg)) => {
+                    push_rex_prefix(x86, None, Some(reg), ty);
+                    x86.machine_code.push(0xf7); // M
+                    x86.machine_code.push(modrm(opcode_reg, reg_id(reg)));
+                }
+
+                _ => panic!("x86: expected register or memory operand to `{}`", insn.opcode),
+            }
+        }
+
+        X86Opcode::SETL  |
+        X86Opcode::SETLE |
+        X86Opcode::SETG  |
+        X86Opcode::SETGE |
+        X86Opcode::SETE  |
+        X86Opcode::SETNE |
+        X86Opcode::SETB  |
+        X86Opcode::SETBE |
+        X86Opcode::SETA  |
+        X86Opcode::SETAE => {
+            let opcode_byte = match insn.opcode {
+                X86Opcode::SETL  => 0x9c,
+                X86Opcode::SETLE => 0x9e,
+                X86Opcode::SETG  => 0x9f,
+                X86Opcode::SETGE => 0x9d,
+                X86Opcode::SETE  => 0x94,
+                X86Opcode::SETNE => 0x95,
+                X86Opcode::SETB  => 0x92,
+                X86Opcode::SETBE => 0x96,
+                X86Opcode::SETA  => 0x97,
+                X86Opcode::SETAE => 0x93,
+                _ => unreachable!(),
+            };
+
+            match insn.op1 {
+                Some(X86Operand::Register(reg)) => {
+                    push_rex_prefix(x86, None, Some(reg), IrType::I8);
+                    x86.machine_code.push(0x0f);
+                    x86.machine_code.push(opcode_byte);
+                    x86.machine_code.push(modrm(0, reg_id(reg)));
+                }
+
+                Some(X86Operand::Stack(sreg, disp)) => {
+                    push_memory_rex_prefix(x86, None, sreg, IrType::I8);
+                    x86.machine_code.push(0x0f);
+                    x86.machine_code.push(opcode_byte);
+                    push_memory_operand(x86, 0, sreg, disp);
+                }
+
+                _ => panic!("x86: cannot assign to value"),
+            }
+        }
+
+        X86Opcode::MOV  |
+        X86Opcode::ADD  |
+        X86Opcode::SUB  |
+        X86Opcode::AND  |
+        X86Opcode::OR   |
+        X86Opcode::XOR  |
+        X86Opcode::CMP  |
+        X86Opcode::TEST => {
+            encode_binary_instruction(x86, insn.opcode, ty, insn.op1.unwrap(), insn.op2.unwrap());
+        }
+
+        X86Opcode::CMOVL  |
+        X86Opcode::CMOVLE |
+        X86Opcode::CMOVG  |
+        X86Opcode::CMOVGE |
+        X86Opcode::CMOVE  |
+        X86Opcode::CMOVNE |
+        X86Opcode::CMOVB  |
+        X86Opcode::CMOVBE |
+        X86Opcode::CMOVA  |
+        X86Opcode::CMOVAE => {
+            let opcode_byte = match insn.opcode {
+                X86Opcode::CMOVL  => 0x4c,
+                X86Opcode::CMOVLE => 0x4e,
+                X86Opcode::CMOVG  => 0x4f,
+                X86Opcode::CMOVGE => 0x4d,
+                X86Opcode::CMOVE  => 0x44,
+                X86Opcode::CMOVNE => 0x45,
+                X86Opcode::CMOVB  => 0x42,
+                X86Opcode::CMOVBE => 0x46,
+                X86Opcode::CMOVA  => 0x47,
+                X86Opcode::CMOVAE => 0x43,
+                _ => unreachable!(),
+            };
+
+            let reg = match insn.op1 {
+                Some(X86Operand::Register(reg)) => reg,
+                _ => panic!("x86: expected register as destination of `{}`", insn.opcode),
+            };
+
+            match insn.op2 {
+                Some(X86Operand::Register(rm)) => {
+                    push_rex_prefix(x86, Some(reg), Some(rm), ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(opcode_byte);
+                    x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
+                }
+
+                Some(X86Operand::Stack(sreg, disp)) => {
+                    push_memory_rex_prefix(x86, Some(reg), sreg, ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(opcode_byte);
+                    push_memory_operand(x86, reg_id(reg), sreg, disp);
+                }
+
+                _ => panic!("x86: invalid operands to `{}`", insn.opcode),
+            }
+        }
+
+        X86Opcode::MOVSX => {
+            let reg = match insn.op1 {
+                Some(X86Operand::Register(reg)) => reg,
+                _ => panic!("x86: expected register as destination of `movsx`"),
+            };
+
+            // NOTE(alexander): the source is always a byte, the type is the size of the destination
+            match insn.op2 {
+                Some(X86Operand::Register(rm)) => {
+                    push_movsx_rex_prefix(x86, reg, rm, ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(0xbe);
+                    x86.machine_code.push(modrm(reg_id(reg), reg_id(rm)));
+                }
+
+                Some(X86Operand::Stack(sreg, disp)) => {
+                    push_memory_rex_prefix(x86, Some(reg), sreg, ty);
+                    x86.machine_code.push(0x0f); // RM
+                    x86.machine_code.push(0xbe);
+                    push_memory_operand(x86, reg_id(reg), sreg, disp);
+                }
+
+                _ => panic!("x86: invalid operands to `movsx`"),
+            }
+        }
+
+        X86Opcode::SHR => {
+            let count = match insn.op2 {
+                Some(X86Operand::Value(val)) => x86_value_to_i64(val) as u8,
+                _ => panic!("x86: expected immediate shift count to `shr`"),
+            };
+
+            let opcode_byte = match (ty, count) {
+                (IrType::I8, 1) => 0xd0, // M1
+                (IrType::I8, _) => 0xc0, // MI
+                (_, 1)          => 0xd1, // M1
+                (_, _)          => 0xc1, // MI
+            };
+
+            match insn.op1 {
+                Some(X86Operand::Register(reg)) => {
+                    push_rex_prefix(x86, None, Some(reg), ty);
+                    x86.machine_code.push(opcode_byte);
+                    x86.machine_code.push(modrm(5, reg_id(reg)));
+                }
+
+                Some(X86Operand::Stack(sreg, disp)) => {
+                    push_memory_rex_prefix(x86, None, sreg, ty);
+                    x86.machine_code.push(opcode_byte);
+                    push_memory_operand(x86, 5, sreg, disp);
+                }
+
+                _ => panic!("x86: cannot assign to value"),
+            }
+
+            if count != 1 {
+                x86.machine_code.push(count);
             }
         }
-        x86.allocated_registers.push_back((reg, ident));
-        // x86.assembly.push_str(&format!("allocated: {}\n", reg));
-        reg
-    } else {
-        let reg = x86.free_registers.pop_front().unwrap();
-        x86.allocated_registers.push_back((reg, ident));
-        // x86.assembly.push_str(&format!("allocated: {}\n", reg));
-        reg
     }
 }
 
-#[inline]
-fn free_register(x86: &mut X86Assembler, reg: X86Reg) {
-    x86.free_registers.push_front(reg);
-    // NOTE(alexander): slow has to iterate through all allocated registers
-    // not too big of a concern right now since alloced regsiters is rather small.
-    for (i, (areg, _)) in x86.allocated_registers.iter().enumerate() {
-        if *areg == reg {
-            x86.allocated_registers.remove(i);
-            break;
+fn encode_binary_instruction(x86: &mut X86Assembler, opcode: X86Opcode, ty: IrType, dst: X86Operand, src: X86Operand) {
+    let opcode_offset = match ty {
+        IrType::I8 => 1,
+        _ => 0,
+    };
+
+    match (dst, src) {
+        (X86Operand::Stack(sreg, disp), X86Operand::Register(reg)) => {
+            push_memory_rex_prefix(x86, Some(reg), sreg, ty);
+            x86.machine_code.push(get_mr_opcode(opcode, opcode_offset));
+            push_memory_operand(x86, reg_id(reg), sreg, disp);
+        }
+
+        (X86Operand::Register(reg), X86Operand::Stack(sreg, disp)) => {
+            push_memory_rex_prefix(x86, Some(reg), sreg, ty);
+            x86.machine_code.push(get_rm_opcode(opcode, opcode_offset));
+            push_memory_operand(x86, reg_id(reg), sreg, disp);
+        }
+
+        (X86Operand::Register(reg1), X86Operand::Register(reg2)) => {
+            // NOTE(alexander): register to register uses the MR form, same as the GNU assembler
+            push_rex_prefix(x86, Some(reg2), Some(reg1), ty);
+            x86.machine_code.push(get_mr_opcode(opcode, opcode_offset));
+            x86.machine_code.push(modrm(reg_id(reg2), reg_id(reg1)));
+        }
+
+        (X86Operand::Stack(_, _), X86Operand::Value(val)) |
+        (X86Operand::Register(_), X86Operand::Value(val)) => {
+            encode_immediate_instruction(x86, opcode, ty, dst, val);
         }
+
+        _ => panic!("x86: invalid operands `{}, {}` to `{}`", dst, src, opcode),
     }
 }
 
-fn allocate_specific_register(x86: &mut X86Assembler, reg: X86Reg) -> Option<X86Reg> {
-    let mut is_occupied = false;
-    let mut prev_ident: Option<IrIdent> = None;
-    for (areg, ident) in x86.allocated_registers.iter() {
-        if *areg == reg {
-            prev_ident = *ident;
-            is_occupied = true;
-            break;
-        }
-    }
+/**
+ * Encodes instructions with an immediate source operand, the shortest encoding
+ * is selected in the same way as the GNU assembler does, e.g. sign-extended 8-bit
+ * immediates and the short forms when the destination is the accumulator.
+ */
+fn encode_immediate_instruction(x86: &mut X86Assembler, opcode: X86Opcode, ty: IrType, dst: X86Operand, val: X86Value) {
+    let imm = x86_value_to_i64(val);
+    let is_byte = ty == IrType::I8;
+    let is_quad = x86.x64_mode && size_of_ir_type(ty, x86.addr_size) == 8;
+    let fits_i8 = imm >= i8::MIN as i64 && imm <= i8::MAX as i64;
+    let fits_i32 = imm >= i32::MIN as i64 && imm <= i32::MAX as i64;
+    let sized_imm = if is_byte {
+        X86Value::Int8(imm as i8)
+    } else {
+        X86Value::Int32(imm as i32)
+    };
 
-    if !is_occupied {
-        return None;
+    let is_accumulator = dst == X86Operand::Register(X86Reg::RAX);
+    if is_quad && !fits_i32 && !(opcode == X86Opcode::MOV && matches!(dst, X86Operand::Register(_))) {
+        panic!("x86: immediate `{}` does not fit in 32 bits", imm);
     }
 
-    let temp_reg = allocate_register(x86, prev_ident);
-    if temp_reg != reg {
-        let src = X86Operand::Register(reg);
-        let dst = X86Operand::Register(temp_reg);
-        if let Some(ident) = prev_ident {
-            match x86.local_variables.get_mut(&ident) {
-                Some(var) => {
-                    var.0 = dst;
-                }
-                None => {},
-            }
+    fn push_rm_operand(x86: &mut X86Assembler, reg: u8, dst: X86Operand) {
+        match dst {
+            X86Operand::Register(rm) => x86.machine_code.push(modrm(reg, reg_id(rm))),
+            X86Operand::Stack(sreg, disp) => push_memory_operand(x86, reg, sreg, disp),
+            X86Operand::Indexed(_, _, _, _) |
+            X86Operand::Value(_) => unreachable!(),
         }
-        push_instruction(x86, X86Opcode::MOV, IrType::PtrI32(1), dst, src);
     }
-    Some(temp_reg)
-}
 
-fn free_specific_register(x86: &mut X86Assembler, reg: X86Reg, prev_reg: Option<X86Reg>) {
-    if let Some(src_reg) = prev_reg {
-        let src = X86Operand::Register(src_reg);
-        let dst = X86Operand::Register(reg);
-        push_instruction(x86, X86Opcode::MOV, IrType::PtrI32(1), dst, src);
-        free_register(x86, src_reg);
+    match dst {
+        X86Operand::Register(reg) => push_rex_prefix(x86, None, Some(reg), ty),
+        X86Operand::Stack(sreg, _) => push_memory_rex_prefix(x86, None, sreg, ty),
+        X86Operand::Indexed(_, _, _, _) => panic!("x86: indexed operands are only used by `lea`"),
+        X86Operand::Value(_) => panic!("x86: cannot use value as destination operand"),
     }
-}
+    match opcode {
+        X86Opcode::MOV => {
+            match dst {
+                X86Operand::Register(reg) if !is_quad => {
+                    let base = if is_byte { 0xb0 } else { 0xb8 };
+                    x86.machine_code.push(base + reg_id(reg));
+                    push_immediate(x86, sized_imm);
+                }
+
+                X86Operand::Register(reg) if !fits_i32 => {
+                    // movabs reg, imm64
+                    x86.machine_code.push(0xb8 + reg_id(reg));
+                    push_immediate(x86, X86Value::Int64(imm));
+                }
 
-fn insert_variable(x86: &mut X86Assembler, ty: IrType, dst: IrOperand, src: X86Operand) {
-    let ident = get_ir_ident(dst);
-    x86.local_variables.insert(ident, (src, ty));
-    if let X86Operand::Register(reg) = src {
-        for (r, id) in x86.allocated_registers.iter_mut() {
-            if *r == reg {
-                *id = Some(ident);
+                _ => {
+                    x86.machine_code.push(if is_byte { 0xc6 } else { 0xc7 });
+                    push_rm_operand(x86, 0, dst);
+                    push_immediate(x86, sized_imm);
+                }
             }
         }
-    }
-}
 
-fn to_x86_operand(x86: &mut X86Assembler, op: IrOperand, insn_ty: IrType) -> X86Operand {
-    match op {
-        IrOperand::Ident(ident) => match x86.local_variables.get(&ident) {
-            Some((operand, _)) => *operand,
-            None => {
-                let operand = X86Operand::Register(allocate_register(x86, Some(ident)));
-                x86.local_variables.insert(ident, (operand, insn_ty));
-                operand
+        X86Opcode::TEST => {
+            if is_accumulator {
+                x86.machine_code.push(if is_byte { 0xa8 } else { 0xa9 });
+            } else {
+                x86.machine_code.push(if is_byte { 0xf6 } else { 0xf7 });
+                push_rm_operand(x86, 0, dst);
             }
+            push_immediate(x86, sized_imm);
         }
 
-        IrOperand::Value(value) => match value {
-            IrValue::I32(v)  => X86Operand::Value(X86Value::Int32(v)),
-            IrValue::U32(v)  => X86Operand::Value(X86Value::Int32(v as i32)),
-            IrValue::U64(v)  => X86Operand::Value(X86Value::Int64(v as i64)),
-            IrValue::Bool(v) => X86Operand::Value(X86Value::Int8(v as i8)),
+        _ => {
+            let opcode_reg = get_mi_opcode_extension(opcode);
+            if is_byte {
+                if is_accumulator {
+                    x86.machine_code.push(opcode_reg * 8 + 4);
+                } else {
+                    x86.machine_code.push(0x80);
+                    push_rm_operand(x86, opcode_reg, dst);
+                }
+                push_immediate(x86, sized_imm);
+            } else if fits_i8 {
+                x86.machine_code.push(0x83);
+                push_rm_operand(x86, opcode_reg, dst);
+                push_immediate(x86, X86Value::Int8(imm as i8));
+            } else {
+                if is_accumulator {
+                    x86.machine_code.push(opcode_reg * 8 + 5);
+                } else {
+                    x86.machine_code.push(0x81);
+                    push_rm_operand(x86, opcode_reg, dst);
+                }
+                push_immediate(x86, sized_imm);
+            }
         }
-
-        IrOperand::None => panic!("x86: unexpected empty operand"),
-    }
-}
-
-fn to_ref_type(ty: IrType) -> IrType {
-    match ty {
-        IrType::I8 => IrType::PtrI8(1),
-        IrType::I32 => IrType::PtrI32(1),
-        IrType::PtrI8(i) => IrType::PtrI8(i + 1),
-        IrType::PtrI32(i) => IrType::PtrI32(i + 1),
-        _ => panic!("unexpected type"),
-    }
-}
-
-fn move_operand_to_register(
-    x86: &mut X86Assembler,
-    op: X86Operand,
-    ident: Option<IrIdent>,
-    ty: IrType
-) -> (X86Reg, X86Operand) {
-
-    if let X86Operand::Register(reg) = op {
-        (reg, op)
-    } else {
-        let reg = allocate_register(x86, ident);
-        let new_operand = X86Operand::Register(reg);
-        push_instruction(x86, X86Opcode::MOV, ty, new_operand, op);
-        (reg, new_operand)
     }
 }
 
-fn move_operand_to_stack(x86: &mut X86Assembler, op: X86Operand, ty: IrType) -> (isize, X86Operand) {
-    if let X86Operand::Stack(_, disp) = op {
-        (disp, op)
-    } else {
-        x86.curr_stack_offset -= size_of_ir_type(ty, x86.addr_size);
-        let stack_op = X86Operand::Stack(X86Reg::RBP, x86.curr_stack_offset);
-        push_instruction(x86, X86Opcode::MOV, ty, stack_op, op);
-        (x86.curr_stack_offset, stack_op)
+fn get_mr_opcode(opcode: X86Opcode, opcode_offset: u8) -> u8{
+    match opcode {
+        X86Opcode::MOV  => 0x89 - opcode_offset,
+        X86Opcode::ADD  => 0x01 - opcode_offset,
+        X86Opcode::SUB  => 0x29 - opcode_offset,
+        X86Opcode::AND  => 0x21 - opcode_offset,
+        X86Opcode::OR   => 0x09 - opcode_offset,
+        X86Opcode::XOR  => 0x31 - opcode_offset,
+        X86Opcode::CMP  => 0x39 - opcode_offset,
+        X86Opcode::TEST => 0x85 - opcode_offset,
+        _ => panic!("x86: `{}` has no register or memory destination form", opcode),
     }
 }
 
-#[inline]
-fn get_ir_ident(op: IrOperand) -> IrIdent {
-    if let IrOperand::Ident(ident) = op {
-        ident
-    } else {
-        panic!("x86: expected an identifier as operand")
+fn get_rm_opcode(opcode: X86Opcode, opcode_offset: u8) -> u8 {
+    match opcode {
+        X86Opcode::MOV  => 0x8b - opcode_offset,
+        X86Opcode::ADD  => 0x03 - opcode_offset,
+        X86Opcode::SUB  => 0x2b - opcode_offset,
+        X86Opcode::AND  => 0x23 - opcode_offset,
+        X86Opcode::OR   => 0x0b - opcode_offset,
+        X86Opcode::XOR  => 0x33 - opcode_offset,
+        X86Opcode::CMP  => 0x3b - opcode_offset,
+        X86Opcode::TEST => 0x85 - opcode_offset, // NOTE(alexander): test is commutative, same as the MR form
+        _ => panic!("x86: `{}` has no memory source form", opcode),
     }
 }
 
-#[inline]
-fn maybe_get_ir_ident(op: IrOperand) -> Option<IrIdent> {
-    if let IrOperand::Ident(ident) = op {
-        Some(ident)
-    } else {
-        None
+/**
+ * The opcode extension stored in the reg field of ModR/M for the group 1
+ * instructions (0x80, 0x81 and 0x83), also gives the short accumulator forms.
+ */
+fn get_mi_opcode_extension(opcode: X86Opcode) -> u8 {
+    match opcode {
+        X86Opcode::ADD  => 0,
+        X86Opcode::OR   => 1,
+        X86Opcode::AND  => 4,
+        X86Opcode::SUB  => 5,
+        X86Opcode::XOR  => 6,
+        X86Opcode::CMP  => 7,
+        _ => panic!("x86: `{}` has no immediate source form", opcode),
     }
 }
 
-fn push_relative_jump(x86: &mut X86Assembler, ident: IrIdent, opcode: X86Opcode, is_long_jump: bool) {
+fn encode_relative_jump(x86: &mut X86Assembler, ident: IrIdent, opcode: X86Opcode, is_long_jump: bool) {
     let pos = x86.machine_code.len();
 
     // NOTE(alexander): opcode + jump distance is filled after the entire program has compiled.
@@ -1422,6 +4013,49 @@ fn push_relative_jump(x86: &mut X86Assembler, ident: IrIdent, opcode: X86Opcode,
 }
 
 fn push_rex_prefix(x86: &mut X86Assembler, reg: Option<X86Reg>, rm: Option<X86Reg>, ty: IrType) {
+    push_rex_prefix_with_base(x86, reg, rm, None, ty);
+}
+
+/**
+ * Same as `push_rex_prefix` but for instructions addressing memory at `[base + disp]`,
+ * the base register only extends the rm field and is never treated as a byte register.
+ */
+fn push_memory_rex_prefix(x86: &mut X86Assembler, reg: Option<X86Reg>, base: X86Reg, ty: IrType) {
+    push_rex_prefix_with_base(x86, reg, None, Some(base), ty);
+}
+
+/**
+ * Same as `push_rex_prefix` but the type only applies to the destination register,
+ * the source is always a byte register.
+ */
+fn push_movsx_rex_prefix(x86: &mut X86Assembler, reg: X86Reg, rm: X86Reg, ty: IrType) {
+    if !x86.x64_mode {
+        return;
+    }
+
+    let mut rex_prefix = if size_of_ir_type(ty, x86.addr_size) == 8 { REX_W } else { 0u8 };
+    if is_reg_x64_only(reg) {
+        rex_prefix |= REX_R;
+    }
+    if is_reg_x64_only(rm) {
+        rex_prefix |= REX_B;
+    }
+    if let X86Reg::RSP | X86Reg::RBP | X86Reg::RSI | X86Reg::RDI = rm {
+        rex_prefix |= REX;
+    }
+
+    if rex_prefix > 0 {
+        x86.machine_code.push(rex_prefix);
+    }
+}
+
+fn push_rex_prefix_with_base(
+    x86: &mut X86Assembler,
+    reg: Option<X86Reg>,
+    rm: Option<X86Reg>,
+    base: Option<X86Reg>,
+    ty: IrType
+) {
     if !x86.x64_mode {
         return;
     }
@@ -1430,19 +4064,30 @@ fn push_rex_prefix(x86: &mut X86Assembler, reg: Option<X86Reg>, rm: Option<X86Re
         IrType::I64       |
         IrType::U64       |
         IrType::PtrI8(_)  |
-        IrType::PtrI32(_) => REX_W,
+        IrType::PtrI32(_) |
+        IrType::PtrI64(_) |
+        IrType::PtrU64(_) => REX_W,
         _ => 0u8,
     };
 
     if let Some(r) = reg {
         if is_reg_x64_only(r) {
-            rex_prefix = rex_prefix | REX_R;
+            rex_prefix |= REX_R;
         }
     }
 
-    if let Some(r) = rm {
+    if let Some(r) = rm.or(base) {
         if is_reg_x64_only(r) {
-            rex_prefix = rex_prefix | REX_B;
+            rex_prefix |= REX_B;
+        }
+    }
+
+    // NOTE(alexander): byte registers spl, bpl, sil and dil are only accessible with a rex prefix
+    if let IrType::I8 = ty {
+        for r in [reg, rm].iter().flatten() {
+            if let X86Reg::RSP | X86Reg::RBP | X86Reg::RSI | X86Reg::RDI = r {
+                rex_prefix |= REX;
+            }
         }
     }
 
@@ -1451,19 +4096,93 @@ fn push_rex_prefix(x86: &mut X86Assembler, reg: Option<X86Reg>, rm: Option<X86Re
     }
 }
 
-fn push_displacement(x86: &mut X86Assembler, disp: isize) {
-    if disp < -128 || disp > 127 {
+/**
+ * Pushes the ModR/M byte addressing memory at `[base + disp]` followed by the displacement,
+ * no displacement is used when it is zero (except for rbp and r13 which requires one)
+ * and rsp and r12 requires the SIB byte.
+ */
+fn push_memory_operand(x86: &mut X86Assembler, reg: u8, base: X86Reg, disp: isize) {
+    let rm = reg_id(base);
+    let requires_disp = rm == reg_id(X86Reg::RBP);
+    if disp == 0 && !requires_disp {
+        x86.machine_code.push((reg << 3) | rm);
+    } else {
+        x86.machine_code.push(modrm_disp(reg, rm, disp));
+    }
+
+    if rm == reg_id(X86Reg::RSP) {
+        x86.machine_code.push(0x24); // SIB: no index, base
+    }
+
+    if !(-128..=127).contains(&disp) {
         let v = disp as i32;
         x86.machine_code.push((v         & 0xFFi32) as u8);
         x86.machine_code.push(((v >> 8)  & 0xFFi32) as u8);
         x86.machine_code.push(((v >> 16) & 0xFFi32) as u8);
         x86.machine_code.push(((v >> 24) & 0xFFi32) as u8);
-    } else {
+    } else if disp != 0 || requires_disp {
         let v = disp as i8;
         x86.machine_code.push(v as u8);
     }
 }
 
+/**
+ * Same as `push_memory_rex_prefix` but for instructions addressing memory at
+ * `[base + index * scale + disp]`, the index register extends the SIB index field.
+ */
+fn push_indexed_rex_prefix(x86: &mut X86Assembler, reg: X86Reg, base: X86Reg, index: X86Reg, ty: IrType) {
+    if !x86.x64_mode {
+        return;
+    }
+
+    let mut rex_prefix = if size_of_ir_type(ty, x86.addr_size) == 8 { REX_W } else { 0u8 };
+    if is_reg_x64_only(reg) {
+        rex_prefix |= REX_R;
+    }
+    if is_reg_x64_only(index) {
+        rex_prefix |= REX_X;
+    }
+    if is_reg_x64_only(base) {
+        rex_prefix |= REX_B;
+    }
+
+    if rex_prefix > 0 {
+        x86.machine_code.push(rex_prefix);
+    }
+}
+
+/**
+ * Pushes the ModR/M and SIB bytes addressing memory at `[base + index * scale + disp]`
+ * followed by the displacement, rbp and r13 as base always requires a displacement.
+ */
+fn push_indexed_memory_operand(x86: &mut X86Assembler, reg: u8, base: X86Reg, index: X86Reg, scale: u8, disp: isize) {
+    if index == X86Reg::RSP {
+        panic!("x86: rsp cannot be used as index register");
+    }
+
+    let requires_disp = reg_id(base) == reg_id(X86Reg::RBP);
+    if disp == 0 && !requires_disp {
+        x86.machine_code.push((reg << 3) | 0b100);
+    } else {
+        x86.machine_code.push(modrm_disp(reg, 0b100, disp));
+    }
+
+    let scale_bits = match scale {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        8 => 3,
+        _ => panic!("x86: invalid scale {} of index register", scale),
+    };
+    x86.machine_code.push((scale_bits << 6) | (reg_id(index) << 3) | reg_id(base));
+
+    if disp != disp as i8 as isize {
+        x86.machine_code.extend(&(disp as i32).to_le_bytes());
+    } else if disp != 0 || requires_disp {
+        x86.machine_code.push(disp as i8 as u8);
+    }
+}
+
 fn push_immediate(x86: &mut X86Assembler, immediate: X86Value) {
     match immediate {
         X86Value::Int8(v) => {
@@ -1488,6 +4207,14 @@ fn push_immediate(x86: &mut X86Assembler, immediate: X86Value) {
     }
 }
 
+fn x86_value_to_i64(val: X86Value) -> i64 {
+    match val {
+        X86Value::Int8(v) => v as i64,
+        X86Value::Int32(v) => v as i64,
+        X86Value::Int64(v) => v,
+    }
+}
+
 fn reg_id(reg: X86Reg) -> u8 {
     match reg {
         X86Reg::RAX => 0,
@@ -1510,35 +4237,196 @@ fn reg_id(reg: X86Reg) -> u8 {
 }
 
 fn is_reg_x64_only(reg: X86Reg) -> bool {
-    match reg {
-        X86Reg::R8  |
-        X86Reg::R9  |
-        X86Reg::R10 |
-        X86Reg::R11 |
-        X86Reg::R12 |
-        X86Reg::R13 |
-        X86Reg::R14 |
-        X86Reg::R15 => true,
-        _ => false,
+    matches!(reg,
+             X86Reg::R8  |
+             X86Reg::R9  |
+             X86Reg::R10 |
+             X86Reg::R11 |
+             X86Reg::R12 |
+             X86Reg::R13 |
+             X86Reg::R14 |
+             X86Reg::R15)
+}
+
+const X86_SYS_WRITE: i32 = 1;
+const X86_SYS_EXIT: i32 = 60;
+
+/**
+ * Writes the decimal representation of the first argument followed by a newline to stdout,
+ * the digits are stored from the end of a buffer on the stack.
+ */
+fn push_print_integer_routine(x86: &mut X86Assembler, ty: IrType) {
+    let rax = X86Operand::Register(X86Reg::RAX);
+    let rcx = X86Operand::Register(X86Reg::RCX);
+    let rdx = X86Operand::Register(X86Reg::RDX);
+    let rsi = X86Operand::Register(X86Reg::RSI);
+    let rdi = X86Operand::Register(X86Reg::RDI);
+    let rbp = X86Operand::Register(X86Reg::RBP);
+    let rsp = X86Operand::Register(X86Reg::RSP);
+    let digit = X86Operand::Stack(X86Reg::RSI, 0);
+    let is_signed = !is_unsigned_ir_type(ty);
+    let digits_label = create_x86_label(x86);
+    let next_label = create_x86_label(x86);
+    let write_label = create_x86_label(x86);
+
+    // NOTE(alexander): 20 digits, the sign and the newline fit in the buffer
+    push_unary_instruction(x86, X86Opcode::PUSH, IrType::I64, rbp);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rbp, rsp);
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rsp, X86Operand::Value(X86Value::Int32(32)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rsi, rbp);
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rsi, X86Operand::Value(X86Value::Int32(1)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I8, digit, X86Operand::Value(X86Value::Int8(b'\n' as i8)));
+    push_instruction(x86, X86Opcode::MOV, ty, rax, rdi);
+
+    // The digits of negative numbers are calculated from the absolute value, which fits unsigned
+    if is_signed {
+        push_instruction(x86, X86Opcode::TEST, ty, rax, rax);
+        push_relative_jump(x86, digits_label, X86Opcode::JGE);
+        push_instruction(x86, X86Opcode::XOR, IrType::I32, rcx, rcx);
+        push_instruction(x86, X86Opcode::SUB, ty, rcx, rax);
+        push_instruction(x86, X86Opcode::MOV, ty, rax, rcx);
+    }
+
+    push_label(x86, digits_label);
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, rcx, X86Operand::Value(X86Value::Int32(10)));
+    push_label(x86, next_label);
+    push_instruction(x86, X86Opcode::XOR, IrType::I32, rdx, rdx);
+    push_unary_instruction(x86, X86Opcode::DIV, ty, rcx);
+    push_instruction(x86, X86Opcode::ADD, IrType::I8, rdx, X86Operand::Value(X86Value::Int8(b'0' as i8)));
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rsi, X86Operand::Value(X86Value::Int32(1)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I8, digit, rdx);
+    push_instruction(x86, X86Opcode::TEST, ty, rax, rax);
+    push_relative_jump(x86, next_label, X86Opcode::JNE);
+
+    if is_signed {
+        push_instruction(x86, X86Opcode::TEST, ty, rdi, rdi);
+        push_relative_jump(x86, write_label, X86Opcode::JGE);
+        push_instruction(x86, X86Opcode::SUB, IrType::I64, rsi, X86Operand::Value(X86Value::Int32(1)));
+        push_instruction(x86, X86Opcode::MOV, IrType::I8, digit, X86Operand::Value(X86Value::Int8(b'-' as i8)));
+    }
+
+    push_label(x86, write_label);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rdx, rbp);
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rdx, rsi);
+    push_write_syscall(x86, 1);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rsp, rbp);
+    push_unary_instruction(x86, X86Opcode::POP, IrType::I64, rbp);
+    push_opcode_instruction(x86, X86Opcode::RET);
+}
+
+/**
+ * Writes `true` or `false` depending on the first argument followed by a newline to stdout,
+ * the string is pushed on the stack since executables have no data section.
+ */
+fn push_print_bool_routine(x86: &mut X86Assembler) {
+    let rax = X86Operand::Register(X86Reg::RAX);
+    let rdx = X86Operand::Register(X86Reg::RDX);
+    let rsi = X86Operand::Register(X86Reg::RSI);
+    let rdi = X86Operand::Register(X86Reg::RDI);
+    let write_label = create_x86_label(x86);
+
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rax, x86_ascii_value(b"true\n"));
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, rdx, X86Operand::Value(X86Value::Int32(5)));
+    push_instruction(x86, X86Opcode::TEST, IrType::I8, rdi, rdi);
+    push_relative_jump(x86, write_label, X86Opcode::JNE);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rax, x86_ascii_value(b"false\n"));
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, rdx, X86Operand::Value(X86Value::Int32(6)));
+    push_label(x86, write_label);
+    push_unary_instruction(x86, X86Opcode::PUSH, IrType::I64, rax);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rsi, X86Operand::Register(X86Reg::RSP));
+    push_write_syscall(x86, 1);
+    push_unary_instruction(x86, X86Opcode::POP, IrType::I64, rax);
+    push_opcode_instruction(x86, X86Opcode::RET);
+}
+
+/**
+ * Assertions return if the check succeeds, otherwise they continue with
+ * writing `assertion failed` to stderr and exit with code 1.
+ */
+fn push_assert_routine(x86: &mut X86Assembler, ty: IrType, compare_arguments: bool) {
+    let rsi = X86Operand::Register(X86Reg::RSI);
+    let rdi = X86Operand::Register(X86Reg::RDI);
+    let failed_label = create_x86_label(x86);
+
+    if compare_arguments {
+        push_instruction(x86, X86Opcode::CMP, ty, rdi, rsi);
+        push_relative_jump(x86, failed_label, X86Opcode::JNE);
+    } else {
+        push_instruction(x86, X86Opcode::TEST, ty, rdi, rdi);
+        push_relative_jump(x86, failed_label, X86Opcode::JE);
     }
+    push_opcode_instruction(x86, X86Opcode::RET);
+
+    // NOTE(alexander): the message is 17 bytes, the stack is not used after the write
+    let rax = X86Operand::Register(X86Reg::RAX);
+    let rsp = X86Operand::Register(X86Reg::RSP);
+    push_label(x86, failed_label);
+    push_instruction(x86, X86Opcode::SUB, IrType::I64, rsp, X86Operand::Value(X86Value::Int32(24)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rax, x86_ascii_value(b"assertio"));
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, X86Operand::Stack(X86Reg::RSP, 0), rax);
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rax, x86_ascii_value(b"n failed"));
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, X86Operand::Stack(X86Reg::RSP, 8), rax);
+    push_instruction(x86, X86Opcode::MOV, IrType::I8, X86Operand::Stack(X86Reg::RSP, 16), X86Operand::Value(X86Value::Int8(b'\n' as i8)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I64, rsi, rsp);
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, X86Operand::Register(X86Reg::RDX), X86Operand::Value(X86Value::Int32(17)));
+    push_write_syscall(x86, 2);
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, rdi, X86Operand::Value(X86Value::Int32(1)));
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, rax, X86Operand::Value(X86Value::Int32(X86_SYS_EXIT)));
+    push_opcode_instruction(x86, X86Opcode::SYSCALL);
 }
 
+/**
+ * Writes rdx bytes starting at rsi to the file descriptor.
+ */
+fn push_write_syscall(x86: &mut X86Assembler, fd: i32) {
+    let sys_write = X86Operand::Value(X86Value::Int32(X86_SYS_WRITE));
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, X86Operand::Register(X86Reg::RAX), sys_write);
+    push_instruction(x86, X86Opcode::MOV, IrType::I32, X86Operand::Register(X86Reg::RDI), X86Operand::Value(X86Value::Int32(fd)));
+    push_opcode_instruction(x86, X86Opcode::SYSCALL);
+}
+
+/**
+ * Packs at most eight characters into an immediate, stored in memory they are in order.
+ */
+fn x86_ascii_value(chars: &[u8]) -> X86Operand {
+    let mut bytes = [0u8; 8];
+    bytes[..chars.len()].copy_from_slice(chars);
+    X86Operand::Value(X86Value::Int64(i64::from_le_bytes(bytes)))
+}
+
+/**
+ * Foreign functions that can be called from executables, these have a runtime routine
+ * except for `trace` and `debug_break` which are handled by the backend itself.
+ */
+pub const X86_EXECUTABLE_FOREIGN_FUNCTIONS: &[&str] = &[
+    "trace",
+    "debug_break",
+    "print_int",
+    "print_i64",
+    "print_u64",
+    "print_bool",
+    "assert",
+    "assert_eq_int",
+    "assert_eq_bool",
+];
+
 const REX:   u8 = 0b01000000;
 const REX_W: u8 = 0b01001000;
 const REX_R: u8 = 0b01000100;
+const REX_X: u8 = 0b01000010;
 const REX_B: u8 = 0b01000001;
 
 #[inline]
 fn modrm(reg: u8, rm: u8) -> u8 {
-    return 0b11000000 | (reg << 3) | rm;
+    0b11000000 | (reg << 3) | rm
 }
 
 #[inline]
 fn modrm_disp(reg: u8, rm: u8, disp: isize) -> u8 {
-    if disp < -128 || disp > 127 {
-        return 0b10000000 | (reg << 3) | rm;
+    if !(-128..=127).contains(&disp) {
+        0b10000000 | (reg << 3) | rm
     } else {
-        return 0b01000000 | (reg << 3) | rm;
+        0b01000000 | (reg << 3) | rm
     }
 }
 
@@ -1546,64 +4434,119 @@ fn modrm_disp(reg: u8, rm: u8, disp: isize) -> u8 {
  * Printing help
  ***************************************************************************/
 
-fn print_instruction(
-    x86: &mut X86Assembler,
-    opcode: X86Opcode,
-    ty: IrType,
-    op1: X86Operand,
-    op1_indirect: bool,
-    op2: X86Operand,
-    op2_indirect: bool
-) {
-    if x86.print_assembly {
-        let ptr_str = match ty {
-            IrType::I8        => "byte ptr",
-            IrType::I32       => "dword ptr",
-            IrType::I64       => "qword ptr",
-            IrType::U32       => "dword ptr",
-            IrType::U64       => "qword ptr",
-            IrType::PtrI8(_)  => "byte ptr",
-            IrType::PtrI32(_) => "dword ptr",
-            IrType::None      => "dword ptr", // NOTE(alexander): default type.
-        };
+fn print_instruction(x86: &mut X86Assembler, insn: &X86Instruction) {
+    if !x86.print_assembly {
+        return;
+    }
 
-        x86.assembly.push_str(&format!("    {:<6}", format!("{}", opcode)));
-        match op1 {
-            X86Operand::Stack(_, _) => x86.assembly.push_str(&format!("{} {}, ", ptr_str, op1)),
-            _ => if op1_indirect {
-                x86.assembly.push_str(&format!("{} [{}], ", ptr_str, op1));
-            } else {
-                x86.assembly.push_str(&format!("{}, ", op1));
-            }
-        }
+    if insn.opcode == X86Opcode::LABEL {
+        sprint_asm!(x86, "{}:\n", x86_label(x86, insn.label.unwrap()));
+        return;
+    }
 
-        match op2 {
-            X86Operand::Stack(_, _) => x86.assembly.push_str(&format!("{} {}\n", ptr_str, op2)),
-            _ => if op2_indirect {
-                x86.assembly.push_str(&format!("{} [{}]\n", ptr_str, op2));
-            } else {
-                x86.assembly.push_str(&format!("{}\n", op2));
-            }
+    let mut operands = Vec::new();
+    if let Some(label) = insn.label {
+        operands.push(x86_label(x86, label));
+    }
+    for (i, op) in [insn.op1, insn.op2, insn.op3].iter().enumerate() {
+        // NOTE(alexander): the source of movsx is always a byte
+        let ty = if insn.opcode == X86Opcode::MOVSX && i == 1 { IrType::I8 } else { insn.ty };
+        if let Some(op) = op {
+            operands.push(sized_operand(*op, ty));
         }
-        // x86.assembly.push_str(&format!("free_registers: {:?}\n\n", x86.free_registers));
+    }
+
+    if operands.is_empty() {
+        sprint_asm!(x86, "    {}\n", insn.opcode);
+    } else {
+        sprint_asm!(x86, "    {:<5} {}\n", format!("{}", insn.opcode), operands.join(", "));
+    }
+}
+
+/**
+ * Formats the operand in Intel syntax, registers are named after the size of the type
+ * and memory operands are prefixed by their size e.g. `dword ptr [rbp - 4]`.
+ */
+fn sized_operand(op: X86Operand, ty: IrType) -> String {
+    let ptr_str = match ty {
+        IrType::I8        => "byte ptr",
+        IrType::I32       => "dword ptr",
+        IrType::I64       => "qword ptr",
+        IrType::U32       => "dword ptr",
+        IrType::U64       => "qword ptr",
+        IrType::PtrI8(_)  => "qword ptr",
+        IrType::PtrI32(_) => "qword ptr",
+        IrType::PtrI64(_) => "qword ptr",
+        IrType::PtrU64(_) => "qword ptr",
+        IrType::None      => "dword ptr", // NOTE(alexander): default type.
+    };
+
+    match op {
+        X86Operand::Stack(_, _) |
+        X86Operand::Indexed(_, _, _, _) => format!("{} {}", ptr_str, op),
+        X86Operand::Register(reg) => String::from(sized_register_name(reg, ty)),
+        X86Operand::Value(val) => format!("{}", val),
+    }
+}
+
+fn sized_register_name(reg: X86Reg, ty: IrType) -> &'static str {
+    let names: [&'static str; 4] = match reg {
+        X86Reg::RAX => ["al",   "eax",  "rax", "ax"],
+        X86Reg::RCX => ["cl",   "ecx",  "rcx", "cx"],
+        X86Reg::RDX => ["dl",   "edx",  "rdx", "dx"],
+        X86Reg::RBX => ["bl",   "ebx",  "rbx", "bx"],
+        X86Reg::RSP => ["spl",  "esp",  "rsp", "sp"],
+        X86Reg::RBP => ["bpl",  "ebp",  "rbp", "bp"],
+        X86Reg::RSI => ["sil",  "esi",  "rsi", "si"],
+        X86Reg::RDI => ["dil",  "edi",  "rdi", "di"],
+        X86Reg::R8  => ["r8b",  "r8d",  "r8",  "r8w"],
+        X86Reg::R9  => ["r9b",  "r9d",  "r9",  "r9w"],
+        X86Reg::R10 => ["r10b", "r10d", "r10", "r10w"],
+        X86Reg::R11 => ["r11b", "r11d", "r11", "r11w"],
+        X86Reg::R12 => ["r12b", "r12d", "r12", "r12w"],
+        X86Reg::R13 => ["r13b", "r13d", "r13", "r13w"],
+        X86Reg::R14 => ["r14b", "r14d", "r14", "r14w"],
+        X86Reg::R15 => ["r15b", "r15d", "r15", "r15w"],
+    };
+
+    match ty {
+        IrType::I8 => names[0],
+        IrType::I32 | IrType::U32 | IrType::None => names[1],
+        IrType::I64 | IrType::U64 | IrType::PtrI8(_) | IrType::PtrI32(_) | IrType::PtrI64(_) | IrType::PtrU64(_) => names[2],
+    }
+}
+
+/**
+ * Functions keep their name, every other label is made local to the assembly file.
+ */
+fn x86_label(x86: &X86Assembler, ident: IrIdent) -> String {
+    if x86.function_labels.contains(&ident) {
+        resolve_symbol(ident.symbol).to_string()
+    } else {
+        format!(".L{}.{}", resolve_symbol(ident.symbol), ident.index)
     }
 }
 
 impl fmt::Display for X86Opcode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            X86Opcode::LABEL => write!(f, "label"),
             X86Opcode::NOP   => write!(f, "nop"),
             X86Opcode::MOV   => write!(f, "mov"),
+            X86Opcode::MOVABS => write!(f, "movabs"),
             X86Opcode::MOVSX => write!(f, "movsx"),
             X86Opcode::LEA   => write!(f, "lea"),
             X86Opcode::ADD   => write!(f, "add"),
             X86Opcode::SUB   => write!(f, "sub"),
             X86Opcode::IMUL  => write!(f, "imul"),
             X86Opcode::IDIV  => write!(f, "idiv"),
+            X86Opcode::DIV   => write!(f, "div"),
             X86Opcode::AND   => write!(f, "and"),
             X86Opcode::OR    => write!(f, "or"),
             X86Opcode::XOR   => write!(f, "xor"),
+            X86Opcode::SHR   => write!(f, "shr"),
             X86Opcode::CDQ   => write!(f, "cdq"),
+            X86Opcode::CQO   => write!(f, "cqo"),
             X86Opcode::CMP   => write!(f, "cmp"),
             X86Opcode::TEST  => write!(f, "test"),
             X86Opcode::SETL  => write!(f, "setl"),
@@ -1612,18 +4555,39 @@ impl fmt::Display for X86Opcode {
             X86Opcode::SETGE => write!(f, "setge"),
             X86Opcode::SETE  => write!(f, "sete"),
             X86Opcode::SETNE => write!(f, "setne"),
+            X86Opcode::SETB  => write!(f, "setb"),
+            X86Opcode::SETBE => write!(f, "setbe"),
+            X86Opcode::SETA  => write!(f, "seta"),
+            X86Opcode::SETAE => write!(f, "setae"),
+            X86Opcode::CMOVL  => write!(f, "cmovl"),
+            X86Opcode::CMOVG  => write!(f, "cmovg"),
+            X86Opcode::CMOVLE => write!(f, "cmovle"),
+            X86Opcode::CMOVGE => write!(f, "cmovge"),
+            X86Opcode::CMOVE  => write!(f, "cmove"),
+            X86Opcode::CMOVNE => write!(f, "cmovne"),
+            X86Opcode::CMOVB  => write!(f, "cmovb"),
+            X86Opcode::CMOVA  => write!(f, "cmova"),
+            X86Opcode::CMOVBE => write!(f, "cmovbe"),
+            X86Opcode::CMOVAE => write!(f, "cmovae"),
             X86Opcode::JL    => write!(f, "jl"),
             X86Opcode::JLE   => write!(f, "jle"),
             X86Opcode::JG    => write!(f, "jg"),
             X86Opcode::JGE   => write!(f, "jge"),
             X86Opcode::JE    => write!(f, "je"),
             X86Opcode::JNE   => write!(f, "jne"),
+            X86Opcode::JB    => write!(f, "jb"),
+            X86Opcode::JBE   => write!(f, "jbe"),
+            X86Opcode::JA    => write!(f, "ja"),
+            X86Opcode::JAE   => write!(f, "jae"),
             X86Opcode::JMP   => write!(f, "jmp"),
             X86Opcode::PUSH  => write!(f, "push"),
             X86Opcode::POP   => write!(f, "pop"),
             X86Opcode::CALL  => write!(f, "call"),
             X86Opcode::RET   => write!(f, "ret"),
             X86Opcode::INT3  => write!(f, "int3"),
+            X86Opcode::SYSCALL => write!(f, "syscall"),
+            X86Opcode::RDTSC => write!(f, "rdtsc"),
+            X86Opcode::CPUID => write!(f, "cpuid"),
         }
     }
 }
@@ -1664,13 +4628,321 @@ impl fmt::Display for X86Value {
 impl fmt::Display for X86Operand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            X86Operand::Stack(sreg, disp) => if *disp > 0 {
+            X86Operand::Stack(sreg, disp) => if *disp == 0 {
+                write!(f, "[{}]", sreg)
+            } else if *disp > 0 {
                 write!(f, "[{} + {}]", sreg, disp)
             } else {
                 write!(f, "[{} - {}]", sreg, -disp)
             }
+            X86Operand::Indexed(base, index, scale, disp) => {
+                if *scale == 1 {
+                    write!(f, "[{} + {}", base, index)?;
+                } else {
+                    write!(f, "[{} + {}*{}", base, index, scale)?;
+                }
+                if *disp > 0 {
+                    write!(f, " + {}]", disp)
+                } else if *disp < 0 {
+                    write!(f, " - {}]", -disp)
+                } else {
+                    write!(f, "]")
+                }
+            }
             X86Operand::Register(reg) => write!(f, "{}", reg),
             X86Operand::Value(val) => write!(f, "{}", val),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jit::{allocate_jit_code, finalize_jit_code};
+    use crate::ssa::compute_ir_live_intervals;
+
+    const BINARY_OPCODES: [IrOpcode; 15] = [
+        IrOpcode::Add,
+        IrOpcode::Sub,
+        IrOpcode::Mul,
+        IrOpcode::Div,
+        IrOpcode::Pow,
+        IrOpcode::Mod,
+        IrOpcode::And,
+        IrOpcode::Or,
+        IrOpcode::Xor,
+        IrOpcode::Lt,
+        IrOpcode::Le,
+        IrOpcode::Gt,
+        IrOpcode::Ge,
+        IrOpcode::Eq,
+        IrOpcode::Ne,
+    ];
+
+    const CONDITIONAL_JUMP_OPCODES: [IrOpcode; 6] = [
+        IrOpcode::IfLt,
+        IrOpcode::IfGt,
+        IrOpcode::IfLe,
+        IrOpcode::IfGe,
+        IrOpcode::IfEq,
+        IrOpcode::IfNe,
+    ];
+
+    const INTEGER_TYPES: [IrType; 4] = [IrType::I32, IrType::U32, IrType::I64, IrType::U64];
+
+    /**
+     * Operands of each test case, these are truncated to the type of the instruction.
+     * Includes negative values and values that don't fit in a 32-bit immediate.
+     */
+    const OPERANDS: [(i64, i64); 6] = [
+        (7, 3),
+        (-9, 2),
+        (3, 3),
+        (0x1_2345_6789, 5),
+        (-0x1_2345_6789, -4),
+        (3, 0x1_0000_0003),
+    ];
+
+    /**
+     * How the operands are passed to the instruction, either stored in a variable or as an immediate.
+     */
+    #[derive(Debug, Clone, Copy)]
+    enum Shape {
+        Variables,
+        RhsValue,
+        LhsValue,
+    }
+
+    const SHAPES: [Shape; 3] = [Shape::Variables, Shape::RhsValue, Shape::LhsValue];
+
+    fn create_value(ty: IrType, value: i64) -> IrValue {
+        match ty {
+            IrType::I8  => IrValue::Bool(value != 0),
+            IrType::I32 => IrValue::I32(value as i32),
+            IrType::U32 => IrValue::U32(value as u32),
+            IrType::I64 => IrValue::I64(value),
+            IrType::U64 => IrValue::U64(value as u64),
+            _ => unreachable!(),
+        }
+    }
+
+    /**
+     * Truncates the value returned in rax to the given type.
+     */
+    fn truncate_value(ty: IrType, value: i64) -> i64 {
+        match ty {
+            IrType::I8  => (value as u8 != 0) as i64,
+            IrType::I32 => value as i32 as i64,
+            IrType::U32 => value as u32 as i64,
+            _ => value,
+        }
+    }
+
+    fn is_comparison(opcode: &IrOpcode) -> bool {
+        matches!(opcode,
+                 IrOpcode::Lt | IrOpcode::Le | IrOpcode::Gt | IrOpcode::Ge | IrOpcode::Eq | IrOpcode::Ne |
+                 IrOpcode::IfLt | IrOpcode::IfLe | IrOpcode::IfGt | IrOpcode::IfGe | IrOpcode::IfEq | IrOpcode::IfNe)
+    }
+
+    /**
+     * Evaluates the instruction the same way the interpreter does, i.e. wrapping arithmetic.
+     */
+    fn evaluate(opcode: &IrOpcode, ty: IrType, lhs: i64, rhs: i64) -> i64 {
+        macro_rules! evaluate_as {
+            ($t:ty) => {{
+                let (lhs, rhs) = (lhs as $t, rhs as $t);
+                match opcode {
+                    IrOpcode::Add => lhs.wrapping_add(rhs) as i64,
+                    IrOpcode::Sub => lhs.wrapping_sub(rhs) as i64,
+                    IrOpcode::Mul => lhs.wrapping_mul(rhs) as i64,
+                    IrOpcode::Div => lhs.wrapping_div(rhs) as i64,
+                    IrOpcode::Pow => lhs.wrapping_pow(rhs as u32) as i64,
+                    IrOpcode::Mod => lhs.wrapping_rem(rhs) as i64,
+                    IrOpcode::And => (lhs & rhs) as i64,
+                    IrOpcode::Or  => (lhs | rhs) as i64,
+                    IrOpcode::Xor => (lhs ^ rhs) as i64,
+                    IrOpcode::Lt | IrOpcode::IfLt => (lhs < rhs) as i64,
+                    IrOpcode::Le | IrOpcode::IfLe => (lhs <= rhs) as i64,
+                    IrOpcode::Gt | IrOpcode::IfGt => (lhs > rhs) as i64,
+                    IrOpcode::Ge | IrOpcode::IfGe => (lhs >= rhs) as i64,
+                    IrOpcode::Eq | IrOpcode::IfEq => (lhs == rhs) as i64,
+                    IrOpcode::Ne | IrOpcode::IfNe => (lhs != rhs) as i64,
+                    IrOpcode::Select => lhs.min(rhs) as i64,
+                    _ => unreachable!(),
+                }
+            }};
+        }
+
+        match ty {
+            IrType::I8  => evaluate(opcode, IrType::U64, (lhs != 0) as i64, (rhs != 0) as i64),
+            IrType::I32 => evaluate_as!(i32),
+            IrType::U32 => evaluate_as!(u32),
+            IrType::I64 => evaluate_as!(i64),
+            IrType::U64 => evaluate_as!(u64),
+            _ => unreachable!(),
+        }
+    }
+
+    /**
+     * Builds the operands of the instruction, values stored in variables are copied to them first.
+     */
+    fn build_operands(
+        insns: &mut Vec<IrInstruction>,
+        ty: IrType,
+        shape: Shape,
+        lhs: i64,
+        rhs: i64
+    ) -> (IrOperand, IrOperand) {
+        let mut build_operand = |index: u32, value: i64, is_value: bool| {
+            let op2 = IrOperand::Value(create_value(ty, value));
+            if is_value {
+                return op2;
+            }
+            let op1 = IrOperand::Ident(create_ir_ident(intern_string(""), index));
+            insns.push(IrInstruction { opcode: IrOpcode::Copy, op1, op2, ty, ..Default::default() });
+            op1
+        };
+
+        let lhs = build_operand(0, lhs, matches!(shape, Shape::LhsValue));
+        let rhs = build_operand(1, rhs, matches!(shape, Shape::RhsValue));
+        (lhs, rhs)
+    }
+
+    /**
+     * Builds a program with only a main function that computes `%2 = opcode ty lhs, rhs` and returns it,
+     * conditional jumps return whether the jump is taken and select returns the smallest operand.
+     */
+    fn build_program(
+        opcode: &IrOpcode,
+        ty: IrType,
+        shape: Shape,
+        lhs: i64,
+        rhs: i64
+    ) -> (Vec<IrInstruction>, HashMap<IrIdent, IrBasicBlock>, IrType) {
+        let main_symbol = intern_string("main");
+        let enter_label = create_ir_ident(main_symbol, 0);
+        let exit_label = create_ir_ident(main_symbol, 1);
+        let return_type = if is_comparison(opcode) { IrType::I8 } else { ty };
+
+        let mut body = vec![IrInstruction { opcode: IrOpcode::AllocParams, ..Default::default() }];
+        let (op2, op3) = build_operands(&mut body, ty, shape, lhs, rhs);
+        if CONDITIONAL_JUMP_OPCODES.contains(opcode) {
+            let taken_label = create_ir_ident(intern_string(".taken"), 0);
+            let target = IrOperand::Ident(taken_label);
+            body.push(IrInstruction { opcode: opcode.clone(), op1: op2, op2: op3, op3: target, ty, ..Default::default() });
+            let not_taken = IrOperand::Value(IrValue::Bool(false));
+            body.push(IrInstruction { opcode: IrOpcode::Return, op1: not_taken, ty: return_type, ..Default::default() });
+            body.push(IrInstruction { opcode: IrOpcode::Label, op1: target, ..Default::default() });
+            let taken = IrOperand::Value(IrValue::Bool(true));
+            body.push(IrInstruction { opcode: IrOpcode::Return, op1: taken, ty: return_type, ..Default::default() });
+        } else if *opcode == IrOpcode::Select {
+            let cond = IrOperand::Ident(create_ir_ident(intern_string(""), 3));
+            body.push(IrInstruction { opcode: IrOpcode::Lt, op1: cond, op2, op3, ty, ..Default::default() });
+            let op1 = IrOperand::Ident(create_ir_ident(intern_string(""), 2));
+            body.push(IrInstruction { opcode: IrOpcode::Select, op1, op2: cond, op3: op2, op4: op3, ty, ..Default::default() });
+            body.push(IrInstruction { opcode: IrOpcode::Return, op1, ty: return_type, ..Default::default() });
+        } else {
+            let op1 = IrOperand::Ident(create_ir_ident(intern_string(""), 2));
+            body.push(IrInstruction { opcode: opcode.clone(), op1, op2, op3, ty, ..Default::default() });
+            body.push(IrInstruction { opcode: IrOpcode::Return, op1, ty: return_type, ..Default::default() });
+        }
+
+        let mut insns = vec![
+            IrInstruction { opcode: IrOpcode::Label, op1: IrOperand::Ident(enter_label), ..Default::default() },
+            IrInstruction { opcode: IrOpcode::Prologue, ..Default::default() },
+        ];
+        let live_intervals = compute_ir_live_intervals(&body, insns.len());
+        insns.extend(body);
+        insns.push(IrInstruction { opcode: IrOpcode::Label, op1: IrOperand::Ident(exit_label), ..Default::default() });
+        insns.push(IrInstruction { opcode: IrOpcode::Epilogue, ..Default::default() });
+
+        let mut functions = HashMap::new();
+        functions.insert(enter_label, IrBasicBlock {
+            prologue_index: 1,
+            epilogue_index: insns.len() - 1,
+            enter_label,
+            exit_label,
+            return_type,
+            func_address: None,
+            is_foreign: false,
+            inline_hint: IrInlineHint::None,
+            live_intervals,
+        });
+        (insns, functions, return_type)
+    }
+
+    /**
+     * Every opcode and type combination that the IR builder can produce,
+     * booleans only support the logical and equality operators.
+     */
+    fn opcode_type_table() -> Vec<(IrOpcode, IrType)> {
+        let mut table = Vec::new();
+        let select = [IrOpcode::Select];
+        for opcode in BINARY_OPCODES.iter().chain(CONDITIONAL_JUMP_OPCODES.iter()).chain(select.iter()) {
+            for ty in &INTEGER_TYPES {
+                table.push((opcode.clone(), *ty));
+            }
+            if matches!(opcode, IrOpcode::And | IrOpcode::Or | IrOpcode::Xor |
+                                IrOpcode::Eq | IrOpcode::Ne | IrOpcode::IfEq | IrOpcode::IfNe) {
+                table.push((opcode.clone(), IrType::I8));
+            }
+        }
+        table
+    }
+
+    /**
+     * Division by zero traps on x86, so those operands are skipped.
+     */
+    fn is_defined(opcode: &IrOpcode, ty: IrType, rhs: i64) -> bool {
+        match opcode {
+            IrOpcode::Div | IrOpcode::Mod => evaluate(&IrOpcode::Ne, ty, rhs, 0) != 0,
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn compile_every_opcode_and_type() {
+        for (opcode, ty) in opcode_type_table() {
+            for shape in &SHAPES {
+                for peephole in &[false, true] {
+                    let (insns, functions, _) = build_program(&opcode, ty, *shape, 7, 3);
+                    let (machine_code, _) = compile_ir_to_x86_machine_code(&insns, &functions, *peephole);
+                    assert!(!machine_code.is_empty(), "{:?} {:?} {:?}", opcode, ty, shape);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn execute_every_opcode_and_type() {
+        for (opcode, ty) in opcode_type_table() {
+            for (lhs, rhs) in &OPERANDS {
+                let (lhs, rhs) = (*lhs, *rhs);
+                if !is_defined(&opcode, ty, rhs) {
+                    continue;
+                }
+
+                let expected = evaluate(&opcode, ty, lhs, rhs);
+                for shape in &SHAPES {
+                    for peephole in &[false, true] {
+                        let (insns, functions, return_type) = build_program(&opcode, ty, *shape, lhs, rhs);
+                        let (machine_code, assembly) = compile_ir_to_x86_machine_code(&insns, &functions, *peephole);
+
+                        let jit_code = allocate_jit_code(machine_code.len());
+                        let result = unsafe {
+                            std::ptr::copy_nonoverlapping(machine_code.as_ptr(), jit_code.addr, machine_code.len());
+                            finalize_jit_code(&jit_code);
+                            let main_function_ptr: extern "C" fn () -> i64 = std::mem::transmute(jit_code.addr);
+                            main_function_ptr()
+                        };
+
+                        assert_eq!(truncate_value(return_type, result), truncate_value(return_type, expected),
+                                   "{:?} {:?} {} {} ({:?}, peephole = {})\n{}",
+                                   opcode, ty, lhs, rhs, shape, peephole, assembly);
+                    }
+                }
+            }
+        }
+    }
+}